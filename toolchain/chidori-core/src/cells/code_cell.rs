@@ -12,41 +12,45 @@ use crate::execution::primitives::serialized_value::RkyvSerializedValue;
 /// Code cells allow notebooks to evaluate source code in a variety of languages.
 #[tracing::instrument]
 pub fn code_cell(execution_state_id: ExecutionNodeId, cell: &CodeCell, range: &TextRange) -> anyhow::Result<OperationNode> {
+    let report = code_cell_report(cell)?;
+    let (input_signature, output_signature) = signatures_from_report(&report);
+
+    let cell = cell.clone();
+    Ok(OperationNode::new(
+        cell.name.clone(),
+        execution_state_id,
+        input_signature,
+        output_signature,
+        CellTypes::Code(cell, Default::default()),
+    ))
+}
+
+/// Static analysis of a code cell's source, annotated with the signatures of the functions it declares.
+pub fn code_cell_report(cell: &CodeCell) -> anyhow::Result<Report> {
     match cell.language {
         SupportedLanguage::PyO3 => {
             let paths =
                 chidori_static_analysis::language::python::parse::extract_dependencies_python(
                     &cell.source_code,
                 )?;
-            let report = chidori_static_analysis::language::python::parse::build_report(&paths);
-            let (input_signature, output_signature) = signatures_from_report(&report);
-
-            let cell = cell.clone();
-            Ok(OperationNode::new(
-                cell.name.clone(),
-                execution_state_id,
-                input_signature,
-                output_signature,
-                CellTypes::Code(cell, Default::default()),
-            ))
+            let signatures =
+                chidori_static_analysis::language::python::parse::extract_function_signatures_python(
+                    &cell.source_code,
+                )?;
+            Ok(chidori_static_analysis::language::python::parse::build_report(&paths)
+                .with_signatures(&signatures))
         }
         SupportedLanguage::Deno => {
             let paths =
                 chidori_static_analysis::language::javascript::parse::extract_dependencies_js(
                     &cell.source_code,
                 )?;
-            let report = chidori_static_analysis::language::javascript::parse::build_report(&paths);
-
-            let (input_signature, output_signature) = signatures_from_report(&report);
-
-            let cell = cell.clone();
-            Ok(OperationNode::new(
-                cell.name.clone(),
-                execution_state_id,
-                input_signature,
-                output_signature,
-                CellTypes::Code(cell, Default::default()),
-            ))
+            let signatures =
+                chidori_static_analysis::language::javascript::parse::extract_function_signatures_js(
+                    &cell.source_code,
+                )?;
+            Ok(chidori_static_analysis::language::javascript::parse::build_report(&paths)
+                .with_signatures(&signatures))
         }
     }
 }
@@ -63,6 +67,7 @@ pub(crate) fn code_cell_exec_deno(cell: CodeCell) -> Box<OperationFn> {
                 &cell.source_code,
                 &x,
                 &cell.function_invocation,
+                &cell.policy.permissions(),
             ).await?;
             Ok(OperationFnOutput {
                 has_error: false,
@@ -70,6 +75,7 @@ pub(crate) fn code_cell_exec_deno(cell: CodeCell) -> Box<OperationFn> {
                 output: result.0,
                 stdout: result.1,
                 stderr: result.2,
+                usage: vec![],
             })
         }.boxed()
     })
@@ -88,7 +94,7 @@ pub fn code_cell_exec_python(cell: CodeCell) -> Box<OperationFn> {
                 &x,
                 &cell.function_invocation,
                 &None,
-                &None,
+                &cell.policy.requirements,
             ).await?;
             Ok(OperationFnOutput {
                 has_error: false,
@@ -96,6 +102,7 @@ pub fn code_cell_exec_python(cell: CodeCell) -> Box<OperationFn> {
                 output: result.0,
                 stdout: result.1,
                 stderr: result.2,
+                usage: vec![],
             })
         }.boxed()
     })
@@ -109,6 +116,9 @@ fn signatures_from_report(report: &Report) -> (InputSignature, OutputSignature)
             InputItemConfiguration {
                 ty: Some(InputType::String),
                 default: None,
+                // Whether the cell waits for the value is decided once the cells exposing it are known
+                may_be_star_imported: !report.star_imports.is_empty(),
+                ..Default::default()
             },
         );
     }
@@ -123,19 +133,17 @@ fn signatures_from_report(report: &Report) -> (InputSignature, OutputSignature)
 
     for (key, value) in &report.triggerable_functions {
         let mut input_signature = InputSignature::new();
-        for (i, arg) in value.arguments.iter().enumerate() {
-            input_signature.args.insert(arg.clone(), InputItemConfiguration {
-                ty: Some(InputType::String),
-                default: None,
-            });
+        for arg in &value.arguments {
+            input_signature.args.insert(arg.name.clone(), InputItemConfiguration::from(arg));
         }
 
         output_signature.functions.insert(
             key.clone(),
             OutputItemConfiguration::Function {
                 input_signature,
-                emit_event: vec![],
-                trigger_on: vec![],
+                description: value.description.clone(),
+                emit_event: value.emit_event.clone(),
+                trigger_on: value.trigger_on.clone(),
             },
         );
     }
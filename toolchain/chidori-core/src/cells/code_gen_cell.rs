@@ -1,7 +1,7 @@
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::mpsc::Sender;
-use crate::cells::{CellTypes, LLMCodeGenCell, LLMCodeGenCellChatConfiguration, LLMPromptCell, SupportedModelProviders, TextRange};
+use crate::cells::{CellTypes, LLMCodeGenCell, LLMCodeGenCellChatConfiguration, LLMPromptCell, TextRange};
 use crate::execution::primitives::operation::{AsyncRPCCommunication, InputItemConfiguration, InputSignature, InputType, OperationFn, OperationFnOutput, OperationNode, OutputItemConfiguration, OutputSignature};
 use crate::execution::primitives::serialized_value::{RkyvSerializedValue as RKV, RkyvSerializedValue, serialized_value_to_json_value};
 use futures_util::FutureExt;
@@ -13,7 +13,6 @@ use crate::execution::execution::ExecutionState;
 pub fn code_gen_cell(execution_state_id: ExecutionNodeId, cell: &LLMCodeGenCell, range: &TextRange) -> anyhow::Result<OperationNode> {
     let LLMCodeGenCell {
         name,
-        provider,
         function_invocation,
         complete_body,
         ..
@@ -24,7 +23,7 @@ pub fn code_gen_cell(execution_state_id: ExecutionNodeId, cell: &LLMCodeGenCell,
     let configuration: LLMCodeGenCellChatConfiguration = serde_yaml::from_str(&frontmatter)?;
 
 
-    let schema = chidori_prompt_format::templating::templates::analyze_referenced_partials(&&req);
+    let schema = chidori_prompt_format::templating::templates::analyze_referenced_partials(&&req)?;
     let mut role_blocks = chidori_prompt_format::templating::templates::extract_roles_from_template(r#"
 {{#system}}
    You are a developer working on a code generation tool. You have been tasked with creating a function that performs the described functionality.
@@ -53,12 +52,13 @@ pub fn code_gen_cell(execution_state_id: ExecutionNodeId, cell: &LLMCodeGenCell,
 
     // We only require the globals to be passed in if the user has not specified this prompt as a function
     if configuration.function_name.is_none() {
-        for (key, value) in &schema.unwrap().items {
+        for (key, value) in &schema.items {
             input_signature.globals.insert(
                 key.clone(),
                 InputItemConfiguration {
                     ty: Some(InputType::String),
                     default: None,
+                    ..Default::default()
                 },
             );
         }
@@ -68,19 +68,17 @@ pub fn code_gen_cell(execution_state_id: ExecutionNodeId, cell: &LLMCodeGenCell,
     let configuration = configuration.clone();
     let is_function_invocation = function_invocation.clone();
     if configuration.function_name.is_none() && is_function_invocation {
-        return panic!("Cell is called as a function invocation without a declared fn name");
+        return Err(anyhow::anyhow!("Cell is called as a function invocation without a declared fn name"));
     }
 
-    match provider {
-        SupportedModelProviders::OpenAI => Ok(OperationNode::new(
-            name.clone(),
-            execution_state_id,
-            input_signature,
-            output_signature,
-            CellTypes::CodeGen(cell.clone(), Default::default())
-            // code_gen_cell_exec_openai(cell.clone()),
-        )),
-    }
+    // Every provider is served through the same chat model interface, resolved when the cell runs
+    Ok(OperationNode::new(
+        name.clone(),
+        execution_state_id,
+        input_signature,
+        output_signature,
+        CellTypes::CodeGen(cell.clone(), Default::default())
+    ))
 }
 
 pub fn code_gen_cell_exec_openai(cell: LLMCodeGenCell) -> Box<OperationFn> {
@@ -117,7 +115,7 @@ pub fn code_gen_cell_exec_openai(cell: LLMCodeGenCell) -> Box<OperationFn> {
         let s = s.clone();
         let configuration = configuration.clone();
         async move {
-            let (value, state) = crate::library::std::ai::llm::ai_llm_code_generation_chat_model(
+            let (value, state, usage) = crate::library::std::ai::llm::ai_llm_code_generation_chat_model(
                 &s,
                 payload,
                 role_blocks,
@@ -131,6 +129,7 @@ pub fn code_gen_cell_exec_openai(cell: LLMCodeGenCell) -> Box<OperationFn> {
                 output: Ok(value),
                 stdout: vec![],
                 stderr: vec![],
+                usage,
             })
         }.boxed()
     })
@@ -5,7 +5,7 @@ use std::future::Future;
 use std::pin::Pin;
 use std::sync::mpsc::Sender;
 use tokio::runtime;
-use crate::cells::{llm_prompt_cell, CellTypes, LLMPromptCell, LLMPromptCellChatConfiguration, SupportedModelProviders, TextRange};
+use crate::cells::{llm_prompt_cell, CellTypes, LLMPromptCell, LLMPromptCellChatConfiguration, TextRange};
 use crate::execution::primitives::operation::{AsyncRPCCommunication, InputItemConfiguration, InputSignature, InputType, OperationFn, OperationFnOutput, OperationNode, OutputItemConfiguration, OutputSignature};
 use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue as RKV, RkyvSerializedValue, serialized_value_to_json_value};
 use futures_util::FutureExt;
@@ -22,7 +22,6 @@ pub fn llm_prompt_cell(execution_state_id: ExecutionNodeId, cell: &LLMPromptCell
         llm_prompt_cell @ LLMPromptCell::Chat {
             is_function_invocation: function_invocation,
             name,
-            provider,
             complete_body,
             ..
         } => {
@@ -30,6 +29,9 @@ pub fn llm_prompt_cell(execution_state_id: ExecutionNodeId, cell: &LLMPromptCell
                 anyhow::Error::msg(e.to_string())
             })?;
             let configuration: LLMPromptCellChatConfiguration = serde_yaml::from_str(&frontmatter)?;
+            // Templates that do not compile are refused here, extracting their roles would panic on them
+            let schema =
+                chidori_prompt_format::templating::templates::analyze_referenced_partials(&&req)?;
             let role_blocks =
                 chidori_prompt_format::templating::templates::extract_roles_from_template(&&req);
 
@@ -51,16 +53,15 @@ pub fn llm_prompt_cell(execution_state_id: ExecutionNodeId, cell: &LLMPromptCell
             }
 
             let mut input_signature = InputSignature::new();
-            let schema =
-                chidori_prompt_format::templating::templates::analyze_referenced_partials(&&req);
             // We only require the globals to be passed in if the user has not specified this prompt as a function
             if configuration.function_name.is_none() {
-                for (key, value) in &schema.unwrap().items {
+                for (key, value) in &schema.items {
                     input_signature.globals.insert(
                         key.clone(),
                         InputItemConfiguration {
                             ty: Some(InputType::String),
                             default: None,
+                            ..Default::default()
                         },
                     );
                 }
@@ -70,7 +71,7 @@ pub fn llm_prompt_cell(execution_state_id: ExecutionNodeId, cell: &LLMPromptCell
             let configuration = configuration.clone();
             let is_function_invocation = function_invocation.clone();
             if configuration.function_name.is_none() && is_function_invocation {
-                return panic!("Cell is called as a function invocation without a declared fn name");
+                return Err(anyhow::anyhow!("Cell is called as a function invocation without a declared fn name"));
             }
 
 
@@ -81,24 +82,23 @@ pub fn llm_prompt_cell(execution_state_id: ExecutionNodeId, cell: &LLMPromptCell
                         InputItemConfiguration {
                             ty: Some(InputType::String),
                             default: None,
+                            ..Default::default()
                         },
                     );
                 }
             }
 
-            match provider {
-                SupportedModelProviders::OpenAI => Ok(OperationNode::new(
-                    name.clone(),
-                    execution_state_id,
-                    input_signature,
-                    output_signature,
-                    CellTypes::Prompt(llm_prompt_cell.clone(), Default::default())
-                    // llm_prompt_cell_exec_chat_openai(),
-                )),
-            }
+            // Every provider is served through the same chat model interface, resolved when the cell runs
+            Ok(OperationNode::new(
+                name.clone(),
+                execution_state_id,
+                input_signature,
+                output_signature,
+                CellTypes::Prompt(llm_prompt_cell.clone(), Default::default())
+            ))
         }
         LLMPromptCell::Completion { .. } => {
-            unreachable!("fail")
+            Err(anyhow::anyhow!("Completion prompt cells are not supported, use a chat prompt instead"))
         },
     }
 }
@@ -118,7 +118,7 @@ pub fn llm_prompt_cell_exec_chat_openai(llm_prompt_cell: LLMPromptCell) -> Box<O
     let role_blocks =
         chidori_prompt_format::templating::templates::extract_roles_from_template(&&req);
 
-    Box::new(move |s, payload, _, _| {
+    Box::new(move |s, payload, intermediate_output, _| {
         let role_blocks = role_blocks.clone();
         let name = name.clone();
         // TODO: this state should error? or what should this do
@@ -134,13 +134,14 @@ pub fn llm_prompt_cell_exec_chat_openai(llm_prompt_cell: LLMPromptCell) -> Box<O
         let s = s.clone();
         let configuration = configuration.clone();
         async move {
-            let (value, state) = crate::library::std::ai::llm::ai_llm_run_chat_model(
+            let (value, state, usage) = crate::library::std::ai::llm::ai_llm_run_chat_model(
                 &s,
                 payload,
                 role_blocks,
                 name,
                 is_function_invocation,
-                configuration.clone()
+                configuration.clone(),
+                intermediate_output,
             ).await?;
             Ok(OperationFnOutput {
                 has_error: false,
@@ -148,6 +149,7 @@ pub fn llm_prompt_cell_exec_chat_openai(llm_prompt_cell: LLMPromptCell) -> Box<O
                 output: value,
                 stdout: vec![],
                 stderr: vec![],
+                usage,
             })
         }.boxed()
     })
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use futures_util::FutureExt;
+use serde::{Deserialize, Serialize};
+use crate::cells::{CellTypes, MemoryCell, TextRange};
+use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::execution::ExecutionState;
+use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::operation::{InputItemConfiguration, InputSignature, OperationFn, OperationFnOutput, OperationNode, OutputItemConfiguration, OutputSignature};
+use crate::execution::primitives::serialized_value::{json_value_to_serialized_value, serialized_value_to_json_value, RkyvObjectBuilder, RkyvSerializedValue as RKV};
+use crate::library::std::ai::memory::in_memory::InMemoryVectorDb;
+
+pub const MEMORY_INSERT_FUNCTION: &str = "insert";
+pub const MEMORY_SEARCH_FUNCTION: &str = "search";
+const SEARCH_TOP_K_KWARG: &str = "top_k";
+const DEFAULT_SEARCH_TOP_K: usize = 3;
+
+/// A value inserted into a memory cell, along with the embedding it is searched by. Records are held by
+/// the execution state, so that reverting to an earlier state also reverts what the notebook remembers.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MemoryRecord {
+    pub embedding: Vec<f32>,
+    pub value: serde_json::Value,
+}
+
+/// Memory cells declare a vector database collection, exposing `insert` and `search` functions to the
+/// rest of the notebook. Values are embedded by dispatching to the function named by `embedding_fn`.
+#[tracing::instrument]
+pub fn memory_cell(execution_state_id: ExecutionNodeId, cell: &MemoryCell, range: &TextRange) -> anyhow::Result<OperationNode> {
+    // Depending on the embedding function orders this cell after the cell that declares it
+    let mut input_signature = InputSignature::new();
+    input_signature.globals.insert(
+        cell.embedding_function.clone(),
+        InputItemConfiguration {
+            ty: None,
+            default: None,
+            ..Default::default()
+        },
+    );
+
+    let mut output_signature = OutputSignature::new();
+    for function_name in [MEMORY_INSERT_FUNCTION, MEMORY_SEARCH_FUNCTION] {
+        output_signature.functions.insert(
+            function_name.to_string(),
+            OutputItemConfiguration::Function {
+                input_signature: InputSignature::new(),
+                description: None,
+                emit_event: vec![],
+                trigger_on: vec![],
+            },
+        );
+    }
+
+    Ok(OperationNode::new(
+        cell.name.clone(),
+        execution_state_id,
+        input_signature,
+        output_signature,
+        CellTypes::Memory(cell.clone(), Default::default()),
+    ))
+}
+
+/// Collections are named after their cell, unnamed cells are told apart by their operation.
+fn collection_name(cell: &MemoryCell, operation_id: OperationId) -> String {
+    cell.name.clone().unwrap_or_else(|| operation_id.to_string())
+}
+
+/// Search the records of a collection for the values nearest to the embedding.
+fn search_records(collection: &str, records: &[MemoryRecord], embedding: Vec<f32>, top_k: usize) -> Vec<RKV> {
+    if records.is_empty() {
+        return vec![];
+    }
+    let mut db = InMemoryVectorDb::new();
+    db.new_collection(collection.to_string());
+    db.insert(collection.to_string(), &records.iter().map(|record| (&record.embedding, record.value.clone())).collect());
+    db.search(collection.to_string(), embedding, top_k)
+        .iter()
+        .map(|(_, value)| json_value_to_serialized_value(value))
+        .collect()
+}
+
+pub fn memory_cell_exec_in_memory(cell: MemoryCell) -> Box<OperationFn> {
+    Box::new(move |s, payload, _, _| {
+        let cell = cell.clone();
+        let s = s.clone();
+        async move {
+            let collection = collection_name(&cell, s.evaluating_operation_id);
+            match cell.function_invocation.as_deref() {
+                // Evaluating the cell itself only declares the functions it makes available
+                None => {
+                    Ok(OperationFnOutput::with_value(RkyvObjectBuilder::new()
+                        .insert_string(MEMORY_INSERT_FUNCTION, "function".to_string())
+                        .insert_string(MEMORY_SEARCH_FUNCTION, "function".to_string())
+                        .build()))
+                }
+                Some(MEMORY_INSERT_FUNCTION) => {
+                    let record = memory_record_from_payload(&payload);
+                    let (embedding, mut state) = embed_payload(&s, &cell, payload).await?;
+                    let mut records = state.memories.get(&collection).cloned().unwrap_or_default();
+                    records.push_back(MemoryRecord { embedding, value: serialized_value_to_json_value(&record) });
+                    state.memories.insert(collection, records);
+                    Ok(OperationFnOutput {
+                        has_error: false,
+                        execution_state: Some(state),
+                        output: Ok(RKV::Null),
+                        stdout: vec![],
+                        stderr: vec![],
+                        usage: vec![],
+                    })
+                }
+                Some(MEMORY_SEARCH_FUNCTION) => {
+                    let (payload, top_k) = take_top_k(payload)?;
+                    let (embedding, state) = embed_payload(&s, &cell, payload).await?;
+                    let records: Vec<_> = state.memories.get(&collection).map(|records| records.iter().cloned().collect()).unwrap_or_default();
+                    let results = search_records(&collection, &records, embedding, top_k);
+                    Ok(OperationFnOutput {
+                        has_error: false,
+                        execution_state: Some(state),
+                        output: Ok(RKV::Array(results)),
+                        stdout: vec![],
+                        stderr: vec![],
+                        usage: vec![],
+                    })
+                }
+                Some(other) => {
+                    Err(anyhow::anyhow!("Memory cell {:?} does not provide a function named {}", cell.name, other))
+                }
+            }
+        }.boxed()
+    })
+}
+
+/// Invoke the embedding function of the memory cell with the arguments of the insert or search call.
+async fn embed_payload(state: &ExecutionState, cell: &MemoryCell, payload: RKV) -> anyhow::Result<(Vec<f32>, ExecutionState)> {
+    let (result, state) = state.dispatch(&cell.embedding_function, payload, None).await?;
+    let value = result.map_err(|e| anyhow::anyhow!("Embedding function {} failed: {:?}", cell.embedding_function, e))?;
+    Ok((embedding_from_value(&value)?, state))
+}
+
+fn embedding_from_value(value: &RKV) -> anyhow::Result<Vec<f32>> {
+    if let RKV::Embedding(embedding) = value {
+        return Ok(embedding.clone());
+    }
+    let RKV::Array(items) = value else {
+        return Err(anyhow::anyhow!("Embedding functions must return a list of numbers, received {:?}", value));
+    };
+    items.iter().map(|item| match item {
+        RKV::Float(f) => Ok(*f as f32),
+        RKV::Number(n) => Ok(*n as f32),
+        other => Err(anyhow::anyhow!("Embedding functions must return a list of numbers, received element {:?}", other)),
+    }).collect()
+}
+
+/// The value stored alongside an embedding, a single positional argument is stored as is,
+/// otherwise the keyword arguments are stored as an object.
+fn memory_record_from_payload(payload: &RKV) -> RKV {
+    let RKV::Object(payload) = payload else { return payload.clone() };
+    let args = match payload.get("args") {
+        Some(RKV::Object(args)) => args.clone(),
+        _ => HashMap::new(),
+    };
+    let kwargs = match payload.get("kwargs") {
+        Some(RKV::Object(kwargs)) => kwargs.clone(),
+        _ => HashMap::new(),
+    };
+    if kwargs.is_empty() && args.len() == 1 {
+        if let Some(value) = args.get("0") {
+            return value.clone();
+        }
+    }
+    let mut record = args;
+    record.extend(kwargs);
+    RKV::Object(record)
+}
+
+/// Remove the `top_k` keyword argument from a search payload so that it is not passed to the embedding function.
+fn take_top_k(payload: RKV) -> anyhow::Result<(RKV, usize)> {
+    let RKV::Object(mut payload) = payload else { return Ok((payload, DEFAULT_SEARCH_TOP_K)) };
+    let mut top_k = DEFAULT_SEARCH_TOP_K;
+    if let Some(RKV::Object(kwargs)) = payload.get_mut("kwargs") {
+        match kwargs.remove(SEARCH_TOP_K_KWARG) {
+            Some(RKV::Number(n)) if n > 0 => top_k = n as usize,
+            Some(other) => return Err(anyhow::anyhow!("{} must be a positive integer, received {:?}", SEARCH_TOP_K_KWARG, other)),
+            None => {}
+        }
+    }
+    Ok((RKV::Object(payload), top_k))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use uuid::Uuid;
+    use crate::cells::{python_cell, CellTypes, MemoryCell, SupportedMemoryProviders, TextRange};
+    use crate::execution::execution::ExecutionState;
+    use crate::execution::primitives::operation::OutputItemConfiguration;
+    use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue as RKV};
+
+    fn payload(args: Vec<RKV>, kwargs: Vec<(&str, RKV)>) -> RKV {
+        RkyvObjectBuilder::new()
+            .insert_value("args", RKV::Object(args.into_iter().enumerate().map(|(i, v)| (i.to_string(), v)).collect()))
+            .insert_value("kwargs", RKV::Object(kwargs.into_iter().map(|(k, v)| (k.to_string(), v)).collect()))
+            .build()
+    }
+
+    #[test]
+    fn test_memory_cell_signature() -> anyhow::Result<()> {
+        let cell = MemoryCell {
+            backing_file_reference: None,
+            name: Some("stateful_memory".to_string()),
+            provider: SupportedMemoryProviders::InMemory,
+            embedding_function: "embed".to_string(),
+            function_invocation: None,
+        };
+        let op = super::memory_cell(Uuid::nil(), &cell, &TextRange::default())?;
+        assert!(op.signature.input_signature.globals.contains_key("embed"));
+        let functions = &op.signature.output_signature.functions;
+        assert!(matches!(functions.get("insert"), Some(OutputItemConfiguration::Function { .. })));
+        assert!(matches!(functions.get("search"), Some(OutputItemConfiguration::Function { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_record_from_payload() {
+        let single = payload(vec![RKV::String("hello".to_string())], vec![]);
+        assert_eq!(super::memory_record_from_payload(&single), RKV::String("hello".to_string()));
+
+        let keyword = payload(vec![], vec![("message", RKV::String("hello".to_string()))]);
+        let mut expected = HashMap::new();
+        expected.insert("message".to_string(), RKV::String("hello".to_string()));
+        assert_eq!(super::memory_record_from_payload(&keyword), RKV::Object(expected));
+    }
+
+    #[test]
+    fn test_take_top_k() -> anyhow::Result<()> {
+        let search = payload(vec![RKV::String("query".to_string())], vec![("top_k", RKV::Number(7))]);
+        let (remaining, top_k) = super::take_top_k(search)?;
+        assert_eq!(top_k, 7);
+        assert_eq!(remaining, payload(vec![RKV::String("query".to_string())], vec![]));
+
+        let (_, top_k) = super::take_top_k(payload(vec![], vec![]))?;
+        assert_eq!(top_k, super::DEFAULT_SEARCH_TOP_K);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_memories_are_kept_by_the_state_they_were_inserted_in() -> anyhow::Result<()> {
+        let (state, _) = ExecutionState::new_with_random_id()
+            .update_operation(python_cell("embedding", "def embed(text):\n    return [1.0, 0.0]", Default::default()), Uuid::now_v7()).await?;
+        let (state, _) = state.update_operation(CellTypes::Memory(MemoryCell {
+            backing_file_reference: None,
+            name: None,
+            provider: SupportedMemoryProviders::InMemory,
+            embedding_function: "embed".to_string(),
+            function_invocation: None,
+        }, TextRange::default()), Uuid::now_v7()).await?;
+
+        let hello = || payload(vec![RKV::String("hello".to_string())], vec![]);
+        let (inserted, after_insert) = state.dispatch("insert", hello(), None).await?;
+        inserted?;
+        let (found, _) = after_insert.dispatch("search", hello(), None).await?;
+        assert_eq!(found?, RKV::Array(vec![RKV::String("hello".to_string())]));
+
+        // States from before the insert do not remember it
+        let (found, _) = state.dispatch("search", hello(), None).await?;
+        assert_eq!(found?, RKV::Array(vec![]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_embedding_from_value() {
+        let value = RKV::Array(vec![RKV::Float(0.5), RKV::Number(1)]);
+        assert_eq!(super::embedding_from_value(&value).unwrap(), vec![0.5, 1.0]);
+        assert_eq!(super::embedding_from_value(&RKV::Embedding(vec![0.25, 2.0])).unwrap(), vec![0.25, 2.0]);
+        assert!(super::embedding_from_value(&RKV::String("nope".to_string())).is_err());
+    }
+}
@@ -2,11 +2,16 @@ pub mod template_cell;
 pub mod code_cell;
 pub mod llm_prompt_cell;
 pub mod code_gen_cell;
+pub mod memory_cell;
+pub mod webservice_cell;
+pub mod schedule_cell;
 
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
 use rkyv::{Archive, Deserialize, Serialize};
 use serde_json::Value;
+use crate::execution::primitives::operation::{Mutability, Purity};
 use crate::library::std::ai::llm::ChatModelBatch;
 
 #[derive(
@@ -74,6 +79,250 @@ pub struct CodeCell {
     pub language: SupportedLanguage,
     pub source_code: String,
     pub function_invocation: Option<String>,
+    #[serde(default, skip_serializing_if = "ExecutionPolicy::is_default")]
+    pub policy: ExecutionPolicy,
+}
+
+impl CodeCell {
+    /// Range in the file the cell was loaded from of a range in the cell's source code, such as the range
+    /// of a value in the cell's static analysis report. None if the cell was not loaded from a file, or if
+    /// the given contents of the file no longer hold the cell's source.
+    pub fn range_in_backing_file(&self, file_contents: &str, range: &TextRange) -> Option<TextRange> {
+        let block_range = self.backing_file_reference.as_ref()?.text_range.as_ref()?;
+        let block = file_contents.get(block_range.start..block_range.end)?;
+        // The block opens with its language tag, the source ends the block after any frontmatter
+        let leading_whitespace = block.len() - block.trim_start().len();
+        let body_start = leading_whitespace + block[leading_whitespace..].find('\n')? + 1;
+        let source_start = block_range.start + body_start + block[body_start..].rfind(self.source_code.as_str())?;
+        Some(TextRange {
+            start: source_start + range.start,
+            end: source_start + range.end,
+        })
+    }
+}
+
+/// What to do with a cell once it has failed on every attempt.
+#[derive(
+    Archive,
+    serde::Serialize,
+    serde::Deserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    PartialEq,
+    Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+    bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+#[serde(rename_all = "snake_case")]
+pub enum OnError {
+    /// Record the failure and pause playback, the default
+    Pause,
+    /// Record the failure and continue executing the rest of the graph
+    Skip,
+    /// Record the failure and evaluate the named cell in its place
+    Fallback(String),
+    /// Record the failure and pass it to the cells depending on this one as an error value
+    Propagate,
+}
+
+/// Limits on how a cell is evaluated, set in the frontmatter of its block:
+///
+/// ```yaml
+/// timeout: 30
+/// retries: 2
+/// backoff: 1.5
+/// on_error:
+///   fallback: cached_answer
+/// purity: pure
+/// handle_errors: true
+/// permissions:
+///   net: [api.github.com]
+/// requirements: [requests<3]
+/// ```
+#[derive(
+    Default,
+    Archive,
+    serde::Serialize,
+    serde::Deserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    PartialEq,
+    Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+    bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+pub struct ExecutionPolicy {
+    /// Seconds an attempt may run before it is abandoned as failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<f64>,
+    /// Attempts made after the first one fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Seconds to wait before the first retry, doubling before every retry after it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoff: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_error: Option<OnError>,
+    /// Marks the cell as pure so that its outputs are memoized, overriding the purity inferred for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purity: Option<Purity>,
+    /// Evaluate the cell when it receives an error from a cell it depends on, the error is given to it as an
+    /// exception. Otherwise the cell is not evaluated and the error is passed on to the cells depending on it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handle_errors: Option<bool>,
+    /// Resources a JavaScript cell may access, overriding the permissions set for its notebook
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<CellPermissions>,
+    /// Packages a Python cell needs installed, in addition to those required by its notebook
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requirements: Option<Vec<String>>,
+}
+
+impl ExecutionPolicy {
+    pub fn is_default(&self) -> bool {
+        self == &ExecutionPolicy::default()
+    }
+
+    pub fn timeout(&self) -> Option<std::time::Duration> {
+        self.timeout.map(std::time::Duration::from_secs_f64)
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        1 + self.retries.unwrap_or(0)
+    }
+
+    /// Delay before the given retry, the first retry being 1.
+    pub fn backoff_before_retry(&self, retry: u32) -> std::time::Duration {
+        match self.backoff {
+            Some(backoff) if retry > 0 => std::time::Duration::from_secs_f64(backoff * 2f64.powi(retry as i32 - 1)),
+            _ => std::time::Duration::ZERO,
+        }
+    }
+
+    pub fn on_error(&self) -> OnError {
+        self.on_error.clone().unwrap_or(OnError::Pause)
+    }
+
+    pub fn handles_errors(&self) -> bool {
+        self.handle_errors.unwrap_or(false)
+    }
+
+    pub fn permissions(&self) -> CellPermissions {
+        self.permissions.clone().unwrap_or_default()
+    }
+}
+
+/// Access to a kind of resource, either `true` for any of them or a list of the ones that may be accessed.
+#[derive(
+    Archive,
+    serde::Serialize,
+    serde::Deserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    PartialEq,
+    Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+    bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+#[serde(untagged)]
+pub enum PermissionGrant {
+    All(bool),
+    Only(Vec<String>),
+}
+
+/// Resources a JavaScript cell may access, everything not granted is denied. Set for every cell of a notebook
+/// in the frontmatter of its markdown file, or for a single cell in its own frontmatter:
+///
+/// ```yaml
+/// permissions:
+///   net: [api.github.com, "localhost:8080"]
+///   read: [./data]
+///   write: [./out]
+///   env: [OPENAI_API_KEY]
+///   run: [git]
+/// ```
+#[derive(
+    Default,
+    Archive,
+    serde::Serialize,
+    serde::Deserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    PartialEq,
+    Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+    bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+pub struct CellPermissions {
+    /// Hosts that may be reached, optionally with a port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub net: Option<PermissionGrant>,
+    /// Paths that may be read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read: Option<PermissionGrant>,
+    /// Paths that may be written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write: Option<PermissionGrant>,
+    /// Environment variables that may be read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<PermissionGrant>,
+    /// Programs that may be run as subprocesses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<PermissionGrant>,
+}
+
+impl CellPermissions {
+    /// These permissions, with the ones they do not set taken from the given permissions.
+    pub fn or(self, fallback: &CellPermissions) -> CellPermissions {
+        CellPermissions {
+            net: self.net.or_else(|| fallback.net.clone()),
+            read: self.read.or_else(|| fallback.read.clone()),
+            write: self.write.or_else(|| fallback.write.clone()),
+            env: self.env.or_else(|| fallback.env.clone()),
+            run: self.run.or_else(|| fallback.run.clone()),
+        }
+    }
+
+    /// These permissions, with the relative paths they grant reading or writing resolved against the given
+    /// directory rather than wherever the runtime happens to be started from.
+    pub fn resolve_paths(self, directory: &Path) -> CellPermissions {
+        let resolve = |grant: Option<PermissionGrant>| match grant {
+            Some(PermissionGrant::Only(paths)) => Some(PermissionGrant::Only(paths.into_iter().map(|path| {
+                if Path::new(&path).is_relative() {
+                    let resolved: PathBuf = directory.join(&path).components().filter(|c| !matches!(c, Component::CurDir)).collect();
+                    resolved.to_string_lossy().to_string()
+                } else {
+                    path
+                }
+            }).collect())),
+            grant => grant,
+        };
+        CellPermissions {
+            read: resolve(self.read),
+            write: resolve(self.write),
+            ..self
+        }
+    }
 }
 
 
@@ -115,9 +364,11 @@ bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error:
 ))]
 #[archive_attr(derive(Debug))]
 pub struct MemoryCell {
+    pub backing_file_reference: Option<BackingFileReference>,
     pub name: Option<String>,
     pub provider: SupportedMemoryProviders,
     pub embedding_function: String,
+    pub function_invocation: Option<String>,
 }
 
 
@@ -183,8 +434,11 @@ bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error:
 ))]
 #[archive_attr(derive(Debug))]
 pub struct WebserviceCell {
+    pub backing_file_reference: Option<BackingFileReference>,
     pub name: Option<String>,
     pub configuration: String,
+    /// Address the routes are served on, `DEFAULT_WEBSERVICE_HOST` unless given
+    pub host: Option<String>,
     pub port: u16,
 }
 
@@ -206,6 +460,8 @@ bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error:
 ))]
 #[archive_attr(derive(Debug))]
 pub struct ScheduleCell {
+    pub backing_file_reference: Option<BackingFileReference>,
+    pub name: Option<String>,
     pub configuration: String,
 }
 
@@ -226,8 +482,58 @@ pub struct ScheduleCell {
     bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
 ))]
 #[archive_attr(derive(Debug))]
+#[serde(from = "String", into = "String")]
 pub enum SupportedModelProviders {
     OpenAI,
+    Anthropic,
+    Ollama,
+    /// Echoes the prompt back, for running notebooks without a model
+    Mock,
+    /// A provider registered by name with `register_chat_model_provider`
+    Registered(String),
+}
+
+impl Default for SupportedModelProviders {
+    fn default() -> Self {
+        SupportedModelProviders::OpenAI
+    }
+}
+
+impl SupportedModelProviders {
+    /// Name the provider is given by in frontmatter and registered under.
+    pub fn name(&self) -> &str {
+        match self {
+            SupportedModelProviders::OpenAI => "openai",
+            SupportedModelProviders::Anthropic => "anthropic",
+            SupportedModelProviders::Ollama => "ollama",
+            SupportedModelProviders::Mock => "mock",
+            SupportedModelProviders::Registered(name) => name,
+        }
+    }
+}
+
+impl From<&str> for SupportedModelProviders {
+    fn from(name: &str) -> Self {
+        match name {
+            "openai" => SupportedModelProviders::OpenAI,
+            "anthropic" => SupportedModelProviders::Anthropic,
+            "ollama" => SupportedModelProviders::Ollama,
+            "mock" => SupportedModelProviders::Mock,
+            name => SupportedModelProviders::Registered(name.to_string()),
+        }
+    }
+}
+
+impl From<String> for SupportedModelProviders {
+    fn from(name: String) -> Self {
+        SupportedModelProviders::from(name.as_str())
+    }
+}
+
+impl From<SupportedModelProviders> for String {
+    fn from(provider: SupportedModelProviders) -> Self {
+        provider.name().to_string()
+    }
 }
 
 
@@ -281,6 +587,10 @@ pub struct LLMPromptCellChatConfiguration {
     #[serde(rename = "fn")]
     pub(crate) function_name: Option<String>,
 
+    /// Chat model provider, defaults to openai
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<SupportedModelProviders>,
+
     pub model: Option<String>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -303,6 +613,9 @@ pub struct LLMPromptCellChatConfiguration {
     pub seed: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f64>,
+
+    #[serde(flatten)]
+    pub policy: ExecutionPolicy,
 }
 
 #[derive(
@@ -358,6 +671,8 @@ pub struct LLMCodeGenCellChatConfiguration {
     #[serde(rename = "fn")]
     pub function_name: Option<String>,
 
+    /// Chat model provider, defaults to openai
+    pub provider: Option<SupportedModelProviders>,
     pub api_url: Option<String>,
     pub model: Option<String>,
     pub frequency_penalty: Option<f64>,
@@ -371,6 +686,9 @@ pub struct LLMCodeGenCellChatConfiguration {
     pub top_p: Option<f64>,
 
     pub language: Option<String>,
+
+    #[serde(flatten)]
+    pub policy: ExecutionPolicy,
 }
 
 #[derive(
@@ -467,6 +785,25 @@ impl PartialOrd for TextRange {
     }
 }
 
+impl TextRange {
+    /// Zero based line and column of the start of the range in the given text, columns count bytes.
+    pub fn start_position(&self, text: &str) -> (usize, usize) {
+        let before = &text.as_bytes()[..self.start.min(text.len())];
+        let line = before.iter().filter(|b| **b == b'\n').count();
+        let line_start = before.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+        (line, before.len() - line_start)
+    }
+}
+
+impl From<&chidori_static_analysis::language::TextRange> for TextRange {
+    fn from(range: &chidori_static_analysis::language::TextRange) -> Self {
+        TextRange {
+            start: range.start,
+            end: range.end,
+        }
+    }
+}
+
 #[derive(
     Archive,
     serde::Serialize,
@@ -488,6 +825,9 @@ pub enum CellTypes {
     CodeGen(LLMCodeGenCell, TextRange),
     Prompt(LLMPromptCell, TextRange),
     Template(TemplateCell, TextRange),
+    Memory(MemoryCell, TextRange),
+    Webservice(WebserviceCell, TextRange),
+    Schedule(ScheduleCell, TextRange),
 }
 
 impl Eq for CellTypes {
@@ -515,8 +855,73 @@ impl CellTypes {
                 LLMPromptCell::Completion { .. } => &None,
             },
             CellTypes::Template(c, _) => &c.name,
-            CellTypes::CodeGen(c, _) => &c.name
+            CellTypes::CodeGen(c, _) => &c.name,
+            CellTypes::Memory(c, _) => &c.name,
+            CellTypes::Webservice(c, _) => &c.name,
+            CellTypes::Schedule(c, _) => &c.name,
+        }
+    }
+
+    /// Range of the cell's source in the file it was loaded from.
+    pub fn text_range(&self) -> &TextRange {
+        match &self {
+            CellTypes::Code(_, range)
+            | CellTypes::CodeGen(_, range)
+            | CellTypes::Prompt(_, range)
+            | CellTypes::Template(_, range)
+            | CellTypes::Memory(_, range)
+            | CellTypes::Webservice(_, range)
+            | CellTypes::Schedule(_, range) => range,
+        }
+    }
+
+    /// Timeout, retry and failure handling configured for the cell. Only code, prompt and codegen
+    /// cells can be configured, every other cell runs once without a timeout.
+    pub fn policy(&self) -> ExecutionPolicy {
+        match &self {
+            CellTypes::Code(c, _) => c.policy.clone(),
+            CellTypes::Prompt(LLMPromptCell::Chat { configuration, .. }, _) => configuration.policy.clone(),
+            CellTypes::CodeGen(c, _) => c.configuration.policy.clone(),
+            _ => ExecutionPolicy::default(),
         }
     }
+
+    /// Purity set in the cell's policy, otherwise inferred from its type. Templates render the same
+    /// output for the same inputs, every other cell is assumed to be impure unless it is marked pure.
+    pub fn purity(&self) -> Purity {
+        if let Some(purity) = self.policy().purity {
+            return purity;
+        }
+        match &self {
+            CellTypes::Template(..) => Purity::Pure,
+            _ => Purity::Impure,
+        }
+    }
+
+    /// Memory, webservice and schedule cells hold state that outlives a single evaluation.
+    pub fn mutability(&self) -> Mutability {
+        match &self {
+            CellTypes::Memory(..) | CellTypes::Webservice(..) | CellTypes::Schedule(..) => Mutability::Mutable,
+            _ => Mutability::Immutable,
+        }
+    }
+
+    /// Whether outputs of the cell can be read from the memoization cache instead of evaluating it.
+    pub fn is_memoizable(&self) -> bool {
+        self.purity() == Purity::Pure && self.mutability() == Mutability::Immutable
+    }
 }
 
+
+/// Python code cell with the given policy, not backed by a file.
+#[cfg(test)]
+pub(crate) fn python_cell(name: &str, source_code: &str, policy: ExecutionPolicy) -> CellTypes {
+    CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: Some(name.to_string()),
+        language: SupportedLanguage::PyO3,
+        source_code: source_code.to_string(),
+        function_invocation: None,
+        policy,
+    }, TextRange::default())
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use dashmap::DashMap;
+use futures_util::FutureExt;
+use once_cell::sync::Lazy;
+use tokio::task::JoinHandle;
+use tracing::warn;
+use crate::cells::{CellTypes, ScheduleCell, TextRange};
+use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::execution::ExecutionState;
+use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::operation::{InputItemConfiguration, InputSignature, OperationFn, OperationFnOutput, OperationNode, OutputSignature};
+use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
+use crate::library::std::scheduling::local::{parse_configuration_string, run_schedule, SchedulerClock, SystemClock};
+
+// Jobs that are currently scheduled, keyed by the schedule cell that started them. Re-running a
+// schedule cell replaces the jobs it previously started.
+static RUNNING_SCHEDULES: Lazy<DashMap<OperationId, Vec<JoinHandle<()>>>> = Lazy::new(|| DashMap::new());
+
+/// Schedule cells invoke functions declared elsewhere in the notebook on cron schedules.
+#[tracing::instrument]
+pub fn schedule_cell(execution_state_id: ExecutionNodeId, cell: &ScheduleCell, range: &TextRange) -> anyhow::Result<OperationNode> {
+    let jobs = parse_configuration_string(&cell.configuration)?;
+
+    // Depending on the scheduled functions orders this cell after the cells that declare them
+    let mut input_signature = InputSignature::new();
+    for job in &jobs {
+        input_signature.globals.insert(
+            job.function_identity.clone(),
+            InputItemConfiguration {
+                ty: None,
+                default: None,
+                ..Default::default()
+            },
+        );
+    }
+
+    Ok(OperationNode::new(
+        cell.name.clone(),
+        execution_state_id,
+        input_signature,
+        OutputSignature::new(),
+        CellTypes::Schedule(cell.clone(), Default::default()),
+    ))
+}
+
+/// Start the jobs of the schedule cell evaluated by the provided execution state. Every firing dispatches
+/// from the execution head the state follows, or from the state itself when it follows none.
+pub fn start_schedule(execution_state: ExecutionState, cell: &ScheduleCell, clock: Arc<dyn SchedulerClock>) -> anyhow::Result<usize> {
+    let jobs = parse_configuration_string(&cell.configuration)?;
+    let job_count = jobs.len();
+    let operation_id = execution_state.evaluating_operation_id;
+    let execution_state = Arc::new(execution_state);
+    let handles = run_schedule(jobs, clock, move |job, instant| {
+        let execution_state = execution_state.clone();
+        async move {
+            let payload = RkyvObjectBuilder::new()
+                .insert_value("args", RkyvSerializedValue::Object(HashMap::new()))
+                .insert_value("kwargs", RkyvSerializedValue::Object(HashMap::new()))
+                .build();
+            // There is no caller to return failures to, each one is logged instead of skipped silently
+            match execution_state.dispatch_from_execution_head(&job.function_identity, payload).await {
+                Ok((Ok(_), _)) => {}
+                Ok((Err(e), _)) => warn!("Scheduled invocation of {} at {:?} raised: {}", job.function_identity, instant, e),
+                Err(e) => warn!("Scheduled invocation of {} at {:?} failed: {}", job.function_identity, instant, e),
+            }
+        }
+    });
+
+    if let Some(previous) = RUNNING_SCHEDULES.insert(operation_id, handles) {
+        for handle in previous {
+            handle.abort();
+        }
+    }
+    Ok(job_count)
+}
+
+pub fn schedule_cell_exec(cell: ScheduleCell) -> Box<OperationFn> {
+    Box::new(move |s, _, _, _| {
+        let cell = cell.clone();
+        let s = s.clone();
+        async move {
+            let job_count = start_schedule(s, &cell, Arc::new(SystemClock))?;
+            Ok(OperationFnOutput::with_value(RkyvObjectBuilder::new()
+                .insert_number("jobs", job_count as i64)
+                .build()))
+        }.boxed()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+    use crate::cells::{CellTypes, CodeCell, ScheduleCell, SupportedLanguage, TextRange};
+    use crate::execution::execution::execution_graph::ExecutionGraphSendPayload;
+    use crate::execution::execution::ExecutionState;
+    use crate::library::std::scheduling::local::VirtualClock;
+    use super::{start_schedule, RUNNING_SCHEDULES};
+
+    #[test]
+    fn test_schedule_cell_depends_on_scheduled_functions() -> anyhow::Result<()> {
+        let cell = ScheduleCell {
+            backing_file_reference: None,
+            name: Some("jobs".to_string()),
+            configuration: "0 */5 * * * * refresh_feed".to_string(),
+        };
+        let op = super::schedule_cell(Uuid::nil(), &cell, &TextRange::default())?;
+        assert!(op.signature.input_signature.globals.contains_key("refresh_feed"));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_each_firing_dispatches_from_the_schedule_cell_state() -> anyhow::Result<()> {
+        let (mut state, _) = ExecutionState::new_with_random_id().update_operation(CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: Some("feed".to_string()),
+            language: SupportedLanguage::PyO3,
+            source_code: "def refresh_feed():\n    return 1".to_string(),
+            function_invocation: None,
+            policy: Default::default(),
+        }, TextRange::default()), Uuid::now_v7()).await?;
+        let (sender, mut receiver) = tokio::sync::mpsc::channel::<ExecutionGraphSendPayload>(64);
+        state.graph_sender = Some(Arc::new(sender));
+        state.evaluating_operation_id = Uuid::now_v7();
+        let schedule_operation_id = state.evaluating_operation_id;
+        let schedule_state_id = state.chronology_id;
+
+        let cell = ScheduleCell {
+            backing_file_reference: None,
+            name: Some("test_each_firing_dispatches".to_string()),
+            configuration: "0 * * * * * refresh_feed".to_string(),
+        };
+        let clock = Arc::new(VirtualClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 30).unwrap()));
+        start_schedule(state, &cell, clock.clone())?;
+
+        let mut dispatches = vec![];
+        for firing in 1..=2 {
+            clock.advance(chrono::Duration::seconds(60));
+            while dispatches.len() < firing {
+                let (received, resume) = tokio::time::timeout(Duration::from_secs(10), receiver.recv()).await?.unwrap();
+                if received.parent_state_chronology_id == schedule_state_id {
+                    dispatches.push(received.evaluating_fn.clone());
+                }
+                if let Some(resume) = resume {
+                    let _ = resume.send(());
+                }
+            }
+        }
+        if let Some((_, handles)) = RUNNING_SCHEDULES.remove(&schedule_operation_id) {
+            handles.iter().for_each(|handle| handle.abort());
+        }
+        assert_eq!(dispatches, vec![Some("refresh_feed".to_string()); 2]);
+        Ok(())
+    }
+}
@@ -18,12 +18,13 @@ pub fn template_cell(execution_state_id: ExecutionNodeId, cell: &TemplateCell, r
         chidori_prompt_format::templating::templates::analyze_referenced_partials(&cell.body);
 
     let mut input_signature = InputSignature::new();
-    for (key, value) in &schema.unwrap().items {
+    for (key, value) in &schema?.items {
         input_signature.globals.insert(
             key.clone(),
             InputItemConfiguration {
                 ty: Some(InputType::String),
                 default: None,
+                ..Default::default()
             },
         );
     }
@@ -35,6 +36,7 @@ pub fn template_cell(execution_state_id: ExecutionNodeId, cell: &TemplateCell, r
             name.clone(),
             OutputItemConfiguration::Function {
                 input_signature: InputSignature::new(),
+                description: None,
                 emit_event: vec![],
                 trigger_on: vec![],
             },
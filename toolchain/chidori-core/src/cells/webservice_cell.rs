@@ -0,0 +1,70 @@
+use futures_util::FutureExt;
+use crate::cells::{CellTypes, TextRange, WebserviceCell};
+use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::primitives::operation::{InputItemConfiguration, InputSignature, OperationFn, OperationFnOutput, OperationNode, OutputSignature};
+use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+use crate::library::std::webservice::{parse_configuration_string, run_webservice, DEFAULT_WEBSERVICE_HOST};
+
+/// Webservice cells serve HTTP routes on a port of this machine, each route dispatching to a function declared
+/// elsewhere in the notebook and responding with its result as JSON.
+#[tracing::instrument]
+pub fn webservice_cell(execution_state_id: ExecutionNodeId, cell: &WebserviceCell, range: &TextRange) -> anyhow::Result<OperationNode> {
+    let endpoints = parse_configuration_string(&cell.configuration)?;
+
+    // Depending on the routed functions orders this cell after the cells that declare them
+    let mut input_signature = InputSignature::new();
+    for endpoint in &endpoints {
+        input_signature.globals.insert(
+            endpoint.depended_function_identity.clone(),
+            InputItemConfiguration {
+                ty: None,
+                default: None,
+                ..Default::default()
+            },
+        );
+    }
+
+    Ok(OperationNode::new(
+        cell.name.clone(),
+        execution_state_id,
+        input_signature,
+        OutputSignature::new(),
+        CellTypes::Webservice(cell.clone(), Default::default()),
+    ))
+}
+
+pub fn webservice_cell_exec(cell: WebserviceCell) -> Box<OperationFn> {
+    Box::new(move |s, _, _, _| {
+        let cell = cell.clone();
+        let s = s.clone();
+        async move {
+            let endpoints = parse_configuration_string(&cell.configuration)?;
+            let host = cell.host.as_deref().unwrap_or(DEFAULT_WEBSERVICE_HOST);
+            let addr = run_webservice(s, host, cell.port, endpoints).await?;
+            Ok(OperationFnOutput::with_value(RkyvObjectBuilder::new()
+                .insert_number("port", addr.port() as i64)
+                .build()))
+        }.boxed()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+    use crate::cells::{TextRange, WebserviceCell};
+
+    #[test]
+    fn test_webservice_cell_depends_on_routed_functions() -> anyhow::Result<()> {
+        let cell = WebserviceCell {
+            backing_file_reference: None,
+            name: Some("api".to_string()),
+            configuration: "POST /add add a b\nGET /status status".to_string(),
+            host: None,
+            port: 0,
+        };
+        let op = super::webservice_cell(Uuid::nil(), &cell, &TextRange::default())?;
+        assert!(op.signature.input_signature.globals.contains_key("add"));
+        assert!(op.signature.input_signature.globals.contains_key("status"));
+        Ok(())
+    }
+}
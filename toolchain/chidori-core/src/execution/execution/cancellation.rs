@@ -0,0 +1,144 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use tokio::sync::Notify;
+use tracing::debug;
+use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::serialized_value::ErrorValue;
+
+/// Type of the error a cancelled operation fails with
+pub const CANCELLED_ERROR_TYPE: &str = "CancelledError";
+
+type Interrupt = Box<dyn Fn() + Send + Sync>;
+
+struct InFlightOperation {
+    operation_id: OperationId,
+    cancelled: AtomicBool,
+    notify: Notify,
+    /// Registered by the runtime evaluating the operation, stopping the code it is running
+    interrupts: Mutex<Vec<Interrupt>>,
+}
+
+/// Operations currently being evaluated, keyed by the chronology id of the state evaluating them
+static IN_FLIGHT: Lazy<DashMap<ExecutionNodeId, Arc<InFlightOperation>>> = Lazy::new(|| DashMap::new());
+
+/// The error recorded as the output of a cancelled operation.
+pub fn cancelled_error() -> ErrorValue {
+    ErrorValue::new(CANCELLED_ERROR_TYPE, "Cell execution was cancelled")
+}
+
+pub fn is_cancelled_error(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ErrorValue>().map_or(false, |error| error.ty == CANCELLED_ERROR_TYPE)
+}
+
+/// Mark an operation as in flight until the returned guard is dropped, making it cancellable.
+pub(crate) fn begin_operation(execution_node_id: ExecutionNodeId, operation_id: OperationId) -> InFlightGuard {
+    let operation = Arc::new(InFlightOperation {
+        operation_id,
+        cancelled: AtomicBool::new(false),
+        notify: Notify::new(),
+        interrupts: Mutex::new(vec![]),
+    });
+    IN_FLIGHT.insert(execution_node_id, operation.clone());
+    InFlightGuard { execution_node_id, operation }
+}
+
+/// Register how to stop the code evaluating an operation. Runtimes call this once they are running the
+/// operation's code, an operation that was cancelled before then is interrupted immediately. Nothing is
+/// registered for operations that are not in flight, such as code evaluated outside of a graph.
+pub fn on_cancel(execution_node_id: ExecutionNodeId, interrupt: impl Fn() + Send + Sync + 'static) {
+    let Some(operation) = IN_FLIGHT.get(&execution_node_id).map(|operation| operation.value().clone()) else {
+        return;
+    };
+    if operation.cancelled.load(Ordering::SeqCst) {
+        interrupt();
+        return;
+    }
+    operation.interrupts.lock().unwrap().push(Box::new(interrupt));
+}
+
+/// Cancel the operation evaluated by the given state. Returns false if no such operation is in flight.
+pub fn cancel_operation(execution_node_id: ExecutionNodeId) -> bool {
+    let Some(operation) = IN_FLIGHT.get(&execution_node_id).map(|operation| operation.value().clone()) else {
+        return false;
+    };
+    if operation.cancelled.swap(true, Ordering::SeqCst) {
+        return true;
+    }
+    debug!("Cancelling the operation evaluated by state {:?}", execution_node_id);
+    for interrupt in operation.interrupts.lock().unwrap().iter() {
+        interrupt();
+    }
+    operation.notify.notify_waiters();
+    true
+}
+
+/// Cancel every operation in flight, returning how many were cancelled.
+pub fn cancel_all_operations() -> usize {
+    let in_flight: Vec<ExecutionNodeId> = IN_FLIGHT.iter().map(|entry| *entry.key()).collect();
+    in_flight.into_iter().filter(|id| cancel_operation(*id)).count()
+}
+
+/// Operations in flight, with the chronology id of the state evaluating each of them, oldest first.
+pub fn in_flight_operations() -> Vec<(ExecutionNodeId, OperationId)> {
+    let mut in_flight: Vec<(ExecutionNodeId, OperationId)> = IN_FLIGHT.iter()
+        .map(|entry| (*entry.key(), entry.value().operation_id))
+        .collect();
+    in_flight.sort();
+    in_flight
+}
+
+pub(crate) struct InFlightGuard {
+    execution_node_id: ExecutionNodeId,
+    operation: Arc<InFlightOperation>,
+}
+
+impl InFlightGuard {
+    /// Resolves once the operation has been cancelled.
+    pub(crate) async fn cancelled(&self) {
+        loop {
+            let notified = self.operation.notify.notified();
+            if self.operation.cancelled.load(Ordering::SeqCst) {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT.remove_if(&self.execution_node_id, |_, operation| Arc::ptr_eq(operation, &self.operation));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_cancelling_interrupts_registered_runtimes() {
+        let id = Uuid::now_v7();
+        let guard = begin_operation(id, Uuid::now_v7());
+        let interrupted = Arc::new(AtomicUsize::new(0));
+        let counter = interrupted.clone();
+        on_cancel(id, move || { counter.fetch_add(1, Ordering::SeqCst); });
+
+        assert!(cancel_operation(id));
+        guard.cancelled().await;
+        assert_eq!(interrupted.load(Ordering::SeqCst), 1);
+
+        // Runtimes starting after the cancellation are interrupted as they register
+        let counter = interrupted.clone();
+        on_cancel(id, move || { counter.fetch_add(1, Ordering::SeqCst); });
+        assert_eq!(interrupted.load(Ordering::SeqCst), 2);
+
+        drop(guard);
+        assert!(!cancel_operation(id));
+        assert!(in_flight_operations().iter().all(|(in_flight, _)| *in_flight != id));
+    }
+}
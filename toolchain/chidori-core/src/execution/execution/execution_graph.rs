@@ -4,6 +4,7 @@ use std::fmt;
 use std::fmt::Debug;
 use std::future::Future;
 use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::{Arc, mpsc};
 use std::task::{Context, Poll};
@@ -33,8 +34,11 @@ use tokio::sync::oneshot::Receiver;
 use uuid::Uuid;
 use crate::cells::CellTypes;
 use crate::execution::primitives::operation::OperationFnOutput;
+use crate::execution::primitives::usage::UsageTotals;
+use crate::execution::execution::execution_graph_store::ExecutionGraphStore;
+use crate::execution::execution::human_input;
 use tokio::sync::mpsc::{Sender, channel};
-use tracing::debug;
+use tracing::{debug, error, warn};
 // TODO: update all of these identifies to include a "space" they're within
 
 type EdgeIdentity = (OperationId, OperationId, DependencyReference);
@@ -78,6 +82,12 @@ pub struct ExecutionGraph {
 
     pub(crate) execution_node_id_to_state: Arc<DashMap<ExecutionNodeId, ExecutionState>>,
 
+    /// Optional on-disk store that every state received by the graph is written to
+    store: Option<Arc<Mutex<ExecutionGraphStore>>>,
+
+    /// Failures to write states to the store since they were last taken, a session missing states cannot be resumed
+    persistence_errors: Arc<Mutex<Vec<String>>>,
+
     pub execution_depth_orchestration_handle: tokio::task::JoinHandle<()>,
     pub execution_depth_orchestration_initialized_notify: Arc<Notify>,
     pub cancellation_notify: Arc<Notify>,
@@ -96,11 +106,34 @@ impl std::fmt::Debug for ExecutionGraph {
 }
 
 
+/// Sum the usage evaluated by each state of a run. Each evaluation is only recorded by the state that
+/// produced it, so totals are not double counted when branches share history.
+pub fn run_usage(evaluated_usage: impl Iterator<Item = UsageTotals>) -> UsageTotals {
+    let mut totals = UsageTotals::default();
+    for usage in evaluated_usage {
+        totals.merge(&usage);
+    }
+    totals
+}
+
 impl ExecutionGraph {
     #[tracing::instrument]
     pub fn new() -> Self {
+        Self::new_with_store(None).expect("Failed to initialize in-memory execution graph")
+    }
+
+    /// Open an execution graph backed by a SQLite database at the given path. States that were
+    /// previously stored at that path are restored into the graph, and every new state is persisted.
+    #[tracing::instrument]
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        Self::new_with_store(Some(ExecutionGraphStore::open(path)?))
+    }
+
+    #[tracing::instrument]
+    pub fn new_with_store(store: Option<ExecutionGraphStore>) -> anyhow::Result<Self> {
         debug!("Initializing ExecutionGraph");
         let (sender_new_execution_states, mut receiver_new_execution_states) = tokio::sync::mpsc::channel::<ExecutionGraphSendPayload>(1028);
+        let sender_new_execution_states = Arc::new(sender_new_execution_states);
 
         let mut state_id_to_state = DashMap::new();
 
@@ -108,12 +141,30 @@ impl ExecutionGraph {
         let init_id = Uuid::nil();
         state_id_to_state.insert(init_id, ExecutionState::new_with_graph_sender(
             init_id,
-            Arc::new(sender_new_execution_states)
+            sender_new_execution_states.clone()
         ));
 
         // Graph of execution states
-        let mut execution_graph = Arc::new(Mutex::new(DiGraphMap::new()));
+        let mut execution_graph = DiGraphMap::new();
+
+        // Restore any states previously written to the store
+        let store = store.map(|store| Arc::new(Mutex::new(store)));
+        if let Some(store) = &store {
+            let restored_states = store.lock().unwrap().load_states(Some(sender_new_execution_states.clone()))?;
+            debug!("Restoring {} execution states from the store", restored_states.len());
+            for state in restored_states {
+                // Answers given in an earlier run of the session are reused when their evaluation is replayed
+                human_input::remember_answer_in_state(&state);
+                execution_graph.add_edge(state.parent_state_chronology_id, state.chronology_id, state.clone());
+                state_id_to_state.insert(state.chronology_id, state);
+            }
+        }
+
+        let mut execution_graph = Arc::new(Mutex::new(execution_graph));
         let execution_graph_clone = execution_graph.clone();
+        let store_clone = store.clone();
+        let persistence_errors = Arc::new(Mutex::new(vec![]));
+        let persistence_errors_clone = persistence_errors.clone();
 
         // Mapping of state_ids to state
         let mut state_id_to_state = Arc::new(state_id_to_state);
@@ -161,6 +212,15 @@ impl ExecutionGraph {
                             chronology_id,
                             resulting_execution_state.clone());
 
+                        // Persist the state before resuming so that it survives a restart
+                        if let Some(store) = &store_clone {
+                            if let Err(e) = store.lock().unwrap().insert_state(&resulting_execution_state) {
+                                let message = format!("Failed to persist execution state {:?}: {}", chronology_id, e);
+                                error!("{}", message);
+                                persistence_errors_clone.lock().unwrap().push(message);
+                            }
+                        }
+
                         // Resume execution
                         if let Some(oneshot) = oneshot {
                             oneshot.send(()).expect("Failed to send oneshot completion signal")
@@ -173,16 +233,64 @@ impl ExecutionGraph {
                 }
             }
         });
-        ExecutionGraph {
+        Ok(ExecutionGraph {
             cancellation_notify,
             execution_depth_orchestration_initialized_notify: initialization_notify,
             execution_depth_orchestration_handle: handle,
             execution_node_id_to_state: state_id_to_state,
             execution_graph,
+            store,
+            persistence_errors,
             chat_message_queue: vec![],
             execution_state_sender: execution_event_tx,
             execution_state_receiver: Some(execution_event_rx)
+        })
+    }
+
+    /// Path of the database backing this graph, if it is persisted.
+    pub fn store_path(&self) -> Option<PathBuf> {
+        self.store.as_ref().and_then(|store| store.lock().unwrap().path().map(|p| p.to_path_buf()))
+    }
+
+    /// Record the current execution head so that a reopened session resumes from it.
+    pub fn record_execution_head(&self, id: ExecutionNodeId) -> anyhow::Result<()> {
+        if let Some(store) = &self.store {
+            store.lock().unwrap().set_execution_head(id)?;
         }
+        Ok(())
+    }
+
+    /// Take the failures to persist states that occurred since this was last called.
+    pub fn take_persistence_errors(&self) -> Vec<String> {
+        std::mem::take(&mut *self.persistence_errors.lock().unwrap())
+    }
+
+    /// Resolve the execution head of a restored session. This is the head recorded in the store
+    /// when available, otherwise the most recent state that is valid as an execution head.
+    pub fn restored_execution_head(&self) -> ExecutionNodeId {
+        if let Some(store) = &self.store {
+            match store.lock().unwrap().get_execution_head() {
+                Ok(Some(id)) if self.execution_node_id_to_state.contains_key(&id) => return id,
+                Ok(_) => {}
+                Err(e) => warn!("Failed to read the recorded execution head, resuming from the latest state: {}", e),
+            }
+        }
+
+        // Chronology ids are v7 uuids, so the maximum id is the most recent state
+        self.execution_node_id_to_state
+            .iter()
+            .filter(|entry| entry.value().is_valid_execution_head())
+            .map(|entry| *entry.key())
+            .max()
+            .unwrap_or(Uuid::nil())
+    }
+
+    /// Remove all persisted states, used when resetting a session.
+    pub fn clear_store(&self) -> anyhow::Result<()> {
+        if let Some(store) = &self.store {
+            store.lock().unwrap().clear()?;
+        }
+        Ok(())
     }
 
     pub fn take_execution_event_receiver(&mut self) -> tokio::sync::mpsc::Receiver<ExecutionState> {
@@ -203,6 +311,11 @@ impl ExecutionGraph {
         self.execution_node_id_to_state.get(&id).map(|x| x.clone())
     }
 
+    /// Model usage of every evaluation recorded in the graph, across all branches.
+    pub fn run_usage(&self) -> UsageTotals {
+        run_usage(self.execution_node_id_to_state.iter().map(|entry| entry.value().evaluated_usage()))
+    }
+
     /// Performs a depth first traversal of the execution graph to resolve the combined
     /// state at a given node.
     // #[tracing::instrument]
@@ -336,6 +449,7 @@ mod tests {
             output: Ok(arg0),
             stdout: vec![],
             stderr: vec![],
+            usage: vec![],
         });
         state.state_insert(id_b, OperationFnOutput {
             has_error: false,
@@ -343,6 +457,7 @@ mod tests {
             output: Ok(arg1),
             stdout: vec![],
             stderr: vec![],
+            usage: vec![],
         });
         let (_, new_state, _) = ExecutionGraph::immutable_external_step_execution(state.clone()).await?;
         assert!(new_state.state_get_value(&id_c).is_some());
@@ -834,7 +949,7 @@ mod tests {
                     if let RSV::Object(args) = m.get("args").unwrap() {
                         if let Some(RSV::Number(a)) = args.get(&"0".to_string()) {
                             let plus = atomic_usize.fetch_add(1, Ordering::SeqCst);
-                            return Ok(OperationFnOutput::with_value(RSV::Number(a + plus as i32)));
+                            return Ok(OperationFnOutput::with_value(RSV::Number(a + plus as i64)));
                         }
                     }
                 }
@@ -0,0 +1,354 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use im::{HashMap as ImHashMap, HashSet as ImHashSet, Vector as ImVector};
+use indexmap::set::IndexSet;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::cells::CellTypes;
+use crate::cells::memory_cell::MemoryRecord;
+use crate::execution::execution::execution_graph::{ChronologyId, ExecutionGraphSendPayload, ExecutionNodeId};
+use crate::execution::execution::execution_state::{EnclosedState, ExecutionStateErrors};
+use crate::execution::execution::human_input::HumanInput;
+use crate::execution::execution::validation;
+use crate::execution::execution::ExecutionState;
+use crate::execution::primitives::identifiers::{DependencyReference, OperationId};
+use crate::execution::primitives::operation::OperationFnOutput;
+use crate::execution::primitives::serialized_value::{deserialize_from_buf, serialize_to_vec};
+use crate::execution::primitives::usage::{ModelUsage, UsageTotals};
+
+const EXECUTION_HEAD_KEY: &str = "execution_head_state_id";
+
+/// Output of an operation as it is written to disk. Values are stored as rkyv bytes rather than
+/// through the JSON serde implementation of RkyvSerializedValue, which is lossy for cells and pointers.
+/// Memoized outputs of pure cells are stored in the same form.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct PersistedOperationFnOutput {
+    has_error: bool,
+    output: Result<Vec<u8>, ExecutionStateErrors>,
+    stdout: Vec<String>,
+    stderr: Vec<String>,
+    #[serde(default)]
+    pub(crate) usage: Vec<ModelUsage>,
+}
+
+impl From<&OperationFnOutput> for PersistedOperationFnOutput {
+    fn from(output: &OperationFnOutput) -> Self {
+        PersistedOperationFnOutput {
+            has_error: output.has_error,
+            output: output.output.as_ref().map(serialize_to_vec).map_err(|e| e.clone()),
+            stdout: output.stdout.clone(),
+            stderr: output.stderr.clone(),
+            usage: output.usage.clone(),
+        }
+    }
+}
+
+impl From<PersistedOperationFnOutput> for OperationFnOutput {
+    fn from(output: PersistedOperationFnOutput) -> Self {
+        OperationFnOutput {
+            has_error: output.has_error,
+            // Nested execution states are recorded as their own nodes in the graph
+            execution_state: None,
+            output: output.output.map(|buf| deserialize_from_buf(&buf)),
+            stdout: output.stdout,
+            stderr: output.stderr,
+            usage: output.usage,
+        }
+    }
+}
+
+/// Snapshot of an ExecutionState as it is written to disk.
+///
+/// OperationNodes hold closures and are not persisted, they are rebuilt from `cells_by_id`
+/// when the snapshot is restored.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct PersistedExecutionState {
+    resolving_execution_node_state_id: ChronologyId,
+    chronology_id: ChronologyId,
+    exec_counter: usize,
+    stack: VecDeque<ExecutionNodeId>,
+    parent_state_chronology_id: ChronologyId,
+    external_event_queue_head: usize,
+    evaluating_operation_id: OperationId,
+    evaluating_name: Option<String>,
+    evaluating_fn: Option<String>,
+    evaluating_arguments: Option<Vec<u8>>,
+    evaluating_cell: Option<CellTypes>,
+    evaluating_enclosed_state: EnclosedState,
+    evaluated_mutation_of_cell: Option<(OperationId, CellTypes)>,
+    exec_queue: VecDeque<OperationId>,
+    state: Vec<(OperationId, PersistedOperationFnOutput)>,
+    fresh_values: Vec<OperationId>,
+    operation_name_to_id: HashMap<String, OperationId>,
+    cells_by_id: Vec<(OperationId, CellTypes)>,
+    has_been_set: Vec<OperationId>,
+    dependency_map: Vec<(OperationId, Vec<(OperationId, DependencyReference)>)>,
+    value_freshness_map: Vec<(OperationId, usize)>,
+    #[serde(default)]
+    usage_by_operation: Vec<(OperationId, UsageTotals)>,
+    #[serde(default)]
+    step_in_progress: bool,
+    #[serde(default)]
+    evaluating_event: Option<String>,
+    #[serde(default)]
+    human_input: Option<HumanInput>,
+    #[serde(default)]
+    cached: bool,
+    #[serde(default)]
+    memories: Vec<(String, Vec<MemoryRecord>)>,
+}
+
+impl From<&ExecutionState> for PersistedExecutionState {
+    fn from(state: &ExecutionState) -> Self {
+        PersistedExecutionState {
+            resolving_execution_node_state_id: state.resolving_execution_node_state_id,
+            chronology_id: state.chronology_id,
+            exec_counter: state.exec_counter,
+            stack: state.stack.clone(),
+            parent_state_chronology_id: state.parent_state_chronology_id,
+            external_event_queue_head: state.external_event_queue_head,
+            evaluating_operation_id: state.evaluating_operation_id,
+            evaluating_name: state.evaluating_name.clone(),
+            evaluating_fn: state.evaluating_fn.clone(),
+            evaluating_arguments: state.evaluating_arguments.as_ref().map(serialize_to_vec),
+            evaluating_cell: state.evaluating_cell.clone(),
+            evaluating_enclosed_state: state.evaluating_enclosed_state.clone(),
+            evaluated_mutation_of_cell: state.evaluated_mutation_of_cell.clone(),
+            exec_queue: state.exec_queue.clone(),
+            state: state.state.iter().map(|(k, v)| (*k, v.as_ref().into())).collect(),
+            fresh_values: state.fresh_values.iter().copied().collect(),
+            operation_name_to_id: state.operation_name_to_id.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            cells_by_id: state.cells_by_id.iter().map(|(k, v)| (*k, v.clone())).collect(),
+            has_been_set: state.has_been_set.iter().copied().collect(),
+            dependency_map: state.dependency_map.iter().map(|(k, v)| (*k, v.iter().cloned().collect())).collect(),
+            value_freshness_map: state.value_freshness_map.iter().map(|(k, v)| (*k, *v)).collect(),
+            usage_by_operation: state.usage_by_operation.iter().map(|(k, v)| (*k, v.clone())).collect(),
+            step_in_progress: state.step_in_progress,
+            evaluating_event: state.evaluating_event.clone(),
+            human_input: state.human_input.clone(),
+            cached: state.cached,
+            memories: state.memories.iter().map(|(k, v)| (k.clone(), v.iter().cloned().collect())).collect(),
+        }
+    }
+}
+
+impl PersistedExecutionState {
+    /// Rebuild a live ExecutionState from this snapshot, re-deriving the OperationNodes of every cell.
+    pub(crate) fn into_execution_state(
+        self,
+        graph_sender: Option<Arc<tokio::sync::mpsc::Sender<ExecutionGraphSendPayload>>>,
+    ) -> anyhow::Result<ExecutionState> {
+        let mut state = ExecutionState {
+            resolving_execution_node_state_id: self.resolving_execution_node_state_id,
+            chronology_id: self.chronology_id,
+            exec_counter: self.exec_counter,
+            stack: self.stack,
+            parent_state_chronology_id: self.parent_state_chronology_id,
+            external_event_queue_head: self.external_event_queue_head,
+            evaluating_operation_id: self.evaluating_operation_id,
+            evaluating_name: self.evaluating_name,
+            evaluating_fn: self.evaluating_fn,
+            evaluating_arguments: self.evaluating_arguments.map(|buf| deserialize_from_buf(&buf)),
+            evaluating_cell: self.evaluating_cell,
+            evaluating_enclosed_state: self.evaluating_enclosed_state,
+            evaluated_mutation_of_cell: self.evaluated_mutation_of_cell,
+            graph_sender,
+            exec_queue: self.exec_queue,
+            state: self.state.into_iter().map(|(k, v)| (k, Arc::new(v.into()))).collect(),
+            fresh_values: IndexSet::from_iter(self.fresh_values),
+            operation_name_to_id: ImHashMap::from_iter(self.operation_name_to_id),
+            cells_by_id: ImHashMap::from_iter(self.cells_by_id),
+            has_been_set: ImHashSet::from_iter(self.has_been_set),
+            dependency_map: self.dependency_map.into_iter().map(|(k, v)| (k, IndexSet::from_iter(v))).collect(),
+            value_freshness_map: ImHashMap::from_iter(self.value_freshness_map),
+            usage_by_operation: ImHashMap::from_iter(self.usage_by_operation),
+            step_in_progress: self.step_in_progress,
+            evaluating_event: self.evaluating_event,
+            human_input: self.human_input,
+            cached: self.cached,
+            memories: self.memories.into_iter().map(|(k, v)| (k, ImVector::from(v))).collect(),
+            ..ExecutionState::default()
+        };
+
+        for (op_id, cell) in state.cells_by_id.clone().into_iter() {
+            let mut op = state.get_operation_from_cell_type(&cell)?;
+            op.id = op_id;
+            state.operation_by_id.insert(op_id, op);
+        }
+        state.update_callable_functions();
+        state.resolve_star_imported_inputs();
+        state.validation = validation::validate(&state);
+        Ok(state)
+    }
+}
+
+/// SQLite backed storage for the states of an ExecutionGraph. Every state received by the graph
+/// is written here so that a session can be reopened after the process exits.
+pub struct ExecutionGraphStore {
+    path: Option<PathBuf>,
+    connection: Connection,
+}
+
+impl fmt::Debug for ExecutionGraphStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExecutionGraphStore")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl ExecutionGraphStore {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() && !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let connection = Connection::open(&path)?;
+        Self::initialize(Some(path), connection)
+    }
+
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::initialize(None, Connection::open_in_memory()?)
+    }
+
+    fn initialize(path: Option<PathBuf>, connection: Connection) -> anyhow::Result<Self> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS execution_states (
+                chronology_id TEXT PRIMARY KEY,
+                parent_chronology_id TEXT NOT NULL,
+                snapshot TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS session_metadata (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );",
+        )?;
+        Ok(ExecutionGraphStore { path, connection })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn insert_state(&self, state: &ExecutionState) -> anyhow::Result<()> {
+        let snapshot = serde_json::to_string(&PersistedExecutionState::from(state))?;
+        self.connection.execute(
+            "INSERT OR REPLACE INTO execution_states (chronology_id, parent_chronology_id, snapshot) VALUES (?1, ?2, ?3)",
+            params![state.chronology_id.to_string(), state.parent_state_chronology_id.to_string(), snapshot],
+        )?;
+        Ok(())
+    }
+
+    /// Load every stored state in the order it was inserted.
+    pub fn load_states(
+        &self,
+        graph_sender: Option<Arc<tokio::sync::mpsc::Sender<ExecutionGraphSendPayload>>>,
+    ) -> anyhow::Result<Vec<ExecutionState>> {
+        let mut statement = self.connection.prepare("SELECT snapshot FROM execution_states ORDER BY rowid")?;
+        let snapshots = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        snapshots
+            .into_iter()
+            .map(|snapshot| {
+                let persisted: PersistedExecutionState = serde_json::from_str(&snapshot)?;
+                persisted.into_execution_state(graph_sender.clone())
+            })
+            .collect()
+    }
+
+    pub fn set_execution_head(&self, id: ExecutionNodeId) -> anyhow::Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO session_metadata (key, value) VALUES (?1, ?2)",
+            params![EXECUTION_HEAD_KEY, id.to_string()],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_execution_head(&self) -> anyhow::Result<Option<ExecutionNodeId>> {
+        let value: Option<String> = self.connection
+            .query_row(
+                "SELECT value FROM session_metadata WHERE key = ?1",
+                params![EXECUTION_HEAD_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(match value {
+            Some(value) => Some(Uuid::parse_str(&value)?),
+            None => None,
+        })
+    }
+
+    /// Remove all stored states and metadata, used when a session is reset.
+    pub fn clear(&self) -> anyhow::Result<()> {
+        self.connection.execute_batch("DELETE FROM execution_states; DELETE FROM session_metadata;")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::{CodeCell, SupportedLanguage, TextRange};
+    use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
+
+    fn state_with_code_cell() -> (ExecutionState, OperationId) {
+        let state = ExecutionState::new_with_random_id();
+        let op_id = Uuid::now_v7();
+        let cell = CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: Some("a".to_string()),
+            language: SupportedLanguage::PyO3,
+            source_code: String::from("x = 20"),
+            function_invocation: None,
+            policy: Default::default(),
+        }, TextRange::default());
+        let op = state.get_operation_from_cell_type(&cell).unwrap();
+        let (op_id, mut state) = state.upsert_operation(op, op_id).unwrap();
+        state.state_insert(op_id, OperationFnOutput::with_value(RkyvObjectBuilder::new().insert_number("x", 20).build()));
+        (state, op_id)
+    }
+
+    #[test]
+    fn test_round_trip_execution_state() -> anyhow::Result<()> {
+        let store = ExecutionGraphStore::open_in_memory()?;
+        let (mut state, op_id) = state_with_code_cell();
+        state.memories.insert("notes".to_string(), ImVector::from(vec![MemoryRecord {
+            embedding: vec![1.0, 0.0],
+            value: serde_json::json!("hello"),
+        }]));
+        store.insert_state(&state)?;
+
+        let restored = store.load_states(None)?;
+        assert_eq!(restored.len(), 1);
+        let restored = &restored[0];
+        assert_eq!(restored.chronology_id, state.chronology_id);
+        assert_eq!(restored.parent_state_chronology_id, state.parent_state_chronology_id);
+        assert_eq!(restored.cells_by_id, state.cells_by_id);
+        assert_eq!(restored.dependency_map, state.dependency_map);
+        assert_eq!(restored.memories, state.memories);
+        assert!(restored.operation_by_id.contains_key(&op_id));
+        assert_eq!(
+            restored.state_get_value(&op_id),
+            Some(&Ok(RkyvObjectBuilder::new().insert_number("x", 20).build()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_execution_head_is_stored() -> anyhow::Result<()> {
+        let store = ExecutionGraphStore::open_in_memory()?;
+        assert_eq!(store.get_execution_head()?, None);
+        let id = Uuid::now_v7();
+        store.set_execution_head(id)?;
+        assert_eq!(store.get_execution_head()?, Some(id));
+        store.clear()?;
+        assert_eq!(store.get_execution_head()?, None);
+        Ok(())
+    }
+}
@@ -1,7 +1,8 @@
 use crate::execution::primitives::identifiers::{DependencyReference, OperationId};
 use crate::execution::primitives::operation::{InputSignature, OperationFnOutput, OperationNode, OutputItemConfiguration};
-use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
-use im::{HashMap as ImHashMap, HashSet as ImHashSet};
+use crate::execution::primitives::serialized_value::{ErrorValue, RkyvObjectBuilder, RkyvSerializedValue};
+use crate::execution::primitives::usage::UsageTotals;
+use im::{HashMap as ImHashMap, HashSet as ImHashSet, Vector as ImVector};
 
 use indexmap::set::IndexSet;
 use indoc::indoc;
@@ -12,7 +13,7 @@ use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::fmt::{Debug, Formatter};
 use std::ops::{Deref};
-use std::sync::{Arc, mpsc};
+use std::sync::{Arc, mpsc, RwLock};
 use no_deadlocks::{Mutex, MutexGuard};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde::de::{MapAccess, Visitor};
@@ -26,8 +27,18 @@ use futures_util::FutureExt;
 use tokio::sync::oneshot::error::TryRecvError;
 use tracing::debug;
 use uuid::Uuid;
-use crate::cells::{CellTypes, CodeCell, LLMPromptCell};
+use crate::cells::{CellTypes, CodeCell, LLMPromptCell, OnError};
+use crate::cells::memory_cell::MemoryRecord;
 use crate::execution::execution::execution_graph::{ExecutionGraphSendPayload, ExecutionNodeId, ChronologyId};
+use crate::execution::execution::memoization;
+use crate::execution::execution::memoization::MemoizationCache;
+use crate::execution::execution::cancellation;
+use crate::execution::execution::validation::{self, ValidationReport};
+use crate::execution::execution::human_input::{await_input, input_request_key, recorded_answer, HumanInput, InputRequest};
+
+/// Upper bound on the emissions routed from a single event, handlers that emit events which
+/// eventually trigger themselves would otherwise never finish.
+const MAX_EVENT_EMISSIONS: usize = 256;
 
 pub enum OperationExecutionStatusOption {
     Running,
@@ -51,7 +62,7 @@ pub enum DependencyGraphMutation {
     },
 }
 
-#[derive(thiserror::Error, Debug, PartialOrd, PartialEq, Clone, Serialize)]
+#[derive(thiserror::Error, Debug, PartialOrd, PartialEq, Clone, Serialize, Deserialize)]
 pub enum ExecutionStateErrors {
     #[error("the execution of this graph has reached a fixed point and will not continue without outside influence")]
     NoFurtherExecutionDetected,
@@ -61,6 +72,14 @@ pub enum ExecutionStateErrors {
     Unknown(String),
     #[error("Anyhow Error: {0}")]
     AnyhowError(String),
+    #[error("{0}")]
+    CellRaisedError(ErrorValue),
+    #[error("the notebook failed validation and will not be run:\n{0}")]
+    ValidationFailed(String),
+    #[error("no function named {0} is declared by the notebook")]
+    UnknownFunction(String),
+    #[error("looped through all operations without detecting an execution, these never ran and are waiting on inputs: {}", .0.join(", "))]
+    PendingOperationsNotRunnable(Vec<String>),
 }
 
 impl From<anyhow::Error> for ExecutionStateErrors {
@@ -74,20 +93,26 @@ impl From<anyhow::Error> for ExecutionStateErrors {
 pub struct FunctionMetadata {
     operation_id: OperationId,
     pub(crate) input_signature: InputSignature,
+    /// Summary of what the function does, offered to models calling it as a tool
+    pub(crate) description: Option<String>,
+    /// Events whose return value of this function is emitted as, from `emit_as`
+    pub(crate) emit_event: Vec<String>,
+    /// Events that invoke this function, from `on_event`
+    pub(crate) trigger_on: Vec<String>,
 }
 
 pub struct OperationRunningStatus {
     running: bool
 }
 
-#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum CloseReason {
     Failure,
     Error,
     Complete
 }
 
-#[derive(Default, Clone, Eq, PartialEq, Debug)]
+#[derive(Default, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum EnclosedState {
     Open,
     Close(CloseReason),
@@ -95,6 +120,27 @@ pub enum EnclosedState {
     SelfContained
 }
 
+/// Execution head of the instance a state is stepped by. Operations that keep serving after their evaluation,
+/// such as webservices and schedules, dispatch from the head so they see the notebook as it is now.
+#[derive(Clone, Default)]
+pub struct ExecutionHead(Arc<RwLock<Option<ExecutionState>>>);
+
+impl ExecutionHead {
+    /// The state at the head, if the instance has set one.
+    pub fn current(&self) -> Option<ExecutionState> {
+        let mut state = self.0.read().unwrap().clone()?;
+        state.execution_head = Some(self.clone());
+        Some(state)
+    }
+
+    pub fn set(&self, state: &ExecutionState) {
+        // The handle is attached again when the head is read, keeping it here would make the head own itself
+        let mut state = state.clone();
+        state.execution_head = None;
+        *self.0.write().unwrap() = Some(state);
+    }
+}
+
 
 #[derive(Clone)]
 pub struct ExecutionState {
@@ -123,6 +169,20 @@ pub struct ExecutionState {
     pub evaluating_cell: Option<CellTypes>,
     pub evaluating_enclosed_state: EnclosedState,
 
+    /// Name of the event emitted by this state, its payload is held in `evaluating_arguments`
+    pub evaluating_event: Option<String>,
+
+    /// Request for input made by the evaluating operation, answered in the state that follows it
+    pub human_input: Option<HumanInput>,
+
+    /// Set on states whose operation output was read from the memoization cache rather than evaluated
+    pub cached: bool,
+
+    /// Set on states closed while their step is still evaluating, such as a failed attempt that will be
+    /// retried or an operation evaluated alongside others. They are recorded in the graph but never
+    /// become the execution head, the state the step concludes with does.
+    pub step_in_progress: bool,
+
     /// CellType applied, by a state that is mutating cell definitions
     pub evaluated_mutation_of_cell: Option<(OperationId, CellTypes)>,
 
@@ -170,6 +230,21 @@ pub struct ExecutionState {
     pub dependency_map: ImHashMap<OperationId, IndexSet<(OperationId, DependencyReference)>>,
 
     pub value_freshness_map: ImHashMap<OperationId, usize>,
+
+    /// Model usage of each operation, accumulated over every evaluation along this branch of execution
+    pub usage_by_operation: ImHashMap<OperationId, UsageTotals>,
+
+    /// Records inserted into the collections of memory cells along this branch of execution, keyed by collection
+    pub memories: ImHashMap<String, ImVector<MemoryRecord>>,
+
+    /// Issues found validating the operations when they were last upserted
+    pub validation: ValidationReport,
+
+    /// Cache the outputs of pure cells are memoized in, they are always evaluated without one
+    pub memoization: Option<Arc<MemoizationCache>>,
+
+    /// Execution head of the instance stepping this state, requests from outside the notebook are dispatched from it
+    pub execution_head: Option<ExecutionHead>,
 }
 
 impl std::fmt::Debug for ExecutionState {
@@ -208,6 +283,10 @@ impl Default for ExecutionState {
             evaluating_arguments: None,
             evaluating_cell: None,
             evaluating_enclosed_state: Default::default(),
+            evaluating_event: None,
+            human_input: None,
+            cached: false,
+            step_in_progress: false,
             evaluated_mutation_of_cell: None,
             graph_sender: None,
             exec_queue: VecDeque::new(),
@@ -221,6 +300,11 @@ impl Default for ExecutionState {
             has_been_set: Default::default(),
             dependency_map: Default::default(),
             value_freshness_map: Default::default(),
+            usage_by_operation: Default::default(),
+            memories: Default::default(),
+            validation: Default::default(),
+            memoization: None,
+            execution_head: None,
             external_event_queue_head: 0,
         }
     }
@@ -279,9 +363,13 @@ impl ExecutionState {
         new.evaluating_name = None;
         new.evaluating_arguments = None;
         new.evaluating_cell = None;
+        new.evaluating_event = None;
+        new.human_input = None;
+        new.cached = false;
         new.parent_state_chronology_id = new.chronology_id;
         new.fresh_values = IndexSet::new();
         new.evaluating_enclosed_state = EnclosedState::Open;
+        new.step_in_progress = false;
         let new_id = Uuid::now_v7();
         new.resolving_execution_node_state_id = new_id;
         new.chronology_id = new_id;
@@ -289,12 +377,21 @@ impl ExecutionState {
         new
     }
 
+    /// Execution heads can only be completed states, not states that are still evaluating
+    pub fn is_valid_execution_head(&self) -> bool {
+        let is_closed = matches!(&self.evaluating_enclosed_state, EnclosedState::Close(_))
+            || self.evaluating_enclosed_state == EnclosedState::SelfContained;
+        is_closed && self.evaluating_fn.is_none() && !self.step_in_progress
+    }
+
     fn close_and_set_chronological_parent(&self, parent_state: &ExecutionState) -> Self {
         let mut new = self.clone();
         new.chronology_id = Uuid::now_v7();
         new.resolving_execution_node_state_id = self.resolving_execution_node_state_id;
         new.parent_state_chronology_id = parent_state.chronology_id;
         new.evaluating_enclosed_state = EnclosedState::Close(CloseReason::Complete);
+        // Records inserted into memory cells by the evaluation are kept, the parent descends from this state
+        new.memories = parent_state.memories.clone();
         new
     }
 
@@ -318,6 +415,71 @@ impl ExecutionState {
         self.has_been_set.insert(operation_id);
     }
 
+    /// Add the model usage of an evaluation to the totals of the operation that produced it.
+    fn record_usage(&mut self, operation_id: OperationId, output: &OperationFnOutput) {
+        let usage_base = self.usage_by_operation.clone();
+        self.record_usage_since(&usage_base, operation_id, output);
+    }
+
+    /// Add the records a nested evaluation inserted into memory cells since the state with `memories_base`,
+    /// so that records inserted by siblings evaluated side by side are all kept.
+    fn record_memories_since(&mut self, memories_base: &ImHashMap<String, ImVector<MemoryRecord>>, output: &OperationFnOutput) {
+        let Some(nested) = &output.execution_state else { return };
+        for (collection, records) in nested.memories.iter() {
+            let known = memories_base.get(collection).map_or(0, |records| records.len());
+            if records.len() > known {
+                let mut merged = self.memories.get(collection).cloned().unwrap_or_default();
+                merged.extend(records.iter().skip(known).cloned());
+                self.memories.insert(collection.clone(), merged);
+            }
+        }
+    }
+
+    /// Add the usage of an output to the totals of this state. Operations that dispatch to other functions
+    /// return the state those invocations resolved in, which descends from the state with `usage_base`,
+    /// only the usage accrued since then is added so that siblings evaluated side by side all count.
+    fn record_usage_since(&mut self, usage_base: &ImHashMap<OperationId, UsageTotals>, operation_id: OperationId, output: &OperationFnOutput) {
+        if let Some(nested) = &output.execution_state {
+            for (nested_operation_id, nested_totals) in nested.usage_by_operation.iter() {
+                let accrued = nested_totals.since(&usage_base.get(nested_operation_id).cloned().unwrap_or_default());
+                if !accrued.is_empty() {
+                    self.usage_by_operation.entry(*nested_operation_id).or_insert_with(UsageTotals::default).merge(&accrued);
+                }
+            }
+        }
+        if !output.usage.is_empty() {
+            let totals = self.usage_by_operation.entry(operation_id).or_insert_with(UsageTotals::default);
+            for usage in &output.usage {
+                totals.add(usage);
+            }
+        }
+    }
+
+    /// Model usage of a single cell along this branch of execution.
+    pub fn usage_for_operation(&self, operation_id: &OperationId) -> UsageTotals {
+        self.usage_by_operation.get(operation_id).cloned().unwrap_or_default()
+    }
+
+    /// Model usage of every cell along this branch of execution.
+    pub fn branch_usage(&self) -> UsageTotals {
+        let mut totals = UsageTotals::default();
+        for usage in self.usage_by_operation.values() {
+            totals.merge(usage);
+        }
+        totals
+    }
+
+    /// Model usage of the outputs recorded by this state itself, excluding its ancestors.
+    pub fn evaluated_usage(&self) -> UsageTotals {
+        let mut totals = UsageTotals::default();
+        for operation_id in &self.fresh_values {
+            if let Some(output) = self.state.get(operation_id) {
+                totals.merge(&UsageTotals::from_usage(&output.usage));
+            }
+        }
+        totals
+    }
+
     #[cfg(test)]
     pub fn render_dependency_graph(&self) {
         println!("================ Dependency graph ================");
@@ -372,6 +534,9 @@ impl ExecutionState {
             CellTypes::Prompt(c, r) => crate::cells::llm_prompt_cell::llm_prompt_cell(self.chronology_id.clone(), c, r),
             CellTypes::Template(c, r) => crate::cells::template_cell::template_cell(self.chronology_id.clone(), c, r),
             CellTypes::CodeGen(c, r) => crate::cells::code_gen_cell::code_gen_cell(self.chronology_id.clone(), c, r),
+            CellTypes::Memory(c, r) => crate::cells::memory_cell::memory_cell(self.chronology_id.clone(), c, r),
+            CellTypes::Webservice(c, r) => crate::cells::webservice_cell::webservice_cell(self.chronology_id.clone(), c, r),
+            CellTypes::Schedule(c, r) => crate::cells::schedule_cell::schedule_cell(self.chronology_id.clone(), c, r),
         }?;
         Ok(op)
     }
@@ -390,39 +555,27 @@ impl ExecutionState {
 
     #[tracing::instrument]
     fn assign_dependencies_to_operations(new_state: &ExecutionState) -> anyhow::Result<Vec<DependencyGraphMutation>> {
-        let (available_values, available_functions) = Self::extract_available_values_and_functions(new_state)?;
+        let exports = validation::exports_by_name(new_state);
 
         // Anywhere there is a matched value, we create a dependency graph edge
         let mut mutations = vec![];
 
-        // let mut unsatisfied_dependencies = vec![];
         // For each destination cell, we inspect their input signatures and accumulate the
         // mutation operations that we need to apply to the dependency graph.
         for (destination_cell_id, operation) in new_state.operation_by_id.iter() {
             // The currently running operation will be locked and will fail this condition, but we're not updating it.
             let input_signature = &operation.signature.input_signature;
             let mut accum = vec![];
-            for (value_name, value) in input_signature.globals.iter() {
-
-                // TODO: we need to handle collisions between the two of these
-                if let Some(source_cell_id) = available_functions.get(value_name) {
-                    if source_cell_id != &destination_cell_id {
-                        accum.push((
-                            *source_cell_id.clone(),
-                            DependencyReference::FunctionInvocation(value_name.to_string()),
-                        ));
+            let mut names: Vec<&String> = input_signature.globals.keys().collect();
+            names.sort();
+            for value_name in names {
+                // A name exposed by several cells, or as both a value and a function, depends on all of them
+                // rather than on whichever was found first. Validation reports these collisions.
+                for (source_cell_id, reference) in exports.get(value_name).into_iter().flatten() {
+                    if source_cell_id != destination_cell_id {
+                        accum.push((*source_cell_id, reference.clone()));
                     }
                 }
-
-                if let Some(source_cell_id) = available_values.get(value_name) {
-                    if source_cell_id != &destination_cell_id {
-                        accum.push((
-                            *source_cell_id.clone(),
-                            DependencyReference::Global(value_name.to_string()),
-                        ));
-                    }
-                }
-                // unsatisfied_dependencies.push(value_name.clone())
             }
             if accum.len() > 0 {
                 mutations.push(DependencyGraphMutation::Create {
@@ -434,33 +587,6 @@ impl ExecutionState {
         Ok(mutations)
     }
 
-    #[tracing::instrument]
-    fn extract_available_values_and_functions(new_state: &ExecutionState) -> anyhow::Result<(HashMap<String, &OperationId>, HashMap<String, &OperationId>)> {
-        let mut available_values = HashMap::new();
-        let mut available_functions = HashMap::new();
-
-        // For all reported cells, add their exposed values to the available values
-        for (id, operation) in new_state.operation_by_id.iter() {
-            let output_signature = &operation.signature.output_signature;
-
-            // Store values that are available as globals
-            for (key, value) in output_signature.globals.iter() {
-                let insert_result = available_values.insert(key.clone(), id);
-                if insert_result.is_some() {
-                    return Err(anyhow::Error::msg(format!("Naming collision detected for value {} when storing op #{}", key, id)));
-                }
-            }
-
-            for (key, value) in output_signature.functions.iter() {
-                let insert_result = available_functions.insert(key.clone(), id);
-                if insert_result.is_some() {
-                    return Err(anyhow::Error::msg(format!("Naming collision detected for value {}", key)));
-                }
-            }
-        }
-        Ok((available_values, available_functions))
-    }
-
     /// Inserts a new operation into the execution state, returning the operation id and the new state.
     /// That operation can then be referred to by its id.
     #[tracing::instrument]
@@ -480,9 +606,14 @@ impl ExecutionState {
         s.evaluated_mutation_of_cell = Some((op_id, operation_node.cell.clone()));
         s.operation_by_id.insert(op_id, operation_node);
         s.update_callable_functions();
+        s.resolve_star_imported_inputs();
         s.exec_queue.push_back(op_id);
         let mutations = Self::assign_dependencies_to_operations(&s)?;
-        let final_state = s.apply_dependency_graph_mutations(mutations);
+        let mut final_state = s.apply_dependency_graph_mutations(mutations);
+        final_state.validation = validation::validate(&final_state);
+        if !final_state.validation.is_empty() {
+            debug!("Validating operations after upserting {:?}:\n{}", op_id, final_state.validation.render(&final_state));
+        }
         Ok((op_id, final_state))
     }
 
@@ -519,22 +650,51 @@ impl ExecutionState {
         s
     }
 
+    /// Globals a star import may provide are waited on while another cell exposes them, otherwise the
+    /// star import is left to provide them.
+    pub(crate) fn resolve_star_imported_inputs(&mut self) {
+        let exports = validation::exports_by_name(self);
+        let mut resolved = vec![];
+        for (operation_id, operation) in self.operation_by_id.iter() {
+            for (name, configuration) in operation.signature.input_signature.globals.iter() {
+                if configuration.may_be_star_imported {
+                    let exposed_elsewhere = exports.get(name).into_iter().flatten()
+                        .any(|(provider, _)| provider != operation_id);
+                    if configuration.computed_default == exposed_elsewhere {
+                        resolved.push((*operation_id, name.clone(), !exposed_elsewhere));
+                    }
+                }
+            }
+        }
+        for (operation_id, name, computed_default) in resolved {
+            if let Some(configuration) = self.operation_by_id.get_mut(&operation_id)
+                .and_then(|operation| operation.signature.input_signature.globals.get_mut(&name)) {
+                configuration.computed_default = computed_default;
+            }
+        }
+    }
+
     #[tracing::instrument]
-    fn update_callable_functions(&mut self) {
+    pub(crate) fn update_callable_functions(&mut self) {
         // Ensure no stale data exists
         self.function_name_to_metadata.clear();
 
         for (id, op_node) in &self.operation_by_id {
             self.function_name_to_metadata.extend(
                 op_node.signature.output_signature.functions.iter().map(|(name, config)| {
-                    let input_signature = match config {
-                        OutputItemConfiguration::Function { input_signature, .. } => input_signature.clone(),
-                        _ => InputSignature::new(),
+                    let (input_signature, description, emit_event, trigger_on) = match config {
+                        OutputItemConfiguration::Function { input_signature, description, emit_event, trigger_on } => {
+                            (input_signature.clone(), description.clone(), emit_event.clone(), trigger_on.clone())
+                        }
+                        _ => (InputSignature::new(), None, vec![], vec![]),
                     };
 
                     (name.clone(), FunctionMetadata {
                         operation_id: id.clone(),
                         input_signature,
+                        description,
+                        emit_event,
+                        trigger_on,
                     })
                 })
             );
@@ -546,15 +706,155 @@ impl ExecutionState {
     // TODO: this should create a coroutine that yields with the result of the function invocation
     #[tracing::instrument(parent = parent_span_id.clone(), skip(self, payload))]
     pub async fn dispatch(&self, function_name: &str, payload: RkyvSerializedValue, parent_span_id: Option<tracing::Id>) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, ExecutionState)> {
+        // Arguments that do not match the annotated types of the function fail the call before it is made
+        if let Some(meta) = self.function_name_to_metadata.get(function_name) {
+            if let Err(mismatches) = meta.input_signature.check_argument_types(&payload) {
+                let error = ErrorValue::new("TypeError", format!("{}() was called with {}", function_name, mismatches));
+                return Ok((Err(ExecutionStateErrors::CellRaisedError(error)), self.clone()));
+            }
+        }
+        let (result, mut after_execution_state) = self.invoke_function(function_name, payload, parent_span_id.clone()).await?;
+
+        // Functions marked with emit_as publish their return value to the handlers of that event
+        if let Ok(value) = &result {
+            let emit_event = self.function_name_to_metadata.get(function_name)
+                .map(|meta| meta.emit_event.clone())
+                .unwrap_or_default();
+            for event_name in emit_event {
+                let (emitted, state) = after_execution_state.emit_event(&event_name, value.clone(), parent_span_id.clone()).await?;
+                after_execution_state = state;
+                if let Err(e) = emitted {
+                    return Ok((Err(e), after_execution_state));
+                }
+            }
+        }
+        Ok((result, after_execution_state))
+    }
+
+    /// Dispatch a function on behalf of a caller outside of the notebook, such as a webservice request or a
+    /// scheduled job. The function is invoked from the execution head of the instance when there is one rather
+    /// than from this state, and its evaluation concludes in a completed state recorded as a new execution head.
+    pub async fn dispatch_from_execution_head(&self, function_name: &str, payload: RkyvSerializedValue) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, ExecutionState)> {
+        let head = self.execution_head.as_ref()
+            .and_then(|head| head.current())
+            .unwrap_or_else(|| self.clone());
+        let (result, after_execution_state) = head.dispatch(function_name, payload, None).await?;
+        let mut concluded_state = after_execution_state.create_new_revision_of_execution_state();
+        concluded_state.evaluating_enclosed_state = EnclosedState::Close(CloseReason::Complete);
+        after_execution_state.send_new_state_to_graph_and_pause_with_oneshot(&mut concluded_state).await;
+        Ok((result, concluded_state))
+    }
+
+    /// Functions registered to be invoked by the named event, in a stable order
+    fn event_handlers(&self, event_name: &str) -> Vec<String> {
+        let mut handlers: Vec<String> = self.function_name_to_metadata.iter()
+            .filter(|(_, meta)| meta.trigger_on.iter().any(|e| e == event_name))
+            .map(|(name, _)| name.clone())
+            .collect();
+        handlers.sort();
+        handlers
+    }
+
+    /// Emit a named event, invoking every function registered with `on_event(event_name)` with the payload
+    /// as its only argument. Handlers that are themselves marked with `emit_as` emit their return values in
+    /// turn. The emission and every handler invocation are recorded in the execution graph, the returned state
+    /// follows the last of them. Handlers that fail do not prevent the remaining handlers from running, the
+    /// first failure is returned once the event has been routed.
+    #[tracing::instrument(parent = parent_span_id.clone(), skip(self, payload))]
+    pub async fn emit_event(&self, event_name: &str, payload: RkyvSerializedValue, parent_span_id: Option<tracing::Id>) -> anyhow::Result<(Result<(), ExecutionStateErrors>, ExecutionState)> {
+        debug!("Emitting event {:?}", event_name);
+        let mut pending = VecDeque::from([(event_name.to_string(), payload)]);
+        let mut current = self.clone();
+        let mut first_error = None;
+        let mut emissions = 0;
+        while let Some((event_name, payload)) = pending.pop_front() {
+            emissions += 1;
+            if emissions > MAX_EVENT_EMISSIONS {
+                return Err(anyhow::anyhow!(
+                    "Event {:?} was emitted after {} emissions from a single event, handlers may be emitting events in a cycle",
+                    event_name, MAX_EVENT_EMISSIONS
+                ));
+            }
+
+            // Record the emission itself, it is part of the evaluation that emitted it and never an execution head
+            let mut emission_state = current.create_new_revision_of_execution_state();
+            emission_state.evaluating_event = Some(event_name.clone());
+            emission_state.evaluating_arguments = Some(payload.clone());
+            emission_state.evaluating_enclosed_state = EnclosedState::SelfContained;
+            emission_state.step_in_progress = true;
+            current.send_new_state_to_graph_and_pause_with_oneshot(&mut emission_state).await;
+            current = emission_state;
+
+            for handler in current.event_handlers(&event_name) {
+                let handler_payload = RkyvObjectBuilder::new()
+                    .insert_object("args", RkyvObjectBuilder::new().insert_value("0", payload.clone()))
+                    .build();
+                let (result, state) = current.invoke_function(&handler, handler_payload, parent_span_id.clone()).await?;
+                current = state;
+                match result {
+                    Ok(value) => {
+                        let emit_event = current.function_name_to_metadata.get(&handler)
+                            .map(|meta| meta.emit_event.clone())
+                            .unwrap_or_default();
+                        pending.extend(emit_event.into_iter().map(|e| (e, value.clone())));
+                    }
+                    Err(e) => {
+                        first_error.get_or_insert(e);
+                    }
+                }
+            }
+        }
+        Ok((first_error.map_or(Ok(()), Err), current))
+    }
+
+    /// Suspend the evaluating operation until a person answers the prompt. The request and its answer are
+    /// recorded as states of their own, a request that was answered by an earlier evaluation at the same
+    /// point in the history of execution reuses that answer rather than asking again.
+    pub async fn request_input(&self, prompt: &str, schema: Option<RkyvSerializedValue>) -> anyhow::Result<(RkyvSerializedValue, ExecutionState)> {
+        let mut request_state = self.create_new_revision_of_execution_state();
+        let request = InputRequest {
+            id: request_state.chronology_id,
+            operation_id: self.evaluating_operation_id,
+            prompt: prompt.to_string(),
+            key: input_request_key(&self.evaluating_operation_id, prompt, &schema, request_state.exec_counter),
+            schema,
+        };
+        request_state.human_input = Some(HumanInput { request: request.clone(), answer: None });
+        request_state.evaluating_enclosed_state = EnclosedState::SelfContained;
+        request_state.step_in_progress = true;
+
+        let answer = match recorded_answer(&request.key) {
+            Some(answer) => {
+                self.send_new_state_to_graph_and_pause_with_oneshot(&mut request_state).await;
+                answer
+            }
+            None => {
+                // Registered before the request is announced, so that it can be answered as soon as it is seen
+                let receiver = await_input(request.clone());
+                self.send_new_state_to_graph_and_pause_with_oneshot(&mut request_state).await;
+                receiver.await.map_err(|_| anyhow::anyhow!("Request for input {} was abandoned", request.id))?
+            }
+        };
+
+        let mut answer_state = request_state.create_new_revision_of_execution_state();
+        answer_state.human_input = Some(HumanInput { request, answer: Some(answer.clone()) });
+        answer_state.evaluating_enclosed_state = EnclosedState::SelfContained;
+        answer_state.step_in_progress = true;
+        request_state.send_new_state_to_graph_and_pause_with_oneshot(&mut answer_state).await;
+        Ok((answer, answer_state))
+    }
+
+    /// Invoke a named function through the operation that defines it, recording the invocation and its result.
+    async fn invoke_function(&self, function_name: &str, payload: RkyvSerializedValue, parent_span_id: Option<tracing::Id>) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, ExecutionState)> {
         debug!("Running dispatch {:?}", function_name);
 
         // Store the invocation payload into an execution state and record this before executing
         let mut before_execution_state = self.create_new_revision_of_execution_state();
         before_execution_state.stack.push_back(self.resolving_execution_node_state_id);
 
-        let meta = self.function_name_to_metadata.get(function_name).map(|meta| {
-            meta
-        }).expect("Failed to find named function");
+        let Some(meta) = self.function_name_to_metadata.get(function_name) else {
+            return Err(anyhow::Error::new(ExecutionStateErrors::UnknownFunction(function_name.to_string())));
+        };
 
         let cell = before_execution_state.cells_by_id.get(&meta.operation_id).unwrap();
         // modify code cell to indicate execution of the target function
@@ -578,6 +878,7 @@ impl ExecutionState {
             .close_and_set_chronological_parent(&result.execution_state.as_ref().unwrap_or(&before_execution_state));
 
         after_execution_state.stack.pop_back();
+        after_execution_state.record_usage(meta.operation_id, &result);
         after_execution_state.state_insert(Uuid::max(), result.clone());
         after_execution_state.fresh_values.insert(Uuid::max());
         self.send_new_state_to_graph_and_pause_with_oneshot(&mut after_execution_state).await;
@@ -604,6 +905,11 @@ impl ExecutionState {
                     }
                 }
             }
+            CellTypes::Memory(c, r) => {
+                let mut c = c.clone();
+                c.function_invocation = Some(clone_function_name.to_string());
+                crate::cells::memory_cell::memory_cell(Uuid::nil(), &c, &r)?
+            }
             _ => {
                 unreachable!("Unsupported cell type");
             }
@@ -640,18 +946,29 @@ impl ExecutionState {
 
         for (from, _, argument_indices) in dependency_graph.edges_directed(operation_id, Direction::Incoming) {
             let Some(output) = self.state_get(&from) else { continue; };
-            let output_value = &output.output;
+            // Failed dependencies are passed on as the error they failed with
+            let output_value = match &output.output {
+                Ok(value) => value.clone(),
+                Err(e) => RkyvSerializedValue::Error(self.error_value_of_failure(from, e)),
+            };
             for argument_index in argument_indices {
                 match argument_index {
                     DependencyReference::Positional(pos) => {
-                        inputs.args.insert(pos.to_string(), output_value.clone().unwrap());
+                        inputs.args.insert(pos.to_string(), output_value.clone());
                     }
                     DependencyReference::Keyword(kw) => {
-                        inputs.kwargs.insert(kw.clone(), output_value.clone().unwrap());
+                        inputs.kwargs.insert(kw.clone(), output_value.clone());
                     }
                     DependencyReference::Global(name) => {
-                        if let RkyvSerializedValue::Object(value) = &output.output.clone().unwrap() {
-                            inputs.globals.insert(name.clone(), value.get(name).ok_or_else(|| anyhow::anyhow!("Expected value with name: {:?} to be available", name))?.clone());
+                        match &output_value {
+                            RkyvSerializedValue::Object(value) => {
+                                inputs.globals.insert(name.clone(), value.get(name).ok_or_else(|| anyhow::anyhow!("Expected value with name: {:?} to be available", name))?.clone());
+                            }
+                            // The dependency failed before it could define the value
+                            RkyvSerializedValue::Error(_) => {
+                                inputs.globals.insert(name.clone(), output_value.clone());
+                            }
+                            _ => {}
                         }
                     }
                     DependencyReference::FunctionInvocation(name) => {
@@ -666,6 +983,18 @@ impl ExecutionState {
         Ok(inputs)
     }
 
+    /// The error a failed operation is seen to have raised by the operations depending on it.
+    fn error_value_of_failure(&self, operation_id: OperationId, error: &ExecutionStateErrors) -> ErrorValue {
+        let error = match error {
+            ExecutionStateErrors::CellRaisedError(error) => error.clone(),
+            e => ErrorValue::new("CellExecutionError", e.to_string()),
+        };
+        match self.cells_by_id.get(&operation_id) {
+            Some(cell) => error.raised_in(cell),
+            None => error,
+        }
+    }
+
     fn has_fresher_inputs(&self, operation_id: OperationId) -> anyhow::Result<bool> {
         let our_freshness = self.value_freshness_map.get(&operation_id).copied().unwrap_or(0);
         let dependency_graph = self.get_dependency_graph();
@@ -681,16 +1010,23 @@ impl ExecutionState {
 
     #[tracing::instrument]
     pub(crate) fn determine_next_operation(&self) -> anyhow::Result<ExecutionState> {
+        Ok(self.determine_next_operations(1)?.remove(0))
+    }
+
+    /// Find up to `limit` operations whose inputs are fresh and satisfied, staging a new execution state
+    /// for each of them. Operations that depend on one another are never staged together.
+    #[tracing::instrument]
+    pub(crate) fn determine_next_operations(&self, limit: usize) -> anyhow::Result<Vec<ExecutionState>> {
         let mut exec_queue = self.exec_queue.clone();
         let operation_count = self.cells_by_id.keys().count();
+        let dependency_graph = self.get_dependency_graph();
         let mut count_loops = 0;
+        let mut ready: Vec<(OperationId, Option<String>, OperationInputs)> = vec![];
+        // Operations that are ready but depend on one that was already staged, they are considered again next step
+        let mut deferred: Vec<OperationId> = vec![];
 
-        loop {
+        while ready.len() < limit.max(1) && count_loops < operation_count * 2 {
             debug!("Looping through queue of executable cells {:?} {:?}", self.exec_queue, count_loops);
-
-            if count_loops >= operation_count * 2 {
-                return Err(Error::msg("Looped through all operations without detecting an execution"));
-            }
             count_loops += 1;
 
             // Get next operation from queue, reload queue if empty
@@ -704,6 +1040,10 @@ impl ExecutionState {
                 }
             };
 
+            if ready.iter().any(|(id, _, _)| *id == next_operation_id) || deferred.contains(&next_operation_id) {
+                continue;
+            }
+
             // Get operation node and check validity
             let op_node = self.get_operation_node(next_operation_id)?;
             let signature = &op_node.signature.input_signature;
@@ -719,64 +1059,406 @@ impl ExecutionState {
             }
 
             // Prepare and validate inputs
-            let inputs = self.prepare_operation_inputs(signature, next_operation_id, self.get_dependency_graph())?;
+            let inputs = self.prepare_operation_inputs(signature, next_operation_id, dependency_graph.clone())?;
             if !signature.check_input_against_signature(&inputs) {
                 continue;
             }
 
-            // Create and stage new execution state
+            // A dependent evaluated alongside its dependency would only see the dependency's previous output
+            if ready.iter().any(|(id, _, _)| {
+                dependency_graph.contains_edge(*id, next_operation_id) || dependency_graph.contains_edge(next_operation_id, *id)
+            }) {
+                deferred.push(next_operation_id);
+                continue;
+            }
+
+            ready.push((next_operation_id, op_node.name.clone(), inputs));
+        }
+
+        if ready.is_empty() {
+            // Execution only settled if every operation has run, the rest are waiting on inputs that were never produced
+            let mut pending: Vec<String> = self.operation_by_id.iter()
+                .filter(|(operation_id, _)| !self.has_been_set.contains(*operation_id))
+                .map(|(operation_id, op_node)| op_node.name.clone().unwrap_or_else(|| operation_id.to_string()))
+                .collect();
+            if pending.is_empty() {
+                return Err(ExecutionStateErrors::NoFurtherExecutionDetected.into());
+            }
+            pending.sort();
+            return Err(ExecutionStateErrors::PendingOperationsNotRunnable(pending).into());
+        }
+
+        for operation_id in deferred.into_iter().rev() {
+            exec_queue.push_front(operation_id);
+        }
+
+        // Create and stage new execution states
+        Ok(ready.into_iter().map(|(operation_id, name, inputs)| {
             let mut new_state = self.create_new_revision_of_execution_state();
-            new_state.evaluating_operation_id = next_operation_id;
-            new_state.evaluating_name = op_node.name.clone();
+            new_state.evaluating_operation_id = operation_id;
+            new_state.evaluating_name = name;
             new_state.evaluating_arguments = Some(inputs.to_serialized_value());
-            new_state.exec_queue = exec_queue;
-            return Ok(new_state);
-        }
+            new_state.exec_queue = exec_queue.clone();
+            new_state
+        }).collect())
     }
 
     #[tracing::instrument]
     pub async fn step_execution(
         &self,
+    ) -> anyhow::Result<(ExecutionState, Vec<(OperationId, OperationFnOutput)>)> {
+        self.step_execution_with_intermediate_output(None).await
+    }
+
+    /// Step execution, forwarding partial output (such as the tokens of a streaming prompt) to the given channel
+    /// while the operation runs. Partial output is tagged with the id of the state evaluating the operation.
+    #[tracing::instrument]
+    pub async fn step_execution_with_intermediate_output(
+        &self,
+        intermediate_output_channel_tx: Option<std::sync::mpsc::Sender<(ExecutionNodeId, RkyvSerializedValue)>>,
+    ) -> anyhow::Result<(ExecutionState, Vec<(OperationId, OperationFnOutput)>)> {
+        self.step_execution_concurrently(1, intermediate_output_channel_tx).await
+    }
+
+    /// Step execution, evaluating up to `max_concurrency` ready operations side by side. Each operation is
+    /// evaluated from a sibling state of its own, and the siblings are merged into a single successor state
+    /// once all of them have finished.
+    #[tracing::instrument]
+    pub async fn step_execution_concurrently(
+        &self,
+        max_concurrency: usize,
+        intermediate_output_channel_tx: Option<std::sync::mpsc::Sender<(ExecutionNodeId, RkyvSerializedValue)>>,
     ) -> anyhow::Result<(ExecutionState, Vec<(OperationId, OperationFnOutput)>)> {
         debug!("Running step_execution for state {:?}", self.chronology_id);
         // 1. Initialize state and prepare for execution
-        let mut before_execution_state = self.determine_next_operation()?;
+        let mut before_execution_states = self.determine_next_operations(max_concurrency)?;
+        if before_execution_states.len() == 1 {
+            return self.evaluate_operation(before_execution_states.remove(0), intermediate_output_channel_tx, false).await;
+        }
+        let evaluations = before_execution_states
+            .into_iter()
+            .map(|before_execution_state| self.evaluate_operation(before_execution_state, intermediate_output_channel_tx.clone(), true));
+        let results = futures::future::join_all(evaluations).await;
+        self.merge_sibling_evaluations(results).await
+    }
+
+    /// Combine the results of operations evaluated side by side into one state. The merged state follows
+    /// the sibling that finished last. If any sibling failed, the merged state is still recorded and the
+    /// first failure is returned.
+    async fn merge_sibling_evaluations(
+        &self,
+        evaluations: Vec<anyhow::Result<(ExecutionState, Vec<(OperationId, OperationFnOutput)>)>>,
+    ) -> anyhow::Result<(ExecutionState, Vec<(OperationId, OperationFnOutput)>)> {
+        let mut first_error = None;
+        let mut siblings = vec![];
+        for evaluation in evaluations {
+            match evaluation {
+                Ok(sibling) => siblings.push(sibling),
+                Err(e) => { first_error.get_or_insert(e); }
+            }
+        }
+        let Some(last_sibling) = siblings.iter().map(|(state, _)| state).max_by_key(|state| state.chronology_id) else {
+            return Err(first_error.unwrap_or_else(|| ExecutionStateErrors::NoFurtherExecutionDetected.into()));
+        };
+
+        let mut merged_state = self.create_new_revision_of_execution_state();
+        merged_state.parent_state_chronology_id = last_sibling.chronology_id;
+        merged_state.exec_counter = siblings.iter().map(|(state, _)| state.exec_counter).max().unwrap_or(merged_state.exec_counter);
+        merged_state.exec_queue = last_sibling.exec_queue.clone();
+        merged_state.evaluating_enclosed_state = EnclosedState::Close(CloseReason::Complete);
+
+        let mut outputs = vec![];
+        for (sibling_state, sibling_outputs) in siblings {
+            for (operation_id, output) in sibling_outputs {
+                // Every sibling was evaluated from this state, the usage each accrued is added to the others'
+                merged_state.record_usage_since(&self.usage_by_operation, operation_id, &output);
+                merged_state.record_memories_since(&self.memories, &output);
+                if sibling_state.fresh_values.contains(&operation_id) {
+                    merged_state.complete_operation(operation_id, &output);
+                } else if sibling_state.value_freshness_map.get(&operation_id) != self.value_freshness_map.get(&operation_id) {
+                    // A failure that was propagated to the operation's dependents
+                    merged_state.propagate_failure(operation_id, &output);
+                } else {
+                    merged_state.state_insert(operation_id, output.clone());
+                }
+                outputs.push((operation_id, output));
+            }
+        }
+        self.send_new_state_to_graph_and_pause_with_oneshot(&mut merged_state).await;
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok((merged_state, outputs)),
+        }
+    }
+
+    /// Evaluate the operation staged in the given state, applying the retry and failure policy of its cell.
+    /// The states of a sibling evaluation are all left in progress, to be concluded by their merge.
+    async fn evaluate_operation(
+        &self,
+        mut before_execution_state: ExecutionState,
+        intermediate_output_channel_tx: Option<std::sync::mpsc::Sender<(ExecutionNodeId, RkyvSerializedValue)>>,
+        is_sibling: bool,
+    ) -> anyhow::Result<(ExecutionState, Vec<(OperationId, OperationFnOutput)>)> {
         let operation_id = before_execution_state.evaluating_operation_id.clone();
         let args = before_execution_state.evaluating_arguments.take().unwrap();
 
         // 2. Update operation node info
         let op_node = self.get_operation_node(operation_id)?;
+        let policy = op_node.cell.policy();
         before_execution_state.evaluating_cell = Some(op_node.cell.clone());
 
         // 3. Pause if needed, sending in progress execution to the graph
         self.send_new_state_to_graph_and_pause_with_oneshot(&mut before_execution_state).await;
 
-        // 4. Execute the operation
-        let result = op_node.execute(&mut before_execution_state, args, None, None).await?;
+        // Cells that do not handle errors pass on an error they receive instead of evaluating
+        if !policy.handles_errors() {
+            if let Some(error) = received_error(&args) {
+                let result = OperationFnOutput {
+                    has_error: true,
+                    execution_state: None,
+                    output: Err(ExecutionStateErrors::CellRaisedError(error)),
+                    stdout: vec![],
+                    stderr: vec![],
+                    usage: vec![],
+                };
+                let mut after_execution_state = before_execution_state.close_and_set_chronological_parent(&before_execution_state);
+                after_execution_state.evaluating_enclosed_state = EnclosedState::Close(CloseReason::Failure);
+                after_execution_state.step_in_progress = is_sibling;
+                after_execution_state.record_usage(operation_id, &result);
+                after_execution_state.propagate_failure(operation_id, &result);
+                self.send_new_state_to_graph_and_pause_with_oneshot(&mut after_execution_state).await;
+                return Ok((after_execution_state, vec![(operation_id, result)]));
+            }
+        }
 
-        // 5. Update state with execution results
-        // If the result of the execution returned a new execution state
-        // make sure that our Close for the step_execution is parented by
-        // that new state.
-        let mut after_execution_state = before_execution_state
-            .close_and_set_chronological_parent(&result.execution_state.as_ref().unwrap_or(&before_execution_state));
+        // Pure cells evaluated with the same inputs before are read from the memoization cache
+        let memoization_cache = self.memoization.clone().filter(|_| op_node.cell.is_memoizable());
+        let cache_key = memoization_cache.as_ref().and_then(|_| memoization::cache_key(&op_node.cell, &args));
+        if let Some(result) = memoization_cache.as_ref().zip(cache_key.as_ref()).and_then(|(cache, key)| cache.lookup(&op_node.cell, key)) {
+            let mut after_execution_state = before_execution_state.close_and_set_chronological_parent(&before_execution_state);
+            after_execution_state.cached = true;
+            after_execution_state.step_in_progress = is_sibling;
+            after_execution_state.record_usage(operation_id, &result);
+            after_execution_state.complete_operation(operation_id, &result);
+            self.send_new_state_to_graph_and_pause_with_oneshot(&mut after_execution_state).await;
+            return Ok((after_execution_state, vec![(operation_id, result)]));
+        }
+
+        let mut attempt = 1;
+        loop {
+            // 4. Execute the operation
+            let outcome = Self::execute_with_timeout(
+                op_node,
+                &before_execution_state,
+                args.clone(),
+                intermediate_output_channel_tx.clone(),
+                policy.timeout(),
+            ).await;
+
+            let cancelled = matches!(&outcome, Err(e) if cancellation::is_cancelled_error(e));
+            let failure = match &outcome {
+                Ok(OperationFnOutput { output: Ok(_), .. }) => None,
+                Ok(OperationFnOutput { output: Err(e), .. }) => Some(e.to_string()),
+                Err(e) => Some(e.to_string()),
+            };
+            let result = outcome.unwrap_or_else(|e| OperationFnOutput {
+                has_error: true,
+                execution_state: None,
+                // Exceptions raised by the cell's code are kept whole, so that they reach its dependents as raised
+                output: Err(match e.downcast_ref::<ErrorValue>() {
+                    Some(error) => ExecutionStateErrors::CellRaisedError(error.clone().raised_in(&op_node.cell)),
+                    None => ExecutionStateErrors::CellExecutionUnexpectedFailure(before_execution_state.chronology_id, e.to_string()),
+                }),
+                stdout: vec![],
+                stderr: vec![],
+                usage: vec![],
+            });
 
-        // 6. Finalize state
-        after_execution_state.fresh_values.insert(operation_id.clone());
-        after_execution_state.state_insert(operation_id.clone(), result.clone());
-        after_execution_state.value_freshness_map.insert(operation_id.clone(), after_execution_state.exec_counter);
+            // 5. Update state with execution results
+            // If the result of the execution returned a new execution state
+            // make sure that our Close for the step_execution is parented by
+            // that new state.
+            let mut after_execution_state = before_execution_state
+                .close_and_set_chronological_parent(&result.execution_state.as_ref().unwrap_or(&before_execution_state));
+
+            let Some(message) = failure else {
+                if let Some((cache, key)) = memoization_cache.as_ref().zip(cache_key.as_ref()) {
+                    if let Err(e) = cache.store(&op_node.cell, key, &result) {
+                        debug!("Failed to memoize the output of operation {:?}: {}", operation_id, e);
+                    }
+                }
 
-        self.send_new_state_to_graph_and_pause_with_oneshot(&mut after_execution_state).await;
+                // 6. Finalize state
+                after_execution_state.step_in_progress = is_sibling;
+                after_execution_state.record_usage(operation_id, &result);
+                after_execution_state.complete_operation(operation_id, &result);
+                self.send_new_state_to_graph_and_pause_with_oneshot(&mut after_execution_state).await;
+                return Ok((after_execution_state, vec![(operation_id, result)]));
+            };
+
+            // Failed attempts are closed as failures, their output is kept for inspection
+            // but is not fresh, so nothing downstream evaluates against it
+            debug!("Attempt {} of operation {:?} failed: {}", attempt, operation_id, message);
+            after_execution_state.evaluating_enclosed_state = EnclosedState::Close(CloseReason::Failure);
+
+            // Cancelled operations are neither retried nor replaced by their fallback, their dependents
+            // receive the cancellation as an error
+            if cancelled {
+                after_execution_state.step_in_progress = is_sibling;
+                after_execution_state.record_usage(operation_id, &result);
+                after_execution_state.propagate_failure(operation_id, &result);
+                self.send_new_state_to_graph_and_pause_with_oneshot(&mut after_execution_state).await;
+                return Ok((after_execution_state, vec![(operation_id, result)]));
+            }
+
+            after_execution_state.step_in_progress = is_sibling
+                || attempt < policy.max_attempts()
+                || matches!(policy.on_error(), OnError::Fallback(_));
+            after_execution_state.record_usage(operation_id, &result);
+            if attempt >= policy.max_attempts() && policy.on_error() == OnError::Propagate {
+                after_execution_state.propagate_failure(operation_id, &result);
+            } else {
+                after_execution_state.state_insert(operation_id, result.clone());
+            }
+            self.send_new_state_to_graph_and_pause_with_oneshot(&mut after_execution_state).await;
+
+            if attempt < policy.max_attempts() {
+                tokio::time::sleep(policy.backoff_before_retry(attempt)).await;
+                attempt += 1;
+                before_execution_state = after_execution_state.create_new_revision_of_execution_state();
+                before_execution_state.evaluating_operation_id = operation_id;
+                before_execution_state.evaluating_name = op_node.name.clone();
+                before_execution_state.evaluating_cell = Some(op_node.cell.clone());
+                before_execution_state.evaluating_arguments = Some(args.clone());
+                self.send_new_state_to_graph_and_pause_with_oneshot(&mut before_execution_state).await;
+                continue;
+            }
+
+            return match policy.on_error() {
+                OnError::Pause => Err(ExecutionStateErrors::CellExecutionUnexpectedFailure(
+                    after_execution_state.chronology_id,
+                    format!("{} failed after {} attempt(s): {}", op_node.name.as_deref().unwrap_or("Cell"), attempt, message),
+                ).into()),
+                OnError::Skip | OnError::Propagate => Ok((after_execution_state, vec![(operation_id, result)])),
+                OnError::Fallback(fallback_name) => {
+                    after_execution_state.evaluate_fallback(operation_id, &fallback_name, intermediate_output_channel_tx, is_sibling).await
+                }
+            };
+        }
+    }
+
+    /// Record the output of an operation that evaluated successfully, making it available downstream.
+    /// Its usage is recorded separately, with `record_usage`.
+    fn complete_operation(&mut self, operation_id: OperationId, result: &OperationFnOutput) {
+        self.fresh_values.insert(operation_id.clone());
+        self.state_insert(operation_id.clone(), result.clone());
+        self.value_freshness_map.insert(operation_id.clone(), self.exec_counter);
+    }
+
+    /// Record the failure of an operation as its output, updating its freshness so that the operations
+    /// depending on it are evaluated against the error.
+    fn propagate_failure(&mut self, operation_id: OperationId, result: &OperationFnOutput) {
+        self.state_insert(operation_id, result.clone());
+        self.value_freshness_map.insert(operation_id, self.exec_counter);
+    }
+
+    async fn execute_with_timeout(
+        op_node: &OperationNode,
+        state: &ExecutionState,
+        args: RkyvSerializedValue,
+        intermediate_output_channel_tx: Option<std::sync::mpsc::Sender<(ExecutionNodeId, RkyvSerializedValue)>>,
+        timeout: Option<std::time::Duration>,
+    ) -> anyhow::Result<OperationFnOutput> {
+        let in_flight = cancellation::begin_operation(state.chronology_id, state.evaluating_operation_id);
+        let execution = op_node.execute(state, args, intermediate_output_channel_tx, None);
+        // The operation runs as its own task so that the timeout and cancellation take effect even
+        // when the operation blocks the thread it is polled on
+        let handle = tokio::spawn(execution);
+        let abort_handle = handle.abort_handle();
+        let timed_out = async {
+            match timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            joined = handle => joined.map_err(|e| anyhow::anyhow!("Cell execution panicked: {}", e))?,
+            _ = timed_out => {
+                // Aborting only drops the task, the runtime has to be interrupted to stop the code it runs
+                cancellation::cancel_operation(state.chronology_id);
+                abort_handle.abort();
+                Err(anyhow::anyhow!("Cell execution timed out after {:?}", timeout.unwrap_or_default()))
+            }
+            _ = in_flight.cancelled() => {
+                cancellation::cancel_operation(state.chronology_id);
+                abort_handle.abort();
+                Err(anyhow::Error::new(cancellation::cancelled_error()))
+            }
+        }
+    }
+
+    /// Evaluate the named fallback cell in place of a cell that failed. The fallback's output is stored
+    /// as the output of the failed cell, so cells depending on the failed cell continue with it.
+    async fn evaluate_fallback(
+        &self,
+        failed_operation_id: OperationId,
+        fallback_name: &str,
+        intermediate_output_channel_tx: Option<std::sync::mpsc::Sender<(ExecutionNodeId, RkyvSerializedValue)>>,
+        is_sibling: bool,
+    ) -> anyhow::Result<(ExecutionState, Vec<(OperationId, OperationFnOutput)>)> {
+        let fallback_id = self.cells_by_id.iter()
+            .find(|(_, cell)| cell.name().as_deref() == Some(fallback_name))
+            .map(|(id, _)| *id)
+            .ok_or_else(|| anyhow::anyhow!("Fallback cell {:?} does not exist", fallback_name))?;
+        let fallback_node = self.get_operation_node(fallback_id)?;
+        let inputs = self.prepare_operation_inputs(&fallback_node.signature.input_signature, fallback_id, self.get_dependency_graph())?;
 
-        Ok((after_execution_state, vec![(operation_id, result)]))
+        let mut before_execution_state = self.create_new_revision_of_execution_state();
+        before_execution_state.evaluating_operation_id = failed_operation_id;
+        before_execution_state.evaluating_name = fallback_node.name.clone();
+        before_execution_state.evaluating_cell = Some(fallback_node.cell.clone());
+        self.send_new_state_to_graph_and_pause_with_oneshot(&mut before_execution_state).await;
+
+        let result = Self::execute_with_timeout(
+            fallback_node,
+            &before_execution_state,
+            inputs.to_serialized_value(),
+            intermediate_output_channel_tx,
+            fallback_node.cell.policy().timeout(),
+        ).await?;
+        let mut after_execution_state = before_execution_state
+            .close_and_set_chronological_parent(&result.execution_state.as_ref().unwrap_or(&before_execution_state));
+        after_execution_state.step_in_progress = is_sibling;
+        after_execution_state.record_usage(failed_operation_id, &result);
+        after_execution_state.complete_operation(failed_operation_id, &result);
+        self.send_new_state_to_graph_and_pause_with_oneshot(&mut after_execution_state).await;
+        Ok((after_execution_state, vec![(failed_operation_id, result)]))
     }
 }
 
+/// The first error among the inputs an operation was given.
+fn received_error(args: &RkyvSerializedValue) -> Option<ErrorValue> {
+    let RkyvSerializedValue::Object(sections) = args else {
+        return None;
+    };
+    ["args", "kwargs", "globals"].iter()
+        .filter_map(|section| match sections.get(*section) {
+            Some(RkyvSerializedValue::Object(values)) => Some(values),
+            _ => None,
+        })
+        .flat_map(|values| values.values())
+        .find_map(|value| match value {
+            RkyvSerializedValue::Error(error) => Some(error.clone()),
+            _ => None,
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::cells::{CellTypes, SupportedLanguage, TextRange};
-    use crate::cells::CodeCell;
+    use crate::cells::{python_cell, CodeCell};
     use crate::execution::primitives::operation::{InputItemConfiguration, InputType, OutputSignature, Signature, TriggerConfiguration};
 
     #[test]
@@ -790,6 +1472,7 @@ mod tests {
             output: Ok(value),
             stdout: vec![],
             stderr: vec![],
+            usage: vec![],
         };
         exec_state.state_insert(operation_id, value.clone());
 
@@ -797,6 +1480,51 @@ mod tests {
         assert!(exec_state.has_been_set.contains(&operation_id));
     }
 
+    #[test]
+    fn test_usage_rolls_up_per_operation_and_branch() {
+        use crate::execution::primitives::usage::ModelUsage;
+        let mut exec_state = ExecutionState::new_with_random_id();
+        let prompt_a = Uuid::now_v7();
+        let prompt_b = Uuid::now_v7();
+        let mut output = OperationFnOutput::with_value(RkyvSerializedValue::Null);
+        output.usage = vec![ModelUsage { model: "gpt-4o".to_string(), prompt_tokens: 10, completion_tokens: 5 }];
+
+        exec_state.record_usage(prompt_a, &output);
+        let mut next_state = exec_state.create_new_revision_of_execution_state();
+        next_state.record_usage(prompt_a, &output);
+        next_state.record_usage(prompt_b, &output);
+
+        assert_eq!(exec_state.usage_for_operation(&prompt_a).total_tokens(), 15);
+        assert_eq!(next_state.usage_for_operation(&prompt_a).total_tokens(), 30);
+        assert_eq!(next_state.branch_usage().requests(), 3);
+        assert_eq!(next_state.branch_usage().by_model["gpt-4o"].completion_tokens, 15);
+    }
+
+    #[test]
+    fn test_usage_of_siblings_dispatching_functions_adds_up() {
+        use crate::execution::primitives::usage::ModelUsage;
+        let mut base = ExecutionState::new_with_random_id();
+        let (summarize, caller_a, caller_b) = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+        let mut prompt = OperationFnOutput::with_value(RkyvSerializedValue::Null);
+        prompt.usage = vec![ModelUsage { model: "gpt-4o".to_string(), prompt_tokens: 10, completion_tokens: 5 }];
+        base.record_usage(summarize, &prompt);
+
+        // Both siblings dispatch to the same prompt from the base state, each returning the state it resolved in
+        let sibling_output = || {
+            let mut nested = base.create_new_revision_of_execution_state();
+            nested.record_usage(summarize, &prompt);
+            let mut output = OperationFnOutput::with_value(RkyvSerializedValue::Null);
+            output.execution_state = Some(nested);
+            output
+        };
+        let mut merged = base.create_new_revision_of_execution_state();
+        merged.record_usage_since(&base.usage_by_operation, caller_a, &sibling_output());
+        merged.record_usage_since(&base.usage_by_operation, caller_b, &sibling_output());
+
+        assert_eq!(merged.usage_for_operation(&summarize).requests(), 3);
+        assert_eq!(merged.usage_for_operation(&summarize).total_tokens(), 45);
+    }
+
     #[test]
     fn test_dependency_graph_mutation() {
         let mut exec_state = ExecutionState::new_with_random_id();
@@ -868,6 +1596,7 @@ mod tests {
             language: SupportedLanguage::PyO3,
             source_code: String::from("y = x + 1"),
             function_invocation: None,
+            policy: Default::default(),
         }, Default::default());
 
         let id_a = Uuid::now_v7();
@@ -929,6 +1658,7 @@ mod tests {
             language: SupportedLanguage::PyO3,
             source_code: "def test_fn(): return 2".to_string(),
             function_invocation: None,
+            policy: Default::default(),
         }, TextRange::default());
 
         let id_a = Uuid::now_v7();
@@ -937,6 +1667,9 @@ mod tests {
         new_state.function_name_to_metadata.insert("test_fn".to_string(), FunctionMetadata {
             operation_id: op_id,
             input_signature: InputSignature::new(),
+            description: None,
+            emit_event: vec![],
+            trigger_on: vec![],
         });
         
         let payload = RkyvSerializedValue::Null;
@@ -945,6 +1678,330 @@ mod tests {
         assert_eq!(result.unwrap(), RkyvSerializedValue::Number(2));
     }
 
+    #[tokio::test]
+    async fn test_failed_attempts_are_retried_then_skipped() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let policy = crate::cells::ExecutionPolicy {
+            retries: Some(1),
+            on_error: Some(OnError::Skip),
+            ..Default::default()
+        };
+        let (state, op_id) = state.update_operation(python_cell("a", "raise ValueError('boom')", policy), Uuid::now_v7()).await?;
+
+        let exec_counter = state.exec_counter;
+        let (state, outputs) = state.step_execution().await?;
+        assert_eq!(state.evaluating_enclosed_state, EnclosedState::Close(CloseReason::Failure));
+        assert!(outputs[0].1.output.is_err());
+        assert!(!state.fresh_values.contains(&op_id));
+        // Each attempt is a revision of its own, closed when the attempt fails
+        assert_eq!(state.exec_counter, exec_counter + 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failure_pauses_by_default() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let (state, _) = state.update_operation(python_cell("a", "raise ValueError('boom')", Default::default()), Uuid::now_v7()).await?;
+
+        let err = state.step_execution().await.err().unwrap();
+        assert!(matches!(err.downcast_ref::<ExecutionStateErrors>(), Some(ExecutionStateErrors::CellExecutionUnexpectedFailure(..))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failure_routes_to_fallback_cell() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let policy = crate::cells::ExecutionPolicy {
+            on_error: Some(OnError::Fallback("fallback".to_string())),
+            ..Default::default()
+        };
+        let (state, op_a) = state.update_operation(python_cell("a", "raise ValueError('boom')", policy), Uuid::now_v7()).await?;
+        let (state, _) = state.update_operation(python_cell("fallback", "x = 1", Default::default()), Uuid::now_v7()).await?;
+
+        let (state, _) = state.step_execution().await?;
+        assert_eq!(state.evaluating_enclosed_state, EnclosedState::Close(CloseReason::Complete));
+        assert!(state.fresh_values.contains(&op_a));
+        assert_eq!(
+            state.state_get_value(&op_a).unwrap(),
+            &Ok(RkyvObjectBuilder::new().insert_number("x", 1).build())
+        );
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_attempt_times_out() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let policy = crate::cells::ExecutionPolicy {
+            timeout: Some(0.1),
+            on_error: Some(OnError::Skip),
+            ..Default::default()
+        };
+        let (state, _) = state.update_operation(python_cell("a", "import time\ntime.sleep(1)", policy), Uuid::now_v7()).await?;
+
+        let (_, outputs) = state.step_execution().await?;
+        let Err(ExecutionStateErrors::CellExecutionUnexpectedFailure(_, message)) = &outputs[0].1.output else { panic!("Expected a failure") };
+        assert!(message.contains("timed out"));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_busy_loop_times_out_and_later_cells_run() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let policy = crate::cells::ExecutionPolicy {
+            timeout: Some(0.1),
+            on_error: Some(OnError::Skip),
+            ..Default::default()
+        };
+        let (state, _) = state.update_operation(python_cell("a", "while True:\n    pass", policy), Uuid::now_v7()).await?;
+        let (state, outputs) = state.step_execution().await?;
+        let Err(ExecutionStateErrors::CellExecutionUnexpectedFailure(_, message)) = &outputs[0].1.output else { panic!("Expected a failure") };
+        assert!(message.contains("timed out"));
+
+        // The loop is interrupted rather than left holding the interpreter
+        let (state, op_b) = state.update_operation(python_cell("b", "y = 2", Default::default()), Uuid::now_v7()).await?;
+        let (state, _) = tokio::time::timeout(std::time::Duration::from_secs(5), state.step_execution()).await??;
+        assert_eq!(
+            state.state_get_value(&op_b).unwrap(),
+            &Ok(RkyvObjectBuilder::new().insert_number("y", 2).build())
+        );
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cancelled_operation_closes_as_failure() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let (state, op_id) = state.update_operation(python_cell("a", "x = 0\nwhile True:\n    x += 1", Default::default()), Uuid::now_v7()).await?;
+
+        let canceller = tokio::spawn(async move {
+            loop {
+                if let Some((id, _)) = cancellation::in_flight_operations().into_iter().find(|(_, in_flight)| *in_flight == op_id) {
+                    assert!(cancellation::cancel_operation(id));
+                    return;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        });
+        let (state, outputs) = state.step_execution().await?;
+        canceller.await?;
+        assert_eq!(state.evaluating_enclosed_state, EnclosedState::Close(CloseReason::Failure));
+        let Err(ExecutionStateErrors::CellRaisedError(error)) = &outputs[0].1.output else { panic!("Expected the cancellation as an error") };
+        assert_eq!(error.ty, cancellation::CANCELLED_ERROR_TYPE);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_errors_flow_to_dependents_that_handle_them() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let propagate = crate::cells::ExecutionPolicy {
+            on_error: Some(OnError::Propagate),
+            ..Default::default()
+        };
+        let handle_errors = crate::cells::ExecutionPolicy {
+            handle_errors: Some(true),
+            ..Default::default()
+        };
+        let (state, op_a) = state.update_operation(python_cell("a", "x = 1\nraise ValueError('boom')", propagate), Uuid::now_v7()).await?;
+        let (state, op_b) = state.update_operation(python_cell("b", "y = x + 1", Default::default()), Uuid::now_v7()).await?;
+        let (state, op_c) = state.update_operation(python_cell("c", "z = type(y).__name__ + ' ' + str(y) + ' ' + y.cell", handle_errors), Uuid::now_v7()).await?;
+
+        let (state, _) = state.step_execution().await?;
+        let Some(Err(ExecutionStateErrors::CellRaisedError(error))) = state.state_get_value(&op_a) else { panic!("Expected a raised error") };
+        assert_eq!((error.ty.as_str(), error.message.as_str(), error.cell.as_deref()), ("ValueError", "boom", Some("a")));
+        assert!(error.traceback.is_some());
+
+        // b does not handle errors, it passes the error on without evaluating
+        let (state, _) = state.step_execution().await?;
+        assert_eq!(state.state_get_value(&op_b), Some(&Err(ExecutionStateErrors::CellRaisedError(error.clone()))));
+
+        // c receives it as the exception it was raised as
+        let (state, _) = state.step_execution().await?;
+        assert_eq!(
+            state.state_get_value(&op_c).unwrap(),
+            &Ok(RkyvObjectBuilder::new().insert_string("z", "ValueError boom a".to_string()).build())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pure_cell_output_is_read_from_cache() -> anyhow::Result<()> {
+        let cache = Arc::new(MemoizationCache::new(std::env::temp_dir().join(format!("chidori-memoization-{}", Uuid::now_v7()))));
+        let memoized_state = || ExecutionState {
+            memoization: Some(cache.clone()),
+            ..ExecutionState::new_with_random_id()
+        };
+        let policy = crate::cells::ExecutionPolicy {
+            purity: Some(crate::execution::primitives::operation::Purity::Pure),
+            ..Default::default()
+        };
+        let source = "x = 1";
+
+        let (state, op_a) = memoized_state().update_operation(python_cell("a", source, policy.clone()), Uuid::now_v7()).await?;
+        let (evaluated, _) = state.step_execution().await?;
+        assert!(!evaluated.cached);
+
+        // The same cell with the same inputs in a fresh graph reads the output it evaluated to before
+        let (state, op_b) = memoized_state().update_operation(python_cell("a", source, policy.clone()), Uuid::now_v7()).await?;
+        let (cached, _) = state.step_execution().await?;
+        assert!(cached.cached);
+        assert!(cached.fresh_values.contains(&op_b));
+        assert_eq!(cached.state_get_value(&op_b), evaluated.state_get_value(&op_a));
+
+        // Graphs without the cache evaluate the cell
+        let (state, _) = ExecutionState::new_with_random_id().update_operation(python_cell("a", source, policy), Uuid::now_v7()).await?;
+        let (uncached, _) = state.step_execution().await?;
+        assert!(!uncached.cached);
+
+        cache.invalidate_cell("a")?;
+        let (state, _) = memoized_state().update_operation(python_cell("a", source, Default::default()), Uuid::now_v7()).await?;
+        let (impure, _) = state.step_execution().await?;
+        assert!(!impure.cached);
+        cache.invalidate_notebook()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_star_imports_do_not_make_exposed_names_optional() -> anyhow::Result<()> {
+        let required = |state: &ExecutionState, op_id: &OperationId, name: &str| {
+            state.operation_by_id[op_id].signature.input_signature.globals[name].is_required()
+        };
+        let (state, op_a) = ExecutionState::new_with_random_id()
+            .update_operation(python_cell("a", "from math import *\ny = x + pi", Default::default()), Uuid::now_v7()).await?;
+        assert!(!required(&state, &op_a, "x"));
+        assert!(!required(&state, &op_a, "pi"));
+
+        // Once another cell exposes x, a waits for it
+        let (state, _) = state.update_operation(python_cell("b", "x = 1", Default::default()), Uuid::now_v7()).await?;
+        assert!(required(&state, &op_a, "x"));
+        assert!(!required(&state, &op_a, "pi"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_independent_operations_run_side_by_side() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let (state, op_a) = state.update_operation(python_cell("a", "x = 1", Default::default()), Uuid::now_v7()).await?;
+        let (state, op_b) = state.update_operation(python_cell("b", "y = 2", Default::default()), Uuid::now_v7()).await?;
+        let (state, op_c) = state.update_operation(python_cell("c", "z = x + y", Default::default()), Uuid::now_v7()).await?;
+
+        let (merged_state, outputs) = state.step_execution_concurrently(4, None).await?;
+        assert_eq!(outputs.len(), 2);
+        assert!(merged_state.fresh_values.contains(&op_a));
+        assert!(merged_state.fresh_values.contains(&op_b));
+        assert!(merged_state.is_valid_execution_head());
+
+        // The dependent only becomes ready once both siblings have been merged
+        let (state, outputs) = merged_state.step_execution_concurrently(4, None).await?;
+        assert_eq!(outputs.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![op_c]);
+        assert_eq!(
+            state.state_get_value(&op_c).unwrap(),
+            &Ok(RkyvObjectBuilder::new().insert_number("z", 3).build())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_operations_that_can_never_run_are_reported_as_pending() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let (state, _) = state.update_operation(python_cell("a", "x = y + 1", Default::default()), Uuid::now_v7()).await?;
+        let (state, _) = state.update_operation(python_cell("b", "y = x + 1", Default::default()), Uuid::now_v7()).await?;
+        let error = state.step_execution_concurrently(4, None).await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ExecutionStateErrors>(),
+            Some(&ExecutionStateErrors::PendingOperationsNotRunnable(vec!["a".to_string(), "b".to_string()]))
+        );
+
+        // Once every operation has run, execution has settled instead
+        let (state, _) = ExecutionState::new_with_random_id()
+            .update_operation(python_cell("c", "z = 1", Default::default()), Uuid::now_v7()).await?;
+        let (state, _) = state.step_execution_concurrently(4, None).await?;
+        let error = state.step_execution_concurrently(4, None).await.unwrap_err();
+        assert_eq!(error.downcast_ref::<ExecutionStateErrors>(), Some(&ExecutionStateErrors::NoFurtherExecutionDetected));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_emitted_event_is_routed_to_handlers() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let (state, _) = state.update_operation(python_cell("greeter", indoc! { r#"
+            import chidori as ch
+
+            @ch.on_event("greeted")
+            @ch.emit_as("greeting_ready")
+            def greet(name):
+                return "hello " + name
+            "#}, Default::default()), Uuid::now_v7()).await?;
+        let (state, _) = state.update_operation(python_cell("announcer", indoc! { r#"
+            import chidori as ch
+
+            @ch.on_event("greeting_ready")
+            def announce(greeting):
+                return greeting + "!"
+            "#}, Default::default()), Uuid::now_v7()).await?;
+        assert_eq!(state.event_handlers("greeted"), vec!["greet".to_string()]);
+
+        let (result, state) = state.emit_event("greeted", RkyvSerializedValue::String("world".to_string()), None).await?;
+        assert_eq!(result, Ok(()));
+        // The handler of the re-emitted event ran last
+        assert_eq!(state.evaluating_fn, Some("announce".to_string()));
+        assert_eq!(state.state_get_value(&Uuid::max()), Some(&Ok(RkyvSerializedValue::String("hello world!".to_string()))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_checks_argument_types() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let (state, _) = state.update_operation(python_cell("repeater", indoc! { r#"
+            def repeat(text: str, times: int = 2):
+                return text * times
+            "#}, Default::default()), Uuid::now_v7()).await?;
+
+        let payload = RkyvObjectBuilder::new()
+            .insert_object("args", RkyvObjectBuilder::new().insert_string("0", "ab".to_string()))
+            .insert_object("kwargs", RkyvObjectBuilder::new().insert_number("times", 3))
+            .build();
+        let (result, _) = state.dispatch("repeat", payload, None).await?;
+        assert_eq!(result, Ok(RkyvSerializedValue::String("ababab".to_string())));
+
+        let payload = RkyvObjectBuilder::new()
+            .insert_object("args", RkyvObjectBuilder::new().insert_number("0", 1))
+            .insert_object("kwargs", RkyvObjectBuilder::new().insert_string("times", "3".to_string()))
+            .build();
+        let (result, _) = state.dispatch("repeat", payload, None).await?;
+        let Err(ExecutionStateErrors::CellRaisedError(error)) = result else {
+            panic!("expected the call to fail, got {:?}", result);
+        };
+        assert_eq!(error.ty, "TypeError");
+        assert_eq!(error.message, "repeat() was called with argument \"text\" expected str, got int, argument \"times\" expected int, got str");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_input_suspends_until_answered_and_replays() -> anyhow::Result<()> {
+        use crate::execution::execution::human_input::{pending_input_requests, provide_input};
+        let mut state = ExecutionState::new_with_random_id();
+        state.evaluating_operation_id = Uuid::now_v7();
+
+        let suspended = state.clone();
+        let handle = tokio::spawn(async move { suspended.request_input("Name?", None).await });
+        let request = loop {
+            if let Some(request) = pending_input_requests().into_iter().find(|r| r.operation_id == state.evaluating_operation_id) {
+                break request;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(request.prompt, "Name?");
+        provide_input(request.id, RkyvSerializedValue::String("Ada".to_string()))?;
+        let (answer, answered_state) = handle.await??;
+        assert_eq!(answer, RkyvSerializedValue::String("Ada".to_string()));
+        assert_eq!(answered_state.human_input.as_ref().unwrap().answer, Some(answer.clone()));
+        assert!(!answered_state.is_valid_execution_head());
+
+        // Replaying the same evaluation reuses the recorded answer
+        let (replayed, _) = state.request_input("Name?", None).await?;
+        assert_eq!(replayed, answer);
+        Ok(())
+    }
+
     #[test]
     fn test_get_dependency_graph() {
         let mut state = ExecutionState::new_with_random_id();
@@ -972,14 +2029,17 @@ mod tests {
                 args: HashMap::from([("0".to_string(), InputItemConfiguration {
                     ty: Some(InputType::String),
                     default: None,
+                    ..Default::default()
                 })]),
                 kwargs: HashMap::from([("kwarg1".to_string(), InputItemConfiguration {
                     ty: Some(InputType::String),
                     default: None,
+                    ..Default::default()
                 })]),
                 globals: HashMap::from([("global1".to_string(), InputItemConfiguration {
                     ty: Some(InputType::String),
                     default: None,
+                    ..Default::default()
                 })]),
             },
             output_signature: OutputSignature {
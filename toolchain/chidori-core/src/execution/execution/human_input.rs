@@ -0,0 +1,216 @@
+use std::net::{IpAddr, SocketAddr};
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use tokio::sync::oneshot;
+use tracing::debug;
+use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::execution::ExecutionState;
+use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::serialized_value::{json_value_to_serialized_value, serialized_value_to_json_value, RkyvSerializedValue};
+
+/// A running cell asking a person for input with `chidori.input(prompt, schema)`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputRequest {
+    /// Chronology id of the state that recorded the request, answers are addressed to it
+    pub id: ExecutionNodeId,
+    pub operation_id: OperationId,
+    pub prompt: String,
+    /// JSON schema the answer must satisfy, `type`, `enum` and `required` are checked
+    pub schema: Option<RkyvSerializedValue>,
+    /// Identifies the request across replays of the evaluation that made it
+    pub key: String,
+}
+
+/// A request for input and, once it has been given, its answer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HumanInput {
+    pub request: InputRequest,
+    pub answer: Option<RkyvSerializedValue>,
+}
+
+/// Requests that are waiting for an answer, with the channel that resumes the cell that made them
+static PENDING_INPUTS: Lazy<DashMap<ExecutionNodeId, (InputRequest, oneshot::Sender<RkyvSerializedValue>)>> = Lazy::new(|| DashMap::new());
+
+/// Answers that have been given, keyed by the request they answered, so that replaying an evaluation does not ask again
+static RECORDED_ANSWERS: Lazy<DashMap<String, RkyvSerializedValue>> = Lazy::new(|| DashMap::new());
+
+/// Requests made by the same operation with the same prompt and schema are told apart by their position in
+/// the history of execution, the `exec_counter` of the state recording them, which replays reproduce.
+pub fn input_request_key(operation_id: &OperationId, prompt: &str, schema: &Option<RkyvSerializedValue>, position: usize) -> String {
+    let schema = schema.as_ref().map(serialized_value_to_json_value).unwrap_or(Value::Null);
+    let mut hasher = Sha1::new();
+    hasher.update(json!([operation_id.to_string(), prompt, schema, position]).to_string().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Requests that are currently waiting for an answer, oldest first.
+pub fn pending_input_requests() -> Vec<InputRequest> {
+    let mut requests: Vec<InputRequest> = PENDING_INPUTS.iter().map(|entry| entry.value().0.clone()).collect();
+    requests.sort_by_key(|request| request.id);
+    requests
+}
+
+/// Answer a pending request, resuming the cell that made it. Answers that do not satisfy the
+/// request's schema are refused and the request stays pending.
+pub fn provide_input(request_id: ExecutionNodeId, answer: RkyvSerializedValue) -> anyhow::Result<()> {
+    {
+        let pending = PENDING_INPUTS.get(&request_id)
+            .ok_or_else(|| anyhow::anyhow!("No input is pending for request {}", request_id))?;
+        if let Some(schema) = &pending.value().0.schema {
+            validate_answer(schema, &answer)?;
+        }
+    }
+    let (_, (request, sender)) = PENDING_INPUTS.remove(&request_id)
+        .ok_or_else(|| anyhow::anyhow!("No input is pending for request {}", request_id))?;
+    remember_answer(&request.key, answer.clone());
+    sender.send(answer).map_err(|_| anyhow::anyhow!("The evaluation waiting on request {} has stopped", request_id))
+}
+
+/// Register a request as pending, the returned receiver resolves with its answer.
+pub(crate) fn await_input(request: InputRequest) -> oneshot::Receiver<RkyvSerializedValue> {
+    let (sender, receiver) = oneshot::channel();
+    PENDING_INPUTS.insert(request.id, (request, sender));
+    receiver
+}
+
+pub fn recorded_answer(key: &str) -> Option<RkyvSerializedValue> {
+    RECORDED_ANSWERS.get(key).map(|answer| answer.value().clone())
+}
+
+pub fn remember_answer(key: &str, answer: RkyvSerializedValue) {
+    RECORDED_ANSWERS.insert(key.to_string(), answer);
+}
+
+/// Remember the answer recorded by a state, used when states are restored from a session.
+pub fn remember_answer_in_state(state: &ExecutionState) {
+    if let Some(HumanInput { request, answer: Some(answer) }) = &state.human_input {
+        remember_answer(&request.key, answer.clone());
+    }
+}
+
+fn json_type_matches(ty: &str, answer: &RkyvSerializedValue) -> bool {
+    match (ty, answer) {
+        ("string", RkyvSerializedValue::String(_)) => true,
+        ("integer", RkyvSerializedValue::Number(_)) => true,
+        ("number", RkyvSerializedValue::Number(_) | RkyvSerializedValue::Float(_)) => true,
+        ("boolean", RkyvSerializedValue::Boolean(_)) => true,
+        ("array", RkyvSerializedValue::Array(_) | RkyvSerializedValue::Embedding(_)) => true,
+        ("object", RkyvSerializedValue::Object(_)) => true,
+        ("null", RkyvSerializedValue::Null) => true,
+        _ => false,
+    }
+}
+
+/// Check an answer against the `type`, `enum` and `required` keywords of a JSON schema.
+pub fn validate_answer(schema: &RkyvSerializedValue, answer: &RkyvSerializedValue) -> anyhow::Result<()> {
+    let RkyvSerializedValue::Object(schema) = schema else {
+        return Ok(());
+    };
+    if let Some(RkyvSerializedValue::String(ty)) = schema.get("type") {
+        if !json_type_matches(ty, answer) {
+            return Err(anyhow::anyhow!("Expected an answer of type {}, received {}", ty, answer));
+        }
+    }
+    if let Some(RkyvSerializedValue::Array(options)) = schema.get("enum") {
+        if !options.contains(answer) {
+            return Err(anyhow::anyhow!("Expected one of {:?}, received {:?}", options, answer));
+        }
+    }
+    if let (Some(RkyvSerializedValue::Array(required)), RkyvSerializedValue::Object(fields)) = (schema.get("required"), answer) {
+        for field in required {
+            if let RkyvSerializedValue::String(field) = field {
+                if !fields.contains_key(field) {
+                    return Err(anyhow::anyhow!("Answer is missing required field {}", field));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn input_request_to_json(request: &InputRequest) -> Value {
+    json!({
+        "id": request.id.to_string(),
+        "prompt": request.prompt,
+        "schema": request.schema.as_ref().map(serialized_value_to_json_value),
+    })
+}
+
+/// Serve pending requests for input over HTTP, for answering them while running headless:
+///
+/// ```text
+/// GET /inputs         lists the pending requests
+/// POST /inputs/:id    answers a request with the JSON body
+/// ```
+pub async fn serve_input_requests(host: IpAddr, port: u16) -> anyhow::Result<SocketAddr> {
+    let router = Router::new()
+        .route("/inputs", get(|| async {
+            Json(Value::Array(pending_input_requests().iter().map(input_request_to_json).collect()))
+        }))
+        .route("/inputs/:id", post(|Path(id): Path<String>, Json(body): Json<Value>| async move {
+            let result = uuid::Uuid::parse_str(&id)
+                .map_err(|e| anyhow::anyhow!("Invalid request id {}: {}", id, e))
+                .and_then(|id| provide_input(id, json_value_to_serialized_value(&body)));
+            match result {
+                Ok(()) => (StatusCode::OK, Json(json!({ "answered": id }))),
+                Err(e) => (StatusCode::BAD_REQUEST, Json(json!({ "error": e.to_string() }))),
+            }
+        }));
+    let listener = tokio::net::TcpListener::bind((host, port)).await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, router).await {
+            debug!("Input server on {:?} stopped: {:?}", addr, e);
+        }
+    });
+    Ok(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_validate_answer_against_schema() {
+        let schema = json_value_to_serialized_value(&json!({
+            "type": "object",
+            "required": ["approved"],
+        }));
+        assert!(validate_answer(&schema, &RkyvObjectBuilder::new().insert_boolean("approved", true).build()).is_ok());
+        assert!(validate_answer(&schema, &RkyvObjectBuilder::new().build()).is_err());
+        assert!(validate_answer(&schema, &RkyvSerializedValue::String("yes".to_string())).is_err());
+
+        let choice = json_value_to_serialized_value(&json!({ "type": "string", "enum": ["yes", "no"] }));
+        assert!(validate_answer(&choice, &RkyvSerializedValue::String("no".to_string())).is_ok());
+        assert!(validate_answer(&choice, &RkyvSerializedValue::String("maybe".to_string())).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_provide_input_resumes_request() -> anyhow::Result<()> {
+        let request = InputRequest {
+            id: Uuid::now_v7(),
+            operation_id: Uuid::now_v7(),
+            prompt: "Approve?".to_string(),
+            schema: Some(json_value_to_serialized_value(&json!({ "type": "boolean" }))),
+            key: input_request_key(&Uuid::nil(), "Approve?", &None, 0),
+        };
+        let receiver = await_input(request.clone());
+        assert!(pending_input_requests().contains(&request));
+
+        assert!(provide_input(request.id, RkyvSerializedValue::String("yes".to_string())).is_err());
+        provide_input(request.id, RkyvSerializedValue::Boolean(true))?;
+        assert_eq!(receiver.await?, RkyvSerializedValue::Boolean(true));
+        assert_eq!(recorded_answer(&request.key), Some(RkyvSerializedValue::Boolean(true)));
+        assert!(provide_input(request.id, RkyvSerializedValue::Boolean(false)).is_err());
+        Ok(())
+    }
+}
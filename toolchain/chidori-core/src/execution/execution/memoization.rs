@@ -0,0 +1,292 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use crate::cells::CellTypes;
+use crate::execution::execution::execution_graph_store::PersistedOperationFnOutput;
+use crate::execution::primitives::operation::OperationFnOutput;
+use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+
+/// Directory the outputs of the notebook at the given path are memoized in, under the user's cache directory.
+pub fn notebook_cache_dir(notebook: &Path) -> PathBuf {
+    let notebook = notebook.canonicalize().unwrap_or_else(|_| notebook.to_path_buf());
+    let mut hasher = Sha1::new();
+    hasher.update(notebook.to_string_lossy().as_bytes());
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("chidori")
+        .join("memoized")
+        .join(format!("{:x}", hasher.finalize()))
+}
+
+fn strip_backing_file_references(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.remove("backing_file_reference");
+            map.values_mut().for_each(strip_backing_file_references);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_backing_file_references),
+        _ => {}
+    }
+}
+
+/// The definition of a cell, without where it is located in its file, which does not change what it evaluates to.
+fn cell_fingerprint(cell: &CellTypes) -> String {
+    let mut value = serde_json::to_value(cell).unwrap_or(Value::Null);
+    if let Value::Object(variant) = &mut value {
+        for parts in variant.values_mut() {
+            // Cells serialize along with their text range
+            if let Value::Array(parts) = parts {
+                parts.truncate(1);
+            }
+        }
+    }
+    strip_backing_file_references(&mut value);
+    value.to_string()
+}
+
+/// Hash a value independently of the order of its object keys and set items. Stream pointers and cells
+/// refer to state that is not part of the value, values containing them are not hashed, nor are errors,
+/// which are evaluated again rather than remembered.
+fn hash_value(hasher: &mut Sha1, value: &RkyvSerializedValue) -> bool {
+    match value {
+        RkyvSerializedValue::StreamPointer(_) | RkyvSerializedValue::Cell(_) | RkyvSerializedValue::Error(_) => return false,
+        RkyvSerializedValue::FunctionPointer(cell_idx, name) => {
+            hasher.update(b"f");
+            hasher.update(cell_idx.to_le_bytes());
+            hasher.update((name.len() as u64).to_le_bytes());
+            hasher.update(name.as_bytes());
+        }
+        RkyvSerializedValue::Set(items) => {
+            let mut digests = vec![];
+            for item in items {
+                let mut item_hasher = Sha1::new();
+                if !hash_value(&mut item_hasher, item) {
+                    return false;
+                }
+                digests.push(item_hasher.finalize().to_vec());
+            }
+            digests.sort();
+            hasher.update(b"e");
+            hasher.update((digests.len() as u64).to_le_bytes());
+            digests.iter().for_each(|digest| hasher.update(digest));
+        }
+        RkyvSerializedValue::Float(f) => {
+            hasher.update(b"d");
+            hasher.update(f.to_bits().to_le_bytes());
+        }
+        RkyvSerializedValue::Number(n) => {
+            hasher.update(b"n");
+            hasher.update(n.to_le_bytes());
+        }
+        RkyvSerializedValue::String(s) => {
+            hasher.update(b"s");
+            hasher.update((s.len() as u64).to_le_bytes());
+            hasher.update(s.as_bytes());
+        }
+        RkyvSerializedValue::Boolean(b) => {
+            hasher.update(b"b");
+            hasher.update([*b as u8]);
+        }
+        RkyvSerializedValue::Null => hasher.update(b"0"),
+        RkyvSerializedValue::Bytes(bytes) => {
+            hasher.update(b"y");
+            hasher.update((bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        }
+        RkyvSerializedValue::DateTime(micros) => {
+            hasher.update(b"t");
+            hasher.update(micros.to_le_bytes());
+        }
+        RkyvSerializedValue::Embedding(embedding) => {
+            hasher.update(b"v");
+            hasher.update((embedding.len() as u64).to_le_bytes());
+            embedding.iter().for_each(|f| hasher.update(f.to_bits().to_le_bytes()));
+        }
+        RkyvSerializedValue::Array(items) => {
+            hasher.update(b"a");
+            hasher.update((items.len() as u64).to_le_bytes());
+            for item in items {
+                if !hash_value(hasher, item) {
+                    return false;
+                }
+            }
+        }
+        RkyvSerializedValue::Object(fields) => {
+            let mut keys: Vec<&String> = fields.keys().collect();
+            keys.sort();
+            hasher.update(b"o");
+            hasher.update((keys.len() as u64).to_le_bytes());
+            for key in keys {
+                hasher.update((key.len() as u64).to_le_bytes());
+                hasher.update(key.as_bytes());
+                if !hash_value(hasher, &fields[key]) {
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Key an evaluation of a cell is memoized under, a hash of the cell's definition and the inputs prepared for it.
+/// Inputs that cannot be hashed have no key and are always evaluated.
+pub fn cache_key(cell: &CellTypes, inputs: &RkyvSerializedValue) -> Option<String> {
+    let mut hasher = Sha1::new();
+    let fingerprint = cell_fingerprint(cell);
+    hasher.update((fingerprint.len() as u64).to_le_bytes());
+    hasher.update(fingerprint.as_bytes());
+    if !hash_value(&mut hasher, inputs) {
+        return None;
+    }
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+fn sanitized_dir_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+}
+
+/// Directory the outputs of a cell are memoized in, named after the cell, or after its definition when it is unnamed.
+fn cell_dir_name(cell: &CellTypes) -> String {
+    match cell.name() {
+        Some(name) => sanitized_dir_name(name),
+        None => {
+            let mut hasher = Sha1::new();
+            hasher.update(cell_fingerprint(cell).as_bytes());
+            format!("unnamed-{:x}", hasher.finalize())
+        }
+    }
+}
+
+fn entry_path(dir: &Path, cell: &CellTypes, key: &str) -> PathBuf {
+    dir.join(cell_dir_name(cell)).join(format!("{}.json", key))
+}
+
+/// Outputs of pure cells memoized in a directory, one for each notebook.
+#[derive(Debug, Clone)]
+pub struct MemoizationCache {
+    dir: PathBuf,
+}
+
+impl MemoizationCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Cache of the notebook at the given path, under the user's cache directory.
+    pub fn for_notebook(notebook: &Path) -> Self {
+        Self::new(notebook_cache_dir(notebook))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The memoized output of evaluating the cell with the inputs the key was derived from.
+    pub fn lookup(&self, cell: &CellTypes, key: &str) -> Option<OperationFnOutput> {
+        let contents = std::fs::read(entry_path(&self.dir, cell, key)).ok()?;
+        let persisted: PersistedOperationFnOutput = serde_json::from_slice(&contents).ok()?;
+        Some(persisted.into())
+    }
+
+    /// Memoize a successful output of the cell. Model usage is not kept, reading the output back costs nothing.
+    pub fn store(&self, cell: &CellTypes, key: &str, output: &OperationFnOutput) -> anyhow::Result<()> {
+        if output.output.is_err() {
+            return Ok(());
+        }
+        let path = entry_path(&self.dir, cell, key);
+        let parent = path.parent().unwrap();
+        std::fs::create_dir_all(parent)?;
+        let mut persisted = PersistedOperationFnOutput::from(output);
+        persisted.usage.clear();
+        // Written to a file of its own and renamed into place, so that readers never see a partial entry
+        // and concurrent writers of the same entry do not write into each other's file
+        let mut partial = tempfile::NamedTempFile::new_in(parent)?;
+        partial.write_all(&serde_json::to_vec(&persisted)?)?;
+        partial.persist(&path)?;
+        Ok(())
+    }
+
+    /// Forget the memoized outputs of the cell with the given name.
+    pub fn invalidate_cell(&self, name: &str) -> anyhow::Result<()> {
+        let cell_dir = self.dir.join(sanitized_dir_name(name));
+        if cell_dir.exists() {
+            std::fs::remove_dir_all(cell_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Forget the memoized outputs of every cell of the notebook.
+    pub fn invalidate_notebook(&self) -> anyhow::Result<()> {
+        if self.dir.exists() {
+            std::fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::{python_cell, ExecutionPolicy, TextRange};
+    use crate::execution::primitives::operation::Purity;
+    use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+
+    fn pure() -> ExecutionPolicy {
+        ExecutionPolicy { purity: Some(Purity::Pure), ..Default::default() }
+    }
+
+    #[test]
+    fn test_cache_key_depends_on_source_and_inputs() {
+        let cell = python_cell("a", "y = x + 1", pure());
+        let inputs = RkyvObjectBuilder::new().insert_number("x", 1).insert_string("label", "one".to_string()).build();
+        let key = cache_key(&cell, &inputs).unwrap();
+
+        // Moving a cell within its file keeps its key
+        let CellTypes::Code(code_cell, _) = &cell else { unreachable!() };
+        let moved = CellTypes::Code(code_cell.clone(), TextRange { start: 5, end: 15 });
+        assert_eq!(cache_key(&moved, &inputs), Some(key.clone()));
+        assert_ne!(cache_key(&python_cell("a", "y = x + 2", pure()), &inputs), Some(key.clone()));
+        assert_ne!(cache_key(&cell, &RkyvObjectBuilder::new().insert_number("x", 2).build()), Some(key.clone()));
+        assert_eq!(cache_key(&cell, &RkyvSerializedValue::StreamPointer(0)), None);
+    }
+
+    #[test]
+    fn test_store_lookup_and_invalidate() -> anyhow::Result<()> {
+        let cache = MemoizationCache::new(std::env::temp_dir().join(format!("chidori-memoization-{}", uuid::Uuid::now_v7())));
+        let cell = python_cell("a", "y = 2", pure());
+        let key = cache_key(&cell, &RkyvSerializedValue::Null).unwrap();
+        assert!(cache.lookup(&cell, &key).is_none());
+
+        let mut output = OperationFnOutput::with_value(RkyvObjectBuilder::new().insert_number("y", 2).build());
+        output.stdout = vec!["computed".to_string()];
+        cache.store(&cell, &key, &output)?;
+        let cached = cache.lookup(&cell, &key).unwrap();
+        assert_eq!(cached.output, output.output);
+        assert_eq!(cached.stdout, output.stdout);
+
+        cache.invalidate_cell("a")?;
+        assert!(cache.lookup(&cell, &key).is_none());
+        cache.store(&cell, &key, &output)?;
+        cache.invalidate_notebook()?;
+        assert!(cache.lookup(&cell, &key).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_stores_of_an_entry() -> anyhow::Result<()> {
+        let cache = MemoizationCache::new(std::env::temp_dir().join(format!("chidori-memoization-{}", uuid::Uuid::now_v7())));
+        let cell = python_cell("a", "y = 2", pure());
+        let key = cache_key(&cell, &RkyvSerializedValue::Null).unwrap();
+        let output = OperationFnOutput::with_value(RkyvObjectBuilder::new().insert_number("y", 2).build());
+        std::thread::scope(|scope| {
+            let writers = (0..8).map(|_| scope.spawn(|| cache.store(&cell, &key, &output))).collect::<Vec<_>>();
+            writers.into_iter().try_for_each(|writer| writer.join().unwrap())
+        })?;
+        assert_eq!(cache.lookup(&cell, &key).unwrap().output, output.output);
+        // Only the entry itself is left behind
+        assert_eq!(std::fs::read_dir(cache.dir().join("a"))?.count(), 1);
+        cache.invalidate_notebook()?;
+        Ok(())
+    }
+}
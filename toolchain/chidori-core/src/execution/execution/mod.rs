@@ -1,5 +1,11 @@
 pub mod execution_graph;
 pub mod execution_state;
+pub mod execution_graph_store;
+pub mod human_input;
+pub mod memoization;
+pub mod streams;
+pub mod cancellation;
+pub mod validation;
 
 
 use crate::execution::primitives::identifiers::{OperationId};
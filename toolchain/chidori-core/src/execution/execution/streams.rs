@@ -0,0 +1,299 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use tokio::sync::Notify;
+use crate::execution::primitives::serialized_value::{ErrorValue, RkyvSerializedValue};
+
+/// Number of chunks a producer may get ahead of the slowest reader of its stream before it waits for them
+pub const STREAM_CAPACITY: usize = 16;
+
+/// Chunks a stream still holds, and whether it has ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamSnapshot {
+    /// Number of chunks every reader had passed, which are no longer held
+    pub dropped: usize,
+    pub chunks: Vec<RkyvSerializedValue>,
+    pub done: bool,
+    /// Error the producer failed with, ending the stream
+    pub error: Option<ErrorValue>,
+}
+
+#[derive(Default)]
+struct StreamBuffer {
+    /// Chunks produced that some reader has yet to pass, every chunk until the stream is first read
+    chunks: VecDeque<RkyvSerializedValue>,
+    /// Position in the stream of the first chunk held
+    base: usize,
+    done: bool,
+    error: Option<ErrorValue>,
+    /// Position of each open reader in the stream
+    readers: HashMap<u64, usize>,
+    /// Whether a reader has been opened, streams are kept until then for the cells evaluated after their producer
+    opened: bool,
+}
+
+impl StreamBuffer {
+    /// Position in the stream after the last chunk produced
+    fn end(&self) -> usize {
+        self.base + self.chunks.len()
+    }
+
+    /// Producers wait for the slowest reader, a stream nobody reads yet is buffered without bound
+    fn has_room(&self) -> bool {
+        match self.readers.values().min() {
+            Some(slowest) => self.end() - slowest < STREAM_CAPACITY,
+            None => true,
+        }
+    }
+
+    /// Drop the chunks every open reader has passed.
+    fn prune(&mut self) {
+        let Some(slowest) = self.readers.values().min().copied() else {
+            return;
+        };
+        while self.base < slowest && self.chunks.pop_front().is_some() {
+            self.base += 1;
+        }
+    }
+
+    /// A stream that has ended and been read by every reader opened for it is no longer needed.
+    fn is_finished(&self) -> bool {
+        self.done && self.opened && self.readers.is_empty()
+    }
+}
+
+struct Stream {
+    buffer: Mutex<StreamBuffer>,
+    /// Notified whenever a chunk is pushed or read, and when the stream ends
+    changed: Notify,
+}
+
+/// Streams referred to by `RkyvSerializedValue::StreamPointer`, which hold their id. Streams are removed
+/// once they have ended and their readers have closed.
+static STREAMS: Lazy<DashMap<u32, Arc<Stream>>> = Lazy::new(|| DashMap::new());
+
+static NEXT_STREAM_ID: AtomicU32 = AtomicU32::new(0);
+
+static NEXT_READER_ID: AtomicU64 = AtomicU64::new(0);
+
+fn get_stream(id: u32) -> anyhow::Result<Arc<Stream>> {
+    STREAMS.get(&id)
+        .map(|stream| stream.value().clone())
+        .ok_or_else(|| anyhow::anyhow!("Stream {} is not available, it may have been produced by an earlier run", id))
+}
+
+/// Start a stream, a cell producing it outputs `RkyvSerializedValue::StreamPointer` with the returned id.
+pub fn create_stream() -> u32 {
+    let id = NEXT_STREAM_ID.fetch_add(1, Ordering::SeqCst);
+    STREAMS.insert(id, Arc::new(Stream { buffer: Mutex::new(StreamBuffer::default()), changed: Notify::new() }));
+    id
+}
+
+/// Add a chunk to a stream, waiting while the producer is `STREAM_CAPACITY` chunks ahead of its slowest reader.
+pub async fn push_chunk(id: u32, chunk: RkyvSerializedValue) -> anyhow::Result<()> {
+    let stream = get_stream(id)?;
+    loop {
+        let changed = stream.changed.notified();
+        {
+            let mut buffer = stream.buffer.lock().unwrap();
+            if buffer.done {
+                return Err(anyhow::anyhow!("Stream {} has already ended", id));
+            }
+            if buffer.has_room() {
+                buffer.chunks.push(chunk);
+                drop(buffer);
+                stream.changed.notify_waiters();
+                return Ok(());
+            }
+        }
+        changed.await;
+    }
+}
+
+/// End a stream, readers receive the error once they have read every chunk before it.
+pub fn close_stream(id: u32, error: Option<ErrorValue>) {
+    let Ok(stream) = get_stream(id) else {
+        return;
+    };
+    let finished = {
+        let mut buffer = stream.buffer.lock().unwrap();
+        if buffer.done {
+            return;
+        }
+        buffer.done = true;
+        buffer.error = error;
+        buffer.is_finished()
+    };
+    if finished {
+        STREAMS.remove(&id);
+    }
+    stream.changed.notify_waiters();
+}
+
+/// Read a stream from the first chunk it holds, which is its first chunk unless the stream is already being
+/// read. Streams can be read by any number of readers, each of them sees every chunk from where it started.
+pub fn open_reader(id: u32) -> anyhow::Result<StreamReader> {
+    let stream = get_stream(id)?;
+    let reader_id = NEXT_READER_ID.fetch_add(1, Ordering::SeqCst);
+    let position = {
+        let mut buffer = stream.buffer.lock().unwrap();
+        let position = buffer.base;
+        buffer.readers.insert(reader_id, position);
+        buffer.opened = true;
+        position
+    };
+    Ok(StreamReader { stream_id: id, reader_id, stream, position, reported_error: false })
+}
+
+/// The chunks a stream still holds, which is how the debugger observes it.
+pub fn stream_snapshot(id: u32) -> Option<StreamSnapshot> {
+    let stream = get_stream(id).ok()?;
+    let buffer = stream.buffer.lock().unwrap();
+    Some(StreamSnapshot {
+        dropped: buffer.base,
+        chunks: buffer.chunks.iter().cloned().collect(),
+        done: buffer.done,
+        error: buffer.error.clone(),
+    })
+}
+
+/// Read a stream to its end.
+pub async fn collect_stream(id: u32) -> anyhow::Result<Vec<RkyvSerializedValue>> {
+    let mut reader = open_reader(id)?;
+    let mut chunks = vec![];
+    while let Some(chunk) = reader.next().await {
+        chunks.push(chunk.map_err(anyhow::Error::new)?);
+    }
+    Ok(chunks)
+}
+
+pub struct StreamReader {
+    stream_id: u32,
+    reader_id: u64,
+    stream: Arc<Stream>,
+    position: usize,
+    reported_error: bool,
+}
+
+impl StreamReader {
+    pub fn id(&self) -> u64 {
+        self.reader_id
+    }
+
+    pub fn stream_id(&self) -> u32 {
+        self.stream_id
+    }
+
+    /// The next chunk of the stream, waiting for the producer when the reader has caught up with it.
+    /// Resolves with `None` once the stream has ended, after the error it ended with if it failed.
+    pub async fn next(&mut self) -> Option<Result<RkyvSerializedValue, ErrorValue>> {
+        loop {
+            let changed = self.stream.changed.notified();
+            {
+                let mut buffer = self.stream.buffer.lock().unwrap();
+                if self.position < buffer.end() {
+                    let chunk = buffer.chunks[self.position - buffer.base].clone();
+                    self.position += 1;
+                    buffer.readers.insert(self.reader_id, self.position);
+                    buffer.prune();
+                    drop(buffer);
+                    // The producer may be waiting for this reader to catch up
+                    self.stream.changed.notify_waiters();
+                    return Some(Ok(chunk));
+                }
+                if buffer.done {
+                    if self.reported_error {
+                        return None;
+                    }
+                    self.reported_error = true;
+                    return buffer.error.clone().map(Err);
+                }
+            }
+            changed.await;
+        }
+    }
+}
+
+impl Drop for StreamReader {
+    fn drop(&mut self) {
+        let finished = {
+            let mut buffer = self.stream.buffer.lock().unwrap();
+            buffer.readers.remove(&self.reader_id);
+            buffer.prune();
+            buffer.is_finished()
+        };
+        if finished {
+            STREAMS.remove(&self.stream_id);
+        }
+        self.stream.changed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_producer_waits_for_readers() -> anyhow::Result<()> {
+        let id = create_stream();
+        let mut reader = open_reader(id)?;
+        for i in 0..STREAM_CAPACITY {
+            push_chunk(id, RkyvSerializedValue::Number(i as i64)).await?;
+        }
+        let blocked = tokio::time::timeout(Duration::from_millis(50), push_chunk(id, RkyvSerializedValue::Number(-1))).await;
+        assert!(blocked.is_err());
+
+        assert_eq!(reader.next().await, Some(Ok(RkyvSerializedValue::Number(0))));
+        let producer = tokio::spawn(async move {
+            push_chunk(id, RkyvSerializedValue::Number(STREAM_CAPACITY as i64)).await?;
+            close_stream(id, None);
+            anyhow::Ok(())
+        });
+        let mut read = vec![];
+        while let Some(chunk) = reader.next().await {
+            read.push(chunk.unwrap());
+        }
+        producer.await??;
+        assert_eq!(read.len(), STREAM_CAPACITY);
+        // Chunks the reader has passed are dropped, and the stream once the reader closes
+        let snapshot = stream_snapshot(id).unwrap();
+        assert_eq!((snapshot.dropped, snapshot.chunks.len()), (STREAM_CAPACITY + 1, 0));
+        drop(reader);
+        assert!(stream_snapshot(id).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unread_stream_does_not_block_its_producer() -> anyhow::Result<()> {
+        let id = create_stream();
+        let produced = (STREAM_CAPACITY * 2) as i64;
+        for i in 0..produced {
+            tokio::time::timeout(Duration::from_secs(1), push_chunk(id, RkyvSerializedValue::Number(i))).await??;
+        }
+        close_stream(id, None);
+
+        // Cells evaluated after the producer still read the whole stream
+        let chunks = collect_stream(id).await?;
+        assert_eq!(chunks, (0..produced).map(RkyvSerializedValue::Number).collect::<Vec<_>>());
+        assert!(stream_snapshot(id).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_readers_receive_the_error_a_stream_ended_with() -> anyhow::Result<()> {
+        let id = create_stream();
+        push_chunk(id, RkyvSerializedValue::String("partial".to_string())).await?;
+        close_stream(id, Some(ErrorValue::new("ValueError", "scrape failed")));
+
+        let mut reader = open_reader(id)?;
+        assert_eq!(reader.next().await, Some(Ok(RkyvSerializedValue::String("partial".to_string()))));
+        assert_eq!(reader.next().await, Some(Err(ErrorValue::new("ValueError", "scrape failed"))));
+        assert_eq!(reader.next().await, None);
+        assert!(collect_stream(id).await.is_err());
+        assert!(push_chunk(id, RkyvSerializedValue::Null).await.is_err());
+        Ok(())
+    }
+}
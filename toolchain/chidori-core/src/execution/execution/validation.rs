@@ -0,0 +1,341 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Range;
+use ariadne::{sources, Config, Label, ReportKind};
+use petgraph::algo::tarjan_scc;
+use petgraph::graphmap::DiGraphMap;
+use crate::cells::{CellTypes, LLMPromptCell};
+use crate::cells::code_cell::code_cell_report;
+use crate::execution::execution::ExecutionState;
+use crate::execution::primitives::identifiers::{DependencyReference, OperationId};
+use crate::execution::primitives::operation::OutputItemConfiguration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem with how the cells of a notebook are wired together, found before any of them run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// Several cells expose the same name, as a value or as a function
+    DuplicateExport { name: String, operation_ids: Vec<OperationId> },
+    /// Cells waiting on each other's required inputs, none of them can run first
+    Cycle { operation_ids: Vec<OperationId> },
+    /// A required input that no other cell exposes, the cell will never run
+    UnsatisfiableInput { operation_id: OperationId, name: String },
+    /// A value or function that no other cell depends on
+    UnusedOutput { operation_id: OperationId, name: String },
+}
+
+impl ValidationIssue {
+    /// Unused outputs are only suspicious, every other issue keeps the notebook from running as written.
+    pub fn severity(&self) -> Severity {
+        match self {
+            ValidationIssue::UnusedOutput { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+/// Issues found validating the operations of a state, errors first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(|issue| issue.severity() == Severity::Error)
+    }
+
+    /// Render the issues with ariadne, pointing at the names involved in the source of each cell.
+    pub fn render(&self, state: &ExecutionState) -> String {
+        let cell_sources: Vec<(String, String)> = sorted_operation_ids(state).into_iter()
+            .map(|id| (cell_label(state, &id), cell_source(state, &id)))
+            .collect();
+        let mut rendered = Vec::new();
+        for issue in &self.issues {
+            let kind = match issue.severity() {
+                Severity::Error => ReportKind::Error,
+                Severity::Warning => ReportKind::Warning,
+            };
+            let (message, labels) = describe(state, issue);
+            let (primary, span) = labels.first().map(|(span, _)| span.clone()).unwrap_or_default();
+            let mut report = ariadne::Report::build(kind, primary, span.start)
+                .with_config(Config::default().with_color(false))
+                .with_message(message);
+            for (span, label) in labels {
+                report = report.with_label(Label::new(span).with_message(label));
+            }
+            // Rendering only fails on io errors, which writing to a buffer does not produce
+            let _ = report.finish().write(sources(cell_sources.clone()), &mut rendered);
+        }
+        String::from_utf8_lossy(&rendered).to_string()
+    }
+}
+
+fn sorted_operation_ids(state: &ExecutionState) -> Vec<OperationId> {
+    let mut operation_ids: Vec<OperationId> = state.operation_by_id.keys().copied().collect();
+    operation_ids.sort();
+    operation_ids
+}
+
+/// Names exposed by the operations of a state, each with the operations exposing it ordered by their id.
+pub(crate) fn exports_by_name(state: &ExecutionState) -> BTreeMap<String, Vec<(OperationId, DependencyReference)>> {
+    let mut exports: BTreeMap<String, Vec<(OperationId, DependencyReference)>> = BTreeMap::new();
+    for operation_id in sorted_operation_ids(state) {
+        let output_signature = &state.operation_by_id[&operation_id].signature.output_signature;
+        for name in output_signature.globals.keys() {
+            exports.entry(name.clone()).or_default().push((operation_id, DependencyReference::Global(name.clone())));
+        }
+        for name in output_signature.functions.keys() {
+            exports.entry(name.clone()).or_default().push((operation_id, DependencyReference::FunctionInvocation(name.clone())));
+        }
+    }
+    exports
+}
+
+/// Check the operations of a state for duplicate exports, cycles, unsatisfiable inputs and unused outputs.
+pub fn validate(state: &ExecutionState) -> ValidationReport {
+    let exports = exports_by_name(state);
+    let mut issues = vec![];
+
+    for (name, exporters) in &exports {
+        let mut operation_ids: Vec<OperationId> = exporters.iter().map(|(operation_id, _)| *operation_id).collect();
+        operation_ids.dedup();
+        if operation_ids.len() > 1 {
+            issues.push(ValidationIssue::DuplicateExport { name: name.clone(), operation_ids });
+        }
+    }
+
+    // Edges run from the operation exposing a name to the operation requiring it
+    let mut graph: DiGraphMap<OperationId, ()> = DiGraphMap::new();
+    let mut used: HashSet<(OperationId, &str)> = HashSet::new();
+    for operation_id in sorted_operation_ids(state) {
+        graph.add_node(operation_id);
+        let mut inputs: Vec<_> = state.operation_by_id[&operation_id].signature.input_signature.globals.iter().collect();
+        inputs.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, configuration) in inputs {
+            let providers: Vec<OperationId> = exports.get(name).into_iter().flatten()
+                .map(|(provider, _)| *provider)
+                .filter(|provider| *provider != operation_id)
+                .collect();
+            if providers.is_empty() && configuration.is_required() {
+                issues.push(ValidationIssue::UnsatisfiableInput { operation_id, name: name.clone() });
+            }
+            for provider in providers {
+                used.insert((provider, name.as_str()));
+                if configuration.is_required() {
+                    graph.add_edge(provider, operation_id, ());
+                }
+            }
+        }
+    }
+
+    let mut cycles: Vec<Vec<OperationId>> = tarjan_scc(&graph).into_iter()
+        .filter(|component| component.len() > 1)
+        .map(|mut component| {
+            component.sort();
+            component
+        })
+        .collect();
+    cycles.sort();
+    issues.extend(cycles.into_iter().map(|operation_ids| ValidationIssue::Cycle { operation_ids }));
+
+    for operation_id in sorted_operation_ids(state) {
+        let output_signature = &state.operation_by_id[&operation_id].signature.output_signature;
+        let mut outputs: Vec<&String> = output_signature.globals.keys()
+            .chain(output_signature.functions.iter()
+                // Functions triggered by events run without another cell depending on them
+                .filter(|(_, configuration)| !matches!(configuration, OutputItemConfiguration::Function { trigger_on, .. } if !trigger_on.is_empty()))
+                .map(|(name, _)| name))
+            .collect();
+        outputs.sort();
+        outputs.dedup();
+        for name in outputs {
+            if !used.contains(&(operation_id, name.as_str())) {
+                issues.push(ValidationIssue::UnusedOutput { operation_id, name: name.clone() });
+            }
+        }
+    }
+
+    issues.sort_by_key(|issue| issue.severity());
+    ValidationReport { issues }
+}
+
+/// How rendered issues refer to the cell of an operation.
+fn cell_label(state: &ExecutionState, operation_id: &OperationId) -> String {
+    match state.operation_by_id.get(operation_id).and_then(|operation| operation.name.clone()) {
+        Some(name) => name,
+        None => format!("cell {}", operation_id),
+    }
+}
+
+/// Source of the cell of an operation as written in its fenced block, memory cells are written as their frontmatter.
+fn cell_source(state: &ExecutionState, operation_id: &OperationId) -> String {
+    let Some(cell) = state.cells_by_id.get(operation_id) else {
+        return String::new();
+    };
+    match cell {
+        CellTypes::Code(c, _) => c.source_code.clone(),
+        CellTypes::CodeGen(c, _) => c.complete_body.clone(),
+        CellTypes::Prompt(LLMPromptCell::Chat { complete_body, .. }, _) => complete_body.clone(),
+        CellTypes::Prompt(LLMPromptCell::Completion { req }, _) => req.clone(),
+        CellTypes::Template(c, _) => c.body.clone(),
+        CellTypes::Memory(c, _) => format!("embedding_fn: {}", c.embedding_function),
+        CellTypes::Webservice(c, _) => c.configuration.clone(),
+        CellTypes::Schedule(c, _) => c.configuration.clone(),
+    }
+}
+
+/// Whether an issue refers to a name a cell exposes or to a name it depends on.
+#[derive(Clone, Copy)]
+enum Occurrence {
+    Exposed,
+    Depended,
+}
+
+/// Span of a name in the source of a cell, or of its first line when the name cannot be found. Code cells
+/// are spanned where static analysis found the name, other cells at its first whole word occurrence.
+fn name_span(state: &ExecutionState, operation_id: &OperationId, name: &str, occurrence: Occurrence) -> (String, Range<usize>) {
+    let source = cell_source(state, operation_id);
+    let range = match state.cells_by_id.get(operation_id) {
+        Some(CellTypes::Code(cell, _)) => code_cell_report(cell).ok().and_then(|report| match occurrence {
+            Occurrence::Exposed => report.cell_exposed_values.get(name)
+                .map(|item| item.range.clone())
+                // Functions are ranged over their whole definition, the span is their name within it
+                .or_else(|| report.triggerable_functions.get(name).and_then(|function| function.range.find_identifier(&source, name))),
+            Occurrence::Depended => report.cell_depended_values.get(name).map(|item| item.range.clone()),
+        }),
+        _ => chidori_static_analysis::language::TextRange { start: 0, end: source.len() }.find_identifier(&source, name),
+    };
+    let range = range.map_or_else(|| 0..source.find('\n').unwrap_or(source.len()), |range| range.start..range.end);
+    (cell_label(state, operation_id), range)
+}
+
+/// Message of an issue, with labelled spans in the cells involved.
+fn describe(state: &ExecutionState, issue: &ValidationIssue) -> (String, Vec<((String, Range<usize>), String)>) {
+    match issue {
+        ValidationIssue::DuplicateExport { name, operation_ids } => (
+            format!("`{}` is exposed by {} cells, only one cell may expose a name", name, operation_ids.len()),
+            operation_ids.iter()
+                .map(|id| (name_span(state, id, name, Occurrence::Exposed), format!("exposed by {}", cell_label(state, id))))
+                .collect(),
+        ),
+        ValidationIssue::Cycle { operation_ids } => {
+            let labels: Vec<String> = operation_ids.iter().map(|id| cell_label(state, id)).collect();
+            let exports = exports_by_name(state);
+            let spans = operation_ids.iter()
+                .filter_map(|id| {
+                    // The input each cell of the cycle requires from another cell of the cycle
+                    let mut inputs: Vec<&String> = state.operation_by_id[id].signature.input_signature.globals.iter()
+                        .filter(|(_, configuration)| configuration.is_required())
+                        .map(|(name, _)| name)
+                        .collect();
+                    inputs.sort();
+                    inputs.into_iter().find_map(|name| {
+                        exports.get(name).into_iter().flatten()
+                            .find(|(provider, _)| provider != id && operation_ids.contains(provider))
+                            .map(|(provider, _)| (name_span(state, id, name, Occurrence::Depended), format!("requires `{}` from {}", name, cell_label(state, provider))))
+                    })
+                })
+                .collect();
+            (format!("{} depend on each other, none of them can run first", labels.join(", ")), spans)
+        }
+        ValidationIssue::UnsatisfiableInput { operation_id, name } => (
+            format!("{} requires `{}`, which no other cell exposes", cell_label(state, operation_id), name),
+            vec![(name_span(state, operation_id, name, Occurrence::Depended), "never provided".to_string())],
+        ),
+        ValidationIssue::UnusedOutput { operation_id, name } => (
+            format!("`{}` exposed by {} is not used by any other cell", name, cell_label(state, operation_id)),
+            vec![(name_span(state, operation_id, name, Occurrence::Exposed), "never used".to_string())],
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+    use crate::cells::python_cell;
+
+    fn state_with_cells(cells: Vec<CellTypes>) -> (ExecutionState, Vec<OperationId>) {
+        let mut state = ExecutionState::new_with_random_id();
+        let mut operation_ids = vec![];
+        for cell in cells {
+            let op = state.get_operation_from_cell_type(&cell).unwrap();
+            let (operation_id, next_state) = state.upsert_operation(op, Uuid::now_v7()).unwrap();
+            operation_ids.push(operation_id);
+            state = next_state;
+        }
+        (state, operation_ids)
+    }
+
+    #[test]
+    fn test_valid_notebook_only_warns_about_unused_outputs() {
+        let (state, ids) = state_with_cells(vec![
+            python_cell("a", "x = 1", Default::default()),
+            python_cell("b", "y = x + 1", Default::default()),
+        ]);
+        let report = validate(&state);
+        assert!(!report.has_errors());
+        assert_eq!(report.issues, vec![ValidationIssue::UnusedOutput { operation_id: ids[1], name: "y".to_string() }]);
+        assert_eq!(state.validation, report);
+    }
+
+    #[test]
+    fn test_duplicate_exports_depend_on_every_exporter() {
+        let (state, ids) = state_with_cells(vec![
+            python_cell("a", "x = 1", Default::default()),
+            python_cell("b", "x = 2", Default::default()),
+            python_cell("c", "y = x + 1", Default::default()),
+        ]);
+        let report = validate(&state);
+        assert_eq!(report.errors().collect::<Vec<_>>(), vec![
+            &ValidationIssue::DuplicateExport { name: "x".to_string(), operation_ids: vec![ids[0], ids[1]] },
+        ]);
+        let depends_on: Vec<OperationId> = state.dependency_map[&ids[2]].iter().map(|(id, _)| *id).collect();
+        assert_eq!(depends_on, vec![ids[0], ids[1]]);
+
+        let rendered = report.render(&state);
+        assert!(rendered.contains("`x` is exposed by 2 cells"));
+        assert!(rendered.contains("exposed by a") && rendered.contains("exposed by b"));
+    }
+
+    #[test]
+    fn test_cycles_and_unsatisfiable_inputs_are_errors() {
+        let (state, ids) = state_with_cells(vec![
+            python_cell("a", "x = y + 1", Default::default()),
+            python_cell("b", "y = x + 1", Default::default()),
+            python_cell("c", "z = missing + 1", Default::default()),
+        ]);
+        let report = validate(&state);
+        assert_eq!(report.errors().collect::<Vec<_>>(), vec![
+            &ValidationIssue::UnsatisfiableInput { operation_id: ids[2], name: "missing".to_string() },
+            &ValidationIssue::Cycle { operation_ids: vec![ids[0], ids[1]] },
+        ]);
+        let rendered = report.render(&state);
+        assert!(rendered.contains("a, b depend on each other"));
+        assert!(rendered.contains("requires `y` from b"));
+        assert!(rendered.contains("c requires `missing`"));
+    }
+
+    #[test]
+    fn test_names_are_spanned_where_static_analysis_found_them() {
+        let (state, ids) = state_with_cells(vec![
+            python_cell("a", "# doubles x\ny = x * 2", Default::default()),
+        ]);
+        // The mention of `x` in the comment is not a reference to it
+        assert_eq!(name_span(&state, &ids[0], "x", Occurrence::Depended), ("a".to_string(), 16..17));
+        assert_eq!(name_span(&state, &ids[0], "y", Occurrence::Exposed), ("a".to_string(), 12..13));
+    }
+}
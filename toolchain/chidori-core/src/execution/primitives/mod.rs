@@ -1,3 +1,4 @@
 pub mod identifiers;
 pub mod operation;
 pub mod serialized_value;
+pub mod usage;
@@ -1,6 +1,7 @@
 use crate::execution::primitives::serialized_value::RkyvSerializedValue;
 
 use log::warn;
+use rkyv::Archive;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::future::Future;
@@ -16,19 +17,128 @@ use crate::execution::execution::execution_graph::ExecutionNodeId;
 use crate::execution::execution::execution_state::{ExecutionStateErrors, OperationInputs};
 use crate::execution::execution::ExecutionState;
 use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::usage::ModelUsage;
+use chidori_static_analysis::language::{ArgumentDefault, ArgumentType, ReportArgument};
 // args, kwargs, locals and their configurations
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum InputType {
     String,
+    Int,
+    Float,
+    Bool,
+    Null,
+    Any,
+    List(Box<InputType>),
+    Object,
     Function,
+    Optional(Box<InputType>),
+    Union(Vec<InputType>),
+}
+
+impl InputType {
+    /// Whether a value may be passed where this type is expected. Integers are accepted as floats,
+    /// and streams as lists since they are consumed as iterators.
+    pub fn accepts(&self, value: &RkyvSerializedValue) -> bool {
+        match (self, value) {
+            (InputType::Any, _) => true,
+            (InputType::Optional(_), RkyvSerializedValue::Null) => true,
+            (InputType::Optional(ty), value) => ty.accepts(value),
+            (InputType::Union(tys), value) => tys.iter().any(|ty| ty.accepts(value)),
+            (InputType::String, RkyvSerializedValue::String(_)) => true,
+            (InputType::Int, RkyvSerializedValue::Number(_)) => true,
+            (InputType::Float, RkyvSerializedValue::Float(_) | RkyvSerializedValue::Number(_)) => true,
+            (InputType::Bool, RkyvSerializedValue::Boolean(_)) => true,
+            (InputType::Null, RkyvSerializedValue::Null) => true,
+            (InputType::List(ty), RkyvSerializedValue::Array(items)) => items.iter().all(|item| ty.accepts(item)),
+            (InputType::List(ty), RkyvSerializedValue::Set(items)) => items.iter().all(|item| ty.accepts(item)),
+            (InputType::List(ty), RkyvSerializedValue::Embedding(_)) => ty.accepts(&RkyvSerializedValue::Float(0.0)),
+            (InputType::List(_), RkyvSerializedValue::StreamPointer(_)) => true,
+            (InputType::Object, RkyvSerializedValue::Object(_)) => true,
+            (InputType::Function, RkyvSerializedValue::FunctionPointer(_, _) | RkyvSerializedValue::Cell(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for InputType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputType::String => write!(f, "str"),
+            InputType::Int => write!(f, "int"),
+            InputType::Float => write!(f, "float"),
+            InputType::Bool => write!(f, "bool"),
+            InputType::Null => write!(f, "None"),
+            InputType::Any => write!(f, "Any"),
+            InputType::List(ty) => write!(f, "list[{}]", ty),
+            InputType::Object => write!(f, "dict"),
+            InputType::Function => write!(f, "Callable"),
+            InputType::Optional(ty) => write!(f, "{} | None", ty),
+            InputType::Union(tys) => {
+                write!(f, "{}", tys.iter().map(|ty| ty.to_string()).collect::<Vec<_>>().join(" | "))
+            }
+        }
+    }
+}
+
+impl From<&ArgumentType> for InputType {
+    fn from(ty: &ArgumentType) -> Self {
+        match ty {
+            ArgumentType::String => InputType::String,
+            ArgumentType::Int => InputType::Int,
+            ArgumentType::Float => InputType::Float,
+            ArgumentType::Bool => InputType::Bool,
+            ArgumentType::Null => InputType::Null,
+            ArgumentType::Any => InputType::Any,
+            ArgumentType::List(ty) => InputType::List(Box::new(InputType::from(&**ty))),
+            ArgumentType::Object => InputType::Object,
+            ArgumentType::Function => InputType::Function,
+            ArgumentType::Optional(ty) => InputType::Optional(Box::new(InputType::from(&**ty))),
+            ArgumentType::Union(tys) => InputType::Union(tys.iter().map(InputType::from).collect()),
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct InputItemConfiguration {
-    // TODO: should represent object and vec types
     pub ty: Option<InputType>,
     pub default: Option<RkyvSerializedValue>,
+    /// Set when the input has a default that is only known once the code runs, such as a default evaluated
+    /// when the function is called, inputs with one are not required
+    pub computed_default: bool,
+    /// Set on globals of cells with star imports, which provide the value unless another cell exposes it
+    pub may_be_star_imported: bool,
+    /// Description of the input, such as from the docstring of the function it is an argument of
+    pub description: Option<String>,
+    /// Index of a function argument that may be passed positionally
+    pub position: Option<usize>,
+}
+
+impl InputItemConfiguration {
+    pub fn is_required(&self) -> bool {
+        self.default.is_none() && !self.computed_default
+    }
+}
+
+impl From<&ReportArgument> for InputItemConfiguration {
+    fn from(argument: &ReportArgument) -> Self {
+        let default = match &argument.default {
+            None | Some(ArgumentDefault::Expression) => None,
+            Some(ArgumentDefault::Null) => Some(RkyvSerializedValue::Null),
+            Some(ArgumentDefault::Bool(b)) => Some(RkyvSerializedValue::Boolean(*b)),
+            Some(ArgumentDefault::Int(i)) => Some(RkyvSerializedValue::Number(*i)),
+            Some(ArgumentDefault::Float(f)) => Some(RkyvSerializedValue::Float(*f)),
+            Some(ArgumentDefault::String(s)) => Some(RkyvSerializedValue::String(s.clone())),
+        };
+        Self {
+            ty: argument.ty.as_ref().map(InputType::from),
+            default,
+            computed_default: matches!(argument.default, Some(ArgumentDefault::Expression)),
+            may_be_star_imported: false,
+            description: argument.description.clone(),
+            position: argument.position,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -76,21 +186,21 @@ impl InputSignature {
 
         // Validate args
         for (key, config) in &self.args {
-            if config.default.is_none() && !args.contains_key(key) {
+            if config.is_required() && !args.contains_key(key) {
                 missing_keys.insert(format!("args: {}", key));
             }
         }
 
         // Validate kwargs
         for (key, config) in &self.kwargs {
-            if config.default.is_none() && !kwargs.contains_key(key) {
+            if config.is_required() && !kwargs.contains_key(key) {
                 missing_keys.insert(format!("kwargs: {}", key));
             }
         }
 
         // Validate globals
         for (key, config) in &self.globals {
-            if config.default.is_none()
+            if config.is_required()
                 && (!globals.contains_key(key) && !functions.contains_key(key))
             {
                 missing_keys.insert(format!("globals or functions: {}", key));
@@ -106,6 +216,45 @@ impl InputSignature {
         }
     }
 
+    /// Check the arguments of a function invocation against the declared types of the function's arguments.
+    /// Positional arguments are matched by their index, keyword arguments by their name.
+    pub fn check_argument_types(&self, payload: &RkyvSerializedValue) -> Result<(), String> {
+        let RkyvSerializedValue::Object(payload) = payload else {
+            return Ok(());
+        };
+        let mut mismatches = vec![];
+        let mut check = |name: &str, config: &InputItemConfiguration, value: &RkyvSerializedValue| {
+            if let Some(ty) = &config.ty {
+                if !ty.accepts(value) {
+                    mismatches.push(format!("argument {:?} expected {}, got {}", name, ty, value_type_name(value)));
+                }
+            }
+        };
+        if let Some(RkyvSerializedValue::Object(args)) = payload.get("args") {
+            for (key, value) in args {
+                let Ok(position) = key.parse::<usize>() else {
+                    continue;
+                };
+                if let Some((name, config)) = self.args.iter().find(|(_, config)| config.position == Some(position)) {
+                    check(name, config, value);
+                }
+            }
+        }
+        if let Some(RkyvSerializedValue::Object(kwargs)) = payload.get("kwargs") {
+            for (key, value) in kwargs {
+                if let Some(config) = self.args.get(key).or_else(|| self.kwargs.get(key)) {
+                    check(key, config, value);
+                }
+            }
+        }
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            mismatches.sort();
+            Err(mismatches.join(", "))
+        }
+    }
+
     #[tracing::instrument]
     pub fn prepopulate_defaults(
         &self,
@@ -139,6 +288,26 @@ impl InputSignature {
     }
 }
 
+/// Name of the type of a value, as reported when it does not match the type of an argument
+fn value_type_name(value: &RkyvSerializedValue) -> &'static str {
+    match value {
+        RkyvSerializedValue::StreamPointer(_) => "stream",
+        RkyvSerializedValue::FunctionPointer(_, _) | RkyvSerializedValue::Cell(_) => "function",
+        RkyvSerializedValue::Error(_) => "error",
+        RkyvSerializedValue::Set(_) => "set",
+        RkyvSerializedValue::Float(_) => "float",
+        RkyvSerializedValue::Number(_) => "int",
+        RkyvSerializedValue::String(_) => "str",
+        RkyvSerializedValue::Boolean(_) => "bool",
+        RkyvSerializedValue::Null => "None",
+        RkyvSerializedValue::Bytes(_) => "bytes",
+        RkyvSerializedValue::DateTime(_) => "datetime",
+        RkyvSerializedValue::Embedding(_) => "embedding",
+        RkyvSerializedValue::Array(_) => "list",
+        RkyvSerializedValue::Object(_) => "dict",
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum TriggerConfiguration {
     OnChange,
@@ -150,6 +319,8 @@ pub enum TriggerConfiguration {
 pub enum OutputItemConfiguration {
     Function {
         input_signature: InputSignature,
+        /// Summary of what the function does, such as from its docstring
+        description: Option<String>,
         emit_event: Vec<String>,
         trigger_on: Vec<String>,
     },
@@ -207,14 +378,29 @@ impl Signature {
     }
 }
 
-#[derive(PartialEq, Debug, Clone)]
-enum Purity {
+/// Whether an operation's output depends only on its source and inputs. Pure operations
+/// are memoized, evaluating them again with the same inputs reads their output from the cache.
+#[derive(
+    Archive,
+    serde::Serialize,
+    serde::Deserialize,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    PartialEq,
+    Debug,
+    Clone,
+)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug))]
+#[serde(rename_all = "snake_case")]
+pub enum Purity {
     Pure,
     Impure,
 }
 
+/// Whether an operation holds state that changes between evaluations, mutable operations are never memoized.
 #[derive(PartialEq, Debug, Clone)]
-enum Mutability {
+pub enum Mutability {
     Mutable,
     Immutable,
 }
@@ -260,7 +446,9 @@ pub struct OperationFnOutput {
     pub execution_state: Option<ExecutionState>,
     pub output: Result<RkyvSerializedValue, ExecutionStateErrors>,
     pub stdout: Vec<String>,
-    pub stderr: Vec<String>
+    pub stderr: Vec<String>,
+    /// Model requests made while producing this output
+    pub usage: Vec<ModelUsage>,
 }
 
 impl OperationFnOutput {
@@ -270,7 +458,8 @@ impl OperationFnOutput {
             execution_state: None,
             output: Ok(value),
             stdout: Vec::new(),
-            stderr: Vec::new()
+            stderr: Vec::new(),
+            usage: Vec::new(),
         }
     }
 }
@@ -346,6 +535,7 @@ impl Default for OperationNode {
                 language: SupportedLanguage::PyO3,
                 source_code: "".to_string(),
                 function_invocation: None,
+                policy: Default::default(),
             }, TextRange::default()),
             signature: Signature::new(),
             // operation: Box::new(|_, x, _, _| async move { Ok(OperationFnOutput::with_value(x)) }.boxed()),
@@ -370,6 +560,11 @@ impl OperationNode {
         node
     }
 
+    /// Signature of the values the operation depends on and provides to the rest of the notebook.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
     #[tracing::instrument]
     pub(crate) fn execute(
         &self,
@@ -398,6 +593,19 @@ impl OperationNode {
             CellTypes::Template(crate::cells::TemplateCell {body, ..}, _) => {
                 crate::cells::template_cell::template_cell_exec(body.clone())
             }
+            CellTypes::Memory(memory_cell, _) => {
+                match memory_cell.provider {
+                    crate::cells::SupportedMemoryProviders::InMemory => {
+                        crate::cells::memory_cell::memory_cell_exec_in_memory(memory_cell.clone())
+                    }
+                }
+            }
+            CellTypes::Webservice(webservice_cell, _) => {
+                crate::cells::webservice_cell::webservice_cell_exec(webservice_cell.clone())
+            }
+            CellTypes::Schedule(schedule_cell, _) => {
+                crate::cells::schedule_cell::schedule_cell_exec(schedule_cell.clone())
+            }
         };
 
         /// Receiver that we pass to the exec for it to capture oneshot RPC communication
@@ -448,6 +656,7 @@ mod tests {
                 language: SupportedLanguage::PyO3,
                 source_code: "".to_string(),
                 function_invocation: None,
+                policy: Default::default(),
             }, TextRange::default()),
             signature: Signature::new(),
             // operation: Box::new(|_, p: RkyvSerializedValue, _, async_rpccommunication: Option<AsyncRPCCommunication>| async move {
@@ -1,4 +1,4 @@
-use crate::cells::{CellTypes, LLMPromptCell};
+use crate::cells::{CellTypes, LLMPromptCell, TextRange};
 use rkyv::{
     archived_root, check_archived_root,
     ser::{serializers::AllocSerializer, Serializer},
@@ -8,6 +8,7 @@ use serde::{Deserialize as SerdeDeserialize, Serialize as SerdeSerialize};
 use chidori_prompt_format::serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::hash::Hasher;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug, Clone)]
 #[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
@@ -24,8 +25,9 @@ pub enum RkyvSerializedValue {
 
     Cell(CellTypes),
 
-    // TODO: add Embedding
-    // TODO: add Error
+    /// A failure raised by a cell, passed to the cells depending on it
+    Error(ErrorValue),
+
     Set(
 
         #[omit_bounds]
@@ -33,12 +35,20 @@ pub enum RkyvSerializedValue {
         HashSet<RkyvSerializedValue>
     ),
 
-    Float(f32),
-    Number(i32),
+    Float(f64),
+    Number(i64),
     String(String),
     Boolean(bool),
     Null,
 
+    Bytes(Vec<u8>),
+
+    /// Microseconds since the Unix epoch, in UTC
+    DateTime(i64),
+
+    /// A vector embedding, kept as single precision floats rather than an array of values
+    Embedding(Vec<f32>),
+
     Array(
         #[omit_bounds]
         #[archive_attr(omit_bounds)]
@@ -52,6 +62,76 @@ pub enum RkyvSerializedValue {
     ),
 }
 
+/// Key marking the JSON representation of an ErrorValue, which is an object holding the error under it
+pub const ERROR_VALUE_JSON_KEY: &str = "__chidori_error__";
+
+/// Key marking the JSON representation of bytes, an object holding them base64 encoded
+pub const BYTES_JSON_KEY: &str = "__chidori_bytes__";
+
+/// Key marking the JSON representation of a date time, an object holding it as an RFC 3339 string
+pub const DATETIME_JSON_KEY: &str = "__chidori_datetime__";
+
+/// Key marking the JSON representation of an embedding, an object holding it as an array of numbers
+pub const EMBEDDING_JSON_KEY: &str = "__chidori_embedding__";
+
+/// Key marking the JSON representation of a stream pointer, an object holding the id of the stream
+pub const STREAM_JSON_KEY: &str = "__chidori_stream__";
+
+/// A failure raised while evaluating a cell, as Python and JavaScript cells see it: the exception's type,
+/// message and traceback, along with the cell it was raised in and the range of that cell's source.
+#[derive(
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    SerdeSerialize,
+    SerdeDeserialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd,
+)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug))]
+pub struct ErrorValue {
+    /// Name of the exception, such as `ValueError` or `TypeError`
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub message: String,
+    pub traceback: Option<String>,
+    /// Name of the cell the error was raised in
+    pub cell: Option<String>,
+    pub range: Option<TextRange>,
+}
+
+impl ErrorValue {
+    pub fn new(ty: impl Into<String>, message: impl Into<String>) -> Self {
+        ErrorValue {
+            ty: ty.into(),
+            message: message.into(),
+            traceback: None,
+            cell: None,
+            range: None,
+        }
+    }
+
+    /// Attribute the error to the given cell, unless it was already attributed to the cell that raised it.
+    pub fn raised_in(mut self, cell: &CellTypes) -> Self {
+        if self.cell.is_none() && self.range.is_none() {
+            self.cell = cell.name().clone();
+            self.range = Some(cell.text_range().clone());
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for ErrorValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.ty, self.message)
+    }
+}
+
+impl std::error::Error for ErrorValue {}
+
 pub struct RkyvObjectBuilder {
     object: HashMap<String, RkyvSerializedValue>,
 }
@@ -69,7 +149,7 @@ impl RkyvObjectBuilder {
         self
     }
 
-    pub fn insert_number(mut self, key: &str, value: i32) -> Self {
+    pub fn insert_number(mut self, key: &str, value: i64) -> Self {
         self.object
             .insert(key.to_string(), RkyvSerializedValue::Number(value));
         self
@@ -125,6 +205,12 @@ impl std::cmp::PartialEq for RkyvSerializedValue {
                     _ => unreachable!()
                 }
             }
+            RkyvSerializedValue::Error(a) => {
+                match other {
+                    RkyvSerializedValue::Error(aa) => { a == aa }
+                    _ => unreachable!()
+                }
+            }
             RkyvSerializedValue::Set(a) => {
                 match other {
                     RkyvSerializedValue::Set(aa) => { a == aa }
@@ -161,6 +247,24 @@ impl std::cmp::PartialEq for RkyvSerializedValue {
                     _ => unreachable!()
                 }
             }
+            RkyvSerializedValue::Bytes(a) => {
+                match other {
+                    RkyvSerializedValue::Bytes(aa) => { a == aa }
+                    _ => unreachable!()
+                }
+            }
+            RkyvSerializedValue::DateTime(a) => {
+                match other {
+                    RkyvSerializedValue::DateTime(aa) => { a == aa }
+                    _ => unreachable!()
+                }
+            }
+            RkyvSerializedValue::Embedding(a) => {
+                match other {
+                    RkyvSerializedValue::Embedding(aa) => { a == aa }
+                    _ => unreachable!()
+                }
+            }
             RkyvSerializedValue::Array(a) => {
                 match other {
                     RkyvSerializedValue::Array(aa) => {
@@ -194,6 +298,11 @@ impl std::hash::Hash for RkyvSerializedValue {
             RkyvSerializedValue::Cell(cell_type) => {
                 unimplemented!();
             }
+            RkyvSerializedValue::Error(error) => {
+                error.ty.hash(state);
+                error.message.hash(state);
+                error.cell.hash(state);
+            }
             RkyvSerializedValue::Set(set) => {
                 for item in set {
                     item.hash(state);
@@ -214,6 +323,17 @@ impl std::hash::Hash for RkyvSerializedValue {
             RkyvSerializedValue::Null => {
                 0.hash(state); // Hash a constant for Null
             }
+            RkyvSerializedValue::Bytes(bytes) => {
+                bytes.hash(state);
+            }
+            RkyvSerializedValue::DateTime(micros) => {
+                micros.hash(state);
+            }
+            RkyvSerializedValue::Embedding(embedding) => {
+                for f in embedding {
+                    f.to_bits().hash(state);
+                }
+            }
             RkyvSerializedValue::Array(arr) => {
                 for item in arr {
                     item.hash(state);
@@ -249,11 +369,15 @@ impl std::fmt::Display for RkyvSerializedValue {
             RkyvSerializedValue::StreamPointer(_) => write!(f, "StreamPointer"),
             RkyvSerializedValue::FunctionPointer(_, _) => write!(f, "FunctionPointer"),
             RkyvSerializedValue::Cell(_) => write!(f, "Cell"),
+            RkyvSerializedValue::Error(error) => write!(f, "Error({})", error),
             RkyvSerializedValue::Float(_) => write!(f, "Float"),
             RkyvSerializedValue::Number(_) => write!(f, "Number"),
             RkyvSerializedValue::String(_) => write!(f, "String"),
             RkyvSerializedValue::Boolean(_) => write!(f, "Boolean"),
             RkyvSerializedValue::Null => write!(f, "Null"),
+            RkyvSerializedValue::Bytes(_) => write!(f, "Bytes"),
+            RkyvSerializedValue::DateTime(_) => write!(f, "DateTime"),
+            RkyvSerializedValue::Embedding(embedding) => write!(f, "Embedding[{}]", embedding.len()),
             RkyvSerializedValue::Array(vec) => {
                 let shapes: Vec<String> = vec.iter().map(|item| item.to_string()).collect();
                 write!(f, "Array[{}]", shapes.join(", "))
@@ -295,10 +419,29 @@ pub fn deserialize_from_buf(v: &[u8]) -> RkyvSerializedValue {
     arg1
 }
 
+fn marked_json_value(key: &str, value: Value) -> Value {
+    let mut marked = chidori_prompt_format::serde_json::Map::new();
+    marked.insert(key.to_string(), value);
+    Value::Object(marked)
+}
+
+/// RFC 3339 form of a date time, with as many fractional digits as are needed to keep its microseconds.
+pub fn datetime_to_rfc3339(micros: i64) -> Option<String> {
+    chrono::DateTime::<chrono::Utc>::from_timestamp(micros.div_euclid(1_000_000), (micros.rem_euclid(1_000_000) * 1000) as u32)
+        .map(|datetime| datetime.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true))
+}
+
+pub fn datetime_from_rfc3339(s: &str) -> Option<i64> {
+    chrono::DateTime::parse_from_rfc3339(s).ok().map(|datetime| datetime.timestamp_micros())
+}
+
 pub fn serialized_value_to_json_value(v: &RkyvSerializedValue) -> chidori_prompt_format::serde_json::Value {
     match &v {
-        RkyvSerializedValue::Float(f) => Value::Number(f.to_string().parse().unwrap()),
-        RkyvSerializedValue::Number(n) => Value::Number(n.to_string().parse().unwrap()),
+        // JSON has no representation of NaN or infinities
+        RkyvSerializedValue::Float(f) => chidori_prompt_format::serde_json::Number::from_f64(*f)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        RkyvSerializedValue::Number(n) => Value::Number((*n).into()),
         RkyvSerializedValue::String(s) => Value::String(s.to_string()),
         RkyvSerializedValue::Boolean(b) => Value::Bool(*b),
         RkyvSerializedValue::Array(a) => Value::Array(
@@ -312,9 +455,22 @@ pub fn serialized_value_to_json_value(v: &RkyvSerializedValue) -> chidori_prompt
                 .collect(),
         ),
         RkyvSerializedValue::FunctionPointer(_, _) => Value::Null,
-        RkyvSerializedValue::StreamPointer(_) => Value::Null,
+        RkyvSerializedValue::StreamPointer(id) => marked_json_value(STREAM_JSON_KEY, Value::Number((*id).into())),
         RkyvSerializedValue::Cell(_) => Value::Null,
+        RkyvSerializedValue::Error(error) => {
+            marked_json_value(ERROR_VALUE_JSON_KEY, chidori_prompt_format::serde_json::to_value(error).unwrap_or(Value::Null))
+        }
         RkyvSerializedValue::Null => Value::Null,
+        RkyvSerializedValue::Bytes(bytes) => marked_json_value(BYTES_JSON_KEY, Value::String(BASE64.encode(bytes))),
+        RkyvSerializedValue::DateTime(micros) => {
+            marked_json_value(DATETIME_JSON_KEY, datetime_to_rfc3339(*micros).map(Value::String).unwrap_or(Value::Null))
+        }
+        RkyvSerializedValue::Embedding(embedding) => marked_json_value(
+            EMBEDDING_JSON_KEY,
+            Value::Array(embedding.iter().map(|f| {
+                chidori_prompt_format::serde_json::Number::from_f64(*f as f64).map(Value::Number).unwrap_or(Value::Null)
+            }).collect()),
+        ),
         RkyvSerializedValue::Set(a) => {
             a.iter()
                 .map(|v| serialized_value_to_json_value(v))
@@ -327,12 +483,11 @@ pub fn serialized_value_to_json_value(v: &RkyvSerializedValue) -> chidori_prompt
 pub fn json_value_to_serialized_value(jval: &Value) -> RkyvSerializedValue {
     match jval {
         Value::Number(n) => {
-            if n.is_i64() {
-                RkyvSerializedValue::Number(n.as_i64().unwrap() as i32)
-            } else if n.is_f64() {
-                RkyvSerializedValue::Float(n.as_f64().unwrap() as f32)
+            if let Some(n) = n.as_i64() {
+                RkyvSerializedValue::Number(n)
             } else {
-                panic!("Invalid number value")
+                // Integers beyond the range of an i64 are kept as closely as a float allows
+                RkyvSerializedValue::Float(n.as_f64().unwrap())
             }
         }
         Value::String(s) => RkyvSerializedValue::String(s.clone()),
@@ -342,18 +497,55 @@ pub fn json_value_to_serialized_value(jval: &Value) -> RkyvSerializedValue {
                 .map(|v| json_value_to_serialized_value(v))
                 .collect(),
         ),
-        Value::Object(o) => {
-            let mut map = HashMap::new();
-            for (k, v) in o {
-                map.insert(k.clone(), json_value_to_serialized_value(v));
+        Value::Object(o) if o.len() == 1 && o.contains_key(ERROR_VALUE_JSON_KEY) => {
+            match chidori_prompt_format::serde_json::from_value::<ErrorValue>(o[ERROR_VALUE_JSON_KEY].clone()) {
+                Ok(error) => RkyvSerializedValue::Error(error),
+                Err(_) => RkyvSerializedValue::Object(HashMap::from([
+                    (ERROR_VALUE_JSON_KEY.to_string(), json_value_to_serialized_value(&o[ERROR_VALUE_JSON_KEY]))
+                ])),
+            }
+        }
+        Value::Object(o) if o.len() == 1 && o.contains_key(BYTES_JSON_KEY) => {
+            match o[BYTES_JSON_KEY].as_str().and_then(|encoded| BASE64.decode(encoded).ok()) {
+                Some(bytes) => RkyvSerializedValue::Bytes(bytes),
+                None => unmarked_json_object(o),
+            }
+        }
+        Value::Object(o) if o.len() == 1 && o.contains_key(DATETIME_JSON_KEY) => {
+            match o[DATETIME_JSON_KEY].as_str().and_then(datetime_from_rfc3339) {
+                Some(micros) => RkyvSerializedValue::DateTime(micros),
+                None => unmarked_json_object(o),
+            }
+        }
+        Value::Object(o) if o.len() == 1 && o.contains_key(EMBEDDING_JSON_KEY) => {
+            let embedding = o[EMBEDDING_JSON_KEY].as_array().and_then(|values| {
+                values.iter().map(|v| v.as_f64().map(|f| f as f32)).collect::<Option<Vec<f32>>>()
+            });
+            match embedding {
+                Some(embedding) => RkyvSerializedValue::Embedding(embedding),
+                None => unmarked_json_object(o),
+            }
+        }
+        Value::Object(o) if o.len() == 1 && o.contains_key(STREAM_JSON_KEY) => {
+            match o[STREAM_JSON_KEY].as_u64().and_then(|id| u32::try_from(id).ok()) {
+                Some(id) => RkyvSerializedValue::StreamPointer(id),
+                None => unmarked_json_object(o),
             }
-            RkyvSerializedValue::Object(map)
         }
+        Value::Object(o) => unmarked_json_object(o),
         Value::Null => RkyvSerializedValue::Null,
         _ => panic!("Invalid value type"),
     }
 }
 
+fn unmarked_json_object(o: &chidori_prompt_format::serde_json::Map<String, Value>) -> RkyvSerializedValue {
+    let mut map = HashMap::new();
+    for (k, v) in o {
+        map.insert(k.clone(), json_value_to_serialized_value(v));
+    }
+    RkyvSerializedValue::Object(map)
+}
+
 // Implementing Serialize for RkyvSerializedValue
 impl SerdeSerialize for RkyvSerializedValue {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -448,6 +640,60 @@ mod tests {
         round_trip(value);
     }
 
+    #[test]
+    fn test_error() {
+        let mut error = ErrorValue::new("ValueError", "boom");
+        error.traceback = Some("Traceback (most recent call last):\nValueError: boom".to_string());
+        error.cell = Some("a".to_string());
+        error.range = Some(TextRange { start: 3, end: 9 });
+        let value = RkyvSerializedValue::Error(error);
+        round_trip(value.clone());
+        assert_eq!(json_value_to_serialized_value(&serialized_value_to_json_value(&value)), value);
+    }
+
+    #[test]
+    fn test_wide_numbers() {
+        let values = vec![
+            RkyvSerializedValue::Number(1 << 40),
+            RkyvSerializedValue::Number(i64::MIN),
+            RkyvSerializedValue::Float(0.1 + 0.2),
+        ];
+        for value in values {
+            round_trip(value.clone());
+            assert_eq!(json_value_to_serialized_value(&serialized_value_to_json_value(&value)), value);
+        }
+        assert_eq!(serialized_value_to_json_value(&RkyvSerializedValue::Float(f64::NAN)), Value::Null);
+    }
+
+    #[test]
+    fn test_bytes_datetime_and_embedding() {
+        let values = vec![
+            RkyvSerializedValue::Bytes(vec![0, 159, 146, 150, 255]),
+            RkyvSerializedValue::DateTime(1_717_171_717_123_456),
+            RkyvSerializedValue::DateTime(-1),
+            RkyvSerializedValue::Embedding(vec![0.25, -1.5, 3.0e-7]),
+        ];
+        for value in values {
+            round_trip(value.clone());
+            assert_eq!(json_value_to_serialized_value(&serialized_value_to_json_value(&value)), value);
+            assert_eq!(deserialize_from_buf(&serialize_to_vec(&value)), value);
+        }
+        assert_eq!(
+            serialized_value_to_json_value(&RkyvSerializedValue::DateTime(1_717_171_717_123_000)).to_string(),
+            r#"{"__chidori_datetime__":"2024-05-31T16:08:37.123Z"}"#
+        );
+        // Objects that only look like a marked value are kept as they are
+        let lookalike: Value = chidori_prompt_format::serde_json::from_str(r#"{"__chidori_bytes__": 5}"#).unwrap();
+        assert!(matches!(json_value_to_serialized_value(&lookalike), RkyvSerializedValue::Object(_)));
+    }
+
+    #[test]
+    fn test_stream_pointer() {
+        let value = RkyvSerializedValue::StreamPointer(7);
+        round_trip(value.clone());
+        assert_eq!(json_value_to_serialized_value(&serialized_value_to_json_value(&value)), value);
+    }
+
     #[test]
     fn test_serialize_to_vec() {
         let value = RkyvSerializedValue::String("Hello".to_string());
@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::RwLock;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+/// Tokens consumed by a single request to a model.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ModelUsage {
+    pub model: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+/// Token counts accumulated for one model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TokenCounts {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenCounts {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// Usage rolled up across any number of requests, keyed by the model that served them.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct UsageTotals {
+    pub by_model: BTreeMap<String, TokenCounts>,
+}
+
+impl UsageTotals {
+    pub fn from_usage(usage: &[ModelUsage]) -> Self {
+        let mut totals = UsageTotals::default();
+        for u in usage {
+            totals.add(u);
+        }
+        totals
+    }
+
+    pub fn add(&mut self, usage: &ModelUsage) {
+        let counts = self.by_model.entry(usage.model.clone()).or_default();
+        counts.requests += 1;
+        counts.prompt_tokens += usage.prompt_tokens;
+        counts.completion_tokens += usage.completion_tokens;
+    }
+
+    pub fn merge(&mut self, other: &UsageTotals) {
+        for (model, other_counts) in &other.by_model {
+            let counts = self.by_model.entry(model.clone()).or_default();
+            counts.requests += other_counts.requests;
+            counts.prompt_tokens += other_counts.prompt_tokens;
+            counts.completion_tokens += other_counts.completion_tokens;
+        }
+    }
+
+    /// Usage added since the given earlier totals of the same requests, models without any are left out.
+    pub fn since(&self, earlier: &UsageTotals) -> UsageTotals {
+        let mut accrued = UsageTotals::default();
+        for (model, counts) in &self.by_model {
+            let earlier_counts = earlier.by_model.get(model).copied().unwrap_or_default();
+            let counts = TokenCounts {
+                requests: counts.requests.saturating_sub(earlier_counts.requests),
+                prompt_tokens: counts.prompt_tokens.saturating_sub(earlier_counts.prompt_tokens),
+                completion_tokens: counts.completion_tokens.saturating_sub(earlier_counts.completion_tokens),
+            };
+            if counts != TokenCounts::default() {
+                accrued.by_model.insert(model.clone(), counts);
+            }
+        }
+        accrued
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_model.is_empty()
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.by_model.values().map(|c| c.requests).sum()
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.by_model.values().map(|c| c.total_tokens()).sum()
+    }
+
+    /// Cost in USD under the given price table. Models without a price contribute nothing,
+    /// they are listed by `unpriced_models`.
+    pub fn cost(&self, prices: &PriceTable) -> f64 {
+        self.by_model.iter()
+            .filter_map(|(model, counts)| prices.price_for(model).map(|price| price.cost(counts)))
+            .sum()
+    }
+
+    pub fn unpriced_models(&self, prices: &PriceTable) -> Vec<String> {
+        self.by_model.keys().filter(|model| prices.price_for(model).is_none()).cloned().collect()
+    }
+}
+
+/// Price of a model in USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl ModelPrice {
+    pub fn cost(&self, counts: &TokenCounts) -> f64 {
+        (counts.prompt_tokens as f64 * self.prompt + counts.completion_tokens as f64 * self.completion) / 1_000_000.0
+    }
+}
+
+/// Prices keyed by model name. A model without an exact entry is priced by the longest entry that
+/// prefixes its name, so that dated releases such as `gpt-4o-2024-05-13` use the price of `gpt-4o`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct PriceTable {
+    pub prices: HashMap<String, ModelPrice>,
+}
+
+impl PriceTable {
+    /// List prices of the hosted models supported by the built in providers.
+    pub fn with_default_prices() -> Self {
+        let prices = [
+            ("gpt-4o", 5.0, 15.0),
+            ("gpt-4o-mini", 0.15, 0.6),
+            ("gpt-4-turbo", 10.0, 30.0),
+            ("gpt-4", 30.0, 60.0),
+            ("gpt-4-32k", 60.0, 120.0),
+            ("gpt-3.5-turbo", 0.5, 1.5),
+            ("claude-3-5-sonnet", 3.0, 15.0),
+            ("claude-3-opus", 15.0, 75.0),
+            ("claude-3-sonnet", 3.0, 15.0),
+            ("claude-3-haiku", 0.25, 1.25),
+        ];
+        PriceTable {
+            prices: prices.iter()
+                .map(|(model, prompt, completion)| (model.to_string(), ModelPrice { prompt: *prompt, completion: *completion }))
+                .collect(),
+        }
+    }
+
+    /// Read a price table from a YAML or JSON file mapping model names to `{prompt, completion}` prices.
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let prices: HashMap<String, ModelPrice> = serde_yaml::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("Failed to parse price table {:?}: {}", path, e))?;
+        Ok(PriceTable { prices })
+    }
+
+    /// Add the prices of another table, replacing prices of models present in both.
+    pub fn extend(&mut self, other: PriceTable) {
+        self.prices.extend(other.prices);
+    }
+
+    pub fn price_for(&self, model: &str) -> Option<&ModelPrice> {
+        self.prices.get(model).or_else(|| {
+            self.prices.iter()
+                .filter(|(name, _)| model.starts_with(name.as_str()))
+                .max_by_key(|(name, _)| name.len())
+                .map(|(_, price)| price)
+        })
+    }
+}
+
+static PRICE_TABLE: Lazy<RwLock<PriceTable>> = Lazy::new(|| RwLock::new(PriceTable::with_default_prices()));
+
+/// Replace the price table used to report costs.
+pub fn set_price_table(prices: PriceTable) {
+    *PRICE_TABLE.write().unwrap() = prices;
+}
+
+pub fn price_table() -> PriceTable {
+    PRICE_TABLE.read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totals_roll_up_per_model() {
+        let mut totals = UsageTotals::from_usage(&[
+            ModelUsage { model: "gpt-4o".to_string(), prompt_tokens: 100, completion_tokens: 20 },
+            ModelUsage { model: "gpt-4o".to_string(), prompt_tokens: 50, completion_tokens: 10 },
+        ]);
+        totals.merge(&UsageTotals::from_usage(&[
+            ModelUsage { model: "local-llama".to_string(), prompt_tokens: 7, completion_tokens: 3 },
+        ]));
+        assert_eq!(totals.requests(), 3);
+        assert_eq!(totals.total_tokens(), 190);
+        assert_eq!(totals.by_model["gpt-4o"].prompt_tokens, 150);
+    }
+
+    #[test]
+    fn test_cost_uses_longest_matching_price() {
+        let prices = PriceTable::with_default_prices();
+        assert_eq!(prices.price_for("gpt-4o-mini-2024-07-18").unwrap().prompt, 0.15);
+        assert_eq!(prices.price_for("gpt-4o-2024-05-13").unwrap().prompt, 5.0);
+
+        let totals = UsageTotals::from_usage(&[
+            ModelUsage { model: "gpt-4o".to_string(), prompt_tokens: 1_000_000, completion_tokens: 1_000_000 },
+            ModelUsage { model: "local-llama".to_string(), prompt_tokens: 10, completion_tokens: 10 },
+        ]);
+        assert_eq!(totals.cost(&prices), 20.0);
+        assert_eq!(totals.unpriced_models(&prices), vec!["local-llama".to_string()]);
+    }
+
+    #[test]
+    fn test_price_table_from_path() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("chidori_prices_{}.yaml", uuid::Uuid::now_v7()));
+        std::fs::write(&path, "local-llama:\n  prompt: 0.0\n  completion: 0.0\ngpt-4o:\n  prompt: 2.5\n  completion: 10.0\n")?;
+        let mut prices = PriceTable::with_default_prices();
+        prices.extend(PriceTable::from_path(&path)?);
+        assert_eq!(prices.price_for("gpt-4o").unwrap().prompt, 2.5);
+        assert!(prices.price_for("local-llama").is_some());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::{json, Value};
+use crate::execution::primitives::serialized_value::json_value_to_serialized_value;
+use crate::library::std::ai::llm;
+use crate::library::std::ai::llm::{ChatCompletionReq, ChatCompletionRes, ChatModelBatch, JSONSchemaDefine, JSONSchemaType, Tool, ToolChoiceType};
+
+pub const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+const DEFAULT_MODEL: &str = "claude-3-5-sonnet-20240620";
+// The Messages API requires max_tokens to be set on every request
+const DEFAULT_MAX_TOKENS: i64 = 1024;
+
+/// Chat model backed by Anthropic's Messages API.
+pub struct AnthropicChatModel {
+    api_url: String,
+    api_key: String,
+    client: Client,
+}
+
+impl AnthropicChatModel {
+    pub fn new(api_url: String, api_key: String) -> Self {
+        Self { api_url, api_key, client: Client::new() }
+    }
+
+    pub fn chat_completion_req_to_anthropic_req(chat_completion_req: &ChatCompletionReq) -> Value {
+        let config = &chat_completion_req.config;
+
+        // System prompts are a top level field rather than a message in the Messages API
+        let system = chat_completion_req.template_messages.iter()
+            .filter(|m| matches!(m.role, llm::MessageRole::System))
+            .map(|m| m.content.clone())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let messages = chat_completion_req.template_messages.iter()
+            .filter(|m| !matches!(m.role, llm::MessageRole::System))
+            .map(|m| json!({
+                "role": match m.role {
+                    llm::MessageRole::Assistant => "assistant",
+                    _ => "user",
+                },
+                "content": m.content,
+            }))
+            .collect::<Vec<_>>();
+
+        let mut req = json!({
+            "model": config.model.clone().unwrap_or(DEFAULT_MODEL.to_string()),
+            "max_tokens": config.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            "messages": messages,
+        });
+        let body = req.as_object_mut().unwrap();
+        if !system.is_empty() {
+            body.insert("system".to_string(), json!(system));
+        }
+        if let Some(temperature) = config.temperature {
+            body.insert("temperature".to_string(), json!(temperature));
+        }
+        if let Some(top_p) = config.top_p {
+            body.insert("top_p".to_string(), json!(top_p));
+        }
+        if let Some(stop) = &config.stop {
+            body.insert("stop_sequences".to_string(), json!(stop));
+        }
+        if let Some(tools) = &chat_completion_req.tools {
+            body.insert("tools".to_string(), Value::Array(tools.iter().map(our_tool_to_anthropic_tool).collect()));
+        }
+        match &chat_completion_req.tool_choice {
+            Some(ToolChoiceType::Auto) => { body.insert("tool_choice".to_string(), json!({"type": "auto"})); }
+            Some(ToolChoiceType::ToolChoice { tool }) => { body.insert("tool_choice".to_string(), json!({"type": "tool", "name": tool.function.name})); }
+            Some(ToolChoiceType::None) | None => {}
+        }
+        req
+    }
+
+    pub fn anthropic_res_to_chat_completion_res(res: Value) -> Result<ChatCompletionRes, String> {
+        let content = res.get("content").and_then(|c| c.as_array())
+            .ok_or_else(|| format!("Unexpected response from Anthropic: {}", res))?;
+
+        let text = content.iter()
+            .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("text"))
+            .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("");
+        let tool_calls = content.iter()
+            .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("tool_use"))
+            .map(|block| llm::ChatCompletionToolCall {
+                id: block.get("id").and_then(|id| id.as_str()).unwrap_or_default().to_string(),
+                ty: "function".to_string(),
+                function: llm::ChatCompletionToolCallFunction {
+                    name: block.get("name").and_then(|name| name.as_str()).map(|name| name.to_string()),
+                    arguments: block.get("input").map(json_value_to_serialized_value),
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let prompt_tokens = res.pointer("/usage/input_tokens").and_then(|t| t.as_i64()).unwrap_or(0) as i32;
+        let completion_tokens = res.pointer("/usage/output_tokens").and_then(|t| t.as_i64()).unwrap_or(0) as i32;
+        Ok(ChatCompletionRes {
+            id: res.get("id").and_then(|id| id.as_str()).unwrap_or_default().to_string(),
+            object: res.get("type").and_then(|t| t.as_str()).unwrap_or_default().to_string(),
+            created: 0,
+            model: res.get("model").and_then(|m| m.as_str()).unwrap_or_default().to_string(),
+            choices: vec![llm::ChatCompletionChoice {
+                text: Some(text),
+                index: 0,
+                logprobs: None,
+                finish_reason: res.get("stop_reason").and_then(|r| r.as_str()).unwrap_or_default().to_string(),
+                tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
+            }],
+            usage: llm::Usage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+            },
+        })
+    }
+}
+
+fn our_json_schema_type_to_anthropic(schema_type: &JSONSchemaType) -> Value {
+    serde_json::to_value(schema_type).unwrap_or(Value::Null)
+}
+
+fn our_json_schema_define_to_anthropic(schema_define: &JSONSchemaDefine) -> Value {
+    let mut schema = serde_json::Map::new();
+    if let Some(schema_type) = &schema_define.schema_type {
+        schema.insert("type".to_string(), our_json_schema_type_to_anthropic(schema_type));
+    }
+    if let Some(description) = &schema_define.description {
+        schema.insert("description".to_string(), json!(description));
+    }
+    if let Some(enum_values) = &schema_define.enum_values {
+        schema.insert("enum".to_string(), json!(enum_values));
+    }
+    if let Some(properties) = &schema_define.properties {
+        schema.insert("properties".to_string(), our_json_schema_properties_to_anthropic(properties));
+    }
+    if let Some(required) = &schema_define.required {
+        schema.insert("required".to_string(), json!(required));
+    }
+    if let Some(items) = &schema_define.items {
+        schema.insert("items".to_string(), our_json_schema_define_to_anthropic(items));
+    }
+    Value::Object(schema)
+}
+
+fn our_json_schema_properties_to_anthropic(properties: &HashMap<String, Box<JSONSchemaDefine>>) -> Value {
+    Value::Object(properties.iter().map(|(k, v)| (k.clone(), our_json_schema_define_to_anthropic(v))).collect())
+}
+
+fn our_tool_to_anthropic_tool(tool: &Tool) -> Value {
+    let parameters = &tool.function.parameters;
+    let mut input_schema = json!({
+        "type": our_json_schema_type_to_anthropic(&parameters.schema_type),
+        "properties": parameters.properties.as_ref().map(our_json_schema_properties_to_anthropic).unwrap_or(json!({})),
+    });
+    if let Some(required) = &parameters.required {
+        input_schema.as_object_mut().unwrap().insert("required".to_string(), json!(required));
+    }
+    let mut anthropic_tool = json!({
+        "name": tool.function.name,
+        "input_schema": input_schema,
+    });
+    if let Some(description) = &tool.function.description {
+        anthropic_tool.as_object_mut().unwrap().insert("description".to_string(), json!(description));
+    }
+    anthropic_tool
+}
+
+#[async_trait]
+impl ChatModelBatch for AnthropicChatModel {
+    async fn batch(
+        &self,
+        chat_completion_req: ChatCompletionReq,
+    ) -> Result<ChatCompletionRes, String> {
+        let req = Self::chat_completion_req_to_anthropic_req(&chat_completion_req);
+        let response = self.client
+            .post(format!("{}/messages", self.api_url.trim_end_matches('/')))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .header("content-type", "application/json")
+            .json(&req)
+            .send()
+            .await
+            .map_err(|e| format!("API request error: {}", e))?;
+
+        if !response.status().is_success() {
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| String::from("Unknown error"));
+            return Err(format!("API request error: {}", error_text));
+        }
+        let res: Value = response.json().await.map_err(|e| e.to_string())?;
+        Self::anthropic_res_to_chat_completion_res(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::LLMPromptCellChatConfiguration;
+    use crate::execution::primitives::serialized_value::{RkyvObjectBuilder};
+    use crate::library::std::ai::llm::{Function, FunctionParameters, TemplateMessage};
+
+    #[test]
+    fn test_chat_completion_req_to_anthropic_req() {
+        let req = ChatCompletionReq {
+            config: LLMPromptCellChatConfiguration {
+                model: Some("claude-3-haiku-20240307".to_string()),
+                ..Default::default()
+            },
+            template_messages: vec![
+                TemplateMessage { role: llm::MessageRole::System, content: "Be brief.".to_string(), name: None, function_call: None },
+                TemplateMessage { role: llm::MessageRole::User, content: "Hello".to_string(), name: None, function_call: None },
+            ],
+            tool_choice: None,
+            tools: Some(vec![Tool {
+                tool_type: "function".to_string(),
+                function: Function {
+                    name: "add".to_string(),
+                    description: None,
+                    parameters: FunctionParameters {
+                        schema_type: JSONSchemaType::Object,
+                        properties: Some(HashMap::from([("a".to_string(), Box::new(JSONSchemaDefine {
+                            schema_type: Some(JSONSchemaType::Number),
+                            ..Default::default()
+                        }))])),
+                        required: None,
+                    },
+                },
+            }]),
+        };
+        assert_eq!(AnthropicChatModel::chat_completion_req_to_anthropic_req(&req), json!({
+            "model": "claude-3-haiku-20240307",
+            "max_tokens": 1024,
+            "system": "Be brief.",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "tools": [{"name": "add", "input_schema": {"type": "object", "properties": {"a": {"type": "number"}}}}],
+        }));
+    }
+
+    #[test]
+    fn test_anthropic_res_to_chat_completion_res() {
+        let res = AnthropicChatModel::anthropic_res_to_chat_completion_res(json!({
+            "id": "msg_01",
+            "type": "message",
+            "model": "claude-3-haiku-20240307",
+            "stop_reason": "tool_use",
+            "content": [
+                {"type": "text", "text": "Adding."},
+                {"type": "tool_use", "id": "toolu_01", "name": "add", "input": {"a": 1}},
+            ],
+            "usage": {"input_tokens": 10, "output_tokens": 5},
+        })).unwrap();
+        assert_eq!(res.usage.total_tokens, 15);
+        let choice = &res.choices[0];
+        assert_eq!(choice.text.as_deref(), Some("Adding."));
+        let tool_call = &choice.tool_calls.as_ref().unwrap()[0];
+        assert_eq!(tool_call.function.name.as_deref(), Some("add"));
+        assert_eq!(tool_call.function.arguments, Some(RkyvObjectBuilder::new().insert_number("a", 1).build()));
+    }
+}
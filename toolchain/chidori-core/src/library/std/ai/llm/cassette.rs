@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use tracing::error;
+use crate::library::std::ai::llm::{ChatCompletionChoice, ChatCompletionReq, ChatCompletionRes, ChatModelBatch, ChatModelStream, LLMStream};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CassetteMode {
+    /// Forward requests to the provider and save every response to the cassette file
+    Record,
+    /// Serve responses from the cassette file, a request that was never recorded is an error
+    Replay,
+}
+
+/// A file of recorded chat completions, keyed by a hash of the request that produced them.
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    interactions: Mutex<BTreeMap<String, ChatCompletionRes>>,
+}
+
+impl Cassette {
+    pub fn open(path: &Path, mode: CassetteMode) -> anyhow::Result<Self> {
+        let interactions = if path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(path)?)?
+        } else if mode == CassetteMode::Replay {
+            return Err(anyhow::anyhow!("Cassette {:?} does not exist and cannot be replayed", path));
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            mode,
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn record(&self, key: String, res: &ChatCompletionRes) -> Result<(), String> {
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions.insert(key, res.clone());
+        let serialized = serde_json::to_string_pretty(&*interactions).map_err(|e| e.to_string())?;
+        std::fs::write(&self.path, serialized).map_err(|e| format!("Failed to write cassette {:?}: {}", self.path, e))
+    }
+
+    fn replay(&self, key: &str) -> Option<ChatCompletionRes> {
+        self.interactions.lock().unwrap().get(key).cloned()
+    }
+}
+
+static ACTIVE_CASSETTE: Lazy<Mutex<Option<Arc<Cassette>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Route every chat model request through the given cassette, or stop doing so with `None`.
+pub fn set_active_cassette(cassette: Option<Cassette>) {
+    *ACTIVE_CASSETTE.lock().unwrap() = cassette.map(Arc::new);
+}
+
+pub fn active_cassette() -> Option<Arc<Cassette>> {
+    ACTIVE_CASSETTE.lock().unwrap().clone()
+}
+
+/// Recursively sort object keys so that equivalent requests always serialize identically.
+fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let sorted: BTreeMap<String, Value> = map.into_iter().map(|(k, v)| (k, canonicalize(v))).collect();
+            Value::Object(sorted.into_iter().collect())
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(canonicalize).collect()),
+        other => other,
+    }
+}
+
+/// Identify a request by its model, messages, tools and seed.
+pub fn cassette_key(chat_completion_req: &ChatCompletionReq) -> String {
+    let identity = canonicalize(json!({
+        "model": chat_completion_req.config.model,
+        "messages": chat_completion_req.template_messages,
+        "tools": chat_completion_req.tools,
+        "seed": chat_completion_req.config.seed,
+    }));
+    let mut hasher = Sha1::new();
+    hasher.update(identity.to_string().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Wraps a provider's chat model with the active cassette.
+pub struct CassetteChatModel {
+    inner: Arc<dyn ChatModelBatch + Send + Sync>,
+    cassette: Arc<Cassette>,
+}
+
+impl CassetteChatModel {
+    pub fn new(inner: Arc<dyn ChatModelBatch + Send + Sync>, cassette: Arc<Cassette>) -> Self {
+        Self { inner, cassette }
+    }
+}
+
+#[async_trait]
+impl ChatModelBatch for CassetteChatModel {
+    async fn batch(
+        &self,
+        chat_completion_req: ChatCompletionReq,
+    ) -> Result<ChatCompletionRes, String> {
+        let key = cassette_key(&chat_completion_req);
+        match self.cassette.mode {
+            CassetteMode::Replay => {
+                self.cassette.replay(&key).ok_or_else(|| {
+                    let message = format!(
+                        "Cassette {:?} has no recorded response for request {} (model {:?}), re-record the cassette to include it",
+                        self.cassette.path, key, chat_completion_req.config.model
+                    );
+                    error!("{}", message);
+                    message
+                })
+            }
+            CassetteMode::Record => {
+                let res = self.inner.batch(chat_completion_req).await?;
+                self.cassette.record(key, &res)?;
+                Ok(res)
+            }
+        }
+    }
+
+    fn as_stream(&self) -> Option<&(dyn ChatModelStream + Send + Sync)> {
+        Some(self)
+    }
+}
+
+/// Streamed requests are recorded once the provider's stream is exhausted, so that the cassette holds
+/// the usage it reported at the end, and are replayed as a stream of the recorded response.
+#[async_trait]
+impl ChatModelStream for CassetteChatModel {
+    async fn stream(&self, chat_completion_req: ChatCompletionReq) -> Result<LLMStream, String> {
+        let res = match (self.cassette.mode, self.inner.as_stream()) {
+            (CassetteMode::Record, Some(inner)) => {
+                let key = cassette_key(&chat_completion_req);
+                let model = chat_completion_req.config.model.clone().unwrap_or_default();
+                let mut stream = inner.stream(chat_completion_req).await?;
+                let mut text = String::new();
+                while let Some(partial) = stream.next().await {
+                    text = partial;
+                }
+                let res = ChatCompletionRes {
+                    id: key.clone(),
+                    object: "chat.completion".to_string(),
+                    created: 0,
+                    model,
+                    choices: vec![ChatCompletionChoice {
+                        text: Some(text),
+                        index: 0,
+                        logprobs: None,
+                        finish_reason: "stop".to_string(),
+                        tool_calls: None,
+                    }],
+                    usage: stream.usage().clone(),
+                };
+                self.cassette.record(key, &res)?;
+                res
+            }
+            _ => self.batch(chat_completion_req).await?,
+        };
+        Ok(LLMStream::from_response(&res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::std::ai::llm::mock::{MockChatModel, MockResponse};
+    use crate::library::std::ai::llm::{MessageRole, TemplateMessage};
+
+    fn request(content: &str) -> ChatCompletionReq {
+        ChatCompletionReq {
+            template_messages: vec![TemplateMessage {
+                role: MessageRole::User,
+                content: content.to_string(),
+                name: None,
+                function_call: None,
+            }],
+            ..ChatCompletionReq::default()
+        }
+    }
+
+    #[test]
+    fn test_cassette_key_depends_on_request() {
+        assert_eq!(cassette_key(&request("hello")), cassette_key(&request("hello")));
+        assert_ne!(cassette_key(&request("hello")), cassette_key(&request("goodbye")));
+        let mut seeded = request("hello");
+        seeded.config.seed = Some(7);
+        assert_ne!(cassette_key(&request("hello")), cassette_key(&seeded));
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("chidori_cassette_{}", uuid::Uuid::now_v7()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("cassette.json");
+
+        let recorder = CassetteChatModel::new(
+            Arc::new(MockChatModel::scripted(vec![MockResponse::Text("recorded".to_string())])),
+            Arc::new(Cassette::open(&path, CassetteMode::Record)?),
+        );
+        let res = recorder.batch(request("hello")).await.unwrap();
+        assert_eq!(res.choices[0].text.as_deref(), Some("recorded"));
+
+        // The inner model would echo if it were called, replay must serve the recorded response instead
+        let player = CassetteChatModel::new(
+            Arc::new(MockChatModel::echo()),
+            Arc::new(Cassette::open(&path, CassetteMode::Replay)?),
+        );
+        let res = player.batch(request("hello")).await.unwrap();
+        assert_eq!(res.choices[0].text.as_deref(), Some("recorded"));
+        assert!(player.batch(request("never recorded")).await.is_err());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_streamed_call_reports_usage() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("chidori_cassette_{}", uuid::Uuid::now_v7()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("cassette.json");
+
+        let recorder = CassetteChatModel::new(
+            Arc::new(MockChatModel::scripted(vec![MockResponse::Text("streamed and recorded".to_string())])),
+            Arc::new(Cassette::open(&path, CassetteMode::Record)?),
+        );
+        let mut stream = recorder.as_stream().unwrap().stream(request("hello there")).await.unwrap();
+        while stream.next().await.is_some() {}
+        assert_eq!(stream.usage().completion_tokens, 3);
+
+        let player = CassetteChatModel::new(
+            Arc::new(MockChatModel::echo()),
+            Arc::new(Cassette::open(&path, CassetteMode::Replay)?),
+        );
+        let mut stream = player.as_stream().unwrap().stream(request("hello there")).await.unwrap();
+        let mut text = String::new();
+        while let Some(partial) = stream.next().await {
+            text = partial;
+        }
+        assert_eq!(text, "streamed and recorded");
+        assert_eq!(stream.usage().prompt_tokens, 2);
+        assert_eq!(stream.usage().total_tokens, 5);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use async_trait::async_trait;
+use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+use crate::library::std::ai::llm;
+use crate::library::std::ai::llm::{ChatCompletionReq, ChatCompletionRes, ChatModelBatch, ChatModelStream, LLMStream};
+
+#[derive(Debug, Clone)]
+pub enum MockResponse {
+    Text(String),
+    /// Request invocations of the named functions with the given keyword arguments
+    ToolCalls(Vec<(String, RkyvSerializedValue)>),
+}
+
+/// A deterministic chat model for running notebooks without network access. Scripted responses are
+/// returned in order; once they are exhausted (or when none were provided) the content of the last
+/// message of the request is echoed back. Usage is reported as one token per word.
+pub struct MockChatModel {
+    responses: Mutex<VecDeque<MockResponse>>,
+}
+
+impl MockChatModel {
+    pub fn echo() -> Self {
+        Self::scripted(vec![])
+    }
+
+    pub fn scripted(responses: Vec<MockResponse>) -> Self {
+        Self { responses: Mutex::new(responses.into()) }
+    }
+}
+
+impl MockChatModel {
+    fn respond(&self, chat_completion_req: &ChatCompletionReq) -> ChatCompletionRes {
+        let response = self.responses.lock().unwrap().pop_front();
+        let (text, tool_calls) = match response {
+            Some(MockResponse::Text(text)) => (text, None),
+            Some(MockResponse::ToolCalls(calls)) => (String::new(), Some(calls.into_iter().enumerate().map(|(i, (name, arguments))| {
+                llm::ChatCompletionToolCall {
+                    id: format!("mock_call_{}", i),
+                    ty: "function".to_string(),
+                    function: llm::ChatCompletionToolCallFunction {
+                        name: Some(name),
+                        arguments: Some(arguments),
+                    },
+                }
+            }).collect())),
+            None => (chat_completion_req.template_messages.last().map(|m| m.content.clone()).unwrap_or_default(), None),
+        };
+        // Words stand in for tokens so that usage accounting can be exercised offline
+        let prompt_tokens = chat_completion_req.template_messages.iter().map(|m| m.content.split_whitespace().count() as i32).sum();
+        let completion_tokens = text.split_whitespace().count() as i32;
+        ChatCompletionRes {
+            id: "mock".to_string(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: chat_completion_req.config.model.clone().unwrap_or("mock".to_string()),
+            choices: vec![llm::ChatCompletionChoice {
+                text: Some(text),
+                index: 0,
+                logprobs: None,
+                finish_reason: "stop".to_string(),
+                tool_calls,
+            }],
+            usage: llm::Usage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl ChatModelBatch for MockChatModel {
+    async fn batch(
+        &self,
+        chat_completion_req: ChatCompletionReq,
+    ) -> Result<ChatCompletionRes, String> {
+        Ok(self.respond(&chat_completion_req))
+    }
+
+    fn as_stream(&self) -> Option<&(dyn ChatModelStream + Send + Sync)> {
+        Some(self)
+    }
+}
+
+/// Streams the same responses as `batch`, one word per chunk, encoded as server-sent events like OpenAI's.
+#[async_trait]
+impl ChatModelStream for MockChatModel {
+    async fn stream(&self, chat_completion_req: ChatCompletionReq) -> Result<LLMStream, String> {
+        Ok(LLMStream::from_response(&self.respond(&chat_completion_req)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::std::ai::llm::TemplateMessage;
+
+    fn request(content: &str) -> ChatCompletionReq {
+        ChatCompletionReq {
+            template_messages: vec![TemplateMessage {
+                role: llm::MessageRole::User,
+                content: content.to_string(),
+                name: None,
+                function_call: None,
+            }],
+            ..ChatCompletionReq::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_scripted_then_echo() {
+        let model = MockChatModel::scripted(vec![MockResponse::Text("first".to_string())]);
+        let res = model.batch(request("hello")).await.unwrap();
+        assert_eq!(res.choices[0].text.as_deref(), Some("first"));
+        let res = model.batch(request("hello")).await.unwrap();
+        assert_eq!(res.choices[0].text.as_deref(), Some("hello"));
+    }
+
+    #[tokio::test]
+    async fn test_mock_stream_accumulates_words() {
+        use futures_util::StreamExt;
+        let model = MockChatModel::scripted(vec![MockResponse::Text("one two three".to_string())]);
+        let partials = model.stream(request("hello")).await.unwrap().collect::<Vec<_>>().await;
+        assert_eq!(partials, vec!["one ", "one two ", "one two three"]);
+    }
+}
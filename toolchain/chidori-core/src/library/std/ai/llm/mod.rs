@@ -1,21 +1,30 @@
+pub mod anthropic;
+pub mod cassette;
+pub mod mock;
 pub mod openai;
+pub mod providers;
 
 use async_trait::async_trait;
-use futures_util::stream::Stream;
+use dashmap::DashSet;
+use futures_util::stream::{Stream, StreamExt};
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::env;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
 use tracing::debug;
 use uuid::Uuid;
 use chidori_prompt_format::templating::templates::{ChatModelRoles, TemplateWithSource};
 use crate::cells::{LLMCodeGenCellChatConfiguration, LLMPromptCellChatConfiguration, TextRange};
+use crate::execution::execution::execution_graph::ExecutionNodeId;
 use crate::execution::execution::execution_state::ExecutionStateErrors;
 use crate::execution::execution::ExecutionState;
-use crate::execution::primitives::operation::InputSignature;
+use crate::execution::primitives::operation::{InputSignature, InputType};
 use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue, serialized_value_to_json_value};
+use crate::execution::primitives::usage::ModelUsage;
 use crate::library::std::ai::llm::openai::OpenAIChatModel;
 use crate::sdk::md::interpret_markdown_code_block;
 
@@ -24,7 +33,7 @@ pub enum LLMErrors {
     ConnectionError(String),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Usage {
     pub prompt_tokens: i32,
     pub completion_tokens: i32,
@@ -48,6 +57,34 @@ pub struct LLMStream {
     usage: Usage,
 }
 
+impl LLMStream {
+    /// Usage reported by the provider, only complete once the stream has been consumed.
+    pub fn usage(&self) -> &Usage {
+        &self.usage
+    }
+
+    /// Stream a finished response one word per chunk, encoded as server-sent events like OpenAI's,
+    /// with its usage in a final chunk as reported when `include_usage` is requested.
+    pub fn from_response(res: &ChatCompletionRes) -> Self {
+        let text = res.choices.first().and_then(|choice| choice.text.clone()).unwrap_or_default();
+        let mut events = text
+            .split_inclusive(' ')
+            .map(|token| serde_json::json!({"choices": [{"delta": {"content": token}}]}))
+            .collect::<Vec<_>>();
+        events.push(serde_json::json!({"choices": [], "usage": res.usage}));
+        let chunks = events
+            .into_iter()
+            .map(|event| Ok(bytes::Bytes::from(format!("data: {}\n\n", event))))
+            .collect::<Vec<Result<bytes::Bytes, reqwest::Error>>>();
+        LLMStream {
+            response: Box::pin(futures_util::stream::iter(chunks)),
+            buffer: String::new(),
+            first_chunk: true,
+            usage: Usage::default(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum MessageRole {
     User,
@@ -82,6 +119,7 @@ pub struct Function {
 pub enum JSONSchemaType {
     Object,
     Number,
+    Integer,
     String,
     Array,
     Null,
@@ -132,6 +170,7 @@ impl Default for ChatCompletionReq {
             config: LLMPromptCellChatConfiguration {
                 import: None,
                 function_name: None,
+                provider: None,
                 model: Some(String::from("gpt-3.5-turbo")),
                 api_url: None,
                 frequency_penalty: None,
@@ -143,6 +182,7 @@ impl Default for ChatCompletionReq {
                 user: None,
                 seed: None,
                 top_p: None,
+                policy: Default::default(),
             },
             template_messages: Vec::new(),
             tool_choice: None,
@@ -151,20 +191,20 @@ impl Default for ChatCompletionReq {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatCompletionToolCallFunction {
     pub name: Option<String>,
     pub arguments: Option<RkyvSerializedValue>
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatCompletionToolCall {
     pub id: String,
     pub ty: String,
     pub function: ChatCompletionToolCallFunction
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatCompletionChoice {
     pub text: Option<String>,
     pub index: i32,
@@ -174,7 +214,7 @@ pub struct ChatCompletionChoice {
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatCompletionRes {
     pub id: String,
     pub object: String,
@@ -211,10 +251,15 @@ pub trait ChatModelBatch {
         &self,
         chat_completion_req: ChatCompletionReq,
     ) -> Result<ChatCompletionRes, String>;
+
+    /// Models that can stream their responses return themselves here, all others are only run in batch.
+    fn as_stream(&self) -> Option<&(dyn ChatModelStream + Send + Sync)> {
+        None
+    }
 }
 
 #[async_trait]
-trait ChatModelStream {
+pub trait ChatModelStream {
     async fn stream(&self, chat_completion_req: ChatCompletionReq) -> Result<LLMStream, String>;
 }
 
@@ -268,49 +313,126 @@ pub async fn ai_llm_run_embedding_model(
         let mut result_map = HashMap::new();
         if !is_function_invocation {
             if let Some(name) = &name {
-                result_map.insert(name.clone(), RkyvSerializedValue::Array(result.iter().map(|v| RkyvSerializedValue::Float(*v)).collect()));
+                result_map.insert(name.clone(), RkyvSerializedValue::Embedding(result));
                 return RkyvSerializedValue::Object(result_map);
             }
         }
-        RkyvSerializedValue::Array(result.iter().map(|v| RkyvSerializedValue::Float(*v)).collect())
+        RkyvSerializedValue::Embedding(result)
     } else {
         RkyvSerializedValue::Null
     }
 }
 
-fn input_signature_to_json_properties(input_signature: InputSignature) -> HashMap<String, Box<JSONSchemaDefine>> {
+/// JSON schema of the values accepted by an input. Inputs without a type, and unions of values that are
+/// not of a single JSON type, accept any value and are left without a type.
+fn input_type_to_json_schema(ty: &Option<InputType>) -> JSONSchemaDefine {
+    let Some(ty) = ty else {
+        return JSONSchemaDefine::default();
+    };
+    match ty {
+        InputType::String => JSONSchemaDefine { schema_type: Some(JSONSchemaType::String), ..Default::default() },
+        InputType::Int => JSONSchemaDefine { schema_type: Some(JSONSchemaType::Integer), ..Default::default() },
+        InputType::Float => JSONSchemaDefine { schema_type: Some(JSONSchemaType::Number), ..Default::default() },
+        InputType::Bool => JSONSchemaDefine { schema_type: Some(JSONSchemaType::Boolean), ..Default::default() },
+        InputType::Null => JSONSchemaDefine { schema_type: Some(JSONSchemaType::Null), ..Default::default() },
+        InputType::Object => JSONSchemaDefine { schema_type: Some(JSONSchemaType::Object), ..Default::default() },
+        InputType::List(item) => JSONSchemaDefine {
+            schema_type: Some(JSONSchemaType::Array),
+            items: Some(Box::new(input_type_to_json_schema(&Some(*item.clone())))),
+            ..Default::default()
+        },
+        // Optional inputs are left out of the required inputs rather than accepting null
+        InputType::Optional(ty) => input_type_to_json_schema(&Some(*ty.clone())),
+        InputType::Union(tys) => {
+            if tys.iter().all(|ty| matches!(ty, InputType::Int | InputType::Float)) {
+                JSONSchemaDefine { schema_type: Some(JSONSchemaType::Number), ..Default::default() }
+            } else if tys.iter().all(|ty| matches!(ty, InputType::List(_))) {
+                JSONSchemaDefine { schema_type: Some(JSONSchemaType::Array), ..Default::default() }
+            } else {
+                JSONSchemaDefine::default()
+            }
+        }
+        InputType::Any | InputType::Function => JSONSchemaDefine::default(),
+    }
+}
+
+fn input_signature_to_json_properties(input_signature: &InputSignature) -> HashMap<String, Box<JSONSchemaDefine>> {
     let mut properties = HashMap::new();
-    for (k, v) in input_signature.args {
-        properties.insert(k, Box::new(JSONSchemaDefine {
-            schema_type: Some(JSONSchemaType::String),
-            description: None,
-            enum_values: None,
-            properties: None,
-            required: None,
-            items: None,
-        }));
+    for (k, v) in input_signature.args.iter().chain(input_signature.kwargs.iter()).chain(input_signature.globals.iter()) {
+        let mut schema = input_type_to_json_schema(&v.ty);
+        schema.description = v.description.clone();
+        properties.insert(k.clone(), Box::new(schema));
     }
-    for (k, v) in input_signature.kwargs {
-        properties.insert(k, Box::new(JSONSchemaDefine {
-            schema_type: Some(JSONSchemaType::String),
-            description: None,
-            enum_values: None,
-            properties: None,
-            required: None,
-            items: None,
-        }));
+    properties
+}
+
+/// Arguments a model must provide when calling a function, those without defaults that do not accept null.
+fn input_signature_required_properties(input_signature: &InputSignature) -> Vec<String> {
+    let mut required: Vec<String> = input_signature.args.iter().chain(input_signature.kwargs.iter())
+        .filter(|(_, v)| v.is_required() && !matches!(v.ty, Some(InputType::Optional(_)) | Some(InputType::Null)))
+        .map(|(k, _)| k.clone())
+        .collect();
+    required.sort();
+    required
+}
+
+/// Usage of a completed request, attributed to the model that served it when the provider reports one.
+fn model_usage(res: &ChatCompletionRes, configured_model: &Option<String>) -> ModelUsage {
+    let model = if res.model.is_empty() {
+        configured_model.clone().unwrap_or_default()
+    } else {
+        res.model.clone()
+    };
+    ModelUsage {
+        model,
+        prompt_tokens: res.usage.prompt_tokens.max(0) as u64,
+        completion_tokens: res.usage.completion_tokens.max(0) as u64,
     }
-    for (k, v) in input_signature.globals {
-        properties.insert(k, Box::new(JSONSchemaDefine {
-            schema_type: Some(JSONSchemaType::String),
-            description: None,
-            enum_values: None,
-            properties: None,
-            required: None,
-            items: None,
-        }));
+}
+
+// Evaluations whose streaming output should stop at the next chunk, keyed by the id of the evaluating state
+static CANCELLED_STREAMS: Lazy<DashSet<ExecutionNodeId>> = Lazy::new(|| DashSet::new());
+
+/// Stop a prompt that is streaming its response. The cell completes with the text received so far.
+pub fn cancel_streaming_output(execution_node_id: ExecutionNodeId) {
+    CANCELLED_STREAMS.insert(execution_node_id);
+}
+
+/// Consume a streamed response, sending the accumulated text to `intermediate_output` as each chunk arrives.
+async fn stream_chat_completion(
+    model: &(dyn ChatModelStream + Send + Sync),
+    chat_completion_req: ChatCompletionReq,
+    execution_node_id: ExecutionNodeId,
+    intermediate_output: &Sender<(ExecutionNodeId, RkyvSerializedValue)>,
+) -> Result<ChatCompletionRes, String> {
+    let model_name = chat_completion_req.config.model.clone().unwrap_or_default();
+    let mut stream = model.stream(chat_completion_req).await?;
+    let mut text = String::new();
+    let mut finish_reason = String::from("stop");
+    while let Some(partial) = stream.next().await {
+        if CANCELLED_STREAMS.remove(&execution_node_id).is_some() {
+            finish_reason = String::from("cancelled");
+            break;
+        }
+        text = partial;
+        // The receiver going away only means nobody is watching, the generation itself continues
+        let _ = intermediate_output.send((execution_node_id, RkyvSerializedValue::String(text.clone())));
     }
-    properties
+    CANCELLED_STREAMS.remove(&execution_node_id);
+    Ok(ChatCompletionRes {
+        id: execution_node_id.to_string(),
+        object: "chat.completion".to_string(),
+        created: 0,
+        model: model_name,
+        choices: vec![ChatCompletionChoice {
+            text: Some(text),
+            index: 0,
+            logprobs: None,
+            finish_reason,
+            tool_calls: None,
+        }],
+        usage: stream.usage().clone(),
+    })
 }
 
 pub async fn ai_llm_run_chat_model(
@@ -319,8 +441,9 @@ pub async fn ai_llm_run_chat_model(
     role_blocks: Vec<(ChatModelRoles, Option<TemplateWithSource>)>,
     name: Option<String>,
     is_function_invocation: bool,
-    configuration: LLMPromptCellChatConfiguration
-) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, Option<ExecutionState>)> {
+    configuration: LLMPromptCellChatConfiguration,
+    intermediate_output: Option<Sender<(ExecutionNodeId, RkyvSerializedValue)>>,
+) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, Option<ExecutionState>, Vec<ModelUsage>)> {
     debug!("Executing ai_llm_run_chat_model");
     let mut template_messages: Vec<TemplateMessage> = Vec::new();
     let data = template_data_payload_from_rkyv(&payload);
@@ -340,10 +463,12 @@ pub async fn ai_llm_run_chat_model(
 
     let tools = infer_tool_usage_from_imports(execution_state, &configuration.import);
 
-    let api_url_v1 = configuration.api_url.clone();
-    let c = crate::library::std::ai::llm::openai::OpenAIChatModel::new(api_url_v1.unwrap_or("http://localhost:4000/v1".to_string()), "".to_string());
+    let c = match providers::chat_model_for_provider(configuration.provider.as_ref(), configuration.api_url.clone()) {
+        Ok(c) => c,
+        Err(e) => return Ok((Result::Err(ExecutionStateErrors::AnyhowError(e.to_string())), None, vec![])),
+    };
 
-    let result = c.batch(ChatCompletionReq {
+    let req = ChatCompletionReq {
         config: configuration.clone(),
         template_messages,
         tool_choice: None,
@@ -352,12 +477,31 @@ pub async fn ai_llm_run_chat_model(
         } else {
             Some(tools)
         },
-    }).await;
+    };
+
+    // Tool calls are only resolved from complete responses, so prompts that import functions are always run in batch
+    let result = match (&intermediate_output, c.as_stream()) {
+        (Some(tx), Some(stream_model)) if req.tools.is_none() => {
+            stream_chat_completion(stream_model, req, execution_state.chronology_id, tx).await
+        }
+        (Some(tx), _) => {
+            let result = c.batch(req).await;
+            if let Ok(ChatCompletionRes { choices, .. }) = &result {
+                if let Some(text) = choices.first().and_then(|choice| choice.text.clone()) {
+                    let _ = tx.send((execution_state.chronology_id, RkyvSerializedValue::String(text)));
+                }
+            }
+            result
+        }
+        (None, _) => c.batch(req).await,
+    };
 
     if let Err(e) = result {
-        return Ok((Result::Err(ExecutionStateErrors::AnyhowError(e)), None))
+        return Ok((Result::Err(ExecutionStateErrors::AnyhowError(e)), None, vec![]))
     }
-    let Ok(ChatCompletionRes { choices, .. }) = result else { unreachable!() };
+    let Ok(res) = result else { unreachable!() };
+    let usage = vec![model_usage(&res, &configuration.model)];
+    let ChatCompletionRes { choices, .. } = res;
 
 
     let execution_state_handle = Arc::new(Mutex::new(execution_state.clone()));
@@ -380,7 +524,7 @@ pub async fn ai_llm_run_chat_model(
                         let (dispatch_result, mut result_execution_state) = new_exec_state.dispatch(&function_name, args, None).await?;
 
                         if !dispatch_result.is_ok() {
-                            return Ok((dispatch_result, Some(result_execution_state)));
+                            return Ok((dispatch_result, Some(result_execution_state), usage));
                         }
 
                         let mut exec_state = execution_state_handle.lock().unwrap();
@@ -417,7 +561,7 @@ pub async fn ai_llm_run_chat_model(
         RkyvSerializedValue::Array(results)
     };
     let mut exec_state = execution_state_handle.lock().unwrap().clone();
-    Ok((Ok(out), Some(exec_state)))
+    Ok((Ok(out), Some(exec_state), usage))
 }
 
 pub async fn ai_llm_code_generation_chat_model(
@@ -427,7 +571,7 @@ pub async fn ai_llm_code_generation_chat_model(
     name: Option<String>,
     is_function_invocation: bool,
     configuration: LLMCodeGenCellChatConfiguration
-) -> anyhow::Result<(RkyvSerializedValue, Option<ExecutionState>)> {
+) -> anyhow::Result<(RkyvSerializedValue, Option<ExecutionState>, Vec<ModelUsage>)> {
     let mut template_messages: Vec<TemplateMessage> = Vec::new();
     let data = template_data_payload_from_rkyv(&payload);
 
@@ -444,13 +588,13 @@ pub async fn ai_llm_code_generation_chat_model(
         });
     }
 
-    let api_url_v1 = configuration.api_url.unwrap_or("http://localhost:4000/v1".to_string());
-    let c = crate::library::std::ai::llm::openai::OpenAIChatModel::new(api_url_v1, "".to_string());
+    let c = providers::chat_model_for_provider(configuration.provider.as_ref(), configuration.api_url.clone())?;
 
     let result = c.batch(ChatCompletionReq {
         config: LLMPromptCellChatConfiguration {
             import: None,
             function_name: None,
+            provider: configuration.provider.clone(),
             model: configuration.model.clone(),
             api_url: None,
             frequency_penalty: configuration.frequency_penalty.clone(),
@@ -462,6 +606,7 @@ pub async fn ai_llm_code_generation_chat_model(
             user: configuration.user.clone(),
             seed: configuration.seed.clone(),
             top_p: configuration.top_p.clone(),
+            policy: Default::default(),
         },
         template_messages,
         tool_choice: None,
@@ -469,8 +614,9 @@ pub async fn ai_llm_code_generation_chat_model(
     }).await;
 
 
-    if let Ok(ChatCompletionRes { choices, .. }) = result {
-        for choice in choices {
+    if let Ok(res) = result {
+        let usage = vec![model_usage(&res, &configuration.model)];
+        for choice in res.choices {
             let text = choice.text.as_ref().unwrap().clone();
             println!("Code generation cell run, returning this payload: {}", &text);
             let mut new_execution_state = execution_state.clone();
@@ -487,11 +633,11 @@ pub async fn ai_llm_code_generation_chat_model(
                 new_execution_state = s;
             }
 
-            return Ok((RkyvSerializedValue::String(text.clone()), Some(new_execution_state)));
+            return Ok((RkyvSerializedValue::String(text.clone()), Some(new_execution_state), usage));
         }
-        Ok((RkyvSerializedValue::Null, None))
+        Ok((RkyvSerializedValue::Null, None, usage))
     } else {
-        Ok((RkyvSerializedValue::Null, None))
+        Ok((RkyvSerializedValue::Null, None, vec![]))
     }
 }
 
@@ -506,11 +652,11 @@ pub fn infer_tool_usage_from_imports(execution_state: &ExecutionState, imports:
                 tool_type: "function".to_string(),
                 function: Function {
                     name: import.to_string(),
-                    description: None,
+                    description: function.description.clone(),
                     parameters: FunctionParameters {
                         schema_type: JSONSchemaType::Object,
-                        properties: Some(input_signature_to_json_properties(function.input_signature.clone())),
-                        required: None,
+                        properties: Some(input_signature_to_json_properties(&function.input_signature)),
+                        required: Some(input_signature_required_properties(&function.input_signature)),
                     },
                 },
             });
@@ -541,7 +687,9 @@ mod test {
     use uuid::Uuid;
     use crate::cells::{CellTypes, CodeCell, LLMPromptCellChatConfiguration, SupportedLanguage, TextRange};
     use crate::execution::execution::ExecutionState;
-    use crate::library::std::ai::llm::infer_tool_usage_from_imports;
+    use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+    use crate::library::std::ai::llm::{cancel_streaming_output, infer_tool_usage_from_imports, stream_chat_completion, ChatCompletionReq, JSONSchemaType};
+    use crate::library::std::ai::llm::mock::{MockChatModel, MockResponse};
 
     #[tokio::test]
     async fn test_tool_usage_inference() -> anyhow::Result<()> {
@@ -559,6 +707,7 @@ mod test {
                             return 100 + await demo_second_function_call()
                         "#}),
             function_invocation: None,
+            policy: Default::default(),
         }, TextRange::default()), id_a)?;
         let (mut state, _) = state.update_operation(CellTypes::Code(CodeCell {
             backing_file_reference: None,
@@ -569,6 +718,7 @@ mod test {
                             return a + b + c + d
                         "#}),
             function_invocation: None,
+            policy: Default::default(),
         }, TextRange::default()), id_b)?;
 
         insta::with_settings!({
@@ -586,4 +736,63 @@ mod test {
         });
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_tool_usage_from_annotated_signature() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let (state, _) = state.update_operation(CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: String::from(indoc! {r#"
+                        from typing import Optional
+
+                        def search(query: str, limit: int = 10, tags: Optional[list[str]] = None, weights: dict[str, float] | None = None):
+                            """Search the collection.
+
+                            Args:
+                                query: Text to search for.
+                            """
+                            return []
+                        "#}),
+            function_invocation: None,
+            policy: Default::default(),
+        }, TextRange::default()), Uuid::now_v7())?;
+
+        let tools = infer_tool_usage_from_imports(&state, &Some(vec!["search".to_string()]));
+        let function = &tools[0].function;
+        assert_eq!(function.description.as_deref(), Some("Search the collection."));
+        assert_eq!(function.parameters.required, Some(vec!["query".to_string()]));
+        let properties = function.parameters.properties.as_ref().unwrap();
+        assert!(matches!(properties["query"].schema_type, Some(JSONSchemaType::String)));
+        assert_eq!(properties["query"].description.as_deref(), Some("Text to search for."));
+        assert!(matches!(properties["limit"].schema_type, Some(JSONSchemaType::Integer)));
+        assert!(matches!(properties["tags"].schema_type, Some(JSONSchemaType::Array)));
+        assert!(matches!(properties["tags"].items.as_ref().unwrap().schema_type, Some(JSONSchemaType::String)));
+        assert!(matches!(properties["weights"].schema_type, Some(JSONSchemaType::Object)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_chat_completion_sends_partial_output() {
+        let model = MockChatModel::scripted(vec![MockResponse::Text("streamed in parts".to_string())]);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let id = Uuid::now_v7();
+        let res = stream_chat_completion(&model, ChatCompletionReq::default(), id, &tx).await.unwrap();
+        assert_eq!(res.choices[0].text.as_deref(), Some("streamed in parts"));
+        let partials = rx.try_iter().map(|(_, v)| v).collect::<Vec<_>>();
+        assert_eq!(partials.len(), 3);
+        assert_eq!(partials[0], RkyvSerializedValue::String("streamed ".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_stream_keeps_partial_output() {
+        let model = MockChatModel::scripted(vec![MockResponse::Text("never finished".to_string())]);
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let id = Uuid::now_v7();
+        cancel_streaming_output(id);
+        let res = stream_chat_completion(&model, ChatCompletionReq::default(), id, &tx).await.unwrap();
+        assert_eq!(res.choices[0].finish_reason, "cancelled");
+        assert_eq!(res.choices[0].text.as_deref(), Some(""));
+    }
 }
\ No newline at end of file
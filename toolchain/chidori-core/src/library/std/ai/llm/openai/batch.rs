@@ -4,7 +4,7 @@ use futures_util::TryStreamExt;
 
 use crate::library::std::ai::llm;
 use crate::library::std::ai::llm::openai::OpenAIChatModel;
-use crate::library::std::ai::llm::{ChatCompletionReq, ChatCompletionRes, ChatModelBatch, JSONSchemaDefine, JSONSchemaType, Tool, ToolChoiceType};
+use crate::library::std::ai::llm::{ChatCompletionReq, ChatCompletionRes, ChatModelBatch, ChatModelStream, JSONSchemaDefine, JSONSchemaType, Tool, ToolChoiceType};
 
 use openai_api_rs::v1::chat_completion::{
     ChatCompletionMessage, ChatCompletionRequest, MessageRole,
@@ -90,6 +90,10 @@ impl ChatModelBatch for OpenAIChatModel {
             }})
             .map_err(|e| e.to_string())
     }
+
+    fn as_stream(&self) -> Option<&(dyn ChatModelStream + Send + Sync)> {
+        Some(self)
+    }
 }
 
 
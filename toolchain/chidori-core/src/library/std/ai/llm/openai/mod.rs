@@ -69,6 +69,8 @@ fn our_json_schema_type_to_openai(schema_type: JSONSchemaType) -> openai_api_rs:
     match schema_type {
         JSONSchemaType::Object => openai_api_rs::v1::chat_completion::JSONSchemaType::Object,
         JSONSchemaType::Number => openai_api_rs::v1::chat_completion::JSONSchemaType::Number,
+        // Integers are not distinguished from other numbers by the client
+        JSONSchemaType::Integer => openai_api_rs::v1::chat_completion::JSONSchemaType::Number,
         JSONSchemaType::String => openai_api_rs::v1::chat_completion::JSONSchemaType::String,
         JSONSchemaType::Array => openai_api_rs::v1::chat_completion::JSONSchemaType::Array,
         JSONSchemaType::Null => openai_api_rs::v1::chat_completion::JSONSchemaType::Null,
@@ -76,16 +78,20 @@ fn our_json_schema_type_to_openai(schema_type: JSONSchemaType) -> openai_api_rs:
     }
 }
 
+fn our_json_schema_define_to_openai(schema_define: JSONSchemaDefine) -> openai_api_rs::v1::chat_completion::JSONSchemaDefine {
+    openai_api_rs::v1::chat_completion::JSONSchemaDefine {
+        schema_type: schema_define.schema_type.map(our_json_schema_type_to_openai),
+        description: schema_define.description,
+        enum_values: schema_define.enum_values,
+        properties: schema_define.properties.map(our_json_schema_define_map_to_openai),
+        required: schema_define.required,
+        items: schema_define.items.map(|items| Box::new(our_json_schema_define_to_openai(*items))),
+    }
+}
+
 fn our_json_schema_define_map_to_openai(schema_define: HashMap<String, Box<JSONSchemaDefine>>) -> HashMap<String, Box<openai_api_rs::v1::chat_completion::JSONSchemaDefine>> {
     schema_define.into_iter().map(|(k, v)|
-        (k, Box::new(openai_api_rs::v1::chat_completion::JSONSchemaDefine {
-            schema_type: v.schema_type.map(our_json_schema_type_to_openai),
-            description: v.description,
-            enum_values: v.enum_values,
-            properties: v.properties.map(our_json_schema_define_map_to_openai),
-            required: v.required,
-            items: None,
-        }))
+        (k, Box::new(our_json_schema_define_to_openai(*v)))
     ).collect()
 }
 
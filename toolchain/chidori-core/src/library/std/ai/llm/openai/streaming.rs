@@ -18,11 +18,14 @@ impl ChatModelStream for OpenAIChatModel {
         let client = Client::new();
         let mut req = Self::chat_completion_req_to_openai_req(&chat_completion_req);
         req.stream = Some(true);
+        // Usage is only reported on streams that ask for it, it arrives with the final chunk
+        let mut body = serde_json::to_value(&req).map_err(|e| format!("API request error: {}", e))?;
+        body["stream_options"] = serde_json::json!({"include_usage": true});
         let response: Response = match client
-            .post(api_url)
+            .post(format!("{}/chat/completions", api_url.trim_end_matches('/')))
             .header("Content-Type", "application/json")
             .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&req)
+            .json(&body)
             .send()
             .await
         {
@@ -72,6 +75,12 @@ impl Stream for LLMStream {
 
                     match json_result {
                         Ok(json) => {
+                            // Providers that report usage on streams include it with the final chunk
+                            if let Some(usage) = json.get("usage") {
+                                if let Ok(usage) = serde_json::from_value::<Usage>(usage.clone()) {
+                                    self.usage = usage;
+                                }
+                            }
                             if let Some(choices) = json.get("choices") {
                                 if let Some(choice) = choices.get(0) {
                                     if let Some(content) =
@@ -116,7 +125,7 @@ mod tests {
     #[tokio::test]
     async fn test_gpt_stream_raw_line() {
         dotenv::dotenv().ok();
-        let model = crate::library::std::ai::llm::openai::OpenAIChatModel::new("http://localhost:4000/v1/chat/completions".to_string(), "".to_string());
+        let model = crate::library::std::ai::llm::openai::OpenAIChatModel::new("http://localhost:4000/v1".to_string(), "".to_string());
         let stream = model.stream(Default::default()).await.unwrap();
         let mut stream = Box::pin(stream);
         while let Some(value) = stream.next().await {
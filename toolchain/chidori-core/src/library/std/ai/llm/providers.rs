@@ -0,0 +1,93 @@
+use std::env;
+use std::sync::Arc;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use crate::cells::SupportedModelProviders;
+use crate::library::std::ai::llm::anthropic::{AnthropicChatModel, ANTHROPIC_API_URL};
+use crate::library::std::ai::llm::cassette;
+use crate::library::std::ai::llm::cassette::CassetteChatModel;
+use crate::library::std::ai::llm::mock::MockChatModel;
+use crate::library::std::ai::llm::openai::OpenAIChatModel;
+use crate::library::std::ai::llm::ChatModelBatch;
+
+// Requests to OpenAI are expected to go through a local proxy unless an api_url is configured
+const OPENAI_PROXY_API_URL: &str = "http://localhost:4000/v1";
+const OLLAMA_API_URL: &str = "http://localhost:11434/v1";
+
+pub type ChatModelFactory = Arc<dyn Fn(Option<String>) -> Arc<dyn ChatModelBatch + Send + Sync> + Send + Sync>;
+
+/// Chat model providers, keyed by the name of the `provider` in a cell's frontmatter. Factories receive the
+/// `api_url` from the cell's configuration, if one was set.
+static CHAT_MODEL_PROVIDERS: Lazy<DashMap<String, ChatModelFactory>> = Lazy::new(|| {
+    let providers: DashMap<String, ChatModelFactory> = DashMap::new();
+    providers.insert(SupportedModelProviders::OpenAI.name().to_string(), Arc::new(|api_url| {
+        Arc::new(OpenAIChatModel::new(
+            api_url.unwrap_or(OPENAI_PROXY_API_URL.to_string()),
+            env::var("OPENAI_API_KEY").unwrap_or_default(),
+        ))
+    }));
+    providers.insert(SupportedModelProviders::Ollama.name().to_string(), Arc::new(|api_url| {
+        Arc::new(OpenAIChatModel::new(api_url.unwrap_or(OLLAMA_API_URL.to_string()), "".to_string()))
+    }));
+    providers.insert(SupportedModelProviders::Anthropic.name().to_string(), Arc::new(|api_url| {
+        Arc::new(AnthropicChatModel::new(
+            api_url.unwrap_or(ANTHROPIC_API_URL.to_string()),
+            env::var("ANTHROPIC_API_KEY").unwrap_or_default(),
+        ))
+    }));
+    providers.insert(SupportedModelProviders::Mock.name().to_string(), Arc::new(|_| Arc::new(MockChatModel::echo())));
+    providers
+});
+
+/// Register a provider, replacing any existing provider with the same name.
+pub fn register_chat_model_provider<F>(name: &str, factory: F)
+where
+    F: Fn(Option<String>) -> Arc<dyn ChatModelBatch + Send + Sync> + Send + Sync + 'static,
+{
+    CHAT_MODEL_PROVIDERS.insert(name.to_string(), Arc::new(factory));
+}
+
+/// Register a single model instance as a provider, for example a scripted `MockChatModel` in tests.
+pub fn register_chat_model(name: &str, model: Arc<dyn ChatModelBatch + Send + Sync>) {
+    register_chat_model_provider(name, move |_| model.clone());
+}
+
+pub fn chat_model_for_provider(provider: Option<&SupportedModelProviders>, api_url: Option<String>) -> anyhow::Result<Arc<dyn ChatModelBatch + Send + Sync>> {
+    let default = SupportedModelProviders::default();
+    let provider = provider.unwrap_or(&default).name();
+    let factory = CHAT_MODEL_PROVIDERS
+        .get(provider)
+        .map(|factory| factory.value().clone())
+        .ok_or_else(|| {
+            let mut known = CHAT_MODEL_PROVIDERS.iter().map(|p| p.key().clone()).collect::<Vec<_>>();
+            known.sort();
+            anyhow::anyhow!("Unknown model provider {:?}, expected one of: {}", provider, known.join(", "))
+        })?;
+    let model = factory(api_url);
+    Ok(match cassette::active_cassette() {
+        Some(cassette) => Arc::new(CassetteChatModel::new(model, cassette)),
+        None => model,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::std::ai::llm::mock::MockResponse;
+    use crate::library::std::ai::llm::ChatCompletionReq;
+
+    #[tokio::test]
+    async fn test_registered_provider_is_resolved_by_name() -> anyhow::Result<()> {
+        register_chat_model("test_registry_mock", Arc::new(MockChatModel::scripted(vec![MockResponse::Text("scripted".to_string())])));
+        let model = chat_model_for_provider(Some(&"test_registry_mock".into()), None)?;
+        let res = model.batch(ChatCompletionReq::default()).await.unwrap();
+        assert_eq!(res.choices[0].text.as_deref(), Some("scripted"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_provider() {
+        let err = chat_model_for_provider(Some(&"not_a_provider".into()), None).err().unwrap();
+        assert!(err.to_string().contains("anthropic"));
+    }
+}
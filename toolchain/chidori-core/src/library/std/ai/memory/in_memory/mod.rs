@@ -23,6 +23,10 @@ impl InMemoryVectorDb {
         }
     }
 
+    pub fn has_collection(&self, collection_name: &str) -> bool {
+        self.collections.contains_key(collection_name)
+    }
+
     pub fn new_collection(&mut self, collection_name: String) {
         let mut hnsw = Hnsw::<f32, DistDot>::new(
             // max_nb_connection (in hnsw initialization) The maximum number of links from one
@@ -2,3 +2,4 @@
 /// RkyvSerializedValue, and whose AST can be parsed into a Report.
 pub mod runtime_deno;
 pub mod runtime_pyo3;
+pub mod python_venv;
@@ -0,0 +1,280 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use sha1::{Digest, Sha1};
+use tracing::debug;
+use uuid::Uuid;
+
+/// Written into a virtualenv once its requirements are installed, virtualenvs without it are incomplete
+const COMPLETE_MARKER: &str = ".chidori_complete";
+
+/// Updated with the time a virtualenv was last used, read when collecting stale virtualenvs
+const LAST_USED_MARKER: &str = ".chidori_last_used";
+
+/// Directory of wheels requirements are installed from without reaching an index, unset to install from PyPI
+static WHEEL_DIR: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+
+/// Virtualenvs are created one at a time, so that cells needing the same one do not both create it
+static VENV_CREATION_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Install requirements offline from the wheels in the given directory, or from PyPI with `None`.
+/// Defaults to the `CHIDORI_WHEEL_DIR` environment variable.
+pub fn set_wheel_dir(dir: Option<PathBuf>) {
+    *WHEEL_DIR.lock().unwrap() = dir;
+}
+
+pub fn wheel_dir() -> Option<PathBuf> {
+    WHEEL_DIR.lock().unwrap().clone().or_else(|| std::env::var_os("CHIDORI_WHEEL_DIR").map(PathBuf::from))
+}
+
+/// Directory virtualenvs are kept in, `.chidori_venvs` under the user's home directory.
+pub fn venv_root() -> anyhow::Result<PathBuf> {
+    let home_dir = std::env::var("CHIDORI_HOME_DIRECTORY")
+        .or_else(|_| std::env::var("HOME"))
+        .or_else(|_| std::env::var("USERPROFILE"))?;
+    Ok(PathBuf::from(home_dir).join(".chidori_venvs"))
+}
+
+/// Requirements declared by inline script metadata (PEP 723) at the top of a cell:
+///
+/// ```python
+/// # /// script
+/// # dependencies = [
+/// #   "requests<3",
+/// # ]
+/// # ///
+/// ```
+pub fn requirements_from_script_metadata(source_code: &str) -> Vec<String> {
+    let block = Regex::new(r"(?m)^# /// script\s*$((?:\n#(?: .*)?)*?)\n# ///\s*$").unwrap();
+    let Some(captures) = block.captures(source_code) else {
+        return vec![];
+    };
+    let metadata: String = captures[1].lines()
+        .map(|line| line.strip_prefix("# ").or_else(|| line.strip_prefix('#')).unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    // Requirements may have extras in brackets, only brackets outside of strings close the list
+    let dependencies = Regex::new(r#"(?:^|\n)\s*dependencies\s*=\s*\[((?:[^\]"']|"[^"]*"|'[^']*')*)\]"#).unwrap();
+    let Some(dependencies) = dependencies.captures(&metadata) else {
+        return vec![];
+    };
+    let requirement = Regex::new(r#""([^"]*)"|'([^']*)'"#).unwrap();
+    requirement.captures_iter(&dependencies[1])
+        .filter_map(|captures| captures.get(1).or_else(|| captures.get(2)))
+        .map(|requirement| requirement.as_str().to_string())
+        .collect()
+}
+
+/// Requirements listed in a `requirements.txt` file, one per line.
+pub fn requirements_from_file(path: &Path) -> anyhow::Result<Vec<String>> {
+    Ok(normalize_requirements(std::fs::read_to_string(path)?.lines().map(|line| line.to_string())))
+}
+
+/// Requirements without comments, blank lines and duplicates, in a stable order.
+pub fn normalize_requirements(requirements: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut requirements: Vec<String> = requirements.into_iter()
+        .map(|requirement| requirement.split('#').next().unwrap_or_default().trim().to_string())
+        .filter(|requirement| !requirement.is_empty())
+        .collect();
+    requirements.sort();
+    requirements.dedup();
+    requirements
+}
+
+/// Identifies the virtualenv for a version of Python and a set of requirements. The key is per specifier:
+/// the same specifiers share a virtualenv even if they would resolve to newer releases today, and specifiers
+/// that resolve to the same packages get virtualenvs of their own. Collect stale virtualenvs to pick up
+/// new releases.
+pub fn venv_key(python_version: (u8, u8), requirements: &[String]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("python{}.{}\n", python_version.0, python_version.1).as_bytes());
+    for requirement in normalize_requirements(requirements.iter().cloned()) {
+        hasher.update(requirement.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+fn mark_used(venv_path: &Path) {
+    if let Err(e) = std::fs::write(venv_path.join(LAST_USED_MARKER), now_secs().to_string()) {
+        debug!("Failed to record the use of virtualenv {:?}: {}", venv_path, e);
+    }
+}
+
+/// The virtualenv with the given requirements installed for this version of Python, creating it the first
+/// time it is needed. Virtualenvs are reused by every cell and notebook with the same requirements.
+pub fn get_or_create_venv(python_version: (u8, u8), requirements: &[String]) -> anyhow::Result<PathBuf> {
+    let root = venv_root()?;
+    let venv_path = root.join(format!("chidori_venv_{}", venv_key(python_version, requirements)));
+    if venv_path.join(COMPLETE_MARKER).exists() {
+        mark_used(&venv_path);
+        return Ok(venv_path);
+    }
+
+    let _creating = VENV_CREATION_LOCK.lock().unwrap();
+    if venv_path.join(COMPLETE_MARKER).exists() {
+        mark_used(&venv_path);
+        return Ok(venv_path);
+    }
+    std::fs::create_dir_all(&root)?;
+
+    // Built next to where it is kept and moved into place once complete, so that a virtualenv whose creation
+    // was interrupted is never reused. Cells only read its site-packages, which do not depend on its location.
+    let partial_path = root.join(format!(".partial_{}", Uuid::now_v7()));
+    let created = create_venv(python_version, requirements, &partial_path);
+    if let Err(e) = created {
+        let _ = std::fs::remove_dir_all(&partial_path);
+        return Err(e);
+    }
+    // Another process may have completed the same virtualenv in the meantime
+    if venv_path.join(COMPLETE_MARKER).exists() {
+        std::fs::remove_dir_all(&partial_path)?;
+        mark_used(&venv_path);
+        return Ok(venv_path);
+    }
+    if venv_path.exists() {
+        std::fs::remove_dir_all(&venv_path)?;
+    }
+    std::fs::rename(&partial_path, &venv_path)?;
+    mark_used(&venv_path);
+    Ok(venv_path)
+}
+
+fn create_venv(python_version: (u8, u8), requirements: &[String], venv_path: &Path) -> anyhow::Result<()> {
+    let (major, minor) = python_version;
+    let uv_path = which::which("uv").map_err(|_| anyhow!("uv not found in PATH"))?;
+    let status = Command::new(&uv_path)
+        .arg("venv")
+        .arg("--python")
+        .arg(format!("{}.{}", major, minor))
+        .arg(venv_path)
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("Failed to create a virtualenv for Python {}.{}", major, minor));
+    }
+
+    let requirements = normalize_requirements(requirements.iter().cloned());
+    if !requirements.is_empty() {
+        let mut install = Command::new(&uv_path);
+        install.arg("pip").arg("install").env("VIRTUAL_ENV", venv_path);
+        if let Some(wheel_dir) = wheel_dir() {
+            install.arg("--offline").arg("--no-index").arg("--find-links").arg(wheel_dir);
+        }
+        let status = install.args(&requirements).status()?;
+        if !status.success() {
+            return Err(anyhow!("Failed to install requirements: {}", requirements.join(", ")));
+        }
+    }
+    std::fs::write(venv_path.join(COMPLETE_MARKER), requirements.join("\n"))?;
+    Ok(())
+}
+
+/// When a virtualenv was last used, or last modified if that was not recorded.
+fn last_used(venv_path: &Path) -> Option<u64> {
+    std::fs::read_to_string(venv_path.join(LAST_USED_MARKER)).ok()
+        .and_then(|secs| secs.trim().parse().ok())
+        .or_else(|| {
+            std::fs::metadata(venv_path).and_then(|metadata| metadata.modified()).ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs())
+        })
+}
+
+/// Remove the virtualenvs in the given directory that have not been used for longer than `max_age`, along with
+/// virtualenvs whose creation was interrupted. Returns the removed virtualenvs, which are only listed with `dry_run`.
+pub fn collect_stale_venvs_in(root: &Path, max_age: Duration, dry_run: bool) -> anyhow::Result<Vec<PathBuf>> {
+    if !root.exists() {
+        return Ok(vec![]);
+    }
+    let cutoff = now_secs().saturating_sub(max_age.as_secs());
+    let mut stale = vec![];
+    for entry in root.read_dir()? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let is_stale = if name.starts_with(".partial_") {
+            last_used(&path).map_or(true, |used| used < cutoff)
+        } else if name.starts_with("chidori_venv_") {
+            !path.join(COMPLETE_MARKER).exists() || last_used(&path).map_or(true, |used| used < cutoff)
+        } else {
+            false
+        };
+        if is_stale {
+            if !dry_run {
+                std::fs::remove_dir_all(&path)?;
+            }
+            stale.push(path);
+        }
+    }
+    stale.sort();
+    Ok(stale)
+}
+
+/// Remove the virtualenvs that have not been used for longer than `max_age`.
+pub fn collect_stale_venvs(max_age: Duration, dry_run: bool) -> anyhow::Result<Vec<PathBuf>> {
+    collect_stale_venvs_in(&venv_root()?, max_age, dry_run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_requirements_from_script_metadata() {
+        let source_code = indoc! { r#"
+            # /// script
+            # requires-python = ">=3.11"
+            # dependencies = [
+            #   "requests[socks]<3",
+            #   'rich',
+            # ]
+            # ///
+            import requests
+        "#};
+        assert_eq!(requirements_from_script_metadata(source_code), vec!["requests[socks]<3".to_string(), "rich".to_string()]);
+        assert!(requirements_from_script_metadata("import requests").is_empty());
+    }
+
+    #[test]
+    fn test_venv_key_ignores_order_and_comments() {
+        let a = venv_key((3, 12), &["rich".to_string(), "requests<3  # http".to_string()]);
+        let b = venv_key((3, 12), &["requests<3".to_string(), "rich".to_string(), "".to_string()]);
+        assert_eq!(a, b);
+        assert_ne!(a, venv_key((3, 11), &["requests<3".to_string(), "rich".to_string()]));
+        assert_ne!(a, venv_key((3, 12), &["rich".to_string()]));
+    }
+
+    #[test]
+    fn test_collect_stale_venvs() -> anyhow::Result<()> {
+        let root = std::env::temp_dir().join(format!("chidori_venvs_{}", Uuid::now_v7()));
+        let fresh = root.join("chidori_venv_fresh");
+        let stale = root.join("chidori_venv_stale");
+        let incomplete = root.join("chidori_venv_incomplete");
+        for venv in [&fresh, &stale, &incomplete] {
+            std::fs::create_dir_all(venv)?;
+        }
+        std::fs::write(fresh.join(COMPLETE_MARKER), "")?;
+        mark_used(&fresh);
+        std::fs::write(stale.join(COMPLETE_MARKER), "")?;
+        std::fs::write(stale.join(LAST_USED_MARKER), (now_secs() - 3600).to_string())?;
+
+        let max_age = Duration::from_secs(60);
+        assert_eq!(collect_stale_venvs_in(&root, max_age, true)?, vec![incomplete.clone(), stale.clone()]);
+        assert!(stale.exists());
+        collect_stale_venvs_in(&root, max_age, false)?;
+        assert!(fresh.exists() && !stale.exists() && !incomplete.exists());
+        std::fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
@@ -6,7 +6,7 @@ use deno;
 use std::sync::{Arc, Mutex};
 
 use crate::execution::primitives::serialized_value::{
-    json_value_to_serialized_value, RkyvObjectBuilder, RkyvSerializedValue,
+    json_value_to_serialized_value, ErrorValue, RkyvObjectBuilder, RkyvSerializedValue,
 };
 use chidori_static_analysis::language::javascript::parse::{build_report, extract_dependencies_js};
 use deno_core::_ops::{RustToV8, RustToV8NoScope};
@@ -26,32 +26,153 @@ use pyo3::{Py, PyAny};
 use pyo3::types::{IntoPyDict, PyTuple};
 use tokio::runtime::{Builder, Runtime};
 use tracing::{debug, Id, Span};
-use crate::cells::{CellTypes, CodeCell, LLMPromptCell};
+use crate::cells::{CellPermissions, CellTypes, CodeCell, LLMPromptCell, PermissionGrant};
 use crate::execution::execution::execution_state::{EnclosedState, ExecutionStateErrors};
 use crate::execution::execution::ExecutionState;
+use crate::execution::execution::cancellation::on_cancel;
+use crate::execution::execution::streams::{close_stream, create_stream, open_reader, push_chunk, StreamReader};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 
-fn serde_v8_to_rkyv(
-    mut scope: &mut HandleScope,
-    arg0: v8::Local<v8::Value>,
-) -> Result<RkyvSerializedValue, String> {
-    let arg0 = match deno_core::_ops::serde_v8_to_rust(&mut scope, arg0) {
-        Ok(t) => t,
-        Err(arg0_err) => {
-            let msg = deno_core::v8::String::new(&mut scope, &{
-                let res = std::fmt::format(std::format_args!(
-                    "{}",
-                    deno_core::anyhow::Error::msg(arg0_err.to_string())
-                ));
-                res
-            })
-            .unwrap();
-            let exc = deno_core::v8::Exception::type_error(&mut scope, msg);
-            scope.throw_exception(exc);
-            return Err(arg0_err.to_string());
+fn set_v8_property<'s>(scope: &mut HandleScope<'s>, object: v8::Local<'s, v8::Object>, key: &str, value: v8::Local<'s, v8::Value>) {
+    let key = v8::String::new(scope, key).unwrap();
+    object.set(scope, key.into(), value.into());
+}
+
+fn optional_v8_string<'s>(scope: &mut HandleScope<'s>, value: &Option<String>) -> v8::Local<'s, v8::Value> {
+    match value {
+        Some(value) => v8::String::new(scope, value).unwrap().into(),
+        None => v8::null(scope).into(),
+    }
+}
+
+/// Give an error to a cell as an `Error` with the name of the exception it was raised as. The cell and
+/// traceback it was raised with are kept as its `cell` and `traceback` properties.
+fn error_value_to_v8<'s>(scope: &mut HandleScope<'s>, error: &ErrorValue) -> v8::Local<'s, v8::Value> {
+    let message = v8::String::new(scope, &error.message).unwrap();
+    let exception = v8::Exception::error(scope, message);
+    if let Some(object) = exception.to_object(scope) {
+        let name = v8::String::new(scope, &error.ty).unwrap();
+        set_v8_property(scope, object, "name", name.into());
+        let cell = optional_v8_string(scope, &error.cell);
+        set_v8_property(scope, object, "cell", cell);
+        let traceback = optional_v8_string(scope, &error.traceback);
+        set_v8_property(scope, object, "traceback", traceback);
+        let range: v8::Local<v8::Value> = match &error.range {
+            Some(range) => {
+                let range_object = v8::Object::new(scope);
+                let start = v8::Number::new(scope, range.start as f64);
+                set_v8_property(scope, range_object, "start", start.into());
+                let end = v8::Number::new(scope, range.end as f64);
+                set_v8_property(scope, range_object, "end", end.into());
+                range_object.into()
+            }
+            None => v8::null(scope).into(),
+        };
+        set_v8_property(scope, object, "range", range);
+    }
+    exception
+}
+
+/// Give a value to a cell as the JavaScript value it corresponds to: bytes as a `Uint8Array`, date times
+/// as a `Date`, embeddings as a `Float32Array` and errors as an `Error`, other values in their JSON form.
+fn rkyv_to_v8<'s>(scope: &mut HandleScope<'s>, value: &RkyvSerializedValue) -> Result<v8::Local<'s, v8::Value>, serde_v8::Error> {
+    match value {
+        RkyvSerializedValue::Error(error) => Ok(error_value_to_v8(scope, error)),
+        RkyvSerializedValue::StreamPointer(id) => Ok(open_stream_in_v8(scope, *id)),
+        RkyvSerializedValue::Bytes(bytes) => {
+            let store = v8::ArrayBuffer::new_backing_store_from_vec(bytes.clone()).make_shared();
+            let buffer = v8::ArrayBuffer::with_backing_store(scope, &store);
+            Ok(v8::Uint8Array::new(scope, buffer, 0, bytes.len()).unwrap().into())
+        }
+        RkyvSerializedValue::DateTime(micros) => Ok(v8::Date::new(scope, *micros as f64 / 1000.0).unwrap().into()),
+        RkyvSerializedValue::Embedding(embedding) => {
+            let bytes: Vec<u8> = embedding.iter().flat_map(|f| f.to_ne_bytes()).collect();
+            let store = v8::ArrayBuffer::new_backing_store_from_vec(bytes).make_shared();
+            let buffer = v8::ArrayBuffer::with_backing_store(scope, &store);
+            Ok(v8::Float32Array::new(scope, buffer, 0, embedding.len()).unwrap().into())
+        }
+        RkyvSerializedValue::Array(items) => {
+            let items = items.iter().map(|item| rkyv_to_v8(scope, item)).collect::<Result<Vec<_>, _>>()?;
+            Ok(v8::Array::new_with_elements(scope, &items).into())
+        }
+        RkyvSerializedValue::Object(fields) => {
+            let object = v8::Object::new(scope);
+            for (key, field) in fields {
+                let field = rkyv_to_v8(scope, field)?;
+                set_v8_property(scope, object, key, field);
+            }
+            Ok(object.into())
         }
+        value => serde_v8::to_v8(scope, value),
+    }
+}
+
+/// Give a stream to a cell as the async iterable created by `Chidori.openStream`.
+fn open_stream_in_v8<'s>(scope: &mut HandleScope<'s>, id: u32) -> v8::Local<'s, v8::Value> {
+    let global = scope.get_current_context().global(scope);
+    let chidori_key = v8::String::new(scope, "Chidori").unwrap();
+    let open_stream_key = v8::String::new(scope, "openStream").unwrap();
+    let Some(chidori) = global.get(scope, chidori_key.into()).and_then(|chidori| chidori.to_object(scope)) else {
+        return v8::undefined(scope).into();
     };
-    Ok(arg0)
+    let Some(open_stream) = chidori.get(scope, open_stream_key.into())
+        .and_then(|open_stream| v8::Local::<v8::Function>::try_from(open_stream).ok()) else {
+        return v8::undefined(scope).into();
+    };
+    let id = v8::Number::new(scope, id as f64);
+    open_stream.call(scope, chidori.into(), &[id.into()]).unwrap_or_else(|| v8::undefined(scope).into())
+}
+
+/// JavaScript numbers do not tell integers apart from floats, and are read as floats when they do not fit
+/// in 32 bits. Integral numbers that a float represents exactly are read as integers.
+fn integral_js_numbers(value: RkyvSerializedValue) -> RkyvSerializedValue {
+    const MAX_SAFE_INTEGER: f64 = 9007199254740991.0;
+    match value {
+        RkyvSerializedValue::Float(f) if f.fract() == 0.0 && f.abs() <= MAX_SAFE_INTEGER => RkyvSerializedValue::Number(f as i64),
+        RkyvSerializedValue::Array(items) => RkyvSerializedValue::Array(items.into_iter().map(integral_js_numbers).collect()),
+        RkyvSerializedValue::Object(fields) => RkyvSerializedValue::Object(
+            fields.into_iter().map(|(key, field)| (key, integral_js_numbers(field))).collect()
+        ),
+        value => value,
+    }
+}
+
+/// The error a cell failed with, keeping the name, message and stack of the exception it threw.
+fn js_error_to_error_value(js_error: &deno_core::error::JsError) -> ErrorValue {
+    let message = js_error.message.clone().unwrap_or_else(|| js_error.exception_message.clone());
+    let mut error = ErrorValue::new(
+        js_error.name.clone().unwrap_or_else(|| "Error".to_string()),
+        permission_violation_message(&message).unwrap_or(message),
+    );
+    error.traceback = js_error.stack.clone();
+    error
+}
+
+/// Deno's flags for a kind of resource: `None` denies all of them and an empty list allows all of them.
+/// Relative paths were resolved against the notebook's directory when its configuration was loaded.
+fn deno_allow_list(grant: &Option<PermissionGrant>) -> Option<Vec<String>> {
+    match grant {
+        Some(PermissionGrant::All(true)) => Some(vec![]),
+        Some(PermissionGrant::Only(allowed)) if !allowed.is_empty() => Some(allowed.clone()),
+        _ => None,
+    }
+}
+
+/// Deno tells how to grant access it denied with the flags of its command line, cells are granted access in
+/// their frontmatter instead. Names the permission the cell is missing, for messages such as
+/// `Requires net access to "example.com:443", run again with the --allow-net flag`.
+fn permission_violation_message(message: &str) -> Option<String> {
+    let re = regex::Regex::new(r#"Requires (net|read|write|env|run) access to "([^"]*)""#).unwrap();
+    let captures = re.captures(message)?;
+    let kind = &captures[1];
+    let target = &captures[2];
+    Some(format!(
+        "Requires {kind} access to \"{target}\", which this cell has not been granted. \
+        Add it to the cell's or the notebook's frontmatter:\npermissions:\n  {kind}: [\"{target}\"]"
+    ))
 }
 
 struct MyOpState {
@@ -66,6 +187,72 @@ struct MyOpState {
         String,
         FunctionConstructorState,
     >,
+    /// Streams started by the cell, which keep being produced after the cell has saved its output
+    streams: Vec<u32>,
+}
+
+/// Readers of streams opened by JavaScript cells, which refer to them by these ids
+static JS_STREAM_READERS: Lazy<DashMap<u32, Arc<tokio::sync::Mutex<StreamReader>>>> = Lazy::new(|| DashMap::new());
+
+static NEXT_JS_STREAM_READER_ID: AtomicU32 = AtomicU32::new(0);
+
+#[op2]
+fn op_stream_create(state: Rc<RefCell<OpState>>) -> u32 {
+    let op_state = state.borrow();
+    let my_op_state: &Arc<Mutex<MyOpState>> = op_state.borrow();
+    let id = create_stream();
+    my_op_state.lock().unwrap().streams.push(id);
+    id
+}
+
+#[op2(async)]
+async fn op_stream_push(#[smi] id: u32, #[serde] chunk: RkyvSerializedValue) -> Result<(), AnyError> {
+    push_chunk(id, integral_js_numbers(chunk)).await
+}
+
+#[op2]
+fn op_stream_close(#[smi] id: u32, #[serde] error: RkyvSerializedValue) {
+    match error {
+        RkyvSerializedValue::Error(error) => close_stream(id, Some(error)),
+        _ => close_stream(id, None),
+    }
+}
+
+#[op2]
+fn op_stream_open(#[smi] id: u32) -> Result<u32, AnyError> {
+    let reader = open_reader(id)?;
+    let reader_id = NEXT_JS_STREAM_READER_ID.fetch_add(1, Ordering::SeqCst);
+    JS_STREAM_READERS.insert(reader_id, Arc::new(tokio::sync::Mutex::new(reader)));
+    Ok(reader_id)
+}
+
+/// The next chunk of a stream as an iterator result, `{ done, value }`.
+#[op2(async)]
+#[serde]
+async fn op_stream_next(#[smi] reader_id: u32) -> Result<RkyvSerializedValue, AnyError> {
+    let reader = JS_STREAM_READERS.get(&reader_id)
+        .map(|reader| reader.value().clone())
+        .ok_or_else(|| anyhow::anyhow!("Stream reader {} is closed", reader_id))?;
+    let next = reader.lock().await.next().await;
+    match next {
+        Some(Ok(chunk)) => Ok(RkyvObjectBuilder::new()
+            .insert_boolean("done", false)
+            .insert_value("value", chunk)
+            .build()),
+        Some(Err(error)) => {
+            JS_STREAM_READERS.remove(&reader_id);
+            Err(anyhow::Error::new(error))
+        }
+        None => {
+            JS_STREAM_READERS.remove(&reader_id);
+            Ok(RkyvObjectBuilder::new().insert_boolean("done", true).build())
+        }
+    }
+}
+
+#[op2(fast)]
+fn op_stream_close_reader(#[smi] reader_id: u32) {
+    JS_STREAM_READERS.remove(&reader_id);
 }
 
 #[op2]
@@ -135,10 +322,11 @@ async fn op_call_rust(
         )
     };
 
+    let args: Vec<RkyvSerializedValue> = args.into_iter().map(integral_js_numbers).collect();
     let kwargs = if kwargs.is_empty() {
         None
     } else {
-        Some(kwargs)
+        Some(kwargs.into_iter().map(|(key, value)| (key, integral_js_numbers(value))).collect())
     };
 
     let clone_function_name = func_constructor.function_name.clone();
@@ -170,6 +358,65 @@ async fn op_call_rust(
     Ok(result)
 }
 
+#[op2(async, reentrant)]
+#[serde]
+async fn op_emit_event(
+    state: Rc<RefCell<OpState>>,
+    #[string] name: String,
+    #[serde] payload: RkyvSerializedValue,
+) -> Result<RkyvSerializedValue, AnyError> {
+    let (execution_state_handle, parent_span_id) = {
+        let op_state = state.borrow();
+        let my_op_state: &Arc<Mutex<MyOpState>> = (*op_state).borrow();
+        let my_op_state = my_op_state.lock().unwrap();
+        (my_op_state.execution_state_handle.clone(), my_op_state.parent_span_id.clone())
+    };
+
+    let new_exec_state = {
+        let exec_state = execution_state_handle.lock().unwrap();
+        exec_state.clone()
+    };
+
+    let (result, mut result_execution_state) = new_exec_state.emit_event(&name, integral_js_numbers(payload), parent_span_id).await?;
+
+    // continue the evaluation of this cell from the state where the handlers have resolved
+    let mut exec_state = execution_state_handle.lock().unwrap();
+    std::mem::swap(&mut *exec_state, &mut result_execution_state);
+    result?;
+    Ok(RkyvSerializedValue::Null)
+}
+
+#[op2(async, reentrant)]
+#[serde]
+async fn op_request_input(
+    state: Rc<RefCell<OpState>>,
+    #[string] prompt: String,
+    #[serde] schema: RkyvSerializedValue,
+) -> Result<RkyvSerializedValue, AnyError> {
+    let execution_state_handle = {
+        let op_state = state.borrow();
+        let my_op_state: &Arc<Mutex<MyOpState>> = (*op_state).borrow();
+        let my_op_state = my_op_state.lock().unwrap();
+        my_op_state.execution_state_handle.clone()
+    };
+
+    let new_exec_state = {
+        let exec_state = execution_state_handle.lock().unwrap();
+        exec_state.clone()
+    };
+
+    let schema = match schema {
+        RkyvSerializedValue::Null => None,
+        schema => Some(schema),
+    };
+    let (answer, mut result_execution_state) = new_exec_state.request_input(&prompt, schema).await?;
+
+    // continue the evaluation of this cell from the state where the answer was recorded
+    let mut exec_state = execution_state_handle.lock().unwrap();
+    std::mem::swap(&mut *exec_state, &mut result_execution_state);
+    Ok(answer)
+}
+
 
 #[op2]
 #[serde]
@@ -180,7 +427,7 @@ fn op_save_result<'scope>(
     let op_state = state.borrow();
     let my_op_state: &Arc<Mutex<MyOpState>> = op_state.borrow();
     let mut my_op_state = my_op_state.lock().unwrap();
-    my_op_state.output = Some(val);
+    my_op_state.output = Some(integral_js_numbers(val));
     Ok(())
 }
 
@@ -209,7 +456,7 @@ fn op_save_result_object<'scope>(
     let mut my_op_state = my_op_state.lock().unwrap();
     let mut output = RkyvObjectBuilder::new();
     for (key, value) in kwargs {
-        output = output.insert_value(&key, value);
+        output = output.insert_value(&key, integral_js_numbers(value));
     }
     // TODO: union with the existing value if there is one
     my_op_state.output = Some(output.build());
@@ -217,13 +464,14 @@ fn op_save_result_object<'scope>(
 }
 
 
+/// Invoke a function of the cell with the arguments of the payload. The result is returned as it is, to be
+/// saved by `Chidori.saveValue` like any other value of the cell.
 #[op2(reentrant)]
-#[serde]
 fn op_invoke_function<'scope>(
     scope: &mut v8::HandleScope<'scope>,
     state: Rc<RefCell<OpState>>,
     input: v8::Local<v8::Function>,
-) -> Result<RkyvSerializedValue, AnyError> {
+) -> Result<v8::Local<'scope, v8::Value>, AnyError> {
     let global = scope.get_current_context().global(scope);
 
     // TODO: handle async functions
@@ -248,14 +496,7 @@ fn op_invoke_function<'scope>(
             args.extend(
                 args_vec
                     .into_iter()
-                    .map(|(_, v)|
-                        deno_core::_ops::RustToV8Fallible::to_v8_fallible(
-                            deno_core::_ops::RustToV8Marker::<deno_core::_ops::SerdeMarker, _>::from(
-                                v,
-                            ),
-                            scope,
-                        ).unwrap()
-                    ),
+                    .map(|(_, v)| rkyv_to_v8(scope, v).unwrap()),
             );
         }
 
@@ -263,14 +504,7 @@ fn op_invoke_function<'scope>(
             payload_map.get("kwargs")
         {
             for (k, v) in kwargs_map.iter() {
-                kwargs.push((k,
-                             deno_core::_ops::RustToV8Fallible::to_v8_fallible(
-                                 deno_core::_ops::RustToV8Marker::<deno_core::_ops::SerdeMarker, _>::from(
-                                     v,
-                                 ),
-                                 scope,
-                             ).unwrap()
-                ));
+                kwargs.push((k, rkyv_to_v8(scope, v).unwrap()));
             }
         }
     }
@@ -279,7 +513,6 @@ fn op_invoke_function<'scope>(
     let result = input.call(scope, global.into(), args.as_slice());
 
     if let Some(result) = result {
-        let result = serde_v8_to_rkyv(scope, result).unwrap();
         Ok(result)
     } else {
         Err(anyhow::Error::msg("Failure".to_string()))
@@ -301,17 +534,19 @@ fn op_set_globals<'scope>(
     let my_op_state: &Arc<Mutex<MyOpState>> = op_state.borrow();
     let mut my_op_state = my_op_state.lock().unwrap();
 
+    // Cancelling the cell terminates the isolate evaluating it
+    let isolate_handle = scope.thread_safe_handle();
+    let execution_node_id = my_op_state.execution_state_handle.lock().unwrap().chronology_id;
+    on_cancel(execution_node_id, move || {
+        isolate_handle.terminate_execution();
+    });
+
     // put globals into the global scope before invoking
     if let RkyvSerializedValue::Object(ref payload_map) = my_op_state.payload {
         if let Some(RkyvSerializedValue::Object(globals_map)) = payload_map.get("globals") {
             for (key, value) in globals_map {
                 let key = deno_core::v8::String::new(scope, key).unwrap();
-                if let Ok(value) = match deno_core::_ops::RustToV8Fallible::to_v8_fallible(
-                    deno_core::_ops::RustToV8Marker::<deno_core::_ops::SerdeMarker, _>::from(
-                        value,
-                    ),
-                    scope,
-                ) {
+                if let Ok(value) = match rkyv_to_v8(scope, value) {
                     // Create a new property in the global object
                     Ok(v) => Ok(v),
                     Err(rv_err) => {
@@ -425,6 +660,7 @@ pub async fn source_code_run_deno(
     source_code: &String,
     payload: &RkyvSerializedValue,
     function_invocation: &Option<String>,
+    permissions: &CellPermissions,
 ) -> anyhow::Result<(
     Result<RkyvSerializedValue, ExecutionStateErrors>,
     Vec<String>,
@@ -435,10 +671,12 @@ pub async fn source_code_run_deno(
     let source_code = source_code.clone();
     let function_invocation = function_invocation.clone();
     let payload = payload.clone();
+    let permissions = permissions.clone();
     let (tx, rx) = std::sync::mpsc::channel();
     // let (tx, rx) = tokio::sync::oneshot::channel();
 
     std::thread::spawn(move || {
+        let early_tx = tx.clone();
         let thread_result = (|| -> anyhow::Result<(
             Result<RkyvSerializedValue, ExecutionStateErrors>,
             Vec<String>,
@@ -467,7 +705,8 @@ pub async fn source_code_run_deno(
                 payload: payload.clone(),
                 cell_depended_values,
                 functions: Default::default(),
-                execution_state_handle
+                execution_state_handle,
+                streams: vec![],
             }));
 
             let my_op_state_clone = my_op_state.clone();
@@ -477,6 +716,14 @@ pub async fn source_code_run_deno(
                     Box::new([
                         op_set_globals(),
                         op_call_rust(),
+                        op_stream_create(),
+                        op_stream_push(),
+                        op_stream_close(),
+                        op_stream_open(),
+                        op_stream_next(),
+                        op_stream_close_reader(),
+                        op_emit_event(),
+                        op_request_input(),
                         op_assert_eq(),
                         op_save_result(),
                         op_save_result_object(),
@@ -498,14 +745,24 @@ pub async fn source_code_run_deno(
           const { ops } = core;
           const op_assert_eq = Deno.core.ops.ops_assert_eq;
           const op_call_rust = Deno.core.ops.op_call_rust;
+          const op_emit_event = Deno.core.ops.op_emit_event;
+          const op_request_input = Deno.core.ops.op_request_input;
           const op_save_result_object = Deno.core.ops.op_save_result_object;
           const op_save_result = Deno.core.ops.op_save_result;
           const op_invoke_function = Deno.core.ops.op_invoke_function;
           const op_console_log = Deno.core.ops.op_console_log;
           const op_console_err = Deno.core.ops.op_console_err;
+          const op_stream_create = Deno.core.ops.op_stream_create;
+          const op_stream_push = Deno.core.ops.op_stream_push;
+          const op_stream_close = Deno.core.ops.op_stream_close;
+          const op_stream_open = Deno.core.ops.op_stream_open;
+          const op_stream_next = Deno.core.ops.op_stream_next;
+          const op_stream_close_reader = Deno.core.ops.op_stream_close_reader;
 
           globalThis.op_invoke_function = op_invoke_function;
-          globalThis.op_call_rust = op_call_rust;
+          globalThis.op_call_rust = async (name, args, kwargs) => fromChidoriValue(
+              await op_call_rust(name, args.map(toChidoriValue), toChidoriValue(kwargs))
+          );
 
           function argsToMessage(...args) {
               return args.map((arg) => JSON.stringify(arg)).join(" ");
@@ -522,15 +779,157 @@ pub async fn source_code_run_deno(
               },
           };
 
+          const registeredTests = [];
+
+          // Iterates an async iterator or generator into a new stream, waiting whenever its readers fall behind
+          const streamFrom = (iterable) => {
+              const id = op_stream_create();
+              (async () => {
+                  try {
+                      for await (const chunk of iterable) {
+                          await op_stream_push(id, toChidoriValue(chunk));
+                      }
+                      op_stream_close(id, null);
+                  } catch (e) {
+                      op_stream_close(id, toChidoriValue(e instanceof Error ? e : new Error(String(e))));
+                  }
+              })();
+              return id;
+          };
+
+          // Reads a stream produced by another cell, every loop over it starts from its first chunk
+          const openStream = (id) => ({
+              __chidori_stream__: id,
+              [Symbol.asyncIterator]: () => {
+                  const reader = op_stream_open(id);
+                  return {
+                      next: async () => {
+                          const chunk = await op_stream_next(reader);
+                          return chunk.done ? { done: true, value: undefined } : { done: false, value: fromChidoriValue(chunk.value) };
+                      },
+                      return: async () => {
+                          op_stream_close_reader(reader);
+                          return { done: true, value: undefined };
+                      },
+                  };
+              },
+          });
+
+          // Errors, bytes, dates, embeddings and streams are saved in the form they are read as by Rust, as every other value is
+          const toChidoriValue = (value) => {
+              if (value instanceof Error) {
+                  return {
+                      __chidori_error__: {
+                          type: value.name,
+                          message: value.message,
+                          traceback: value.traceback ?? value.stack ?? null,
+                          cell: value.cell ?? null,
+                          range: value.range ?? null,
+                      }
+                  };
+              }
+              if (value instanceof Uint8Array) {
+                  return { __chidori_bytes__: btoa(Array.from(value, (byte) => String.fromCharCode(byte)).join("")) };
+              }
+              if (value instanceof Float32Array) {
+                  return { __chidori_embedding__: Array.from(value) };
+              }
+              if (value instanceof Date) {
+                  return { __chidori_datetime__: value.toISOString() };
+              }
+              if (value !== null && typeof value === "object" && typeof value.__chidori_stream__ === "number") {
+                  return { __chidori_stream__: value.__chidori_stream__ };
+              }
+              if (value !== null && typeof value === "object"
+                  && (typeof value[Symbol.asyncIterator] === "function" || Object.prototype.toString.call(value) === "[object Generator]")) {
+                  return { __chidori_stream__: streamFrom(value) };
+              }
+              if (Array.isArray(value)) {
+                  return value.map(toChidoriValue);
+              }
+              if (value !== null && typeof value === "object" && Object.getPrototypeOf(value) === Object.prototype) {
+                  return Object.fromEntries(Object.entries(value).map(([key, item]) => [key, toChidoriValue(item)]));
+              }
+              return value;
+          };
+
+          // Values returned by Rust hold bytes, dates and embeddings in their JSON form
+          const fromChidoriValue = (value) => {
+              if (Array.isArray(value)) {
+                  return value.map(fromChidoriValue);
+              }
+              if (value !== null && typeof value === "object") {
+                  const keys = Object.keys(value);
+                  if (keys.length === 1) {
+                      const marked = value[keys[0]];
+                      if (keys[0] === "__chidori_bytes__" && typeof marked === "string") {
+                          return Uint8Array.from(atob(marked), (c) => c.charCodeAt(0));
+                      }
+                      if (keys[0] === "__chidori_datetime__" && typeof marked === "string") {
+                          return new Date(marked);
+                      }
+                      if (keys[0] === "__chidori_embedding__" && Array.isArray(marked)) {
+                          return Float32Array.from(marked);
+                      }
+                      if (keys[0] === "__chidori_stream__" && typeof marked === "number") {
+                          return openStream(marked);
+                      }
+                  }
+                  return Object.fromEntries(Object.entries(value).map(([key, item]) => [key, fromChidoriValue(item)]));
+              }
+              return value;
+          };
+
           globalThis.Chidori = {
               assertEq: (a, b) => {
                   return a == b;
               },
+              // Accepts the same forms as Deno.test: (name, fn), ({ name, fn }) or a named function
+              test: (nameOrDefinition, fn) => {
+                  if (typeof nameOrDefinition === "function") {
+                      registeredTests.push({ name: nameOrDefinition.name, fn: nameOrDefinition });
+                  } else if (typeof nameOrDefinition === "object") {
+                      registeredTests.push({ name: nameOrDefinition.name, fn: nameOrDefinition.fn });
+                  } else {
+                      registeredTests.push({ name: nameOrDefinition, fn });
+                  }
+              },
+              // Reports in the same format as Python's unittest so that test cells of either language are read alike
+              runTests: async () => {
+                  if (registeredTests.length === 0) {
+                      return;
+                  }
+                  let failures = 0;
+                  for (const { name, fn } of registeredTests) {
+                      try {
+                          await fn();
+                          console.error(`test ${name} ... ok`);
+                      } catch (e) {
+                          failures += 1;
+                          console.error(`test ${name} ... FAILED: ${e && e.message ? e.message : e}`);
+                      }
+                  }
+                  console.error(`Ran ${registeredTests.length} test${registeredTests.length === 1 ? "" : "s"}`);
+                  console.error(failures === 0 ? "OK" : `FAILED (failures=${failures})`);
+              },
+              // Markers read by static analysis, registering the function they are called in as a handler
+              // of the named event or as emitting its return value as the named event
+              onEvent: (_eventName) => {},
+              emitAs: (_eventName) => {},
+              // Resolves once every handler of the event has run
+              emitEvent: async (eventName, payload) => {
+                  await op_emit_event(eventName, payload === undefined ? null : toChidoriValue(payload));
+              },
+              // Suspends the cell until a person answers, resolving with the answer
+              input: async (prompt, schema) => {
+                  return fromChidoriValue(await op_request_input(prompt, schema === undefined ? null : schema));
+              },
+              openStream,
               saveValue: (val) => {
-                  op_save_result(val);
+                  op_save_result(toChidoriValue(val));
               },
               saveOutput: (object) => {
-                  op_save_result_object(object);
+                  op_save_result_object(toChidoriValue(object));
               }
           };
 
@@ -563,10 +962,16 @@ pub async fn source_code_run_deno(
             // );
 
 
+            // Deno's namespace is replaced when the runtime bootstraps, after the extension above has run, so the
+            // shim collecting tests through Chidori.test is registered on it by the cell itself
+            let test_shim = "globalThis.Deno.test = Chidori.test;";
+
             // Set global variables, provide specialized ops
             let source = if let Some(func_name) = function_invocation {
                 let mut source = String::new();
                 source.push_str("\n");
+                source.push_str(test_shim);
+                source.push_str("\n");
                 source.push_str(&source_code);
                 source.push_str("\n");
                 source.push_str(&format!(
@@ -580,6 +985,9 @@ pub async fn source_code_run_deno(
                 source.push_str("\n");
                 source.push_str("export const chidoriResult = {};");
                 source.push_str("\n");
+                // Tests are collected while the cell evaluates and run once it has finished
+                source.push_str(test_shim);
+                source.push_str("\n");
                 source.push_str(&source_code);
                 for (name, report_item) in &report.triggerable_functions {
                     source.push_str("\n");
@@ -598,6 +1006,8 @@ pub async fn source_code_run_deno(
                     source.push_str("\n");
                 }
                 source.push_str("\n");
+                source.push_str("await Chidori.runTests();");
+                source.push_str("\n");
                 source.push_str("Chidori.saveOutput(chidoriResult);");
                 source.push_str("\n");
                 source
@@ -605,13 +1015,13 @@ pub async fn source_code_run_deno(
 
 
             let mut flags = deno::args::Flags::default();
-            // TODO: give user control over this in configuration
-            // TODO: allow_net is causing this to block our execution entirely
-            flags.permissions.allow_net = Some(vec![]);
-            flags.permissions.allow_env = Some(vec![]);
-            flags.permissions.allow_read = Some(vec![]);
-            flags.permissions.allow_write = Some(vec![]);
-            flags.permissions.allow_run = Some(vec![]);
+            flags.permissions.allow_net = deno_allow_list(&permissions.net);
+            flags.permissions.allow_env = deno_allow_list(&permissions.env);
+            flags.permissions.allow_read = deno_allow_list(&permissions.read);
+            flags.permissions.allow_write = deno_allow_list(&permissions.write);
+            flags.permissions.allow_run = deno_allow_list(&permissions.run);
+            // Access that was not granted fails instead of prompting, which would block the cell
+            flags.permissions.no_prompt = true;
             let factory = deno::factory::CliFactory::from_flags(Arc::new(flags));
             let cli_options = factory.cli_options()?;
             let file_fetcher = factory.file_fetcher()?;
@@ -648,19 +1058,22 @@ pub async fn source_code_run_deno(
                     )
                     .await?;
 
-                let exit_code = worker.run().await?;
+                worker.execute_main_module().await?;
+                // Cells that output streams hand over their result before the streams are read to the end, the
+                // worker keeps producing them while downstream cells read them
+                if !my_op_state.lock().unwrap().streams.is_empty() {
+                    let _ = early_tx.send(Ok(deno_cell_result(&my_op_state)));
+                }
+                let mut worker = worker.into_main_worker();
+                worker.dispatch_load_event()?;
+                worker.run_event_loop(false).await?;
+                worker.dispatch_unload_event()?;
                 Ok::<(), anyhow::Error>(())
-            }).map_err(|e| {
-                // TODO: map error
-                dbg!(&e);
-                e
+            }).map_err(|e| match e.downcast_ref::<deno_core::error::JsError>() {
+                Some(js_error) => anyhow::Error::new(js_error_to_error_value(js_error)),
+                None => e,
             })?;
-            let mut my_op_state = my_op_state.lock().unwrap();
-            let output = Ok(my_op_state.output.clone().unwrap_or(RkyvSerializedValue::Null));
-            let execution_state = my_op_state.execution_state_handle.lock().unwrap().clone();
-            let stdout = my_op_state.stdout.clone();
-            let stderr = my_op_state.stderr.clone();
-            Ok((output, stdout, stderr, execution_state))
+            Ok(deno_cell_result(&my_op_state))
         })();
 
         if tx.send(thread_result).is_err() {
@@ -679,6 +1092,20 @@ pub async fn source_code_run_deno(
     Ok(result_of_thread)
 }
 
+fn deno_cell_result(my_op_state: &Arc<Mutex<MyOpState>>) -> (
+    Result<RkyvSerializedValue, ExecutionStateErrors>,
+    Vec<String>,
+    Vec<String>,
+    ExecutionState
+) {
+    let my_op_state = my_op_state.lock().unwrap();
+    let output = Ok(my_op_state.output.clone().unwrap_or(RkyvSerializedValue::Null));
+    let execution_state = my_op_state.execution_state_handle.lock().unwrap().clone();
+    let stdout = my_op_state.stdout.clone();
+    let stderr = my_op_state.stderr.clone();
+    (output, stdout, stderr, execution_state)
+}
+
 fn replace_identifier(code: &str, old_identifier: &str, new_identifier: &str) -> String {
     let pattern = if old_identifier.starts_with('$') {
         format!(r"(^|[^a-zA-Z0-9_$])({})(?![a-zA-Z0-9_$])", regex::escape(old_identifier))
@@ -700,6 +1127,7 @@ fn replace_identifier(code: &str, old_identifier: &str, new_identifier: &str) ->
 mod tests {
     use super::*;
     use crate::cells::{SupportedLanguage, TextRange};
+    use crate::execution::execution::streams::stream_snapshot;
     use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
     use indoc::indoc;
     use uuid::Uuid;
@@ -721,6 +1149,7 @@ mod tests {
                                 "#
                                 }),
                 function_invocation: None,
+                policy: Default::default(),
             }, TextRange::default()), id_a)?;
         let result = source_code_run_deno(
             &state,
@@ -728,6 +1157,7 @@ mod tests {
             &RkyvObjectBuilder::new()
                 .build(),
             &None,
+            &CellPermissions::default(),
         ).await;
         assert_eq!(
             result.unwrap(),
@@ -755,6 +1185,7 @@ mod tests {
                 )
                 .build(),
             &None,
+            &CellPermissions::default(),
         ).await;
         assert_eq!(
             result.unwrap(),
@@ -769,7 +1200,7 @@ mod tests {
     #[tokio::test]
     async fn test_source_code_run_deno_success() {
         let source_code = String::from("const x = 42;");
-        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None).await;
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &CellPermissions::default()).await;
         assert_eq!(
             result.unwrap(),
             (
@@ -783,14 +1214,14 @@ mod tests {
     #[tokio::test]
     async fn test_source_code_run_deno_failure() {
         let source_code = String::from("throw new Error('Test Error');");
-        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None).await;
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &CellPermissions::default()).await;
         assert!(result.is_err());
     }
 
     #[tokio::test]
     async fn test_source_code_run_deno_json_serialization() {
         let source_code = String::from("const obj  = {foo: 'bar'};");
-        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None).await;
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &CellPermissions::default()).await;
         assert_eq!(
             result.unwrap(),
             (
@@ -808,7 +1239,7 @@ mod tests {
     #[tokio::test]
     async fn test_source_code_run_deno_expose_global_variables() {
         let source_code = String::from("const x = 30;");
-        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None).await;
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &CellPermissions::default()).await;
         assert_eq!(
             result.unwrap(),
             (
@@ -825,7 +1256,7 @@ mod tests {
         let args = RkyvObjectBuilder::new()
             .insert_object("args", RkyvObjectBuilder::new().insert_number("0", 10).insert_number("1", 20))
             .build();
-        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &args, &Some("demonstrationAdd".to_string())).await;
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &args, &Some("demonstrationAdd".to_string()), &CellPermissions::default()).await;
         assert_eq!(
             result.unwrap(),
             (
@@ -836,6 +1267,93 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_wide_numbers_bytes_datetimes_and_embeddings_round_trip() {
+        let source_code = String::from(r#"
+function roundTrip(big, precise, data, when, embedding) {
+    if (big !== 2 ** 40 || precise !== 0.1 + 0.2) throw new Error("numbers were truncated");
+    if (!(data instanceof Uint8Array) || data[1] !== 255) throw new Error("bytes were not a Uint8Array");
+    if (!(when instanceof Date) || when.toISOString() !== "2024-05-31T16:08:37.123Z") throw new Error("date was not a Date");
+    if (!(embedding instanceof Float32Array) || embedding[1] !== -1.5) throw new Error("embedding was not a Float32Array");
+    return [big * 2, precise, new Uint8Array([...data, 33]), new Date(when.getTime() + 1000), embedding.map((x) => x * 2)];
+}"#);
+        let args = RkyvObjectBuilder::new()
+            .insert_object("args", RkyvObjectBuilder::new()
+                .insert_number("0", 1 << 40)
+                .insert_value("1", RkyvSerializedValue::Float(0.1 + 0.2))
+                .insert_value("2", RkyvSerializedValue::Bytes(vec![0, 255]))
+                .insert_value("3", RkyvSerializedValue::DateTime(1_717_171_717_123_000))
+                .insert_value("4", RkyvSerializedValue::Embedding(vec![0.25, -1.5])))
+            .build();
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &args, &Some("roundTrip".to_string()), &CellPermissions::default()).await;
+        assert_eq!(
+            result.unwrap().0.unwrap(),
+            RkyvSerializedValue::Array(vec![
+                RkyvSerializedValue::Number(1 << 41),
+                RkyvSerializedValue::Float(0.1 + 0.2),
+                RkyvSerializedValue::Bytes(vec![0, 255, 33]),
+                RkyvSerializedValue::DateTime(1_717_171_718_123_000),
+                RkyvSerializedValue::Embedding(vec![0.5, -3.0]),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_permissions_are_granted_per_cell() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("chidori_permissions_{}.txt", Uuid::now_v7()));
+        std::fs::write(&path, "granted")?;
+        let path = path.to_string_lossy().to_string();
+        let source_code = format!("const contents = Deno.readTextFileSync({:?});", path);
+
+        let denied = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &CellPermissions::default()).await;
+        let error = denied.unwrap_err();
+        let error = error.downcast_ref::<ErrorValue>().expect("Expected the error the cell raised");
+        assert_eq!(error.ty, "PermissionDenied");
+        assert!(error.message.contains(&format!("permissions:\n  read: [{:?}]", path)));
+
+        let permissions = CellPermissions {
+            read: Some(PermissionGrant::Only(vec![path.clone()])),
+            ..Default::default()
+        };
+        let (output, _, _, _) = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &permissions).await?;
+        assert_eq!(output?, RkyvObjectBuilder::new().insert_string("contents", "granted".to_string()).build());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_generators_are_streamed_to_other_cells() -> anyhow::Result<()> {
+        let producer = String::from(r#"
+async function* produce() {
+    for (let i = 0; i < 40; i++) {
+        yield i;
+    }
+}
+const numbers = produce();
+"#);
+        let (output, _, _, _) = source_code_run_deno(&ExecutionState::new_with_random_id(), &producer, &RkyvSerializedValue::Null, &None, &CellPermissions::default()).await?;
+        let output = output?;
+        let RkyvSerializedValue::Object(streams) = &output else { panic!("Expected an object, received {:?}", output) };
+        let Some(RkyvSerializedValue::StreamPointer(numbers)) = streams.get("numbers").cloned() else { panic!("numbers was not streamed") };
+
+        let consumer = String::from(r#"
+let total = 0;
+for await (const n of numbers) {
+    total += n;
+}
+"#);
+        let payload = RkyvObjectBuilder::new()
+            .insert_object("globals", RkyvObjectBuilder::new()
+                .insert_value("numbers", RkyvSerializedValue::StreamPointer(numbers)))
+            .build();
+        let (output, _, _, _) = source_code_run_deno(&ExecutionState::new_with_random_id(), &consumer, &payload, &None, &CellPermissions::default()).await?;
+        let RkyvSerializedValue::Object(output) = output? else { panic!("Expected an object") };
+        assert_eq!(output.get("total"), Some(&RkyvSerializedValue::Number(780)));
+        // Once ended and read, the stream is no longer held
+        assert!(stream_snapshot(numbers).is_none());
+        Ok(())
+    }
+
 
     #[tokio::test]
     async fn test_console_log_console_err_behaviors() {
@@ -845,7 +1363,7 @@ mod tests {
         "#);
         let args = RkyvObjectBuilder::new()
             .build();
-        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &args, &None).await;
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &args, &None, &CellPermissions::default()).await;
         assert_eq!(
             result.unwrap(),
             (
@@ -885,7 +1403,7 @@ mod tests {
     #[tokio::test]
     async fn test_typescript_basic() {
         let source_code = String::from("const x: number = 42;");
-        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None).await;
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &CellPermissions::default()).await;
         assert_eq!(
             result.unwrap(),
             (
@@ -905,7 +1423,7 @@ mod tests {
         }
         const person: Person = { name: "Alice", age: 30 };
     "#);
-        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None).await;
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &CellPermissions::default()).await;
         assert_eq!(
             result.unwrap(),
             (
@@ -931,7 +1449,7 @@ mod tests {
         }
         const result = identity<string>("TypeScript");
     "#);
-        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None).await;
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &CellPermissions::default()).await;
         assert_eq!(
             result.unwrap(),
             (
@@ -953,7 +1471,7 @@ mod tests {
         }
         const data = await fetchData();
     "#);
-        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None).await;
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &CellPermissions::default()).await;
         assert_eq!(
             result.unwrap(),
             (
@@ -977,7 +1495,7 @@ mod tests {
         }
         const selectedColor: Color = Color.Green;
     "#);
-        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None).await;
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &CellPermissions::default()).await;
         assert_eq!(
             result.unwrap(),
             (
@@ -7,10 +7,11 @@ use chidori_static_analysis::language::python::parse::{
 
 use futures_util::FutureExt;
 use pyo3::prelude::*;
-use pyo3::types::{IntoPyDict, PyCFunction, PyDict, PyList, PySet, PyTuple};
+use pyo3::exceptions::{PyBaseException, PyException, PyKeyboardInterrupt, PyRuntimeError, PyStopAsyncIteration, PyStopIteration};
+use pyo3::types::{IntoPyDict, PyByteArray, PyBytes, PyCFunction, PyDict, PyList, PySet, PyTraceback, PyTuple, PyType};
 use std::sync::mpsc::{self, Sender};
 
-use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
+use crate::execution::primitives::serialized_value::{ErrorValue, RkyvObjectBuilder, RkyvSerializedValue};
 use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::{env, mem};
@@ -20,7 +21,7 @@ use once_cell::sync::OnceCell;
 use pyo3_asyncio::generic;
 use tokio::runtime::Runtime;
 use chidori_static_analysis::language::Report;
-use crate::cells::{CellTypes, CodeCell, LLMPromptCell};
+use crate::cells::{CellTypes, CodeCell, LLMPromptCell, TextRange};
 use crate::execution::execution::ExecutionState;
 
 use std::path::{Path, PathBuf};
@@ -39,73 +40,136 @@ use sha1::{Sha1, Digest};
 use tracing::{debug, Id, Span};
 use uuid::Uuid;
 use crate::execution::execution::execution_state::{EnclosedState, ExecutionStateErrors};
+use crate::execution::execution::cancellation::on_cancel;
+use crate::library::std::code::python_venv::{get_or_create_venv, requirements_from_script_metadata};
+use crate::execution::execution::streams::{close_stream, create_stream, open_reader, push_chunk, StreamReader};
 
 static SOURCE_CODE_RUN_COUNTER: Lazy<AtomicUsize> = Lazy::new(|| AtomicUsize::new(0));
 static CURRENT_PYTHON_EXECUTION_ID: Lazy<AtomicUsize> = Lazy::new(|| AtomicUsize::new(0));
 
-fn install_dependencies_from_requirements(requirements_dir: &str, venv_path: &str) -> anyhow::Result<()> {
-    let requirements_path = Path::new(requirements_dir).join("requirements.txt");
+/// Errors received from other cells keep the traceback they were raised with, it is held by the exception
+/// they are given to cells as, along with the cell they were raised in.
+const PYTHON_ERROR_TRACEBACK_ATTR: &str = "traceback_text";
+const PYTHON_ERROR_CELL_ATTR: &str = "cell";
+const PYTHON_ERROR_RANGE_ATTR: &str = "source_range";
 
-    if !requirements_path.exists() {
-        return Err(anyhow!("requirements.txt not found in the specified directory"));
-    }
-
-    let uv_path = which::which("uv").map_err(|_| anyhow!("uv not found in PATH"))?;
+fn format_python_traceback(traceback: Option<&PyTraceback>) -> Option<String> {
+    traceback.and_then(|traceback| traceback.format().ok())
+}
 
-    // Use 'uv pip sync' to install dependencies
-    let status = Command::new(uv_path)
-        .arg("pip")
-        .arg("sync")
-        .arg(requirements_path)
-        .arg("--virtualenv")
-        .arg(venv_path)
-        .status()?;
+fn python_exception_to_error_value(exception: &PyAny) -> ErrorValue {
+    let ty = exception.get_type().name().unwrap_or("Exception").to_string();
+    let message = exception.str().map(|s| s.to_string()).unwrap_or_default();
+    let mut error = ErrorValue::new(ty, message);
+    error.traceback = exception.getattr(PYTHON_ERROR_TRACEBACK_ATTR).ok()
+        .and_then(|traceback| traceback.extract::<String>().ok())
+        .or_else(|| format_python_traceback(exception.getattr("__traceback__").ok().and_then(|tb| tb.downcast::<PyTraceback>().ok())));
+    error.cell = exception.getattr(PYTHON_ERROR_CELL_ATTR).ok().and_then(|cell| cell.extract::<String>().ok());
+    error.range = exception.getattr(PYTHON_ERROR_RANGE_ATTR).ok()
+        .and_then(|range| range.extract::<(usize, usize)>().ok())
+        .map(|(start, end)| TextRange { start, end });
+    error
+}
 
-    if status.success() {
-        Ok(())
-    } else {
-        Err(anyhow!("Failed to install dependencies from requirements.txt"))
+/// The error a cell failed with, keeping the type, message and traceback of the exception it raised.
+fn python_error_to_error_value(py: Python, err: &PyErr) -> ErrorValue {
+    let mut error = python_exception_to_error_value(err.value(py));
+    if error.traceback.is_none() {
+        error.traceback = format_python_traceback(err.traceback(py));
     }
+    error
 }
 
-fn get_or_create_default_venv(v: &PythonVersionInfo) -> anyhow::Result<PathBuf> {
-    let home_dir = env::var("CHIDORI_HOME_DIRECTORY").or_else(|_| env::var("HOME")).or_else(|_| env::var("USERPROFILE"))?;
-    let default_venv_dir = PathBuf::from(home_dir).join(".chidori_venvs");
-
-    if !default_venv_dir.exists() {
-        std::fs::create_dir_all(&default_venv_dir)?;
-    }
+/// Give an error to a cell as an exception of its original type when that is a builtin exception,
+/// or as an `Exception` otherwise.
+fn error_value_to_python_exception(py: Python, error: &ErrorValue) -> PyResult<PyObject> {
+    let builtin = py.import("builtins")?.getattr(error.ty.as_str()).ok()
+        .and_then(|ty| ty.downcast::<PyType>().ok())
+        .filter(|ty| ty.is_subclass_of::<PyBaseException>().unwrap_or(false));
+    let exception = match builtin.map(|ty| ty.call1((error.message.clone(),))) {
+        Some(Ok(exception)) => exception,
+        // Some builtin exceptions take more than a message to construct
+        _ => py.get_type::<PyException>().call1((error.to_string(),))?,
+    };
+    exception.setattr(PYTHON_ERROR_TRACEBACK_ATTR, error.traceback.clone())?;
+    exception.setattr(PYTHON_ERROR_CELL_ATTR, error.cell.clone())?;
+    exception.setattr(PYTHON_ERROR_RANGE_ATTR, error.range.as_ref().map(|range| (range.start, range.end)))?;
+    Ok(exception.into_py(py))
+}
 
-    let venv_name = format!("chidori_venv_{}", Uuid::now_v7());
-    let venv_path = default_venv_dir.join(venv_name);
+/// Microseconds since the Unix epoch of a `datetime.datetime`, naive date times are read as UTC.
+fn python_datetime_to_micros(p: &PyAny) -> PyResult<i64> {
+    let seconds: i64 = p.py().import("calendar")?
+        .call_method1("timegm", (p.call_method0("utctimetuple")?,))?
+        .extract()?;
+    let micros: i64 = p.getattr("microsecond")?.extract()?;
+    Ok(seconds * 1_000_000 + micros)
+}
 
-    let uv_path = which::which("uv").map_err(|_| anyhow::anyhow!("uv not found in PATH"))?;
-    let status = Command::new(uv_path)
-        .arg("venv")
-        .arg("--python")
-        .arg(format!("{}.{}", v.major, v.minor))
-        .arg(&venv_path)
-        .status()?;
+fn micros_to_python_datetime(py: Python, micros: i64) -> PyResult<PyObject> {
+    let datetime = py.import("datetime")?;
+    let utc = datetime.getattr("timezone")?.getattr("utc")?;
+    let epoch = datetime.getattr("datetime")?.call1((1970, 1, 1, 0, 0, 0, 0, utc))?;
+    let delta = datetime.getattr("timedelta")?.call((), Some([("microseconds", micros)].into_py_dict(py)))?;
+    Ok(epoch.call_method1("__add__", (delta,))?.into_py(py))
+}
 
-    if !status.success() {
-        return Err(anyhow::anyhow!("Failed to create default virtualenv"));
+/// Single precision float arrays, `array.array('f')` or one dimensional float32 numpy arrays, are
+/// embeddings, other arrays are converted as lists.
+fn python_array_to_rkyv_serialized_value(p: &PyAny) -> PyResult<RkyvSerializedValue> {
+    let is_embedding = match p.get_type().name()? {
+        "array" => p.getattr("typecode")?.extract::<String>()? == "f",
+        _ => p.getattr("dtype")?.getattr("name")?.extract::<String>()? == "float32"
+            && p.getattr("ndim")?.extract::<usize>()? == 1,
+    };
+    let items = p.call_method0("tolist")?;
+    if is_embedding {
+        Ok(RkyvSerializedValue::Embedding(items.extract::<Vec<f32>>()?))
+    } else {
+        Ok(pyany_to_rkyv_serialized_value(items))
     }
-
-    Ok(venv_path)
 }
 
-
 fn pyany_to_rkyv_serialized_value(p: &PyAny) -> RkyvSerializedValue {
+    if p.is_instance_of::<PyBaseException>() {
+        return RkyvSerializedValue::Error(python_exception_to_error_value(p));
+    }
     match p.get_type().name() {
         Ok(s) => match s {
             "int" => {
-                let val = p.extract::<i32>().unwrap();
-                RkyvSerializedValue::Number(val)
+                match p.extract::<i64>() {
+                    Ok(val) => RkyvSerializedValue::Number(val),
+                    // Integers beyond 64 bits are kept as closely as a float allows
+                    Err(_) => RkyvSerializedValue::Float(p.extract::<f64>().unwrap()),
+                }
             }
             "float" => {
-                let val = p.extract::<f32>().unwrap();
+                let val = p.extract::<f64>().unwrap();
                 RkyvSerializedValue::Float(val)
             }
+            "bytes" => {
+                let val = p.downcast::<PyBytes>().unwrap();
+                RkyvSerializedValue::Bytes(val.as_bytes().to_vec())
+            }
+            "bytearray" => {
+                let val = p.downcast::<PyByteArray>().unwrap();
+                RkyvSerializedValue::Bytes(val.to_vec())
+            }
+            "datetime" => {
+                RkyvSerializedValue::DateTime(python_datetime_to_micros(p).unwrap())
+            }
+            "array" | "ndarray" => {
+                python_array_to_rkyv_serialized_value(p).unwrap()
+            }
+            "generator" => {
+                RkyvSerializedValue::StreamPointer(stream_python_generator(p, false))
+            }
+            "async_generator" => {
+                RkyvSerializedValue::StreamPointer(stream_python_generator(p, true))
+            }
+            "ChidoriStream" => {
+                RkyvSerializedValue::StreamPointer(p.getattr("id").unwrap().extract::<u32>().unwrap())
+            }
             "str" => {
                 let val = p.extract::<String>().unwrap();
                 RkyvSerializedValue::String(val)
@@ -185,6 +249,16 @@ fn rkyv_serialized_value_to_pyany(py: Python, value: &RkyvSerializedValue) -> Py
             py_dict.into_py(py)
         }
         RkyvSerializedValue::Null => py.None(),
+        RkyvSerializedValue::Bytes(b) => PyBytes::new(py, b).into_py(py),
+        RkyvSerializedValue::StreamPointer(id) => Py::new(py, ChidoriStream { id: *id })
+            .map(|stream| stream.into_py(py))
+            .unwrap_or_else(|_| py.None()),
+        RkyvSerializedValue::DateTime(micros) => micros_to_python_datetime(py, *micros).unwrap_or_else(|_| py.None()),
+        RkyvSerializedValue::Embedding(embedding) => py.import("array")
+            .and_then(|array| array.getattr("array")?.call1(("f", embedding.clone())))
+            .map(|array| array.into_py(py))
+            .unwrap_or_else(|_| py.None()),
+        RkyvSerializedValue::Error(error) => error_value_to_python_exception(py, error).unwrap_or_else(|_| py.None()),
         // TODO: Handle other types
         _ => py.None(),
     }
@@ -214,6 +288,100 @@ fn on_event(py: Python, arg: PyObject) -> PyResult<PyObject> {
     Ok(obj)
 }
 
+/// Marks a function whose return value is emitted as the named event, like on_event this is
+/// read by static analysis and leaves the function unchanged.
+#[pyfunction]
+fn emit_as(py: Python, arg: PyObject) -> PyResult<PyObject> {
+    let identity_func = wrap_pyfunction!(identity_function, py)?;
+    let obj: PyObject = identity_func.into_py(py);
+    Ok(obj)
+}
+
+/// The chidori module is shared by every evaluation, so the evaluation calling into it is identified
+/// by the globals of the calling frame.
+fn calling_execution_state_handle(py: Python, function_name: &str) -> PyResult<Arc<Mutex<ExecutionState>>> {
+    let frame_globals = py.import("sys")?.call_method1("_getframe", (0,))?.getattr("f_globals")?;
+    let exec_id: usize = frame_globals.get_item(PYTHON_EXECUTION_ID_GLOBAL)?.extract()?;
+    PYTHON_EXECUTION_STATE_HANDLES
+        .get(&exec_id)
+        .map(|handle| handle.value().clone())
+        .ok_or_else(|| pyo3::exceptions::PyRuntimeError::new_err(format!("chidori.{} was called outside of a cell evaluation", function_name)))
+}
+
+/// `chidori.emit_event(name, payload)`, routes the event to its handlers and resolves once they have run.
+fn create_python_emit_event_function(py: Python) -> PyResult<&PyCFunction> {
+    PyCFunction::new_closure(
+        py,
+        None,
+        None,
+        |args: &PyTuple, kwargs: Option<&PyDict>| -> PyResult<PyObject> {
+            let py = args.py();
+            let event_name: String = args.get_item(0)?.extract()?;
+            let payload = if args.len() > 1 {
+                pyany_to_rkyv_serialized_value(args.get_item(1)?)
+            } else {
+                RkyvSerializedValue::Null
+            };
+            let execution_state_handle = calling_execution_state_handle(py, "emit_event")?;
+
+            let new_exec_state = {
+                let exec_state = execution_state_handle.lock().unwrap();
+                exec_state.clone()
+            };
+
+            pyo3_asyncio::tokio::future_into_py(py, async move {
+                let (result, mut result_execution_state) = new_exec_state
+                    .emit_event(&event_name, payload, None)
+                    .await.map_err(|e| AnyhowErrWrapper(e))?;
+
+                // continue the evaluation of this cell from the state where the handlers have resolved
+                let mut exec_state = execution_state_handle.lock().unwrap();
+                std::mem::swap(&mut *exec_state, &mut result_execution_state);
+
+                match result {
+                    Ok(()) => PyResult::Ok(Python::with_gil(|py| py.None())),
+                    Err(e) => Err(pyo3::exceptions::PyRuntimeError::new_err(format!("{:}", e))),
+                }
+            }).map(|x| x.into())
+        },
+    )
+}
+
+/// `chidori.input(prompt, schema=None)`, suspends the evaluation until the request is answered and
+/// resolves with the answer.
+fn create_python_input_function(py: Python) -> PyResult<&PyCFunction> {
+    PyCFunction::new_closure(
+        py,
+        None,
+        None,
+        |args: &PyTuple, kwargs: Option<&PyDict>| -> PyResult<PyObject> {
+            let py = args.py();
+            let prompt: String = args.get_item(0)?.extract()?;
+            let schema = match (args.get_item(1).ok(), kwargs.and_then(|kwargs| kwargs.get_item("schema").ok().flatten())) {
+                (Some(schema), _) | (None, Some(schema)) if !schema.is_none() => Some(pyany_to_rkyv_serialized_value(schema)),
+                _ => None,
+            };
+            let execution_state_handle = calling_execution_state_handle(py, "input")?;
+
+            let new_exec_state = {
+                let exec_state = execution_state_handle.lock().unwrap();
+                exec_state.clone()
+            };
+
+            pyo3_asyncio::tokio::future_into_py(py, async move {
+                let (answer, mut result_execution_state) = new_exec_state
+                    .request_input(&prompt, schema)
+                    .await.map_err(|e| AnyhowErrWrapper(e))?;
+
+                // continue the evaluation of this cell from the state where the answer was recorded
+                let mut exec_state = execution_state_handle.lock().unwrap();
+                std::mem::swap(&mut *exec_state, &mut result_execution_state);
+                PyResult::Ok(Python::with_gil(|py| rkyv_serialized_value_to_pyany(py, &answer)))
+            }).map(|x| x.into())
+        },
+    )
+}
+
 /// When called this suspends execution with a long running rust function
 /// we hand back the GIL for other python execution. Invoke is used to execute another
 /// cell's provided function, or a cell as a function.
@@ -239,9 +407,17 @@ fn increment_source_code_run_counter() -> usize {
     SOURCE_CODE_RUN_COUNTER.fetch_add(1, Ordering::SeqCst)
 }
 
+/// Execution state of each running evaluation, keyed by its execution id, for calls into the chidori module
+static PYTHON_EXECUTION_STATE_HANDLES: Lazy<DashMap<usize, Arc<Mutex<ExecutionState>>>> = Lazy::new(|| DashMap::new());
+const PYTHON_EXECUTION_ID_GLOBAL: &str = "__chidori_exec_id__";
 static PYTHON_OUTPUT_MAP: Lazy<Arc<DashMap<usize, DashMap<String, RkyvSerializedValue>>>> = Lazy::new(|| Arc::new(DashMap::new()));
 static PYTHON_LOGGING_BUFFER_STDOUT: Lazy<Arc<DashMap<usize, Vec<String>>>> = Lazy::new(|| Arc::new(DashMap::new()));
 static PYTHON_LOGGING_BUFFER_STDERR: Lazy<Arc<DashMap<usize, Vec<String>>>> = Lazy::new(|| Arc::new(DashMap::new()));
+/// Execution id of the evaluation each thread is running Python code for, keyed by the thread's Python
+/// ident. Only updated with the GIL held, so a thread found here under the GIL is still running that code.
+static RUNNING_PYTHON_EXECUTIONS: Lazy<DashMap<u64, usize>> = Lazy::new(|| DashMap::new());
+/// Number of evaluations using each virtualenv's site-packages, only updated with the GIL held
+static SITE_PACKAGES_IN_USE: Lazy<DashMap<String, usize>> = Lazy::new(|| DashMap::new());
 
 #[pyclass]
 struct LoggingToChannel {
@@ -283,6 +459,132 @@ impl LoggingToChannel {
 }
 
 
+/// A stream produced by another cell, read with `for` or `async for`. Each loop reads the stream from its first chunk.
+#[pyclass]
+struct ChidoriStream {
+    #[pyo3(get)]
+    id: u32,
+}
+
+impl ChidoriStream {
+    fn open(&self) -> PyResult<ChidoriStreamReader> {
+        let reader = open_reader(self.id).map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        Ok(ChidoriStreamReader { reader: Arc::new(tokio::sync::Mutex::new(reader)) })
+    }
+}
+
+#[pymethods]
+impl ChidoriStream {
+    fn __iter__(&self) -> PyResult<ChidoriStreamReader> {
+        self.open()
+    }
+
+    fn __aiter__(&self) -> PyResult<ChidoriStreamReader> {
+        self.open()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<chidori stream {}>", self.id)
+    }
+}
+
+#[pyclass]
+struct ChidoriStreamReader {
+    reader: Arc<tokio::sync::Mutex<StreamReader>>,
+}
+
+#[pymethods]
+impl ChidoriStreamReader {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Waits for the next chunk with the GIL released, so that producers in this interpreter keep running
+    fn __next__(&self, py: Python) -> PyResult<Option<PyObject>> {
+        let reader = self.reader.clone();
+        let next = py.allow_threads(|| futures::executor::block_on(async move { reader.lock().await.next().await }));
+        match next {
+            Some(Ok(chunk)) => Ok(Some(rkyv_serialized_value_to_pyany(py, &chunk))),
+            Some(Err(error)) => Err(PyErr::from_value(error_value_to_python_exception(py, &error)?.as_ref(py))),
+            None => Ok(None),
+        }
+    }
+
+    fn __anext__(&self, py: Python) -> PyResult<Option<PyObject>> {
+        let reader = self.reader.clone();
+        let next = pyo3_asyncio::tokio::future_into_py(py, async move {
+            let next = reader.lock().await.next().await;
+            Python::with_gil(|py| match next {
+                Some(Ok(chunk)) => Ok(rkyv_serialized_value_to_pyany(py, &chunk)),
+                Some(Err(error)) => Err(PyErr::from_value(error_value_to_python_exception(py, &error)?.as_ref(py))),
+                None => Err(PyStopAsyncIteration::new_err(())),
+            })
+        })?;
+        Ok(Some(next.into()))
+    }
+}
+
+/// Drive a generator returned by a cell on a thread of its own, pushing what it yields into a new stream.
+/// Async generators are driven by an event loop of that thread. The generator is paused while the readers
+/// of the stream fall behind it.
+fn stream_python_generator(generator: &PyAny, is_async: bool) -> u32 {
+    let id = create_stream();
+    let generator: PyObject = generator.into();
+    std::thread::spawn(move || {
+        let event_loop = if is_async {
+            match Python::with_gil(|py| py.import("asyncio")?.call_method0("new_event_loop").map(|event_loop| event_loop.into_py(py))) {
+                Ok(event_loop) => Some(event_loop),
+                Err(e) => {
+                    close_stream(id, Some(Python::with_gil(|py| python_error_to_error_value(py, &e))));
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+        loop {
+            let next = Python::with_gil(|py| -> PyResult<Option<RkyvSerializedValue>> {
+                let generator = generator.as_ref(py);
+                let yielded = match &event_loop {
+                    Some(event_loop) => generator.call_method0("__anext__")
+                        .and_then(|next| event_loop.as_ref(py).call_method1("run_until_complete", (next,))),
+                    None => generator.call_method0("__next__"),
+                };
+                match yielded {
+                    Ok(chunk) => Ok(Some(pyany_to_rkyv_serialized_value(chunk))),
+                    Err(e) if e.is_instance_of::<PyStopIteration>(py) || e.is_instance_of::<PyStopAsyncIteration>(py) => Ok(None),
+                    Err(e) => Err(e),
+                }
+            });
+            match next {
+                Ok(Some(chunk)) => {
+                    if futures::executor::block_on(push_chunk(id, chunk)).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => {
+                    close_stream(id, None);
+                    break;
+                }
+                Err(e) => {
+                    close_stream(id, Some(Python::with_gil(|py| python_error_to_error_value(py, &e))));
+                    break;
+                }
+            }
+        }
+        if let Some(event_loop) = event_loop {
+            Python::with_gil(|py| {
+                let _ = event_loop.as_ref(py).call_method0("close");
+            });
+        }
+    });
+    id
+}
+
 #[derive(Debug)]
 pub struct AnyhowErrWrapper(anyhow::Error);
 
@@ -304,7 +606,7 @@ pub async fn source_code_run_python(
     payload: &RkyvSerializedValue,
     function_invocation: &Option<String>,
     virtualenv_path: &Option<String>,
-    requirements_dir: &Option<String>,
+    requirements: &Option<Vec<String>>,
 ) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, Vec<String>, Vec<String>, ExecutionState)> {
 
     // Capture the current span's ID
@@ -321,23 +623,35 @@ pub async fn source_code_run_python(
     let dependencies = extract_dependencies_python(&source_code)?;
     let report = build_report(&dependencies);
 
+    let execution_node_id = execution_state.chronology_id;
     let execution_state = Arc::new(Mutex::new(execution_state.clone()));
-    let result =  Python::with_gil(|py| {
-        let v = py.version_info();
-
-        // Ensure virtualenv exists or create it
-        let venv_path = if let Some(venv_path) = &virtualenv_path {
-            PathBuf::from(venv_path)
-        } else {
-            let default_venv = get_or_create_default_venv(&v)?;
-            default_venv
-        };
 
-        // Install dependencies from requirements.txt if specified
-        if let Some(req_dir) = &requirements_dir {
-            install_dependencies_from_requirements(req_dir, venv_path.to_str().unwrap())?;
-        }
+    // Cells run in the virtualenv they are given, otherwise in the one shared by every cell with the
+    // same requirements, declared by the cell's script metadata and its notebook. It is resolved without
+    // the GIL, creating it installs the requirements and would block every other Python cell meanwhile.
+    let python_version = Python::with_gil(|py| {
+        let v = py.version_info();
+        (v.major, v.minor)
+    });
+    let venv_path = if let Some(venv_path) = &virtualenv_path {
+        PathBuf::from(venv_path)
+    } else {
+        let mut cell_requirements = requirements_from_script_metadata(&source_code);
+        cell_requirements.extend(requirements.iter().flatten().cloned());
+        get_or_create_venv(python_version, &cell_requirements)?
+    };
+    let site_packages_path = venv_path
+        .join("lib")
+        .join(format!("python{}.{}", python_version.0, python_version.1))
+        .join("site-packages");
+    if !site_packages_path.exists() {
+        return Err(anyhow::anyhow!("Virtualenv site-packages not found: {:?}", site_packages_path));
+    }
+    let site_packages_path = site_packages_path.to_string_lossy().to_string();
+    Python::with_gil(|py| add_site_packages(py, &site_packages_path))?;
 
+    PYTHON_EXECUTION_STATE_HANDLES.insert(exec_id, execution_state.clone());
+    let result =  Python::with_gil(|py| {
 
         // TODO: this was causing a deadlock
         let current_event_loop = pyo3_asyncio::tokio::get_current_loop(py);
@@ -353,31 +667,13 @@ pub async fn source_code_run_python(
 
         // Configure locals and globals passed to evaluation
         let globals = PyDict::new(py);
+        globals.set_item(PYTHON_EXECUTION_ID_GLOBAL, exec_id)?;
         create_external_function_shims(&execution_state, &report, py, globals, current_span_id.clone())?;
         create_internal_proxy_shims(&execution_state, &report, py, globals, current_span_id)?;
 
 
         let sys = py.import("sys")?;
 
-        // Get Python version from PyO3
-        let v = py.version_info();
-        let site_packages_dir = format!("python{}.{}", v.major, v.minor);
-
-        // Add virtualenv path to sys.path
-        let site_packages_path = venv_path
-            .join("lib")
-            .join(site_packages_dir)
-            .join("site-packages");
-
-        if site_packages_path.exists() {
-            let current_path: Vec<String> = sys.getattr("path")?.extract()?;
-            let mut new_path = vec![site_packages_path.to_str().unwrap().to_string()];
-            new_path.extend(current_path);
-            sys.setattr("path", new_path)?;
-        } else {
-            return Err(anyhow::anyhow!("Virtualenv site-packages not found: {:?}", site_packages_path));
-        }
-
         // Create Chidori module if it doesn't already exist
         let py_modules = sys.getattr("modules")?;
         if py_modules.get_item("chidori").is_err() {
@@ -385,6 +681,9 @@ pub async fn source_code_run_python(
             // so this is treated as an initialization handler.
             let chidori_module = PyModule::new(py, "chidori")?;
             chidori_module.add_function(wrap_pyfunction!(on_event, chidori_module)?)?;
+            chidori_module.add_function(wrap_pyfunction!(emit_as, chidori_module)?)?;
+            chidori_module.add("emit_event", create_python_emit_event_function(py)?)?;
+            chidori_module.add("input", create_python_input_function(py)?)?;
             chidori_module.add_function(wrap_pyfunction!(identity_function, chidori_module)?)?;
             let chidori_set_value = PyCFunction::new_closure(
                 py,
@@ -508,8 +807,13 @@ asyncio.run(__wrapper())
         "#, indent_all_source_code)
         };
 
+        // Cancelling the cell interrupts the thread evaluating it
+        let thread_id: u64 = py.import("threading")?.call_method0("get_ident")?.extract()?;
+        on_cancel(execution_node_id, move || interrupt_python_thread(thread_id, exec_id));
+
         // Important: this is the point of initial execution of the source code
-        py.run(&complete_code, Some(globals), None)?;
+        run_interruptible(thread_id, exec_id, || py.run(&complete_code, Some(globals), None))
+            .map_err(|e| anyhow::Error::new(python_error_to_error_value(py, &e)))?;
 
         // With the source environment established, we can now invoke specific methods provided by this node
         return match function_invocation {
@@ -565,10 +869,8 @@ asyncio.run(__wrapper())
                     let args = PyTuple::new(py, &args);
                     let kwargs = kwargs.into_iter().into_py_dict(py);
 
-                    let result = py_func.call(args, Some(kwargs)).map_err(|e| {
-                        dbg!(&e);
-                        e
-                    })?;
+                    let result = run_interruptible(thread_id, exec_id, || py_func.call(args, Some(kwargs)))
+                        .map_err(|e| anyhow::Error::new(python_error_to_error_value(py, &e)))?;
                     if result.get_type().name().unwrap() == "coroutine" {
                         // If the function is a coroutine, we need to await it
                         let is_running = event_loop.call_method0("is_running")?.extract::<bool>()?;
@@ -588,7 +890,7 @@ asyncio.run(__wrapper())
                         Ok(Box::pin(async move {
                             println!("waiting the python coroutine");
                             let final_result = if let Some(fut) = fut {
-                                fut.await.map_err(|e| ExecutionStateErrors::Unknown(e.to_string()))?
+                                fut.await.map_err(|e| Python::with_gil(|py| ExecutionStateErrors::CellRaisedError(python_error_to_error_value(py, &e))))?
                             } else {
                                 result.unwrap()
                             };
@@ -613,21 +915,92 @@ asyncio.run(__wrapper())
             }
         }
     });
+    let result = match result {
+        Ok(result) => Ok(result.await),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = Python::with_gil(|py| remove_site_packages(py, &site_packages_path)) {
+        debug!("Failed to remove {} from sys.path: {}", site_packages_path, e);
+    }
     match result {
-        Ok(result) => {
-            let awaited_result = result.await;
+        Ok(awaited_result) => {
+            PYTHON_EXECUTION_STATE_HANDLES.remove(&exec_id);
             let execution_state = execution_state.lock().unwrap().clone();
             let (_, output_stdout) = PYTHON_LOGGING_BUFFER_STDOUT.remove(&exec_id).unwrap_or((0, vec![]));
             let (_, output_stderr) = PYTHON_LOGGING_BUFFER_STDERR.remove(&exec_id).unwrap_or((0, vec![]));
             Ok((awaited_result, output_stdout, output_stderr, execution_state))
         }
         Err(e) => {
-            return Err(anyhow::anyhow!(e.to_string()));
+            PYTHON_EXECUTION_STATE_HANDLES.remove(&exec_id);
+            return Err(e);
         }
     }
 }
 
 
+/// Put a virtualenv's site-packages on `sys.path` for an evaluation, once however many evaluations use it.
+fn add_site_packages(py: Python, site_packages_path: &str) -> PyResult<()> {
+    let sys_path = py.import("sys")?.getattr("path")?;
+    if !sys_path.contains(site_packages_path)? {
+        sys_path.call_method1("insert", (0, site_packages_path))?;
+    }
+    *SITE_PACKAGES_IN_USE.entry(site_packages_path.to_string()).or_insert(0) += 1;
+    Ok(())
+}
+
+/// Take a virtualenv's site-packages off `sys.path` once no evaluation uses it, so that cells using other
+/// virtualenvs do not import from it.
+fn remove_site_packages(py: Python, site_packages_path: &str) -> PyResult<()> {
+    let unused = match SITE_PACKAGES_IN_USE.get_mut(site_packages_path) {
+        Some(mut users) => {
+            *users = users.saturating_sub(1);
+            *users == 0
+        }
+        None => true,
+    };
+    if !unused {
+        return Ok(());
+    }
+    SITE_PACKAGES_IN_USE.remove(site_packages_path);
+    let sys_path = py.import("sys")?.getattr("path")?;
+    while sys_path.contains(site_packages_path)? {
+        sys_path.call_method1("remove", (site_packages_path,))?;
+    }
+    Ok(())
+}
+
+/// Run Python code of an evaluation on the current thread, cancelling the evaluation only interrupts the
+/// thread while it is running this code. Must be called with the GIL held.
+fn run_interruptible<T>(thread_id: u64, exec_id: usize, run: impl FnOnce() -> T) -> T {
+    // Cells dispatching to other cells nest evaluations on the same thread
+    let outer = RUNNING_PYTHON_EXECUTIONS.insert(thread_id, exec_id);
+    let result = run();
+    match outer {
+        Some(outer) => RUNNING_PYTHON_EXECUTIONS.insert(thread_id, outer),
+        None => RUNNING_PYTHON_EXECUTIONS.remove(&thread_id).map(|(_, id)| id),
+    };
+    result
+}
+
+/// Raise `KeyboardInterrupt` in a thread evaluating Python. The exception is raised once the thread next
+/// executes Python code, a thread blocked in a call into native code is interrupted when the call returns.
+/// Nothing is raised if the thread has moved on from the evaluation, as it would interrupt another cell.
+fn interrupt_python_thread(thread_id: u64, exec_id: usize) {
+    let interrupted = Python::with_gil(|py| -> PyResult<()> {
+        if RUNNING_PYTHON_EXECUTIONS.get(&thread_id).map(|running| *running) != Some(exec_id) {
+            return Ok(());
+        }
+        let ctypes = py.import("ctypes")?;
+        let thread_id = ctypes.getattr("c_ulong")?.call1((thread_id,))?;
+        let exception = ctypes.getattr("py_object")?.call1((py.get_type::<PyKeyboardInterrupt>(),))?;
+        ctypes.getattr("pythonapi")?.getattr("PyThreadState_SetAsyncExc")?.call1((thread_id, exception))?;
+        Ok(())
+    });
+    if let Err(e) = interrupted {
+        debug!("Failed to interrupt Python thread {}: {}", thread_id, e);
+    }
+}
+
 fn create_internal_proxy_shims(execution_state_handle: &Arc<Mutex<ExecutionState>>, report: &Report, py: Python, globals: &PyDict, parent_span_id: Option<tracing::Id>) -> Result<(), Error> {
     // Create shims for the functions declared within this file,
     // when a hashed reference to a function is invoked, we invoke the actual function internally
@@ -944,6 +1317,94 @@ def example(x):
         assert_eq!(result.unwrap(), (Ok(RkyvSerializedValue::Number(25)), vec![], vec![]));
     }
 
+    #[tokio::test]
+    async fn test_wide_numbers_bytes_datetimes_and_embeddings_round_trip() {
+        let source_code = String::from(
+            r#"
+import array
+import datetime
+
+def example(big, precise, data, when, embedding):
+    assert big == 2 ** 40
+    assert precise == 0.1 + 0.2
+    assert isinstance(data, bytes) and data == b"\x00\xff"
+    assert when == datetime.datetime(2024, 5, 31, 16, 8, 37, 123456, tzinfo=datetime.timezone.utc)
+    assert isinstance(embedding, array.array) and list(embedding) == [0.25, -1.5]
+    return [big * 2, precise, bytearray(data + b"!"), when + datetime.timedelta(seconds=1), array.array("f", [x * 2 for x in embedding])]
+        "#,
+        );
+        let args = RkyvObjectBuilder::new()
+            .insert_number("0", 1 << 40)
+            .insert_value("1", RkyvSerializedValue::Float(0.1 + 0.2))
+            .insert_value("2", RkyvSerializedValue::Bytes(vec![0, 255]))
+            .insert_value("3", RkyvSerializedValue::DateTime(1_717_171_717_123_456))
+            .insert_value("4", RkyvSerializedValue::Embedding(vec![0.25, -1.5]));
+        let result = source_code_run_python(&ExecutionState::new_with_random_id(),
+                                            &source_code,
+                                            &RkyvObjectBuilder::new().insert_object("args", args).build(),
+                                            &Some("example".to_string()),
+                                            &None,
+                                            &None,
+        ).await;
+        assert_eq!(result.unwrap().0.unwrap(), RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::Number(1 << 41),
+            RkyvSerializedValue::Float(0.1 + 0.2),
+            RkyvSerializedValue::Bytes(vec![0, 255, b'!']),
+            RkyvSerializedValue::DateTime(1_717_171_718_123_456),
+            RkyvSerializedValue::Embedding(vec![0.5, -3.0]),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_generators_are_streamed_to_other_cells() -> anyhow::Result<()> {
+        let producer = String::from(
+            r#"
+import asyncio
+
+def produce():
+    for i in range(40):
+        yield i
+
+async def produce_words():
+    for word in ["scraped", "pages"]:
+        await asyncio.sleep(0)
+        yield word
+
+numbers = produce()
+words = produce_words()
+        "#,
+        );
+        let (output, _, _, _) = source_code_run_python(&ExecutionState::new_with_random_id(), &producer, &RkyvSerializedValue::Null, &None, &None, &None).await?;
+        let output = output?;
+        let RkyvSerializedValue::Object(streams) = &output else { panic!("Expected an object, received {:?}", output) };
+        let Some(RkyvSerializedValue::StreamPointer(numbers)) = streams.get("numbers").cloned() else { panic!("numbers was not streamed") };
+        let Some(RkyvSerializedValue::StreamPointer(words)) = streams.get("words").cloned() else { panic!("words was not streamed") };
+
+        let consumer = String::from(
+            r#"
+total = 0
+for n in numbers:
+    total += n
+joined = []
+async for word in words:
+    joined.append(word)
+        "#,
+        );
+        let payload = RkyvObjectBuilder::new()
+            .insert_object("globals", RkyvObjectBuilder::new()
+                .insert_value("numbers", RkyvSerializedValue::StreamPointer(numbers))
+                .insert_value("words", RkyvSerializedValue::StreamPointer(words)))
+            .build();
+        let (output, _, _, _) = source_code_run_python(&ExecutionState::new_with_random_id(), &consumer, &payload, &None, &None, &None).await?;
+        let RkyvSerializedValue::Object(output) = output? else { panic!("Expected an object") };
+        assert_eq!(output.get("total"), Some(&RkyvSerializedValue::Number(780)));
+        assert_eq!(output.get("joined"), Some(&RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::String("scraped".to_string()),
+            RkyvSerializedValue::String("pages".to_string()),
+        ])));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_execution_of_python_with_function_provided_via_cell() -> anyhow::Result<()> {
         let source_code = String::from(
@@ -962,6 +1423,7 @@ a = 20 + await demo()
                             return 100
                         "#}),
             function_invocation: None,
+            policy: Default::default(),
         }, TextRange::default()), id_a)?;
         let result = source_code_run_python(&state,
                                             &source_code,
@@ -998,6 +1460,7 @@ a = 20 + await demo()
                             return 100
                         "#}),
             function_invocation: None,
+            policy: Default::default(),
         }, TextRange::default()), id_a)?;
         let result = source_code_run_python(&state,
                                             &String::from( r#"data = await demo()"#, ),
@@ -1041,6 +1504,7 @@ data = await demo()
                             return 100 + await demo_second_function_call()
                         "#}),
             function_invocation: None,
+            policy: Default::default(),
         }, TextRange::default()), id_a)?;
         let id_b = Uuid::now_v7();
         let (state, _) = state.update_operation(CellTypes::Code(CodeCell {
@@ -1054,6 +1518,7 @@ data = await demo()
                             return 100
                         "#}),
             function_invocation: None,
+            policy: Default::default(),
         }, TextRange::default()), id_b)?;
         let result = source_code_run_python(&state,
                                             &source_code,
@@ -1098,6 +1563,7 @@ data = await demo()
                             return 100 + await function_b()
                         "#}),
             function_invocation: None,
+            policy: Default::default(),
         }, TextRange::default()), id_a)?;
         let source_code = String::from(
             r#"data = await function_c()"#,
@@ -1168,8 +1634,8 @@ data = await demo()
         // Helper function to check OperationFnOutput
         fn check_operation_output(output: &Arc<OperationFnOutput>, expected_value: i64) -> bool {
             match output.as_ref() {
-                OperationFnOutput { has_error: false, execution_state: None, output: output_value, stdout, stderr } => {
-                    matches!(output_value, Ok(RkyvSerializedValue::Number(n)) if *n == expected_value as i32)
+                OperationFnOutput { has_error: false, execution_state: None, output: output_value, stdout, stderr, .. } => {
+                    matches!(output_value, Ok(RkyvSerializedValue::Number(n)) if *n == expected_value as i64)
                         && stdout.is_empty()
                         && stderr.is_empty()
                 },
@@ -1427,6 +1893,10 @@ raise ValueError("Raising a python error")
                 map.insert("another_function".to_string(), ReportTriggerableFunctions::default());
                 map
             },
+            imported_values: HashMap::new(),
+            star_imports: vec![],
+            third_party_modules: vec![],
+            defines_tests: false,
         };
 
         let code = r#"
@@ -1,3 +1,4 @@
 pub mod ai;
 pub mod code;
-mod scheduling;
+pub mod scheduling;
+pub mod webservice;
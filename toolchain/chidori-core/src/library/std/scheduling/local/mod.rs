@@ -1,111 +1,169 @@
-use std::collections::{HashMap, HashSet};
-use tokio_cron_scheduler::{Job, JobScheduler, JobSchedulerError};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 use tracing::debug;
-use uuid::Uuid;
-use crate::cells::{CellTypes, CodeCell, ScheduleCell};
-use crate::execution::execution::ExecutionState;
-use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
-
-struct ScheduledJob {
-    schedule: String,
-    function_identity: String,
+
+#[derive(Debug, Clone)]
+pub struct ScheduledJob {
+    pub schedule: Schedule,
+    pub function_identity: String,
 }
 
-pub fn parse_configuration_string(configuration: &str) -> Vec<ScheduledJob> {
+/// Parse the body of a schedule cell. Each non-empty line is a cron expression
+/// (seconds, minutes, hours, day of month, month, day of week and an optional year)
+/// followed by the name of the function to invoke:
+///
+/// ```text
+/// 0 */5 * * * * refresh_feed
+/// ```
+pub fn parse_configuration_string(configuration: &str) -> anyhow::Result<Vec<ScheduledJob>> {
     configuration
         .lines()
-        .filter_map(|line| {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            return Some(ScheduledJob {
-                schedule: parts.get(0)?.to_string(),
-                function_identity: parts.get(1)?.to_string(),
-            });
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let Some((expression, function_identity)) = line.rsplit_once(char::is_whitespace) else {
+                return Err(anyhow::anyhow!("Expected `<cron expression> <function>` in schedule configuration, found: {}", line));
+            };
+            let schedule = Schedule::from_str(expression.trim())
+                .map_err(|e| anyhow::anyhow!("Invalid cron expression {:?}: {}", expression.trim(), e))?;
+            Ok(ScheduledJob {
+                schedule,
+                function_identity: function_identity.to_string(),
+            })
         })
         .collect()
 }
 
-pub async fn run_cron(
-    configuration: &ScheduleCell,
-    payload: &RkyvSerializedValue,
-) -> Result<(), JobSchedulerError> {
-    let jobs = parse_configuration_string(&configuration.configuration);
-    let mut sched = JobScheduler::new().await?;
-
-    let subscribed_functions: HashSet<String> = jobs.iter().map(|x| x.function_identity.clone()).collect();
-    let mut our_functions_map = &HashMap::new();
-    if let RkyvSerializedValue::Object(ref payload_map) = payload {
-        if let Some(RkyvSerializedValue::Object(functions_map)) = payload_map.get("functions") {
-            our_functions_map = functions_map;
-        }
+/// Source of time for scheduled jobs, allowing tests to control when jobs fire.
+#[async_trait]
+pub trait SchedulerClock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Resolves once `now()` has reached the given instant.
+    async fn sleep_until(&self, instant: DateTime<Utc>);
+}
+
+pub struct SystemClock;
+
+#[async_trait]
+impl SchedulerClock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
     }
-    for job in jobs {
-        let function_name = &job.function_identity;
-        let value = our_functions_map.get(function_name).unwrap();
-        if let RkyvSerializedValue::Cell(cell) = value.clone() {
-            if let CellTypes::Code(CodeCell { function_invocation, .. }, r) = &cell
-            {
-                if subscribed_functions
-                    .contains(function_name)
-                {
-                    let cell_clone = cell.clone();
-                    let function_name = function_name.clone();
-                    sched.add(
-                        Job::new(job.schedule.as_str(), move |_uuid, _l| {
-                            // modify code cell to indicate execution of the target function
-                            // reconstruction of the cell
-                            let mut op = match &cell_clone {
-                                CellTypes::Code(c, r) => {
-                                    let mut c = c.clone();
-                                    c.function_invocation =
-                                        Some(function_name.clone());
-                                    crate::cells::code_cell::code_cell(Uuid::nil(), &c, r)
-                                }
-                                CellTypes::Prompt(c, r) => {
-                                    crate::cells::llm_prompt_cell::llm_prompt_cell(Uuid::nil(), &c, r)
-                                }
-                                _ => {
-                                    unreachable!("Unsupported cell type");
-                                }
-                            }.unwrap();
-
-                            let mut argument_payload = RkyvObjectBuilder::new();
-                            // if &arg_mapping.len() > &0 {
-                            //     for (key, value) in &arg_mapping {
-                            //         argument_payload = argument_payload.insert_value(key, json_value_to_serialized_value(payload.get(value).unwrap()));
-                            //     }
-                            // }
-                            let argument_payload = argument_payload.build();
-
-                            dbg!(&argument_payload);
-                            // invocation of the operation
-                            let result = op.execute(&ExecutionState::new_with_random_id(), argument_payload, None, None);
-                        })?
-                    ).await?;
-                }
-            }
+
+    async fn sleep_until(&self, instant: DateTime<Utc>) {
+        if let Ok(duration) = (instant - Utc::now()).to_std() {
+            tokio::time::sleep(duration).await;
         }
     }
+}
 
-    // Add code to be run during/after shutdown
-    sched.set_shutdown_handler(Box::new(|| {
-        Box::pin(async move {
-            debug!("Shut down done");
-        })
-    }));
+/// A clock that only moves when it is advanced, used to test schedules deterministically.
+pub struct VirtualClock {
+    now: watch::Sender<DateTime<Utc>>,
+}
+
+impl VirtualClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        let (now, _) = watch::channel(start);
+        VirtualClock { now }
+    }
+
+    pub fn advance(&self, duration: chrono::Duration) {
+        self.now.send_modify(|now| *now = *now + duration);
+    }
+}
+
+#[async_trait]
+impl SchedulerClock for VirtualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.borrow()
+    }
+
+    async fn sleep_until(&self, instant: DateTime<Utc>) {
+        let mut receiver = self.now.subscribe();
+        let _ = receiver.wait_for(|now| *now >= instant).await;
+    }
+}
 
-    // Start the scheduler
-    sched.start().await?;
-    Ok(())
+/// Spawn a task per job that waits for each upcoming firing of its schedule and then invokes `fire`
+/// with the job and the instant it was scheduled for. Firings missed while a previous invocation was
+/// still running are skipped rather than replayed.
+pub fn run_schedule<F, Fut>(
+    jobs: Vec<ScheduledJob>,
+    clock: Arc<dyn SchedulerClock>,
+    fire: F,
+) -> Vec<JoinHandle<()>>
+where
+    F: Fn(ScheduledJob, DateTime<Utc>) -> Fut + Send + Sync + Clone + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    jobs.into_iter().map(|job| {
+        let clock = clock.clone();
+        let fire = fire.clone();
+        // The first firing is computed before spawning so that it is relative to the time the schedule started
+        let mut upcoming = job.schedule.after(&clock.now()).next();
+        tokio::spawn(async move {
+            while let Some(instant) = upcoming {
+                clock.sleep_until(instant).await;
+                upcoming = job.schedule.after(&instant.max(clock.now())).next();
+                debug!("Scheduled invocation of {} at {:?}", job.function_identity, instant);
+                fire(job.clone(), instant).await;
+            }
+        })
+    }).collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tokio::sync::mpsc;
 
     #[test]
     fn test_parse_configuration_string() {
+        let jobs = parse_configuration_string(indoc::indoc! {"
+            0 */5 * * * * refresh_feed
+            # comments and blank lines are ignored
+
+            0 0 9 * * Mon-Fri send_report
+        "}).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].function_identity, "refresh_feed");
+        assert_eq!(jobs[1].function_identity, "send_report");
+        assert!(parse_configuration_string("not a schedule refresh_feed").is_err());
+        assert!(parse_configuration_string("refresh_feed").is_err());
     }
 
-    #[test]
-    fn test_invocation_of_function_on_schedule() {
+    #[tokio::test]
+    async fn test_invocation_of_function_on_schedule() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 30).unwrap();
+        let clock = Arc::new(VirtualClock::new(start));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let jobs = parse_configuration_string("0 * * * * * ping").unwrap();
+        let handles = run_schedule(jobs, clock.clone(), move |job, instant| {
+            let tx = tx.clone();
+            async move { tx.send((job.function_identity, instant)).unwrap(); }
+        });
+
+        clock.advance(chrono::Duration::seconds(20));
+        tokio::task::yield_now().await;
+        assert!(rx.try_recv().is_err());
+
+        clock.advance(chrono::Duration::seconds(10));
+        assert_eq!(rx.recv().await, Some(("ping".to_string(), Utc.with_ymd_and_hms(2024, 1, 1, 0, 1, 0).unwrap())));
+
+        clock.advance(chrono::Duration::seconds(60));
+        assert_eq!(rx.recv().await, Some(("ping".to_string(), Utc.with_ymd_and_hms(2024, 1, 1, 0, 2, 0).unwrap())));
+
+        for handle in handles {
+            handle.abort();
+        }
     }
 }
@@ -1,4 +1,4 @@
-mod local;
+pub mod local;
 
 enum ScheduledExecutionError {
     None,
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, patch, post, put, MethodRouter};
+use axum::{Json, Router};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use serde_json::{Map, Value};
+use tokio::task::JoinHandle;
+use tracing::debug;
+use crate::cells::WebserviceCellEndpoint;
+use crate::execution::execution::ExecutionState;
+use crate::execution::execution::execution_state::ExecutionStateErrors;
+use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::serialized_value::{json_value_to_serialized_value, serialized_value_to_json_value, RkyvObjectBuilder, RkyvSerializedValue};
+
+/// Host webservices listen on unless their cell gives one, so that only this machine can reach them
+pub const DEFAULT_WEBSERVICE_HOST: &str = "127.0.0.1";
+
+// Servers that are currently running along with the port they are bound to, keyed by the webservice
+// cell that started them. Re-running a webservice cell replaces the server it previously started.
+static RUNNING_WEBSERVICES: Lazy<DashMap<OperationId, (u16, JoinHandle<()>)>> = Lazy::new(|| DashMap::new());
+
+/// Parse the body of a webservice cell. Each non-empty line declares an endpoint:
+///
+/// ```text
+/// POST /summarize summarize text=body
+/// GET /users/:id get_user id
+/// ```
+///
+/// The method and route are followed by the function to dispatch to, and then by any number of
+/// argument mappings. `arg=field` passes the request field `field` as the keyword argument `arg`,
+/// a bare `arg` passes the request field with the same name.
+pub fn parse_configuration_string(configuration: &str) -> anyhow::Result<Vec<WebserviceCellEndpoint>> {
+    configuration
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+            let (Some(method), Some(route), Some(function)) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(anyhow::anyhow!("Expected `METHOD /route function [args]` in webservice configuration, found: {}", line));
+            };
+            let arg_mapping = parts.map(|mapping| match mapping.split_once('=') {
+                Some((arg, field)) => (arg.to_string(), field.to_string()),
+                None => (mapping.to_string(), mapping.to_string()),
+            }).collect();
+            Ok(WebserviceCellEndpoint {
+                method: method.to_uppercase(),
+                route: route.to_string(),
+                depended_function_identity: function.to_string(),
+                arg_mapping,
+            })
+        })
+        .collect()
+}
+
+/// Build the payload used to dispatch an endpoint's function from the fields of a request.
+/// Without an explicit mapping every request field is passed through as a keyword argument.
+pub fn payload_from_request_fields(endpoint: &WebserviceCellEndpoint, fields: &Map<String, Value>) -> anyhow::Result<RkyvSerializedValue> {
+    let mut kwargs = HashMap::new();
+    if endpoint.arg_mapping.is_empty() {
+        for (key, value) in fields {
+            kwargs.insert(key.clone(), json_value_to_serialized_value(value));
+        }
+    } else {
+        for (arg, field) in &endpoint.arg_mapping {
+            let value = fields.get(field).ok_or_else(|| anyhow::anyhow!("Request is missing field: {}", field))?;
+            kwargs.insert(arg.clone(), json_value_to_serialized_value(value));
+        }
+    }
+    Ok(RkyvObjectBuilder::new()
+        .insert_value("args", RkyvSerializedValue::Object(HashMap::new()))
+        .insert_value("kwargs", RkyvSerializedValue::Object(kwargs))
+        .build())
+}
+
+/// Collect the fields of a request, later sources take precedence: path parameters, the query string, then a JSON object body.
+fn request_fields(
+    path: Option<HashMap<String, String>>,
+    query: HashMap<String, String>,
+    body: Option<Value>,
+) -> Map<String, Value> {
+    let mut fields = Map::new();
+    for (key, value) in path.into_iter().flatten().chain(query.into_iter()) {
+        fields.insert(key, Value::String(value));
+    }
+    match body {
+        Some(Value::Object(body)) => fields.extend(body),
+        Some(body) => { fields.insert("body".to_string(), body); }
+        None => {}
+    }
+    fields
+}
+
+async fn handle_request(
+    execution_state: Arc<ExecutionState>,
+    endpoint: Arc<WebserviceCellEndpoint>,
+    fields: Map<String, Value>,
+) -> (StatusCode, Json<Value>) {
+    let payload = match payload_from_request_fields(&endpoint, &fields) {
+        Ok(payload) => payload,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e.to_string() }))),
+    };
+    debug!("Webservice dispatching {} {} to {}", endpoint.method, endpoint.route, endpoint.depended_function_identity);
+    match execution_state.dispatch_from_execution_head(&endpoint.depended_function_identity, payload).await {
+        Ok((Ok(value), _)) => (StatusCode::OK, Json(serialized_value_to_json_value(&value))),
+        Ok((Err(e), _)) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({ "error": format!("{:?}", e) }))),
+        Err(e) => {
+            let status = match e.downcast_ref::<ExecutionStateErrors>() {
+                Some(ExecutionStateErrors::UnknownFunction(_)) => StatusCode::NOT_FOUND,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (status, Json(serde_json::json!({ "error": e.to_string() })))
+        }
+    }
+}
+
+fn endpoint_method_router(execution_state: Arc<ExecutionState>, endpoint: WebserviceCellEndpoint) -> anyhow::Result<MethodRouter> {
+    let method = endpoint.method.clone();
+    let endpoint = Arc::new(endpoint);
+    let handler = move |path: Option<Path<HashMap<String, String>>>, Query(query): Query<HashMap<String, String>>, body: Option<Json<Value>>| {
+        let execution_state = execution_state.clone();
+        let endpoint = endpoint.clone();
+        async move {
+            let fields = request_fields(path.map(|Path(p)| p), query, body.map(|Json(b)| b));
+            handle_request(execution_state, endpoint, fields).await
+        }
+    };
+    Ok(match method.as_str() {
+        "GET" => get(handler),
+        "POST" => post(handler),
+        "PUT" => put(handler),
+        "PATCH" => patch(handler),
+        "DELETE" => delete(handler),
+        _ => return Err(anyhow::anyhow!("Unsupported webservice method: {}", method)),
+    })
+}
+
+/// Start serving the given endpoints on the host and port for the webservice cell evaluated by the provided
+/// execution state, requests are dispatched from the execution head it follows. Returns the address the
+/// server is bound to. Port 0 binds any free port, other ports may only be served by one cell at a time.
+pub async fn run_webservice(
+    execution_state: ExecutionState,
+    host: &str,
+    port: u16,
+    endpoints: Vec<WebserviceCellEndpoint>,
+) -> anyhow::Result<SocketAddr> {
+    let operation_id = execution_state.evaluating_operation_id;
+    let execution_state = Arc::new(execution_state);
+    let mut router = Router::new();
+    for endpoint in endpoints {
+        let route = endpoint.route.clone();
+        router = router.route(&route, endpoint_method_router(execution_state.clone(), endpoint)?);
+    }
+
+    if port != 0 {
+        let conflict = RUNNING_WEBSERVICES.iter()
+            .any(|running| *running.key() != operation_id && running.value().0 == port && !running.value().1.is_finished());
+        if conflict {
+            return Err(anyhow::anyhow!("Port {} is already served by another webservice cell", port));
+        }
+    }
+    if let Some((_, (_, previous))) = RUNNING_WEBSERVICES.remove(&operation_id) {
+        previous.abort();
+    }
+    let listener = tokio::net::TcpListener::bind((host, port)).await?;
+    let addr = listener.local_addr()?;
+    let handle = tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, router).await {
+            debug!("Webservice on {:?} stopped: {:?}", addr, e);
+        }
+    });
+    RUNNING_WEBSERVICES.insert(operation_id, (addr.port(), handle));
+    Ok(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+    use crate::cells::{CellTypes, CodeCell, SupportedLanguage, TextRange};
+    use super::*;
+
+    #[test]
+    fn test_parse_configuration_string() {
+        let endpoints = parse_configuration_string(indoc::indoc! {"
+            POST /summarize summarize text=body
+            # comments and blank lines are ignored
+
+            get /users/:id get_user id
+        "}).unwrap();
+        assert_eq!(endpoints, vec![
+            WebserviceCellEndpoint {
+                method: "POST".to_string(),
+                route: "/summarize".to_string(),
+                depended_function_identity: "summarize".to_string(),
+                arg_mapping: vec![("text".to_string(), "body".to_string())],
+            },
+            WebserviceCellEndpoint {
+                method: "GET".to_string(),
+                route: "/users/:id".to_string(),
+                depended_function_identity: "get_user".to_string(),
+                arg_mapping: vec![("id".to_string(), "id".to_string())],
+            },
+        ]);
+        assert!(parse_configuration_string("POST /missing_function").is_err());
+    }
+
+    #[test]
+    fn test_payload_from_request_fields() {
+        let endpoint = parse_configuration_string("POST / add a=x b=y").unwrap().remove(0);
+        let fields = request_fields(
+            None,
+            HashMap::from([("x".to_string(), "1".to_string())]),
+            Some(serde_json::json!({ "y": 2 })),
+        );
+        let payload = payload_from_request_fields(&endpoint, &fields).unwrap();
+        let expected = RkyvObjectBuilder::new()
+            .insert_value("args", RkyvSerializedValue::Object(HashMap::new()))
+            .insert_object("kwargs", RkyvObjectBuilder::new()
+                .insert_string("a", "1".to_string())
+                .insert_number("b", 2))
+            .build();
+        assert_eq!(payload, expected);
+
+        let missing = request_fields(None, HashMap::new(), None);
+        assert!(payload_from_request_fields(&endpoint, &missing).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_function_responds_not_found() {
+        let endpoint = parse_configuration_string("GET /missing missing").unwrap().remove(0);
+        let (status, Json(body)) = handle_request(Arc::new(ExecutionState::new_with_random_id()), Arc::new(endpoint), Map::new()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body["error"].as_str().unwrap().contains("missing"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_request_is_answered_by_the_notebook_function() -> anyhow::Result<()> {
+        let (mut state, _) = ExecutionState::new_with_random_id().update_operation(CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: Some("math".to_string()),
+            language: SupportedLanguage::PyO3,
+            source_code: "def add(a, b):\n    return a + b".to_string(),
+            function_invocation: None,
+            policy: Default::default(),
+        }, TextRange::default()), Uuid::now_v7()).await?;
+        state.evaluating_operation_id = Uuid::now_v7();
+        let operation_id = state.evaluating_operation_id;
+
+        let addr = run_webservice(state, DEFAULT_WEBSERVICE_HOST, 0, parse_configuration_string("POST /add add a b")?).await?;
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/add", addr))
+            .json(&serde_json::json!({ "a": 2, "b": 3 }))
+            .send()
+            .await?;
+        let status = response.status();
+        let body: Value = response.json().await?;
+        if let Some((_, (_, handle))) = RUNNING_WEBSERVICES.remove(&operation_id) {
+            handle.abort();
+        }
+        assert_eq!(status, reqwest::StatusCode::OK);
+        assert_eq!(body, serde_json::json!(5));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_port_served_by_another_cell_is_refused() -> anyhow::Result<()> {
+        let mut first = ExecutionState::new_with_random_id();
+        first.evaluating_operation_id = Uuid::now_v7();
+        let first_operation_id = first.evaluating_operation_id;
+        let addr = run_webservice(first, DEFAULT_WEBSERVICE_HOST, 0, vec![]).await?;
+
+        let mut second = ExecutionState::new_with_random_id();
+        second.evaluating_operation_id = Uuid::now_v7();
+        let refused = run_webservice(second, DEFAULT_WEBSERVICE_HOST, addr.port(), vec![]).await;
+        if let Some((_, (_, handle))) = RUNNING_WEBSERVICES.remove(&first_operation_id) {
+            handle.abort();
+        }
+        assert!(refused.unwrap_err().to_string().contains("already served"));
+        Ok(())
+    }
+}
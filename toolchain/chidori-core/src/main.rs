@@ -3,7 +3,8 @@
 #![feature(thread_id_value)]
 #![feature(generic_nonzero)]
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::net::IpAddr;
 use std::path::PathBuf;
 use std::sync::{mpsc, Arc, Mutex};
 
@@ -18,6 +19,13 @@ use tracing::info;
 pub use uuid;
 use chidori_core::sdk::interactive_chidori_wrapper::InteractiveChidoriWrapper;
 use chidori_core::sdk::chidori_runtime_instance::PlaybackState;
+use chidori_core::library::std::ai::llm::cassette::{set_active_cassette, Cassette, CassetteMode};
+use chidori_core::execution::primitives::usage::{set_price_table, PriceTable};
+use chidori_core::sdk::chidori_runtime_instance::ChidoriRuntimeInstance;
+use chidori_core::sdk::headless::{answer_input_requests_from_stdin, collect_test_results, dependency_graph_to_dot, dependency_graph_to_json, state_to_json};
+use chidori_core::execution::execution::human_input::serve_input_requests;
+use chidori_core::library::std::code::python_venv::{collect_stale_venvs, set_wheel_dir};
+use chidori_core::execution::execution::memoization::MemoizationCache;
 pub use chidori_static_analysis;
 pub use chidori_prompt_format;
 
@@ -35,16 +43,63 @@ enum Commands {
         /// Path to the configuration file
         #[arg(short, long)]
         load: PathBuf,
+        #[command(flatten)]
+        execution: ExecutionArgs,
+        /// Run without waiting for interaction until no further cells can execute, then print the final state as JSON
+        #[arg(long)]
+        until_idle: bool,
+        /// Ask for the input requested by cells on the terminal, reading the answers from stdin
+        #[arg(long)]
+        stdin_input: bool,
+        /// Serve requests for input from cells over HTTP on this port
+        #[arg(long)]
+        input_port: Option<u16>,
+        /// Address the input server listens on, only this machine can answer requests by default
+        #[arg(long, default_value = "127.0.0.1")]
+        input_host: IpAddr,
+    },
+    /// Run a notebook until it is idle and report the results of its test cells,
+    /// exiting with a non-zero status if any of them failed
+    Test {
+        /// Path to the notebook directory
+        #[arg(short, long)]
+        load: PathBuf,
+        #[command(flatten)]
+        execution: ExecutionArgs,
+    },
+    /// Validate how the cells of a notebook depend on each other without running them,
+    /// exiting with a non-zero status if any errors are found
+    Check {
+        /// Path to the notebook directory
+        #[arg(short, long)]
+        load: PathBuf,
+    },
+    /// Print the dependency graph between the cells of a notebook
+    Graph {
+        /// Path to the notebook directory
+        #[arg(short, long)]
+        load: PathBuf,
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+    /// Forget the memoized outputs of a notebook's pure cells
+    ClearCache {
+        /// Path to the notebook directory
+        #[arg(short, long)]
+        load: PathBuf,
+        /// Only forget the outputs of the cell with this name
+        #[arg(long)]
+        cell: Option<String>,
+    },
+    /// Remove the Python virtualenvs that cells have not used recently
+    GcVenvs {
+        /// Remove virtualenvs that have not been used for this many days
+        #[arg(long, default_value_t = 30)]
+        older_than_days: u64,
+        /// List the virtualenvs that would be removed without removing them
+        #[arg(long)]
+        dry_run: bool,
     },
-    // /// Run tests
-    // Test {
-    //     /// Path to the test directory
-    //     #[arg(short, long)]
-    //     test_dir: PathBuf,
-    //     /// Verbose output
-    //     #[arg(short, long)]
-    //     verbose: bool,
-    // },
     // /// Deploy the application
     // Deploy {
     //     /// Target environment
@@ -56,7 +111,77 @@ enum Commands {
     // },
 }
 
-async fn run_command(run_directory: &PathBuf) -> anyhow::Result<()> {
+/// Options for executing a notebook, shared by the commands that run one
+#[derive(Args)]
+struct ExecutionArgs {
+    /// Path to a session database, execution resumes from it if it already exists
+    #[arg(short, long)]
+    session: Option<PathBuf>,
+    /// Path to a cassette file of recorded LLM responses
+    #[arg(long)]
+    cassette: Option<PathBuf>,
+    /// Whether to record LLM responses into the cassette or replay them from it
+    #[arg(long, value_enum, default_value_t = CassetteMode::Replay)]
+    cassette_mode: CassetteMode,
+    /// Path to a YAML or JSON table of model prices in USD per million tokens, overriding the defaults
+    #[arg(long)]
+    prices: Option<PathBuf>,
+    /// Give up on reaching an idle state after this many steps
+    #[arg(long)]
+    max_steps: Option<usize>,
+    /// Number of independent cells evaluated at the same time
+    #[arg(long, default_value_t = 1)]
+    concurrency: usize,
+    /// Install Python requirements offline from the wheels in this directory
+    #[arg(long)]
+    wheel_dir: Option<PathBuf>,
+    /// Refuse to run the notebook if validating its cells finds errors
+    #[arg(long)]
+    strict: bool,
+}
+
+impl ExecutionArgs {
+    /// Configure the models and Python packages used by every instance of this process
+    fn configure(&self) -> anyhow::Result<()> {
+        if let Some(cassette) = &self.cassette {
+            set_active_cassette(Some(Cassette::open(cassette, self.cassette_mode)?));
+        }
+        if let Some(prices) = &self.prices {
+            let mut price_table = PriceTable::with_default_prices();
+            price_table.extend(PriceTable::from_path(prices)?);
+            set_price_table(price_table);
+        }
+        set_wheel_dir(self.wheel_dir.clone());
+        Ok(())
+    }
+
+    fn configure_instance(&self, instance: &mut ChidoriRuntimeInstance) {
+        instance.set_max_concurrency(self.concurrency);
+        instance.set_refuse_invalid_notebooks(self.strict);
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Json,
+}
+
+/// Load the notebook at the given directory into an instance that is driven directly rather than
+/// through its run loop. The wrapper is returned because it owns the state the instance's cells are loaded from.
+async fn load_headless_instance(run_directory: &PathBuf, session: &Option<PathBuf>) -> anyhow::Result<(InteractiveChidoriWrapper, ChidoriRuntimeInstance)> {
+    let mut chidori = InteractiveChidoriWrapper::new();
+    let mut instance = match session {
+        Some(session) => chidori.get_instance_with_session(session)?,
+        None => chidori.get_instance()?,
+    };
+    instance.wait_until_ready().await?;
+    chidori.load_md_directory(run_directory)?;
+    instance.reload_cells().await?;
+    Ok((chidori, instance))
+}
+
+async fn run_command(run_directory: &PathBuf, session: &Option<PathBuf>, concurrency: usize, strict: bool) -> anyhow::Result<()> {
     let runtime = tokio::runtime::Handle::current();
 
     let (trace_event_sender, trace_event_receiver) = mpsc::channel();
@@ -68,9 +193,15 @@ async fn run_command(run_directory: &PathBuf) -> anyhow::Result<()> {
     );
 
     let run_directory_clone = run_directory.clone();
+    let session_clone = session.clone();
     runtime.spawn(async move {
         loop {
-            let mut instance = chidori.get_instance().unwrap();
+            let mut instance = match &session_clone {
+                Some(session) => chidori.get_instance_with_session(session).unwrap(),
+                None => chidori.get_instance().unwrap(),
+            };
+            instance.set_max_concurrency(concurrency);
+            instance.set_refuse_invalid_notebooks(strict);
             let _await_ready = instance.wait_until_ready().await;
             chidori.load_md_directory(&run_directory_clone).unwrap();
             let result = instance.run(PlaybackState::Running).await;
@@ -100,15 +231,92 @@ async fn main() -> anyhow::Result<()>{
     let cli = Cli::parse();
 
     match &cli.command {
-        Some(Commands::Run { load }) => {
+        Some(Commands::Run { load, execution, until_idle, stdin_input, input_port, input_host }) => {
             info!("Running Chidori with target src directory: {:?}", load);
-            run_command(load).await
+            execution.configure()?;
+            if *stdin_input {
+                answer_input_requests_from_stdin();
+            }
+            if let Some(port) = input_port {
+                let addr = serve_input_requests(*input_host, *port).await?;
+                info!("Serving requests for input on {:?}", addr);
+            }
+            if *until_idle {
+                let (_chidori, mut instance) = load_headless_instance(load, &execution.session).await?;
+                execution.configure_instance(&mut instance);
+                let state = instance.run_until_idle(execution.max_steps).await?;
+                println!("{}", serde_json::to_string_pretty(&state_to_json(&state))?);
+                return Ok(());
+            }
+            run_command(load, &execution.session, execution.concurrency, execution.strict).await
+        }
+        Some(Commands::Test { load, execution }) => {
+            execution.configure()?;
+            let (_chidori, mut instance) = load_headless_instance(load, &execution.session).await?;
+            execution.configure_instance(&mut instance);
+            let state = instance.run_until_idle(execution.max_steps).await?;
+            let results = collect_test_results(&state);
+            if results.is_empty() {
+                println!("No test cells found in {:?}", load);
+                return Ok(());
+            }
+            let mut failed = 0;
+            for result in &results {
+                if result.passed {
+                    println!("ok      {} ({} tests)", result.cell, result.tests_run);
+                } else {
+                    failed += 1;
+                    println!("FAILED  {} ({} tests)", result.cell, result.tests_run);
+                    for line in &result.output {
+                        println!("        {}", line.trim_end());
+                    }
+                }
+            }
+            println!("{} of {} test cells passed", results.len() - failed, results.len());
+            if failed > 0 {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(Commands::Check { load }) => {
+            let (_chidori, instance) = load_headless_instance(load, &None).await?;
+            let state = instance.get_state_at_current_execution_head_result()?.clone();
+            if state.validation.is_empty() {
+                println!("No issues found in {:?}", load);
+                return Ok(());
+            }
+            print!("{}", state.validation.render(&state));
+            if state.validation.has_errors() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(Commands::Graph { load, format }) => {
+            let (_chidori, instance) = load_headless_instance(load, &None).await?;
+            let state = instance.get_state_at_current_execution_head_result()?.clone();
+            match format {
+                GraphFormat::Dot => print!("{}", dependency_graph_to_dot(&state)),
+                GraphFormat::Json => println!("{}", serde_json::to_string_pretty(&dependency_graph_to_json(&state))?),
+            }
+            Ok(())
+        }
+        Some(Commands::ClearCache { load, cell }) => {
+            let cache = MemoizationCache::for_notebook(load);
+            match cell {
+                Some(cell) => cache.invalidate_cell(cell)?,
+                None => cache.invalidate_notebook()?,
+            }
+            Ok(())
+        }
+        Some(Commands::GcVenvs { older_than_days, dry_run }) => {
+            let max_age = std::time::Duration::from_secs(older_than_days * 24 * 60 * 60);
+            let removed = collect_stale_venvs(max_age, *dry_run)?;
+            for venv in &removed {
+                println!("{}  {}", if *dry_run { "stale  " } else { "removed" }, venv.display());
+            }
+            println!("{} virtualenv(s) {}", removed.len(), if *dry_run { "would be removed" } else { "removed" });
+            Ok(())
         }
-        // Some(Commands::Test { test_dir, verbose }) => {
-        //     println!("Running tests in directory: {:?}", test_dir);
-        //     println!("Verbose mode: {}", verbose);
-        //     // Add your test logic here
-        // }
         // Some(Commands::Deploy { environment, script }) => {
         //     println!("Deploying to environment: {}", environment);
         //     println!("Using deployment script: {:?}", script);
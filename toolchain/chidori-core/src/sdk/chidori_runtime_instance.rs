@@ -4,14 +4,19 @@ use std::sync::{mpsc, Arc};
 use tokio::sync::mpsc::Receiver as TokioReceiver;
 use no_deadlocks::Mutex;
 use std::fmt;
+use std::path::Path;
 use uuid::Uuid;
 use std::time::Duration;
 use anyhow::anyhow;
 use dashmap::mapref::one::Ref;
-use tracing::{debug, info};
+use tracing::{debug, error, info, warn};
 use crate::cells::CellTypes;
 use crate::execution::execution::execution_graph::{ExecutionGraph, ExecutionNodeId};
-use crate::execution::execution::execution_state::{EnclosedState};
+use crate::execution::execution::execution_state::{EnclosedState, ExecutionHead, ExecutionStateErrors};
+use crate::execution::execution::human_input::{provide_input, HumanInput};
+use crate::execution::execution::memoization::MemoizationCache;
+use crate::execution::execution::cancellation;
+use crate::execution::execution::validation::ValidationReport;
 use crate::execution::execution::ExecutionState;
 use crate::execution::primitives::identifiers::OperationId;
 use crate::execution::primitives::operation::OperationFnOutput;
@@ -32,6 +37,22 @@ pub struct ChidoriRuntimeInstance {
     pub trace_event_sender: Option<Sender<TraceEvents>>,
     pub shared_state: Arc<Mutex<SharedState>>,
     pub rx_execution_states: TokioReceiver<ExecutionState>,
+
+    /// Partial output of operations that are still running, tagged with the state evaluating them
+    pub intermediate_output_tx: Sender<(ExecutionNodeId, RkyvSerializedValue)>,
+    pub intermediate_output_rx: Receiver<(ExecutionNodeId, RkyvSerializedValue)>,
+
+    /// Number of ready operations evaluated side by side in each step
+    pub max_concurrency: usize,
+
+    /// Refuse to step notebooks whose cells fail validation, rather than leaving the invalid cells to never run
+    pub refuse_invalid_notebooks: bool,
+
+    /// Cache the outputs of the notebook's pure cells are memoized in
+    pub memoization: Option<Arc<MemoizationCache>>,
+
+    /// Follows the execution head, for webservices and schedules started by the stepped states
+    pub execution_head: ExecutionHead,
 }
 
 impl std::fmt::Debug for ChidoriRuntimeInstance {
@@ -44,6 +65,7 @@ impl std::fmt::Debug for ChidoriRuntimeInstance {
 impl ChidoriRuntimeInstance {
     pub fn new() -> ChidoriRuntimeInstance {
         let (tx, rx) = mpsc::channel();
+        let (intermediate_output_tx, intermediate_output_rx) = mpsc::channel();
         let mut db = ExecutionGraph::new();
         let execution_event_rx = db.take_execution_event_receiver();
         let state_id = Uuid::nil();
@@ -58,15 +80,57 @@ impl ChidoriRuntimeInstance {
             playback_state,
             shared_state: Arc::new(Mutex::new(SharedState::new())),
             rx_execution_states: execution_event_rx,
+            intermediate_output_tx,
+            intermediate_output_rx,
+            max_concurrency: 1,
+            refuse_invalid_notebooks: false,
+            memoization: None,
+            execution_head: Default::default(),
         }
     }
 
+    /// Reopen a session persisted at the given path, resuming from its last execution head.
+    /// If no session exists at the path yet, a new one is created there.
+    pub fn open_session(path: &Path) -> anyhow::Result<ChidoriRuntimeInstance> {
+        let (tx, rx) = mpsc::channel();
+        let (intermediate_output_tx, intermediate_output_rx) = mpsc::channel();
+        let mut db = ExecutionGraph::open(path)?;
+        let execution_event_rx = db.take_execution_event_receiver();
+        let state_id = db.restored_execution_head();
+        let playback_state = PlaybackState::Paused;
+
+        let mut shared_state = SharedState::new();
+        shared_state.execution_id_to_evaluation = db.execution_node_id_to_state.clone();
+        shared_state.execution_state_head_id = state_id;
+
+        Ok(ChidoriRuntimeInstance {
+            env_rx: rx,
+            db,
+            execution_head_state_id: state_id,
+            runtime_event_sender: None,
+            trace_event_sender: None,
+            playback_state,
+            shared_state: Arc::new(Mutex::new(shared_state)),
+            rx_execution_states: execution_event_rx,
+            intermediate_output_tx,
+            intermediate_output_rx,
+            max_concurrency: 1,
+            refuse_invalid_notebooks: false,
+            memoization: None,
+            execution_head: Default::default(),
+        })
+    }
+
     // TODO: reload_cells needs to diff the mutations that live on the current branch, with the state
     //       that we see in the shared state when this event is fired.
     pub async fn reload_cells(&mut self) -> anyhow::Result<()> {
         debug!("Reloading cells");
         let cells_to_upsert: Vec<_> = {
             let shared_state = self.shared_state.lock().unwrap();
+            // Notebooks loaded after the instance was created bring their cache along with their cells
+            if let Some(cache) = &shared_state.memoization {
+                self.memoization = Some(cache.clone());
+            }
             shared_state.editor_cells.values().map(|cell| cell.clone()).collect()
         };
 
@@ -105,6 +169,33 @@ impl ChidoriRuntimeInstance {
 
 
     // #[tracing::instrument]
+    /// Evaluate up to the given number of independent operations at once in every step, at least one.
+    pub fn set_max_concurrency(&mut self, max_concurrency: usize) {
+        self.max_concurrency = max_concurrency.max(1);
+    }
+
+    pub fn set_refuse_invalid_notebooks(&mut self, refuse_invalid_notebooks: bool) {
+        self.refuse_invalid_notebooks = refuse_invalid_notebooks;
+    }
+
+    /// Issues found validating the cells at the current execution head.
+    pub fn validation_report(&self) -> anyhow::Result<ValidationReport> {
+        Ok(self.get_state_at_current_execution_head_result()?.validation.clone())
+    }
+
+    /// Fails with the rendered validation errors when the notebook is invalid and invalid notebooks are refused.
+    fn check_validation(&self) -> anyhow::Result<()> {
+        if !self.refuse_invalid_notebooks {
+            return Ok(());
+        }
+        let state = self.get_state_at_current_execution_head_result()?;
+        if state.validation.has_errors() {
+            let errors = ValidationReport { issues: state.validation.errors().cloned().collect() };
+            return Err(ExecutionStateErrors::ValidationFailed(errors.render(&state)).into());
+        }
+        Ok(())
+    }
+
     pub async fn wait_until_ready(&mut self) -> anyhow::Result<()> {
         info!("Awaiting initialization of the execution coordinator");
         self.db.execution_depth_orchestration_initialized_notify.notified().await;
@@ -134,12 +225,17 @@ impl ChidoriRuntimeInstance {
 
             // Check for execution errors
             if let Ok(error) = error_rx.try_recv() {
-                // println!("Received execution error: {:?}", error);
+                // Cells that failed with on_error set to skip or fallback do not reach this point,
+                // the remaining failures pause playback until the user intervenes
                 self.set_playback_state(PlaybackState::Paused);
-                // TODO: notify the client about the error
-                // self.push_update_to_client(&ExecutionState::Error(error));
+                if let Some(sender) = self.runtime_event_sender.as_mut() {
+                    sender.send(EventsFromRuntime::ExecutionError(error.to_string())).unwrap();
+                }
             }
 
+            self.forward_intermediate_output();
+            self.forward_persistence_errors();
+
             // Receives the results of execution during progression of ExecutionStates
             if let Ok(state) = self.rx_execution_states.try_recv() {
                 println!("InstancedEnvironment received an execution event {:?}", &state.chronology_id);
@@ -154,6 +250,13 @@ impl ChidoriRuntimeInstance {
                 if matches!(self.playback_state, PlaybackState::Step) {
                     self.set_playback_state(PlaybackState::Paused);
                 }
+                if let Err(e) = self.check_validation() {
+                    self.set_playback_state(PlaybackState::Paused);
+                    if let Some(sender) = self.runtime_event_sender.as_mut() {
+                        sender.send(EventsFromRuntime::ExecutionError(e.to_string())).unwrap();
+                    }
+                    continue;
+                }
                 let execution_head_state_id = self.execution_head_state_id;
 
                 // Acquire lock and check if we're already executing this state
@@ -166,7 +269,9 @@ impl ChidoriRuntimeInstance {
                     // Spawn the progression of the given step in a separate task
                     let executing_states = Arc::clone(&executing_states);
                     let error_tx = error_tx.clone();
-                    let state = self.get_state_at_current_execution_head_result()?.clone();
+                    let state = self.state_to_step()?;
+                    let intermediate_output_tx = self.intermediate_output_tx.clone();
+                    let max_concurrency = self.max_concurrency;
 
                     std::thread::spawn(move || {
                         // Create a new tokio runtime for this thread
@@ -177,7 +282,7 @@ impl ChidoriRuntimeInstance {
 
                         // Execute the async block on this runtime
                         runtime.block_on(async {
-                            let result = state.step_execution().await;
+                            let result = state.step_execution_concurrently(max_concurrency, Some(intermediate_output_tx)).await;
                             // Clear execution
                             let mut executing_states_lock = executing_states.lock().unwrap();
                             executing_states_lock.remove(&execution_head_state_id);
@@ -218,6 +323,7 @@ impl ChidoriRuntimeInstance {
             UserInteractionMessage::RevertToState(id) => {
                 if let Some(id) = id {
                     self.execution_head_state_id = id;
+                    self.db.record_execution_head(id)?;
                     let sender = self.runtime_event_sender.as_mut().unwrap();
                     // let merged_state = self.db.get_merged_state_history(&id);
                     // sender.send(EventsFromRuntime::ExecutionStateChange(merged_state)).unwrap();
@@ -260,11 +366,45 @@ impl ChidoriRuntimeInstance {
             UserInteractionMessage::PushChatMessage(msg) => {
                 self.db.push_message(msg).await?;
             }
+            UserInteractionMessage::CancelStreamingOutput(id) => {
+                crate::library::std::ai::llm::cancel_streaming_output(id);
+            }
+            UserInteractionMessage::CancelOperation(id) => {
+                if !cancellation::cancel_operation(id) {
+                    info!("No operation is being evaluated by state {:?}", id);
+                }
+            }
+            UserInteractionMessage::CancelAllOperations => {
+                let cancelled = cancellation::cancel_all_operations();
+                info!("Cancelled {} operation(s)", cancelled);
+            }
+            UserInteractionMessage::ProvideInput(id, answer) => {
+                // A refused answer leaves the request pending, it is not a failure of the runtime
+                if let Err(e) = provide_input(id, answer) {
+                    info!("Answer to request {:?} was refused: {}", id, e);
+                }
+            }
+            UserInteractionMessage::InvalidateCache(cell) => {
+                // Instances without a cache have nothing memoized to forget
+                if let Some(cache) = &self.memoization {
+                    match cell {
+                        Some(name) => cache.invalidate_cell(&name)?,
+                        None => cache.invalidate_notebook()?,
+                    }
+                }
+            }
             UserInteractionMessage::RunCellInIsolation(cell, args) => {
                 // self.db.execute_operation_in_isolation(&cell.cell, args).await?;
             }
             UserInteractionMessage::Reset => {
-                self.db = ExecutionGraph::new();
+                self.db.shutdown().await;
+                self.db = if let Some(path) = self.db.store_path() {
+                    self.db.clear_store()?;
+                    ExecutionGraph::open(&path)?
+                } else {
+                    ExecutionGraph::new()
+                };
+                self.rx_execution_states = self.db.take_execution_event_receiver();
                 self.set_playback_state(PlaybackState::Paused);
                 let id = Uuid::nil();
                 self.execution_head_state_id = id;
@@ -293,14 +433,39 @@ impl ChidoriRuntimeInstance {
     fn set_execution_head(&mut self, state: &ExecutionState) {
         debug!("Setting execution head to {:?}", state.chronology_id);
         // Execution heads can only be Completed states, not states still evaluating
-        if matches!(&state.evaluating_enclosed_state, EnclosedState::Close(_)) || (&state).evaluating_enclosed_state == EnclosedState::SelfContained {
-            if state.evaluating_fn.is_none() {
+        if state.is_valid_execution_head() {
+            if let Some(sender) = self.runtime_event_sender.as_mut() {
+                sender.send(EventsFromRuntime::UpdateExecutionHead((&state).chronology_id)).unwrap();
+            }
+            let mut shared_state = self.shared_state.lock().unwrap();
+            shared_state.execution_state_head_id = (&state).chronology_id;
+            self.execution_head_state_id = (&state).chronology_id;
+            self.execution_head.set(state);
+            drop(shared_state);
+            if let Err(e) = self.db.record_execution_head(self.execution_head_state_id) {
+                let message = format!("Failed to record execution head {:?}: {}", self.execution_head_state_id, e);
+                error!("{}", message);
                 if let Some(sender) = self.runtime_event_sender.as_mut() {
-                    sender.send(EventsFromRuntime::UpdateExecutionHead((&state).chronology_id)).unwrap();
+                    sender.send(EventsFromRuntime::ExecutionError(message)).unwrap();
                 }
-                let mut shared_state = self.shared_state.lock().unwrap();
-                shared_state.execution_state_head_id = (&state).chronology_id;
-                self.execution_head_state_id = (&state).chronology_id;
+            }
+        }
+    }
+
+    /// Forward failures to persist states, the session may not be resumable past them
+    fn forward_persistence_errors(&mut self) {
+        for message in self.db.take_persistence_errors() {
+            if let Some(sender) = self.runtime_event_sender.as_mut() {
+                sender.send(EventsFromRuntime::ExecutionError(message)).unwrap();
+            }
+        }
+    }
+
+    /// Forward partial output of running operations, such as prompts streaming their response
+    fn forward_intermediate_output(&mut self) {
+        while let Ok((execution_node_id, value)) = self.intermediate_output_rx.try_recv() {
+            if let Some(sender) = self.runtime_event_sender.as_mut() {
+                sender.send(EventsFromRuntime::IntermediateOutput(execution_node_id, value)).unwrap();
             }
         }
     }
@@ -309,6 +474,9 @@ impl ChidoriRuntimeInstance {
         let state_id = state.chronology_id;
         println!("Resulted in state with id {:?}", &state_id);
         if let Some(sender) = self.runtime_event_sender.as_mut() {
+            if let Some(HumanInput { request, answer: None }) = &state.human_input {
+                sender.send(EventsFromRuntime::InputRequested(request.clone())).unwrap();
+            }
             sender.send(EventsFromRuntime::DefinitionGraphUpdated(state.get_dependency_graph_flattened())).unwrap();
             let mut cells = vec![];
             for (op_id, cell ) in state.cells_by_id.iter() {
@@ -325,20 +493,56 @@ impl ChidoriRuntimeInstance {
         }
     }
 
+    /// The state at the execution head, memoizing pure cells in the instance's cache.
+    fn state_to_step(&self) -> anyhow::Result<ExecutionState> {
+        let mut state = self.get_state_at_current_execution_head_result()?.clone();
+        state.memoization = self.memoization.clone();
+        state.execution_head = Some(self.execution_head.clone());
+        Ok(state)
+    }
+
     /// Increment the execution graph by one step
     #[tracing::instrument]
     pub async fn step(&mut self) -> anyhow::Result<Vec<(OperationId, OperationFnOutput)>> {
+        self.check_validation()?;
         let exec_head = self.execution_head_state_id;
         println!("======================= Executing state with id {:?} ======================", &exec_head);
         let (state, outputs) = {
-            let state = self.get_state_at_current_execution_head_result()?;
-            state.step_execution().await?
+            let state = self.state_to_step()?;
+            state.step_execution_concurrently(self.max_concurrency, Some(self.intermediate_output_tx.clone())).await?
         };
+        self.forward_intermediate_output();
+        self.forward_persistence_errors();
         self.push_update_to_client(&state);
         self.set_execution_head(&state);
         Ok(outputs)
     }
 
+    /// Step until no operation has fresh inputs to evaluate, returning the state execution settled in.
+    /// Notebooks that loop never become idle, `max_steps` bounds how long they are allowed to run.
+    /// Operations that can never run, such as invalid cells, are reported but do not keep execution from settling.
+    pub async fn run_until_idle(&mut self, max_steps: Option<usize>) -> anyhow::Result<ExecutionState> {
+        let mut steps = 0;
+        loop {
+            if max_steps.map_or(false, |max_steps| steps >= max_steps) {
+                return Err(anyhow!("Execution did not become idle within {} steps", steps));
+            }
+            match self.step().await {
+                Ok(_) => steps += 1,
+                Err(e) if matches!(e.downcast_ref::<ExecutionStateErrors>(), Some(ExecutionStateErrors::NoFurtherExecutionDetected)) => {
+                    break;
+                }
+                Err(e) if matches!(e.downcast_ref::<ExecutionStateErrors>(), Some(ExecutionStateErrors::PendingOperationsNotRunnable(_))) => {
+                    warn!("{}", e);
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        info!("Execution became idle after {} steps", steps);
+        Ok(self.get_state_at_current_execution_head_result()?.clone())
+    }
+
     /// Add a cell into the execution graph
     #[tracing::instrument]
     pub async fn upsert_cell(&mut self, cell: CellTypes, op_id: OperationId) -> anyhow::Result<(ExecutionNodeId, OperationId)> {
@@ -349,6 +553,9 @@ impl ChidoriRuntimeInstance {
         };
         println!("Capturing final_state of the mutate graph operation parent {:?}, id {:?}", final_state.parent_state_chronology_id, final_state.chronology_id);
         let ((state_id, state), op_id) = ((final_state.chronology_id.clone(), final_state.clone()), op_id2);
+        if let Some(sender) = self.runtime_event_sender.as_mut() {
+            sender.send(EventsFromRuntime::ValidationReportUpdated(state.validation.clone())).unwrap();
+        }
         self.push_update_to_client(&state);
         self.set_execution_head(&state);
         Ok((state_id, op_id))
@@ -367,6 +574,16 @@ pub enum UserInteractionMessage {
     Shutdown,
     PushChatMessage(String),
     RunCellInIsolation(CellHolder, RkyvSerializedValue),
+    /// Stop a streaming prompt evaluated by the given state, keeping the output received so far
+    CancelStreamingOutput(ExecutionNodeId),
+    /// Stop the cell evaluated by the given state, its evaluation is recorded as a failure
+    CancelOperation(ExecutionNodeId),
+    /// Stop every cell that is being evaluated
+    CancelAllOperations,
+    /// Answer the request for input recorded by the given state, resuming the cell that made it
+    ProvideInput(ExecutionNodeId, RkyvSerializedValue),
+    /// Forget the memoized outputs of the named cell, or of every cell of the notebook
+    InvalidateCache(Option<String>),
     Reset
 }
 
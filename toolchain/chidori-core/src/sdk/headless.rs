@@ -0,0 +1,268 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::BufRead;
+use std::time::Duration;
+use serde_json::{json, Value};
+use crate::cells::CellTypes;
+use crate::cells::code_cell::code_cell_report;
+use crate::execution::execution::human_input::{pending_input_requests, provide_input, InputRequest};
+use crate::execution::execution::ExecutionState;
+use crate::execution::primitives::identifiers::{DependencyReference, OperationId};
+use crate::execution::primitives::serialized_value::{json_value_to_serialized_value, serialized_value_to_json_value, RkyvSerializedValue};
+use crate::execution::primitives::usage::price_table;
+
+/// Name used to refer to a cell in reports, falling back to its operation id when it is unnamed.
+fn cell_label(state: &ExecutionState, operation_id: &OperationId) -> String {
+    state.cells_by_id.get(operation_id)
+        .and_then(|cell| cell.name().clone())
+        .unwrap_or_else(|| operation_id.to_string())
+}
+
+fn cell_kind(cell: &CellTypes) -> &'static str {
+    match cell {
+        CellTypes::Code(..) => "code",
+        CellTypes::CodeGen(..) => "codegen",
+        CellTypes::Prompt(..) => "prompt",
+        CellTypes::Template(..) => "template",
+        CellTypes::Memory(..) => "memory",
+        CellTypes::Webservice(..) => "webservice",
+        CellTypes::Schedule(..) => "schedule",
+    }
+}
+
+/// The outputs of every cell at the given state, along with the model usage of the branch that produced them.
+pub fn state_to_json(state: &ExecutionState) -> Value {
+    let mut cells = BTreeMap::new();
+    for (operation_id, cell) in state.cells_by_id.iter() {
+        let output = state.state.get(operation_id).map(|output| {
+            let mut value = match &output.output {
+                Ok(value) => json!({ "value": serialized_value_to_json_value(value) }),
+                Err(e) => json!({ "error": e.to_string() }),
+            };
+            let entry = value.as_object_mut().unwrap();
+            entry.insert("stdout".to_string(), json!(output.stdout));
+            entry.insert("stderr".to_string(), json!(output.stderr));
+            value
+        });
+        cells.insert(cell_label(state, operation_id), json!({
+            "operation_id": operation_id.to_string(),
+            "kind": cell_kind(cell),
+            "output": output,
+        }));
+    }
+    let usage = state.branch_usage();
+    json!({
+        "execution_state_id": state.chronology_id.to_string(),
+        "cells": cells,
+        "usage": {
+            "by_model": usage.by_model,
+            "total_tokens": usage.total_tokens(),
+            "cost": usage.cost(&price_table()),
+        },
+    })
+}
+
+fn dependency_reference_label(reference: &DependencyReference) -> String {
+    match reference {
+        DependencyReference::Positional(i) => format!("arg {}", i),
+        DependencyReference::Keyword(name) => format!("kwarg {}", name),
+        DependencyReference::Global(name) => name.clone(),
+        DependencyReference::FunctionInvocation(name) => format!("{}()", name),
+        DependencyReference::Ordering => "ordering".to_string(),
+    }
+}
+
+/// Graphviz rendering of the dependencies between the cells of a notebook.
+pub fn dependency_graph_to_dot(state: &ExecutionState) -> String {
+    let mut operation_ids: Vec<&OperationId> = state.cells_by_id.keys().collect();
+    operation_ids.sort();
+    let mut dot = String::from("digraph chidori {\n");
+    for operation_id in operation_ids {
+        let cell = &state.cells_by_id[operation_id];
+        dot.push_str(&format!(
+            "    \"{}\" [label=\"{} ({})\"];\n",
+            operation_id, cell_label(state, operation_id).replace('"', "\\\""), cell_kind(cell)
+        ));
+    }
+    let mut edges = state.get_dependency_graph_flattened();
+    edges.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+    for (from, to, references) in edges {
+        let label = references.iter().map(dependency_reference_label).collect::<Vec<_>>().join(", ");
+        dot.push_str(&format!("    \"{}\" -> \"{}\" [label=\"{}\"];\n", from, to, label.replace('"', "\\\"")));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// JSON rendering of the dependencies between the cells of a notebook.
+pub fn dependency_graph_to_json(state: &ExecutionState) -> Value {
+    let mut operation_ids: Vec<&OperationId> = state.cells_by_id.keys().collect();
+    operation_ids.sort();
+    let nodes = operation_ids.iter().map(|operation_id| json!({
+        "id": operation_id.to_string(),
+        "name": cell_label(state, operation_id),
+        "kind": cell_kind(&state.cells_by_id[*operation_id]),
+    })).collect::<Vec<_>>();
+    let mut edges = state.get_dependency_graph_flattened();
+    edges.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+    let edges = edges.into_iter().map(|(from, to, references)| json!({
+        "from": from.to_string(),
+        "to": to.to_string(),
+        "references": references,
+    })).collect::<Vec<_>>();
+    json!({ "nodes": nodes, "edges": edges })
+}
+
+/// Outcome of a cell that runs tests, either Python `unittest` suites or JavaScript `Deno.test` cases.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellTestResult {
+    pub cell: String,
+    pub tests_run: usize,
+    pub passed: bool,
+    /// Test runner output, or the error the cell failed with
+    pub output: Vec<String>,
+}
+
+fn is_test_cell(cell: &CellTypes) -> bool {
+    match cell {
+        CellTypes::Code(code_cell, _) => {
+            code_cell_report(code_cell).map(|report| report.defines_tests).unwrap_or(false)
+        }
+        _ => false,
+    }
+}
+
+/// Read the summary printed by a `unittest` runner: the number of tests run and whether they all passed.
+/// JavaScript test cells report in the same format.
+pub fn parse_test_summary(lines: &[String]) -> Option<(usize, bool)> {
+    let text = lines.join("\n");
+    let tests_run = text.lines().find_map(|line| {
+        let (_, rest) = line.split_once("Ran ")?;
+        rest.split_whitespace().next()?.parse::<usize>().ok()
+    })?;
+    let failed = text.lines().any(|line| line.contains("FAILED (") || line.contains("FAILED: "));
+    Some((tests_run, !failed))
+}
+
+/// Collect the results of every test cell at the given state. Test cells that never ran or did not
+/// report a summary are failures.
+pub fn collect_test_results(state: &ExecutionState) -> Vec<CellTestResult> {
+    let mut operation_ids: Vec<&OperationId> = state.cells_by_id.keys().collect();
+    operation_ids.sort();
+    operation_ids.into_iter()
+        .filter(|operation_id| is_test_cell(&state.cells_by_id[*operation_id]))
+        .map(|operation_id| {
+            let cell = cell_label(state, operation_id);
+            match state.state.get(operation_id) {
+                None => CellTestResult { cell, tests_run: 0, passed: false, output: vec!["Cell was never evaluated".to_string()] },
+                Some(output) => {
+                    if let Err(e) = &output.output {
+                        return CellTestResult { cell, tests_run: 0, passed: false, output: vec![e.to_string()] };
+                    }
+                    let lines: Vec<String> = output.stderr.iter().chain(output.stdout.iter()).cloned().collect();
+                    match parse_test_summary(&lines) {
+                        Some((tests_run, passed)) => CellTestResult { cell, tests_run, passed, output: lines },
+                        None => CellTestResult { cell, tests_run: 0, passed: false, output: lines },
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+/// Read an answer typed on the terminal. Requests expecting a string, or without a schema, take the
+/// line as it is, other answers are parsed as JSON.
+pub fn parse_terminal_answer(request: &InputRequest, line: &str) -> RkyvSerializedValue {
+    let expects_string = match &request.schema {
+        None => true,
+        Some(RkyvSerializedValue::Object(schema)) => matches!(schema.get("type"), Some(RkyvSerializedValue::String(ty)) if ty == "string"),
+        Some(_) => false,
+    };
+    if expects_string {
+        return RkyvSerializedValue::String(line.to_string());
+    }
+    serde_json::from_str::<Value>(line)
+        .map(|value| json_value_to_serialized_value(&value))
+        .unwrap_or_else(|_| RkyvSerializedValue::String(line.to_string()))
+}
+
+/// Ask for the answers to requests for input on the terminal, prompts are written to stderr so that
+/// they do not mix with output written to stdout. Refused answers are asked for again.
+pub fn answer_input_requests_from_stdin() {
+    std::thread::spawn(|| {
+        let mut asked = HashSet::new();
+        let stdin = std::io::stdin();
+        loop {
+            for request in pending_input_requests() {
+                if !asked.insert(request.id) {
+                    continue;
+                }
+                eprintln!("{}", request.prompt);
+                if let Some(schema) = &request.schema {
+                    eprintln!("(answer matching {})", serialized_value_to_json_value(schema));
+                }
+                loop {
+                    let mut line = String::new();
+                    if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                    let answer = parse_terminal_answer(&request, line.trim_end_matches(['\r', '\n']));
+                    match provide_input(request.id, answer) {
+                        Ok(()) => break,
+                        // The request may have been answered some other way while we were waiting
+                        Err(_) if !pending_input_requests().iter().any(|pending| pending.id == request.id) => break,
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_unittest_summary() {
+        let passing = vec![".\n".to_string(), "Ran 1 test in 0.000s\n\nOK\n".to_string()];
+        assert_eq!(parse_test_summary(&passing), Some((1, true)));
+        let failing = vec!["F.\n".to_string(), "Ran 2 tests in 0.001s\n\nFAILED (failures=1)\n".to_string()];
+        assert_eq!(parse_test_summary(&failing), Some((2, false)));
+        let javascript = vec!["[out]: \"test addition ... FAILED: expected 4\"\n".to_string(), "[out]: \"Ran 1 test\"\n".to_string()];
+        assert_eq!(parse_test_summary(&javascript), Some((1, false)));
+        assert_eq!(parse_test_summary(&vec!["no tests here".to_string()]), None);
+    }
+
+    #[test]
+    fn test_test_cells_are_found_by_static_analysis() {
+        use crate::cells::SupportedLanguage::{Deno, PyO3};
+        let cell = |language, source_code: &str| CellTypes::Code(crate::cells::CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language,
+            source_code: source_code.to_string(),
+            function_invocation: None,
+            policy: Default::default(),
+        }, Default::default());
+        assert!(is_test_cell(&cell(PyO3, "import unittest\nclass T(unittest.TestCase):\n    pass")));
+        assert!(is_test_cell(&cell(Deno, "Deno.test(\"adds\", () => {});")));
+        // Mentioning the frameworks is not enough
+        assert!(!is_test_cell(&cell(PyO3, "note = \"run with unittest\"")));
+        assert!(!is_test_cell(&cell(Deno, "const note = \"Deno.test(\";")));
+    }
+
+    #[test]
+    fn test_parse_terminal_answer() {
+        let mut request = InputRequest {
+            id: uuid::Uuid::nil(),
+            operation_id: uuid::Uuid::nil(),
+            prompt: "How many?".to_string(),
+            schema: None,
+            key: String::new(),
+        };
+        assert_eq!(parse_terminal_answer(&request, "42"), RkyvSerializedValue::String("42".to_string()));
+        request.schema = Some(json_value_to_serialized_value(&json!({ "type": "integer" })));
+        assert_eq!(parse_terminal_answer(&request, "42"), RkyvSerializedValue::Number(42));
+    }
+}
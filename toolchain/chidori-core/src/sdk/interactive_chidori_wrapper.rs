@@ -16,9 +16,14 @@ use std::ops::Deref;
 use crate::cells::{CellTypes};
 use crate::execution::execution::execution_graph::{ExecutionGraph, ExecutionNodeId, MergedStateHistory};
 use crate::execution::execution::ExecutionState;
+use crate::execution::execution::human_input::InputRequest;
+use crate::execution::execution::memoization::MemoizationCache;
+use crate::execution::execution::validation::ValidationReport;
 use crate::execution::primitives::identifiers::{DependencyReference, OperationId};
+use crate::execution::primitives::serialized_value::RkyvSerializedValue;
 use crate::sdk::chidori_runtime_instance::{ChidoriRuntimeInstance, PlaybackState, UserInteractionMessage};
-use crate::sdk::md::{interpret_markdown_code_block, load_folder};
+use crate::library::std::code::python_venv::requirements_from_file;
+use crate::sdk::md::{apply_notebook_configuration, interpret_markdown_code_block, load_folder, notebook_configuration};
 use crate::utils::telemetry::{init_internal_telemetry, TraceEvents};
 
 /// Chidori is the high level interface for interacting with our runtime.
@@ -56,6 +61,7 @@ fn initialize_shared_state_object() -> Arc<Mutex<SharedState>> {
         editor_cells: Default::default(),
         at_execution_state_cells: vec![],
         latest_state: None,
+        memoization: None,
     }))
 }
 
@@ -139,10 +145,14 @@ impl InteractiveChidoriWrapper {
 
     pub fn load_md_string(&mut self, s: &str) -> anyhow::Result<()> {
         let mut cells = vec![];
+        let configuration = notebook_configuration(s, None)?;
         crate::sdk::md::extract_code_blocks(s)
             .iter()
             .filter_map(|block| interpret_markdown_code_block(block, None).unwrap())
-            .for_each(|block| { cells.push(block); });
+            .for_each(|mut block| {
+                apply_notebook_configuration(&mut block, &configuration);
+                cells.push(block);
+            });
         cells.sort();
         self.loaded_path = Some("raw_text".to_string());
         self.load_cells(cells)
@@ -151,29 +161,68 @@ impl InteractiveChidoriWrapper {
     pub fn load_md_directory(&mut self, path: &Path) -> anyhow::Result<()> {
         let files = load_folder(path)?;
         let mut cells = vec![];
+        // Requirements listed next to the notebook apply to all of its Python cells
+        let requirements_path = path.join("requirements.txt");
+        let directory_requirements = if requirements_path.exists() {
+            requirements_from_file(&requirements_path)?
+        } else {
+            vec![]
+        };
         for file in files {
+            let notebook_directory = file.filename.as_ref()
+                .and_then(|filename| filename.parent())
+                .map(|directory| directory.canonicalize().unwrap_or_else(|_| directory.to_path_buf()));
+            let mut configuration = notebook_configuration(file.code.as_deref().unwrap_or_default(), notebook_directory.as_deref())?;
+            if !directory_requirements.is_empty() {
+                configuration.requirements.get_or_insert_with(Vec::new).extend(directory_requirements.iter().cloned());
+            }
             for block in file.result {
-                if let Some(block) = interpret_markdown_code_block(&block, Some(path.to_string_lossy().to_string())).unwrap() {
+                if let Some(mut block) = interpret_markdown_code_block(&block, Some(path.to_string_lossy().to_string())).unwrap() {
+                    apply_notebook_configuration(&mut block, &configuration);
                     cells.push(block);
                 }
             }
         }
         self.loaded_path = Some(path.to_str().unwrap().to_string());
+        self.shared_state.lock().unwrap().memoization = Some(Arc::new(MemoizationCache::for_notebook(path)));
         cells.sort();
         info!("Loading {} cells from {:?}", cells.len(), path);
         self.load_cells(cells)
     }
 
     pub fn get_instance(&mut self) -> anyhow::Result<ChidoriRuntimeInstance> {
+        self.instance_from_execution_graph(ExecutionGraph::new())
+    }
+
+    /// Create an instance backed by a session persisted at the given path. Execution resumes
+    /// from the last execution head of that session, and its cells are loaded into the editor.
+    pub fn get_instance_with_session(&mut self, path: &Path) -> anyhow::Result<ChidoriRuntimeInstance> {
+        self.instance_from_execution_graph(ExecutionGraph::open(path)?)
+    }
+
+    fn instance_from_execution_graph(&mut self, mut db: ExecutionGraph) -> anyhow::Result<ChidoriRuntimeInstance> {
         let (instanced_env_tx, env_rx) = mpsc::channel();
+        let (intermediate_output_tx, intermediate_output_rx) = mpsc::channel();
         self.instanced_env_tx = Some(instanced_env_tx);
-        let mut db = ExecutionGraph::new();
         let execution_event_rx = db.take_execution_event_receiver();
-        let state_id = Uuid::nil();
+        let state_id = db.restored_execution_head();
         let playback_state = PlaybackState::Paused;
 
         let mut shared_state = self.shared_state.lock().unwrap();
         shared_state.execution_id_to_evaluation = db.execution_node_id_to_state.clone();
+        shared_state.execution_state_head_id = state_id;
+
+        // Cells of a restored session are already applied at the restored head
+        if let Some(state) = db.get_state_at_id(state_id) {
+            for (op_id, cell) in state.cells_by_id.iter() {
+                shared_state.editor_cells.insert(*op_id, CellHolder {
+                    cell: cell.clone(),
+                    op_id: *op_id,
+                    applied_at: Some(state_id),
+                    needs_update: false,
+                });
+            }
+        }
 
         Ok(ChidoriRuntimeInstance {
             env_rx,
@@ -184,6 +233,12 @@ impl InteractiveChidoriWrapper {
             playback_state,
             shared_state: self.shared_state.clone(),
             rx_execution_states: execution_event_rx,
+            intermediate_output_tx,
+            intermediate_output_rx,
+            max_concurrency: 1,
+            refuse_invalid_notebooks: false,
+            memoization: shared_state.memoization.clone(),
+            execution_head: Default::default(),
         })
     }
 }
@@ -199,6 +254,14 @@ pub enum EventsFromRuntime {
     UpdateExecutionHead(ExecutionNodeId),
     ReceivedChatMessage(String),
     ExecutionStateCellsViewUpdated(Vec<CellHolder>),
+    /// Output produced so far by the operation being evaluated in the given state
+    IntermediateOutput(ExecutionNodeId, RkyvSerializedValue),
+    /// Execution stopped on a failure, playback is paused
+    ExecutionError(String),
+    /// A cell is suspended until the request is answered with `UserInteractionMessage::ProvideInput`
+    InputRequested(InputRequest),
+    /// Issues found validating the notebook after its cells were upserted
+    ValidationReportUpdated(ValidationReport),
 }
 
 #[derive(Debug)]
@@ -208,6 +271,8 @@ pub struct SharedState {
     pub latest_state: Option<ExecutionState>,
     pub editor_cells: HashMap<OperationId, CellHolder>,
     pub at_execution_state_cells: Vec<CellHolder>,
+    /// Cache of the loaded notebook, picked up by instances when they reload its cells
+    pub memoization: Option<Arc<MemoizationCache>>,
 }
 
 impl Serialize for SharedState {
@@ -233,6 +298,7 @@ impl SharedState {
             latest_state: None,
             editor_cells: Default::default(),
             at_execution_state_cells: vec![],
+            memoization: None,
         }
     }
 
@@ -2,10 +2,11 @@ use crate::sdk::interactive_chidori_wrapper::InteractiveChidoriWrapper;
 use chidori_prompt_format::extract_yaml_frontmatter_string;
 use indoc::indoc;
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use serde_derive::Serialize;
 use thiserror::Error;
-use crate::cells::{BackingFileReference, CellTypes, CodeCell, LLMCodeGenCell, LLMEmbeddingCell, LLMPromptCell, MemoryCell, SupportedLanguage, SupportedMemoryProviders, SupportedModelProviders, TemplateCell, TextRange, WebserviceCell};
+use crate::library::std::code::python_venv::normalize_requirements;
+use crate::cells::{BackingFileReference, CellPermissions, CellTypes, CodeCell, ExecutionPolicy, LLMCodeGenCell, LLMCodeGenCellChatConfiguration, LLMEmbeddingCell, LLMPromptCell, LLMPromptCellChatConfiguration, MemoryCell, ScheduleCell, SupportedLanguage, SupportedMemoryProviders, SupportedModelProviders, TemplateCell, TextRange, WebserviceCell};
 
 #[derive(PartialEq, Serialize, Debug)]
 pub struct MarkdownCodeBlock {
@@ -24,12 +25,12 @@ enum CodeResource {
 #[derive(Debug)]
 pub struct ParsedFile {
     filename: Option<Box<std::path::PathBuf>>,
-    code: Option<String>,
+    pub(crate) code: Option<String>,
     num_lines: usize,
     pub(crate) result: Vec<MarkdownCodeBlock>,
 }
 
-pub(crate) fn extract_code_blocks(body: &str) -> Vec<MarkdownCodeBlock> {
+pub fn extract_code_blocks(body: &str) -> Vec<MarkdownCodeBlock> {
     let mut code_blocks = Vec::new();
     let mut start = 0;
 
@@ -117,6 +118,66 @@ pub fn load_folder(path: &Path) -> anyhow::Result<Vec<ParsedFile>> {
     Ok(res)
 }
 
+/// Frontmatter of a webservice block, the port the routes are served on and optionally the host,
+/// `0.0.0.0` to accept requests from other machines.
+#[derive(serde::Deserialize, Debug)]
+struct WebserviceBlockConfiguration {
+    host: Option<String>,
+    port: Option<u16>,
+}
+
+/// Frontmatter of a memory block, naming the function used to embed inserted values and queries.
+#[derive(serde::Deserialize, Debug)]
+struct MemoryBlockConfiguration {
+    embedding_fn: String,
+}
+
+/// Frontmatter of a notebook's markdown file, applying to every cell in the file.
+#[derive(serde::Deserialize, Debug, Default, PartialEq)]
+pub struct NotebookConfiguration {
+    /// Permissions of the notebook's JavaScript cells, a cell's own permissions take precedence
+    #[serde(default)]
+    pub permissions: Option<CellPermissions>,
+    /// Packages every Python cell of the notebook needs installed
+    #[serde(default)]
+    pub requirements: Option<Vec<String>>,
+    /// Directory of the notebook's file, relative paths its cells are granted access to are resolved against it
+    #[serde(skip)]
+    pub directory: Option<PathBuf>,
+}
+
+/// Read the frontmatter opening a markdown file, which configures the notebook it holds. Notebooks
+/// read from a file are given the file's directory.
+pub fn notebook_configuration(source: &str, directory: Option<&Path>) -> Result<NotebookConfiguration, InterpretError> {
+    let mut configuration = if source.trim_start().starts_with("---") {
+        let (frontmatter, _) = chidori_prompt_format::templating::templates::split_frontmatter(source)
+            .map_err(|e| InterpretError::FrontmatterSplitError(e.to_string()))?;
+        serde_yaml::from_str::<Option<NotebookConfiguration>>(&frontmatter)?.unwrap_or_default()
+    } else {
+        NotebookConfiguration::default()
+    };
+    configuration.directory = directory.map(Path::to_path_buf);
+    Ok(configuration)
+}
+
+/// Give a cell the configuration of the notebook it belongs to, where the cell does not configure itself.
+pub fn apply_notebook_configuration(cell: &mut CellTypes, configuration: &NotebookConfiguration) {
+    let CellTypes::Code(code_cell, _) = cell else {
+        return;
+    };
+    if let Some(permissions) = &configuration.permissions {
+        let own_permissions = code_cell.policy.permissions.take().unwrap_or_default();
+        code_cell.policy.permissions = Some(own_permissions.or(permissions));
+    }
+    if let (Some(directory), Some(permissions)) = (&configuration.directory, code_cell.policy.permissions.take()) {
+        code_cell.policy.permissions = Some(permissions.resolve_paths(directory));
+    }
+    if let Some(requirements) = &configuration.requirements {
+        let own_requirements = code_cell.policy.requirements.take().unwrap_or_default();
+        code_cell.policy.requirements = Some(normalize_requirements(requirements.iter().cloned().chain(own_requirements)));
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum InterpretError {
     #[error("Failed to split frontmatter: {0}")]
@@ -128,6 +189,22 @@ pub enum InterpretError {
 }
 
 
+/// Frontmatter keys configuring the execution policy of a code cell
+const CODE_BLOCK_POLICY_KEYS: [&str; 8] = ["timeout", "retries", "backoff", "on_error", "purity", "handle_errors", "permissions", "requirements"];
+
+/// The execution policy set by the frontmatter opening a code block. Frontmatter is only read as a policy
+/// when each of its keys is a policy key, any other frontmatter is left as part of the cell's source.
+fn code_block_policy(frontmatter: &str) -> Result<Option<ExecutionPolicy>, InterpretError> {
+    let Ok(Some(serde_yaml::Value::Mapping(mapping))) = serde_yaml::from_str::<Option<serde_yaml::Value>>(frontmatter) else {
+        return Ok(None);
+    };
+    let is_policy = mapping.keys().all(|key| key.as_str().map_or(false, |key| CODE_BLOCK_POLICY_KEYS.contains(&key)));
+    if !is_policy {
+        return Ok(None);
+    }
+    Ok(Some(serde_yaml::from_value(serde_yaml::Value::Mapping(mapping))?))
+}
+
 pub fn interpret_markdown_code_block(block: &MarkdownCodeBlock, file_path: Option<String>) -> Result<Option<CellTypes>, InterpretError> {
     let whole_body = block.body.clone();
     let (frontmatter, body) = chidori_prompt_format::templating::templates::split_frontmatter(&block.body)
@@ -143,36 +220,78 @@ pub fn interpret_markdown_code_block(block: &MarkdownCodeBlock, file_path: Optio
                 "javascript" | "js" | "typescript" | "ts" => SupportedLanguage::Deno,
                 _ => unreachable!(), // Given the outer match, this branch should never be reached
             };
+            // Code only has frontmatter when it opens the block, a `---` further down is part of the source
+            let policy = if block.body.trim_start().starts_with("---") {
+                code_block_policy(&frontmatter)?
+            } else {
+                None
+            };
+            let (policy, source_code) = match policy {
+                Some(policy) => (policy, body),
+                None => (ExecutionPolicy::default(), block.body.clone()),
+            };
             Some(CellTypes::Code(CodeCell {
                 backing_file_reference,
                 name: block.name.clone(),
                 language,
-                source_code: block.body.clone(),
+                source_code,
                 function_invocation: None,
+                policy,
             }, block.range.clone()))
         },
-        "prompt" => Some(CellTypes::Prompt(LLMPromptCell::Chat {
-            backing_file_reference,
-            is_function_invocation: false,
-            configuration: serde_yaml::from_str(&frontmatter)?,
-            name: block.name.clone(),
-            provider: SupportedModelProviders::OpenAI,
-            complete_body: whole_body,
-            req: body,
-        }, block.range.clone())),
-        "codegen" => Some(CellTypes::CodeGen(LLMCodeGenCell {
+        "prompt" => {
+            let configuration: LLMPromptCellChatConfiguration = serde_yaml::from_str(&frontmatter)?;
+            Some(CellTypes::Prompt(LLMPromptCell::Chat {
+                backing_file_reference,
+                is_function_invocation: false,
+                provider: configuration.provider.clone().unwrap_or_default(),
+                configuration,
+                name: block.name.clone(),
+                complete_body: whole_body,
+                req: body,
+            }, block.range.clone()))
+        },
+        "codegen" => {
+            let configuration: LLMCodeGenCellChatConfiguration = serde_yaml::from_str(&frontmatter)?;
+            Some(CellTypes::CodeGen(LLMCodeGenCell {
+                backing_file_reference,
+                function_invocation: false,
+                provider: configuration.provider.clone().unwrap_or_default(),
+                configuration,
+                name: block.name.clone(),
+                complete_body: whole_body,
+                req: body,
+            }, block.range.clone()))
+        },
+        "html" | "template" => Some(CellTypes::Template(TemplateCell {
             backing_file_reference,
-            function_invocation: false,
-            configuration: serde_yaml::from_str(&frontmatter)?,
             name: block.name.clone(),
-            complete_body: whole_body,
-            provider: SupportedModelProviders::OpenAI,
-            req: body,
+            body: block.body.clone(),
         }, block.range.clone())),
-        "html" | "template" => Some(CellTypes::Template(TemplateCell {
+        "memory" => {
+            let configuration: MemoryBlockConfiguration = serde_yaml::from_str(&frontmatter)?;
+            Some(CellTypes::Memory(MemoryCell {
+                backing_file_reference,
+                name: block.name.clone(),
+                provider: SupportedMemoryProviders::InMemory,
+                embedding_function: configuration.embedding_fn,
+                function_invocation: None,
+            }, block.range.clone()))
+        },
+        "web" | "webservice" => {
+            let configuration: WebserviceBlockConfiguration = serde_yaml::from_str(&frontmatter)?;
+            Some(CellTypes::Webservice(WebserviceCell {
+                backing_file_reference,
+                name: block.name.clone(),
+                configuration: body,
+                host: configuration.host,
+                port: configuration.port.ok_or(InterpretError::PortParseError)?,
+            }, block.range.clone()))
+        },
+        "schedule" => Some(CellTypes::Schedule(ScheduleCell {
             backing_file_reference,
             name: block.name.clone(),
-            body: block.body.clone(),
+            configuration: body,
         }, block.range.clone())),
         _ => None,
     })
@@ -320,4 +439,231 @@ mod test {
             insta::assert_yaml_snapshot!(extracted);
         });
     }
+
+    #[test]
+    fn test_interpret_memory_block() {
+        let extracted = extract_code_blocks(indoc! {  r#"
+        ```memory (stateful_memory)
+        ---
+        embedding_fn: embed
+        ---
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&extracted[0], None).unwrap();
+        let Some(CellTypes::Memory(memory_cell, _)) = cell else { panic!("Expected a memory cell") };
+        assert_eq!(memory_cell.name, Some("stateful_memory".to_string()));
+        assert_eq!(memory_cell.embedding_function, "embed".to_string());
+        assert_eq!(memory_cell.provider, SupportedMemoryProviders::InMemory);
+    }
+
+    #[test]
+    fn test_interpret_prompt_block_provider() {
+        let extracted = extract_code_blocks(indoc! {  r#"
+        ```prompt (summary)
+        ---
+        provider: anthropic
+        model: claude-3-haiku
+        ---
+        Summarize {{text}}
+        ```
+
+        ```prompt (local)
+        ---
+        provider: my_registered_model
+        ---
+        Summarize {{text}}
+        ```
+        "#
+        });
+        let providers: Vec<SupportedModelProviders> = extracted.iter().map(|block| {
+            let Some(CellTypes::Prompt(LLMPromptCell::Chat { provider, configuration, .. }, _)) = interpret_markdown_code_block(block, None).unwrap() else { panic!("Expected a prompt cell") };
+            assert_eq!(configuration.provider.as_ref(), Some(&provider));
+            provider
+        }).collect();
+        assert_eq!(providers, vec![SupportedModelProviders::Anthropic, SupportedModelProviders::Registered("my_registered_model".to_string())]);
+    }
+
+    #[test]
+    fn test_interpret_webservice_block() {
+        let extracted = extract_code_blocks(indoc! {  r#"
+        ```webservice (api)
+        ---
+        port: 3839
+        ---
+        POST /add add a b
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&extracted[0], None).unwrap();
+        let Some(CellTypes::Webservice(webservice_cell, _)) = cell else { panic!("Expected a webservice cell") };
+        assert_eq!(webservice_cell.name, Some("api".to_string()));
+        assert_eq!(webservice_cell.port, 3839);
+        assert_eq!(webservice_cell.host, None);
+        assert_eq!(webservice_cell.configuration.trim(), "POST /add add a b");
+    }
+
+    #[test]
+    fn test_interpret_schedule_block() {
+        let extracted = extract_code_blocks(indoc! {  r#"
+        ```schedule (jobs)
+        0 */5 * * * * refresh_feed
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&extracted[0], None).unwrap();
+        let Some(CellTypes::Schedule(schedule_cell, _)) = cell else { panic!("Expected a schedule cell") };
+        assert_eq!(schedule_cell.name, Some("jobs".to_string()));
+        assert_eq!(schedule_cell.configuration.trim(), "0 */5 * * * * refresh_feed");
+    }
+
+    #[test]
+    fn test_interpret_code_block_policy() {
+        let extracted = extract_code_blocks(indoc! {  r#"
+        ```python (fetch)
+        ---
+        timeout: 5
+        retries: 2
+        backoff: 0.5
+        on_error:
+          fallback: cached
+        ---
+        x = fetch()
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&extracted[0], None).unwrap();
+        let Some(CellTypes::Code(code_cell, _)) = cell else { panic!("Expected a code cell") };
+        assert_eq!(code_cell.source_code.trim(), "x = fetch()");
+        assert_eq!(code_cell.policy.timeout, Some(5.0));
+        assert_eq!(code_cell.policy.max_attempts(), 3);
+        assert_eq!(code_cell.policy.backoff_before_retry(2), std::time::Duration::from_secs(1));
+        assert_eq!(code_cell.policy.on_error(), crate::cells::OnError::Fallback("cached".to_string()));
+    }
+
+    #[test]
+    fn test_code_block_frontmatter_without_policy_keys_is_kept() {
+        let extracted = extract_code_blocks(indoc! {  r#"
+        ```python (notes)
+        ---
+        title: Notes
+        tags: [draft]
+        ---
+        x = 1
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&extracted[0], None).unwrap();
+        let Some(CellTypes::Code(code_cell, _)) = cell else { panic!("Expected a code cell") };
+        assert_eq!(code_cell.source_code, extracted[0].body);
+        assert_eq!(code_cell.policy, ExecutionPolicy::default());
+    }
+
+    #[test]
+    fn test_cell_permissions_override_the_notebook() {
+        use crate::cells::PermissionGrant;
+        let source = indoc! { r#"
+        ---
+        permissions:
+          net: [api.github.com]
+          env: true
+        ---
+        # Scraper
+
+        ```javascript (scrape)
+        ---
+        permissions:
+          net: ["localhost:8080"]
+          read: [./data]
+        ---
+        const page = await fetch("http://localhost:8080");
+        ```
+        "#
+        };
+        let configuration = notebook_configuration(source, None).unwrap();
+        let extracted = extract_code_blocks(source);
+        let mut cell = interpret_markdown_code_block(&extracted[0], None).unwrap().unwrap();
+        apply_notebook_configuration(&mut cell, &configuration);
+        let CellTypes::Code(code_cell, _) = cell else { panic!("Expected a code cell") };
+        let permissions = code_cell.policy.permissions();
+        assert_eq!(permissions.net, Some(PermissionGrant::Only(vec!["localhost:8080".to_string()])));
+        assert_eq!(permissions.read, Some(PermissionGrant::Only(vec!["./data".to_string()])));
+        assert_eq!(permissions.env, Some(PermissionGrant::All(true)));
+        assert_eq!(permissions.write, None);
+        assert_eq!(notebook_configuration("# No frontmatter\n---\n", None).unwrap(), NotebookConfiguration::default());
+    }
+
+    #[test]
+    fn test_relative_paths_are_granted_within_the_notebook_directory() {
+        let source = indoc! { r#"
+        ---
+        permissions:
+          read: [./data, /etc/hosts]
+        ---
+        ```javascript (scrape)
+        ---
+        permissions:
+          write: [out]
+        ---
+        const page = await fetch("http://localhost:8080");
+        ```
+        "#
+        };
+        let configuration = notebook_configuration(source, Some(Path::new("/notebooks/scraper"))).unwrap();
+        let mut cell = interpret_markdown_code_block(&extract_code_blocks(source)[0], None).unwrap().unwrap();
+        apply_notebook_configuration(&mut cell, &configuration);
+        let CellTypes::Code(code_cell, _) = cell else { panic!("Expected a code cell") };
+        let permissions = code_cell.policy.permissions();
+        assert_eq!(permissions.read, Some(PermissionGrant::Only(vec!["/notebooks/scraper/data".to_string(), "/etc/hosts".to_string()])));
+        assert_eq!(permissions.write, Some(PermissionGrant::Only(vec!["/notebooks/scraper/out".to_string()])));
+    }
+
+    #[test]
+    fn test_notebook_requirements_extend_the_cell() {
+        let source = indoc! { r#"
+        ---
+        requirements: [requests]
+        ---
+        ```python (scrape)
+        ---
+        requirements: [beautifulsoup4, requests]
+        ---
+        import requests
+        ```
+        "#
+        };
+        let configuration = notebook_configuration(source, None).unwrap();
+        let mut cell = interpret_markdown_code_block(&extract_code_blocks(source)[0], None).unwrap().unwrap();
+        apply_notebook_configuration(&mut cell, &configuration);
+        let CellTypes::Code(code_cell, _) = cell else { panic!("Expected a code cell") };
+        assert_eq!(code_cell.policy.requirements, Some(vec!["beautifulsoup4".to_string(), "requests".to_string()]));
+    }
+
+    #[test]
+    fn test_report_ranges_map_to_the_markdown_file() {
+        use chidori_static_analysis::language::python::parse::{build_report, extract_dependencies_python};
+        let source = indoc! { r#"
+        # Greeting
+
+        ```python (greet)
+        ---
+        timeout: 5
+        ---
+        name = "world"
+        greeting = "hello " + name
+        ```
+        "#
+        };
+        let extracted = extract_code_blocks(source);
+        let cell = interpret_markdown_code_block(&extracted[0], Some("greeting.md".to_string())).unwrap().unwrap();
+        let CellTypes::Code(code_cell, _) = cell else { panic!("Expected a code cell") };
+        let report = build_report(&extract_dependencies_python(&code_cell.source_code).unwrap());
+        let greeting = &report.cell_exposed_values["greeting"];
+        let range = code_cell.range_in_backing_file(source, &(&greeting.range).into()).unwrap();
+        assert_eq!(&source[range.start..range.end], "greeting");
+        assert_eq!(range.start_position(source), (7, 0));
+        let name = code_cell.range_in_backing_file(source, &(&report.cell_exposed_values["name"].range).into()).unwrap();
+        assert_eq!(name.start_position(source), (6, 0));
+        assert!(code_cell.range_in_backing_file("# Greeting", &(&greeting.range).into()).is_none());
+    }
 }
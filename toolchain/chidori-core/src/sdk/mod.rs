@@ -1,3 +1,4 @@
 pub mod md;
 pub mod interactive_chidori_wrapper;
 pub mod chidori_runtime_instance;
+pub mod headless;
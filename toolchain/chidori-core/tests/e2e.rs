@@ -1,5 +1,6 @@
 use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::sync::Arc;
 use super::*;
 use chidori_core::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
 use indoc::indoc;
@@ -7,7 +8,10 @@ use uuid::Uuid;
 use chidori_core::cells::{CellTypes, CodeCell, LLMPromptCell, LLMPromptCellChatConfiguration, SupportedLanguage, SupportedModelProviders, TextRange};
 use chidori_core::sdk::interactive_chidori_wrapper::InteractiveChidoriWrapper;
 use chidori_core::sdk::chidori_runtime_instance::ChidoriRuntimeInstance;
+use chidori_core::execution::execution::memoization::MemoizationCache;
 use chidori_core::utils;
+use chidori_core::library::std::ai::llm::mock::{MockChatModel, MockResponse};
+use chidori_core::library::std::ai::llm::providers::register_chat_model;
 
 #[tokio::test]
 async fn test_execute_cells_with_global_dependency() -> anyhow::Result<()> {
@@ -20,6 +24,7 @@ async fn test_execute_cells_with_global_dependency() -> anyhow::Result<()> {
                         x = 20
                         "#}),
         function_invocation: None,
+        policy: Default::default(),
     }, TextRange::default()),
                                        Uuid::now_v7())?;
     let (_, op_id_y) = env.upsert_cell(CellTypes::Code(CodeCell {
@@ -30,6 +35,7 @@ async fn test_execute_cells_with_global_dependency() -> anyhow::Result<()> {
                         y = x + 1
                         "#}),
         function_invocation: None,
+        policy: Default::default(),
     }, TextRange::default()),
                                        Uuid::now_v7())?;
     // env.resolve_dependencies_from_input_signature();
@@ -53,7 +59,7 @@ async fn test_execute_cells_with_global_dependency() -> anyhow::Result<()> {
 
 #[tokio::test]
 async fn test_execute_cells_between_code_and_llm() -> anyhow::Result<()> {
-    dotenv::dotenv().ok();
+    register_chat_model("e2e_first_word", Arc::new(MockChatModel::scripted(vec![MockResponse::Text("Here".to_string())])));
     let mut env = ChidoriRuntimeInstance::new();
     let (_, op_id_x) = env.upsert_cell(CellTypes::Code(CodeCell {
         backing_file_reference: None,
@@ -63,18 +69,27 @@ async fn test_execute_cells_between_code_and_llm() -> anyhow::Result<()> {
                         x = "Here is a sample string"
                         "#}),
         function_invocation: None,
+        policy: Default::default(),
     }, TextRange::default()),
                                        Uuid::now_v7())?;
     let (_, op_id_y) = env.upsert_cell(CellTypes::Prompt(LLMPromptCell::Chat {
         backing_file_reference: None,
         is_function_invocation: false,
         configuration: LLMPromptCellChatConfiguration {
+            provider: Some("e2e_first_word".into()),
             model: Some("gpt-3.5-turbo".into()),
             ..Default::default()
         },
         name: Some("example".into()),
         provider: SupportedModelProviders::OpenAI,
-        complete_body: "".to_string(),
+        complete_body: indoc! { r#"
+                      ---
+                      provider: e2e_first_word
+                      model: gpt-3.5-turbo
+                      ---
+                      Say only a single word. Give no additional explanation.
+                      What is the first word of the following: {{x}}.
+                    "#}.to_string(),
         req: "\
                       Say only a single word. Give no additional explanation.
                       What is the first word of the following: {{x}}.
@@ -90,6 +105,7 @@ async fn test_execute_cells_between_code_and_llm() -> anyhow::Result<()> {
                         z = await example(x=x)
                         "#}),
         function_invocation: None,
+        policy: Default::default(),
     }, TextRange::default()),
                                        Uuid::now_v7())?;
 
@@ -136,6 +152,7 @@ async fn test_execute_cells_prompts_as_functions() -> anyhow::Result<()> {
                         y = generate_names(x="John")
                         "#}),
         function_invocation: None,
+        policy: Default::default(),
     }, TextRange::default()),
                                        Uuid::now_v7())?;
     let (_, op_id_y) = env.upsert_cell(CellTypes::Prompt(LLMPromptCell::Chat {
@@ -177,6 +194,7 @@ async fn test_execute_cells_invoking_a_function() -> anyhow::Result<()> {
                             return x + y
                         "#}),
         function_invocation: None,
+        policy: Default::default(),
     }, TextRange::default()),
                                     Uuid::now_v7())?;
     let (_, id_b) = env.upsert_cell(CellTypes::Code(CodeCell {
@@ -187,6 +205,7 @@ async fn test_execute_cells_invoking_a_function() -> anyhow::Result<()> {
         source_code: String::from(indoc! { r#"
                         y = await add(2, 3)
                         "#}),
+        policy: Default::default(),
     }, TextRange::default()),
                                     Uuid::now_v7())?;
     env.get_state_at_current_execution_head().render_dependency_graph();
@@ -222,6 +241,7 @@ async fn test_execute_inter_runtime_code_plain() -> anyhow::Result<()> {
                             return x + y
                         "#}),
         function_invocation: None,
+        policy: Default::default(),
     }, TextRange::default()),
                                     Uuid::now_v7())?;
     let (_, id_b) = env.upsert_cell(CellTypes::Code(CodeCell {
@@ -232,6 +252,7 @@ async fn test_execute_inter_runtime_code_plain() -> anyhow::Result<()> {
         source_code: String::from(indoc! { r#"
                         const y = await add(2, 3);
                         "#}),
+        policy: Default::default(),
     }, TextRange::default()),
                                     Uuid::now_v7())?;
     env.get_state_at_current_execution_head().render_dependency_graph();
@@ -647,3 +668,75 @@ async fn test_core9_multi_agent_simulation() {
     let out = env.step().await;
     assert_eq!(env.get_state_at_current_execution_head().have_all_operations_been_set_at_least_once(), true);
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_resume_session_from_disk() -> anyhow::Result<()> {
+    let session_path = std::env::temp_dir().join(format!("chidori_session_{}.db", Uuid::now_v7()));
+    let (head, op_id_x) = {
+        let mut env = ChidoriRuntimeInstance::open_session(&session_path)?;
+        env.wait_until_ready().await.unwrap();
+        let (_, op_id_x) = env.upsert_cell(CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: String::from(indoc! { r#"
+                        x = 20
+                        "#}),
+            function_invocation: None,
+            policy: Default::default(),
+        }, TextRange::default()),
+                                           Uuid::now_v7()).await?;
+        env.step().await?;
+        let head = env.execution_head_state_id;
+        env.shutdown().await;
+        (head, op_id_x)
+    };
+
+    // Reopening the session restores the graph and resumes from the same execution head
+    let env = ChidoriRuntimeInstance::open_session(&session_path)?;
+    assert_eq!(env.execution_head_state_id, head);
+    let state = env.db.get_state_at_id(head).unwrap();
+    assert!(state.operation_by_id.contains_key(&op_id_x));
+    assert_eq!(
+        state.state.get(&op_id_x).map(|o| o.output.clone()),
+        Some(Ok(RkyvObjectBuilder::new().insert_number("x", 20).build()))
+    );
+    std::fs::remove_file(&session_path)?;
+    Ok(())
+}
+
+/// Run the notebook at the path in a new instance, returning whether any output was read from its cache.
+/// Cells are loaded after the instance is created, as the command line does.
+async fn run_notebook_until_idle(notebook_path: &Path) -> anyhow::Result<bool> {
+    let mut ee = InteractiveChidoriWrapper::new();
+    let mut env = ee.get_instance()?;
+    env.wait_until_ready().await?;
+    ee.load_md_directory(notebook_path)?;
+    env.reload_cells().await?;
+    env.run_until_idle(Some(10)).await?;
+    let cached = env.db.execution_node_id_to_state.iter().any(|state| state.cached);
+    env.shutdown().await;
+    Ok(cached)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_memoized_cells_are_read_from_the_cache_of_a_loaded_notebook() -> anyhow::Result<()> {
+    let notebook_path = std::env::temp_dir().join(format!("chidori_memoized_notebook_{}", Uuid::now_v7()));
+    std::fs::create_dir_all(&notebook_path)?;
+    std::fs::write(notebook_path.join("notebook.md"), indoc! { r#"
+        ```python (square)
+        ---
+        purity: pure
+        ---
+        x = 20
+        y = x * x
+        ```
+        "#})?;
+
+    assert!(!run_notebook_until_idle(&notebook_path).await?);
+    assert!(run_notebook_until_idle(&notebook_path).await?);
+
+    MemoizationCache::for_notebook(&notebook_path).invalidate_notebook()?;
+    std::fs::remove_dir_all(&notebook_path)?;
+    Ok(())
+}
@@ -22,10 +22,12 @@ use notify_debouncer_full::{
 
 use crate::{tokio_tasks, CurrentTheme};
 use chidori_core::execution::execution::execution_graph::{
-    ExecutionNodeId, MergedStateHistory,
+    run_usage, ExecutionNodeId, MergedStateHistory,
 };
 use chidori_core::execution::execution::ExecutionState;
 use chidori_core::execution::primitives::identifiers::{DependencyReference, OperationId};
+use chidori_core::execution::primitives::serialized_value::RkyvSerializedValue;
+use chidori_core::execution::primitives::usage::UsageTotals;
 use chidori_core::sdk::interactive_chidori_wrapper::{InteractiveChidoriWrapper, EventsFromRuntime};
 use chidori_core::sdk::interactive_chidori_wrapper::CellHolder;
 use chidori_core::tokio::task::JoinHandle;
@@ -300,6 +302,9 @@ pub struct ChidoriState {
 
     pub execution_ids_to_states: HashMap<ExecutionNodeId, ExecutionState>,
 
+    /// Output streamed so far by operations that are still being evaluated
+    pub intermediate_outputs: HashMap<ExecutionNodeId, RkyvSerializedValue>,
+
     pub trace_events: Vec<TraceEvents>,
 }
 
@@ -324,6 +329,7 @@ impl Default for ChidoriState {
             grouped_nodes: Default::default(),
             current_execution_head: Default::default(),
             execution_ids_to_states: Default::default(),
+            intermediate_outputs: Default::default(),
             trace_events: vec![],
         }
     }
@@ -376,6 +382,13 @@ impl ChidoriState {
     }
 
 
+    /// Model usage of every evaluation in the loaded run, across all branches
+    pub fn run_usage(&self) -> UsageTotals {
+        let chidori = self.chidori.lock().unwrap();
+        let shared_state = chidori.shared_state.lock().unwrap();
+        run_usage(shared_state.execution_id_to_evaluation.iter().map(|entry| entry.value().evaluated_usage()))
+    }
+
     pub fn step(&self) -> anyhow::Result<(), String> {
         let env = self.chidori.lock().unwrap();
         env.dispatch_user_interaction_to_instance(UserInteractionMessage::SetPlaybackState(PlaybackState::Step))
@@ -432,10 +445,41 @@ impl ChidoriState {
         self.grouped_nodes = Default::default();
         self.current_execution_head = Default::default();
         self.execution_ids_to_states = Default::default();
+        self.intermediate_outputs = Default::default();
         self.trace_events = vec![];
         Ok(())
     }
 
+    pub fn cancel_streaming_output(&self, id: ExecutionNodeId) -> anyhow::Result<(), String> {
+        let chidori = self.chidori.clone();
+        {
+            let chidori_guard = chidori.lock().expect("Failed to lock chidori");
+            chidori_guard.dispatch_user_interaction_to_instance(UserInteractionMessage::CancelStreamingOutput(id))
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn cancel_operation(&self, id: ExecutionNodeId) -> anyhow::Result<(), String> {
+        let chidori = self.chidori.clone();
+        {
+            let chidori_guard = chidori.lock().expect("Failed to lock chidori");
+            chidori_guard.dispatch_user_interaction_to_instance(UserInteractionMessage::CancelOperation(id))
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn cancel_all_operations(&self) -> anyhow::Result<(), String> {
+        let chidori = self.chidori.clone();
+        {
+            let chidori_guard = chidori.lock().expect("Failed to lock chidori");
+            chidori_guard.dispatch_user_interaction_to_instance(UserInteractionMessage::CancelAllOperations)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
     pub fn update_cell(&self, cell_holder: CellHolder) -> anyhow::Result<(), String> {
         let chidori = self.chidori.clone();
         {
@@ -526,6 +570,7 @@ fn setup(mut commands: Commands, runtime: ResMut<tokio_tasks::TokioTasksRuntime>
         grouped_nodes: Default::default(),
         current_execution_head: Default::default(),
         execution_ids_to_states: Default::default(),
+        intermediate_outputs: Default::default(),
         trace_events: vec![],
     };
 
@@ -564,15 +609,18 @@ fn setup(mut commands: Commands, runtime: ResMut<tokio_tasks::TokioTasksRuntime>
             match runtime_event_receiver.recv_timeout(Duration::from_millis(RECV_RUNTIME_EVENT_TIMEOUT_MS)) {
                 Ok(msg) => {
                     // println!("Received from runtime: {:?}", &msg);
-                    let msg_to_logs = msg.clone();
-                    ctx.run_on_main_thread(move |ctx| {
-                        if let Some(mut s) =
-                            ctx.world.get_resource_mut::<ChidoriState>()
-                        {
-                            s.log_messages.push(format!("Received from runtime: {:?}", &msg_to_logs));
-                        }
-                    })
-                        .await;
+                    // Streamed output arrives once per token, which would flood the logs
+                    if !matches!(msg, EventsFromRuntime::IntermediateOutput(..)) {
+                        let msg_to_logs = msg.clone();
+                        ctx.run_on_main_thread(move |ctx| {
+                            if let Some(mut s) =
+                                ctx.world.get_resource_mut::<ChidoriState>()
+                            {
+                                s.log_messages.push(format!("Received from runtime: {:?}", &msg_to_logs));
+                            }
+                        })
+                            .await;
+                    }
                     match msg {
                         EventsFromRuntime::ExecutionGraphUpdated(state) => {
                             ctx.run_on_main_thread(move |ctx| {
@@ -638,6 +686,12 @@ fn setup(mut commands: Commands, runtime: ResMut<tokio_tasks::TokioTasksRuntime>
                             .await;
                         }
                         EventsFromRuntime::ReceivedChatMessage(_) => {}
+                        // Already surfaced through the log messages above
+                        EventsFromRuntime::ExecutionError(_) => {}
+                        // Pending requests are listed by the state that recorded them in the execution graph
+                        EventsFromRuntime::InputRequested(_) => {}
+                        // Surfaced through the log messages above, and as an execution error when invalid notebooks are refused
+                        EventsFromRuntime::ValidationReportUpdated(_) => {}
                         EventsFromRuntime::ExecutionStateCellsViewUpdated(cells) => {
                             ctx.run_on_main_thread(move |ctx| {
                                 if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
@@ -651,6 +705,14 @@ fn setup(mut commands: Commands, runtime: ResMut<tokio_tasks::TokioTasksRuntime>
                                 .await;
 
                         }
+                        EventsFromRuntime::IntermediateOutput(id, value) => {
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
+                                    s.intermediate_outputs.insert(id, value);
+                                }
+                            })
+                                .await;
+                        }
                         EventsFromRuntime::PlaybackState(state) => {
                             ctx.run_on_main_thread(move |ctx| {
                                 if let Some(mut internal_state) = ctx.world.get_resource_mut::<ChidoriState>() {
@@ -959,6 +1021,9 @@ pub fn update_gui(
                             }
                         }
                     }
+                    if with_cursor(ui.button("Stop All")).clicked() {
+                        let _ = internal_state.cancel_all_operations();
+                    }
                     ui.add_space(8.0);
 
                     // let mut my_f32 = 0.0;
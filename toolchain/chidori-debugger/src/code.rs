@@ -6,7 +6,7 @@ use crate::{CurrentTheme, GameState, Theme};
 use bevy::app::{App, Update};
 use bevy::prelude::{in_state, Component, IntoSystemConfigs, Local, OnExit, Query, Res, ResMut, Window, With};
 use bevy::window::PrimaryWindow;
-use chidori_core::cells::{CellTypes, CodeCell, LLMCodeGenCell, LLMEmbeddingCell, LLMPromptCell, MemoryCell, SupportedLanguage, SupportedModelProviders, TemplateCell, TextRange};
+use chidori_core::cells::{CellTypes, CodeCell, LLMCodeGenCell, LLMEmbeddingCell, LLMPromptCell, MemoryCell, ScheduleCell, SupportedLanguage, SupportedMemoryProviders, SupportedModelProviders, TemplateCell, TextRange, WebserviceCell};
 use chidori_core::chidori_prompt_format::templating::templates::{SchemaItem, SchemaItemType};
 use chidori_core::execution::primitives::identifiers::OperationId;
 use chidori_core::sdk::interactive_chidori_wrapper::CellHolder;
@@ -261,6 +261,15 @@ pub fn editable_chidori_cell_content(
             CellTypes::Template(..) => {
                 render_template_cell(&mut chidori_state, &op_id, ui, cell_holder, exists_in_current_tree);
             }
+            CellTypes::Memory(..) => {
+                render_memory_cell(&mut chidori_state, &op_id, ui, cell_holder, exists_in_current_tree);
+            }
+            CellTypes::Webservice(..) => {
+                render_webservice_cell(&mut chidori_state, &op_id, ui, cell_holder, exists_in_current_tree);
+            }
+            CellTypes::Schedule(..) => {
+                render_schedule_cell(&mut chidori_state, &op_id, ui, cell_holder, exists_in_current_tree);
+            }
         }
 
         let state_binding = chidori_state.local_cell_state.entry(op_id).or_insert(Arc::new(Mutex::new(CellState::default()))).clone();
@@ -338,6 +347,143 @@ fn render_template_cell(
     });
 }
 
+fn render_memory_cell(
+    execution_state: &ChidoriState,
+    op_id: &OperationId,
+    mut ui: &mut Ui,
+    cell_holder: &mut CellHolder,
+    exists_in_current_tree: bool
+) {
+    let CellTypes::Memory(MemoryCell { name, embedding_function, backing_file_reference, .. }, _) = &mut cell_holder.cell else { panic!("Must be memory cell")};
+    if let Some(name) = name {
+        if ui.add(
+            egui::TextEdit::singleline(name)
+                .code_editor()
+                .lock_focus(true)
+                .margin(Margin::symmetric(8.0, 8.0))
+                .desired_width(f32::INFINITY)
+        ).changed() {
+            cell_holder.needs_update = true;
+            cell_holder.applied_at = None;
+        }
+    }
+    ui.horizontal(|ui| {
+        egui_label(ui, "Memory");
+        egui_label(ui, "Embedding Function");
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
+            if backing_file_reference.is_some() {
+                if ui.button("Open File").clicked() {
+                    println!("Should open file");
+                }
+            }
+        });
+    });
+    ui.vertical(|ui| {
+        if ui.add(
+            egui::TextEdit::singleline(embedding_function)
+                .code_editor()
+                .lock_focus(true)
+                .margin(Margin::symmetric(8.0, 8.0))
+                .desired_width(f32::INFINITY)
+        ).changed() {
+            cell_holder.needs_update = true;
+            cell_holder.applied_at = None;
+        }
+        render_operation_output(&execution_state, &op_id, ui);
+    });
+}
+
+fn render_webservice_cell(
+    execution_state: &ChidoriState,
+    op_id: &OperationId,
+    mut ui: &mut Ui,
+    cell_holder: &mut CellHolder,
+    exists_in_current_tree: bool
+) {
+    let CellTypes::Webservice(WebserviceCell { name, configuration, port, backing_file_reference, .. }, _) = &mut cell_holder.cell else { panic!("Must be webservice cell")};
+    if let Some(name) = name {
+        if ui.add(
+            egui::TextEdit::singleline(name)
+                .code_editor()
+                .lock_focus(true)
+                .margin(Margin::symmetric(8.0, 8.0))
+                .desired_width(f32::INFINITY)
+        ).changed() {
+            cell_holder.needs_update = true;
+            cell_holder.applied_at = None;
+        }
+    }
+    ui.horizontal(|ui| {
+        egui_label(ui, "Webservice");
+        egui_label(ui, &format!("Port {}", port));
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
+            if backing_file_reference.is_some() {
+                if ui.button("Open File").clicked() {
+                    println!("Should open file");
+                }
+            }
+        });
+    });
+    ui.vertical(|ui| {
+        if ui.add(
+            egui::TextEdit::multiline(configuration)
+                .code_editor()
+                .lock_focus(true)
+                .margin(Margin::symmetric(8.0, 8.0))
+                .desired_width(f32::INFINITY)
+        ).changed() {
+            cell_holder.needs_update = true;
+            cell_holder.applied_at = None;
+        }
+        render_operation_output(&execution_state, &op_id, ui);
+    });
+}
+
+fn render_schedule_cell(
+    execution_state: &ChidoriState,
+    op_id: &OperationId,
+    mut ui: &mut Ui,
+    cell_holder: &mut CellHolder,
+    exists_in_current_tree: bool
+) {
+    let CellTypes::Schedule(ScheduleCell { name, configuration, backing_file_reference, .. }, _) = &mut cell_holder.cell else { panic!("Must be schedule cell")};
+    if let Some(name) = name {
+        if ui.add(
+            egui::TextEdit::singleline(name)
+                .code_editor()
+                .lock_focus(true)
+                .margin(Margin::symmetric(8.0, 8.0))
+                .desired_width(f32::INFINITY)
+        ).changed() {
+            cell_holder.needs_update = true;
+            cell_holder.applied_at = None;
+        }
+    }
+    ui.horizontal(|ui| {
+        egui_label(ui, "Schedule");
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
+            if backing_file_reference.is_some() {
+                if ui.button("Open File").clicked() {
+                    println!("Should open file");
+                }
+            }
+        });
+    });
+    ui.vertical(|ui| {
+        if ui.add(
+            egui::TextEdit::multiline(configuration)
+                .code_editor()
+                .lock_focus(true)
+                .margin(Margin::symmetric(8.0, 8.0))
+                .desired_width(f32::INFINITY)
+        ).changed() {
+            cell_holder.needs_update = true;
+            cell_holder.applied_at = None;
+        }
+        render_operation_output(&execution_state, &op_id, ui);
+    });
+}
+
 fn render_operation_output(execution_state: &ChidoriState, op_id: &&OperationId, ui: &mut Ui) {
     // if let Some(state) = &execution_state.merged_state_history {
     //     if let Some((exec_id, o)) = state.0.get(op_id) {
@@ -753,6 +899,7 @@ fn render_new_cell_interface(
                     language: SupportedLanguage::PyO3,
                     source_code: "".to_string(),
                     function_invocation: None,
+                    policy: Default::default(),
                 }, TextRange::default()),
                 op_id,
                 applied_at: Default::default(),
@@ -789,6 +936,21 @@ fn render_new_cell_interface(
                 needs_update: false,
             });
         }
+        if ui.button("Add Memory Cell").clicked() {
+            let op_id = Uuid::now_v7();
+            state.temp_cell = Some(CellHolder {
+                cell: CellTypes::Memory(MemoryCell {
+                    backing_file_reference: None,
+                    name: None,
+                    provider: SupportedMemoryProviders::InMemory,
+                    embedding_function: "".to_string(),
+                    function_invocation: None,
+                }, TextRange::default()),
+                op_id,
+                applied_at: Default::default(),
+                needs_update: false,
+            });
+        }
         if ui.button("Add Code Generation Cell").clicked() {
             let op_id = Uuid::now_v7();
             state.temp_cell = Some((CellHolder {
@@ -833,6 +995,15 @@ let exists_in_current_tree = false;
             CellTypes::Template(..) => {
                 render_template_cell(&mut chidori_state, &op_id, ui, temp_cell, exists_in_current_tree);
             }
+            CellTypes::Memory(..) => {
+                render_memory_cell(&mut chidori_state, &op_id, ui, temp_cell, exists_in_current_tree);
+            }
+            CellTypes::Webservice(..) => {
+                render_webservice_cell(&mut chidori_state, &op_id, ui, temp_cell, exists_in_current_tree);
+            }
+            CellTypes::Schedule(..) => {
+                render_schedule_cell(&mut chidori_state, &op_id, ui, temp_cell, exists_in_current_tree);
+            }
         }
 
         if ui.button("Save and Push To Graph").clicked() {
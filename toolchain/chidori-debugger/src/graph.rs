@@ -1,6 +1,6 @@
 use crate::chidori::{ChidoriState, EguiTree, EguiTreeIdentities};
 use crate::tidy_tree::{Layout, Orientation, TidyLayout, TreeGraph};
-use crate::util::{despawn_screen, egui_render_cell_function_evaluation, egui_render_cell_read, serialized_value_to_json_value};
+use crate::util::{despawn_screen, egui_render_cell_function_evaluation, egui_render_cell_read, egui_render_usage, serialized_value_to_json_value};
 use crate::{bevy_egui, chidori, util, CurrentTheme, GameState, Theme, RENDER_LAYER_GRAPH_MINIMAP, RENDER_LAYER_GRAPH_VIEW, RENDER_LAYER_TRACE_MINIMAP, RENDER_LAYER_TRACE_TEXT, RENDER_LAYER_TRACE_VIEW};
 use bevy::app::{App, Update};
 use bevy::input::mouse::{MouseMotion, MouseWheel};
@@ -509,7 +509,42 @@ fn egui_execution_state(
                 if let Some(evaluating_name) = execution_state.evaluating_name.as_ref() {
                     ui.label(format!("Cell Name: {:?}", evaluating_name));
                 }
+                if let Some(evaluating_event) = execution_state.evaluating_event.as_ref() {
+                    ui.label(format!("Emitted Event: {:?}", evaluating_event));
+                }
+                if execution_state.cached {
+                    ui.label("Output read from cache");
+                }
                 egui_render_cell_function_evaluation(ui, execution_state);
+                if matches!(execution_state.evaluating_enclosed_state, EnclosedState::Open) {
+                    if execution_state.evaluating_cell.is_some() && ui.button("Stop Cell").clicked() {
+                        let _ = internal_state.cancel_operation(execution_state.chronology_id);
+                    }
+                    if let Some(partial) = internal_state.intermediate_outputs.get(&execution_state.chronology_id).cloned() {
+                        ui.horizontal(|ui| {
+                            ui.label("Streaming:");
+                            if ui.button("Cancel").clicked() {
+                                let _ = internal_state.cancel_streaming_output(execution_state.chronology_id);
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.add_space(10.0);
+                            let _ = JsonTree::new(format!("intermediate_{}", execution_state.chronology_id), &serialized_value_to_json_value(&partial))
+                                .show(ui);
+                        });
+                    }
+                }
+                let cell_usage = execution_state.usage_for_operation(&execution_state.evaluating_operation_id);
+                if !execution_state.evaluated_usage().is_empty() && !cell_usage.is_empty() {
+                    egui::CollapsingHeader::new("Usage")
+                        .id_source(format!("usage_{}", execution_state.chronology_id))
+                        .show(ui, |ui| {
+                            ui.label("This cell, along this branch:");
+                            egui_render_usage(ui, &cell_usage);
+                            ui.label("All cells, along this branch:");
+                            egui_render_usage(ui, &execution_state.branch_usage());
+                        });
+                }
                 if !execution_state.state.is_empty() {
                     ui.label("Output:");
                     // let mut frame = egui::Frame::default()
@@ -1973,6 +2008,12 @@ fn ui_window(
             ui.vertical(|ui| {
                 ui.label("Sidebar");
                 ui.button("Collapse Alternate Branches");
+                let run_usage = chidori_state.run_usage();
+                if !run_usage.is_empty() {
+                    ui.add_space(12.0);
+                    ui.label("Run Usage");
+                    egui_render_usage(ui, &run_usage);
+                }
             });
         });
 
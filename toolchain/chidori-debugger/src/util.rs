@@ -6,8 +6,10 @@ use egui::{Color32, FontFamily, FontId, Frame, Margin, RichText, Ui};
 use egui_extras::syntax_highlighting;
 use egui_extras::syntax_highlighting::CodeTheme;
 use egui_json_tree::value::{BaseValueType, ExpandableType, JsonTreeValue, ToJsonTreeValue};
-use chidori_core::cells::{CellTypes, CodeCell, LLMCodeGenCell, LLMEmbeddingCell, LLMPromptCell, MemoryCell, SupportedLanguage, TemplateCell, WebserviceCell};
-use chidori_core::execution::primitives::serialized_value::RkyvSerializedValue;
+use chidori_core::cells::{CellTypes, CodeCell, LLMCodeGenCell, LLMEmbeddingCell, LLMPromptCell, MemoryCell, ScheduleCell, SupportedLanguage, TemplateCell, WebserviceCell};
+use chidori_core::execution::execution::streams::stream_snapshot;
+use chidori_core::execution::primitives::serialized_value::{datetime_to_rfc3339, RkyvSerializedValue};
+use chidori_core::execution::primitives::usage::{price_table, UsageTotals};
 
 
 
@@ -57,9 +59,44 @@ pub fn egui_rkyv(ui: &mut Ui, value: &RkyvSerializedValue, with_clip: bool) {
         ui.set_clip_rect(clip_rect);
     }
     match value {
-        RkyvSerializedValue::StreamPointer(_) => {}
+        RkyvSerializedValue::StreamPointer(id) => {
+            ui.vertical(|ui| {
+                let Some(snapshot) = stream_snapshot(*id) else {
+                    ui.label(format!("Stream {} (no longer available)", id));
+                    return;
+                };
+                let state = match (&snapshot.error, snapshot.done) {
+                    (Some(_), _) => "failed",
+                    (None, true) => "done",
+                    (None, false) => "streaming",
+                };
+                ui.label(format!("Stream {}, {} chunks ({})", id, snapshot.dropped + snapshot.chunks.len(), state));
+                if snapshot.dropped > 0 {
+                    ui.label(format!("{} chunks read by every reader are no longer held", snapshot.dropped));
+                }
+                ui.indent("stream", |ui| {
+                    for chunk in &snapshot.chunks {
+                        egui_rkyv(ui, chunk, false);
+                    }
+                });
+                if let Some(error) = &snapshot.error {
+                    ui.label(RichText::new(error.to_string()).color(Color32::from_hex("#E06C75").unwrap()));
+                }
+            });
+        }
         RkyvSerializedValue::FunctionPointer(_, _) => {}
         RkyvSerializedValue::Cell(_) => {}
+        RkyvSerializedValue::Error(e) => {
+            ui.vertical(|ui| {
+                ui.label(RichText::new(e.to_string()).color(Color32::from_hex("#E06C75").unwrap()));
+                if let Some(cell) = &e.cell {
+                    ui.label(format!("Raised in {:?}", cell));
+                }
+                if let Some(traceback) = &e.traceback {
+                    ui.label(traceback);
+                }
+            });
+        }
         RkyvSerializedValue::Set(_) => {}
         RkyvSerializedValue::Float(a) => {
             ui.label(format!("{:?}", a));
@@ -74,6 +111,15 @@ pub fn egui_rkyv(ui: &mut Ui, value: &RkyvSerializedValue, with_clip: bool) {
             ui.label(format!("{:?}", a));
         }
         RkyvSerializedValue::Null => {}
+        RkyvSerializedValue::Bytes(a) => {
+            ui.label(format!("{} bytes", a.len()));
+        }
+        RkyvSerializedValue::DateTime(a) => {
+            ui.label(datetime_to_rfc3339(*a).unwrap_or_else(|| format!("{:?}", a)));
+        }
+        RkyvSerializedValue::Embedding(a) => {
+            ui.label(format!("Embedding of {} dimensions", a.len()));
+        }
         RkyvSerializedValue::Array(a) => {
             ui.vertical(|ui| {
                 ui.label("Array");
@@ -164,6 +210,23 @@ fn arguments_to_string(args: &RkyvSerializedValue) -> String {
     result
 }
 
+/// Token counts per model along with their cost under the active price table.
+pub fn egui_render_usage(ui: &mut Ui, usage: &UsageTotals) {
+    let prices = price_table();
+    ui.vertical(|ui| {
+        for (model, counts) in &usage.by_model {
+            let cost = prices.price_for(model)
+                .map(|price| format!("${:.4}", price.cost(counts)))
+                .unwrap_or("unpriced".to_string());
+            ui.label(format!(
+                "{}: {} requests, {} prompt + {} completion tokens, {}",
+                model, counts.requests, counts.prompt_tokens, counts.completion_tokens, cost
+            ));
+        }
+        ui.label(RichText::new(format!("Total: {} tokens, ${:.4}", usage.total_tokens(), usage.cost(&prices))).strong());
+    });
+}
+
 pub fn egui_render_cell_function_evaluation(ui: &mut Ui, state: &ExecutionState) {
     let theme = CodeTheme::dark();
 
@@ -215,6 +278,15 @@ pub fn egui_render_cell_read(ui: &mut Ui, cell: &CellTypes, state: &ExecutionSta
         CellTypes::Template(TemplateCell { name, body, .. }, _) => {
             render_text_cell(ui, name, body, "Prompt", "", &theme);
         }
+        CellTypes::Memory(MemoryCell { name, embedding_function, .. }, _) => {
+            render_text_cell(ui, name, embedding_function, "Memory", "", &theme);
+        }
+        CellTypes::Webservice(WebserviceCell { name, configuration, .. }, _) => {
+            render_text_cell(ui, name, configuration, "Webservice", "", &theme);
+        }
+        CellTypes::Schedule(ScheduleCell { name, configuration, .. }, _) => {
+            render_text_cell(ui, name, configuration, "Schedule", "", &theme);
+        }
         CellTypes::Prompt(LLMPromptCell::Completion { .. }, _)  => {}
     }
 }
@@ -402,8 +474,8 @@ pub fn find_matching_strings(
 
 pub fn serialized_value_to_json_value(v: &RkyvSerializedValue) -> serde_json::Value {
     match &v {
-        RkyvSerializedValue::Float(f) => Value::Number(f.to_string().parse().unwrap()),
-        RkyvSerializedValue::Number(n) => Value::Number(n.to_string().parse().unwrap()),
+        RkyvSerializedValue::Float(f) => serde_json::Number::from_f64(*f).map(Value::Number).unwrap_or(Value::Null),
+        RkyvSerializedValue::Number(n) => Value::Number((*n).into()),
         RkyvSerializedValue::String(s) => Value::String(s.to_string()),
         RkyvSerializedValue::Boolean(b) => Value::Bool(*b),
         RkyvSerializedValue::Array(a) => Value::Array(
@@ -417,9 +489,26 @@ pub fn serialized_value_to_json_value(v: &RkyvSerializedValue) -> serde_json::Va
                 .collect(),
         ),
         RkyvSerializedValue::FunctionPointer(_, _) => Value::Null,
-        RkyvSerializedValue::StreamPointer(_) => Value::Null,
+        RkyvSerializedValue::StreamPointer(id) => match stream_snapshot(*id) {
+            Some(snapshot) => serde_json::json!({
+                "stream": id,
+                "dropped": snapshot.dropped,
+                "chunks": snapshot.chunks.iter().map(serialized_value_to_json_value).collect::<Vec<_>>(),
+                "done": snapshot.done,
+                "error": snapshot.error.map(|e| e.to_string()),
+            }),
+            None => serde_json::json!({ "stream": id }),
+        },
         RkyvSerializedValue::Cell(_) => Value::Null,
+        RkyvSerializedValue::Error(e) => serde_json::json!({ "error": e.to_string(), "cell": e.cell, "traceback": e.traceback }),
         RkyvSerializedValue::Null => Value::Null,
+        RkyvSerializedValue::Bytes(b) => Value::String(format!("<{} bytes>", b.len())),
+        RkyvSerializedValue::DateTime(micros) => datetime_to_rfc3339(*micros).map(Value::String).unwrap_or(Value::Null),
+        RkyvSerializedValue::Embedding(e) => Value::Array(
+            e.iter()
+                .map(|f| serde_json::Number::from_f64(*f as f64).map(Value::Number).unwrap_or(Value::Null))
+                .collect(),
+        ),
         RkyvSerializedValue::Set(a) => {
             a.iter()
                 .map(|v| serialized_value_to_json_value(v))
@@ -0,0 +1,138 @@
+//! Language server for Chidori markdown notebooks, speaking LSP over stdin and stdout.
+//!
+//! Publishes diagnostics for names no cell provides, names provided by more than one cell and invalid
+//! frontmatter, shows which cell provides a name on hover, jumps to it across fenced blocks and lists
+//! the dependents of each cell in a code lens. Editors start it as `chidori-lsp` for markdown files.
+
+mod notebook;
+mod position;
+
+use dashmap::DashMap;
+use tower_lsp::jsonrpc::Result;
+use tower_lsp::lsp_types::*;
+use tower_lsp::{Client, LanguageServer, LspService, Server};
+use crate::notebook::{NotebookIndex, Severity};
+use crate::position::{offset_of, range_of};
+
+/// An open markdown file and the index of the notebook it holds.
+struct Document {
+    text: String,
+    index: NotebookIndex,
+}
+
+struct Backend {
+    client: Client,
+    documents: DashMap<Url, Document>,
+}
+
+impl Backend {
+    async fn on_change(&self, uri: Url, text: String, version: Option<i32>) {
+        let path = uri.to_file_path()
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or_else(|_| uri.to_string());
+        let index = NotebookIndex::new(&path, &text);
+        let diagnostics = index.diagnostics.iter()
+            .map(|diagnostic| Diagnostic {
+                range: range_of(&text, &diagnostic.range),
+                severity: Some(match diagnostic.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                source: Some("chidori".to_string()),
+                message: diagnostic.message.clone(),
+                ..Default::default()
+            })
+            .collect();
+        self.documents.insert(uri.clone(), Document { text, index });
+        self.client.publish_diagnostics(uri, diagnostics, version).await;
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                code_lens_provider: Some(CodeLensOptions { resolve_provider: Some(false) }),
+                ..Default::default()
+            },
+            server_info: Some(ServerInfo {
+                name: "chidori-lsp".to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            }),
+        })
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let document = params.text_document;
+        self.on_change(document.uri, document.text, Some(document.version)).await;
+    }
+
+    async fn did_change(&self, mut params: DidChangeTextDocumentParams) {
+        // Documents are synced in full, the last change holds the whole text
+        if let Some(change) = params.content_changes.pop() {
+            self.on_change(params.text_document.uri, change.text, Some(params.text_document.version)).await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        self.documents.remove(&params.text_document.uri);
+        self.client.publish_diagnostics(params.text_document.uri, vec![], None).await;
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let position = params.text_document_position_params;
+        let Some(document) = self.documents.get(&position.text_document.uri) else {
+            return Ok(None);
+        };
+        let offset = offset_of(&document.text, position.position);
+        Ok(document.index.hover(offset).map(|value| Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }),
+            range: document.index.symbol_at(offset).map(|(_, symbol)| range_of(&document.text, &symbol.range)),
+        }))
+    }
+
+    async fn goto_definition(&self, params: GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>> {
+        let position = params.text_document_position_params;
+        let Some(document) = self.documents.get(&position.text_document.uri) else {
+            return Ok(None);
+        };
+        let offset = offset_of(&document.text, position.position);
+        Ok(document.index.definition(offset).map(|range| {
+            GotoDefinitionResponse::Scalar(Location::new(position.text_document.uri.clone(), range_of(&document.text, &range)))
+        }))
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        let Some(document) = self.documents.get(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        Ok(Some(document.index.code_lenses().into_iter()
+            .map(|(range, title)| CodeLens {
+                range: range_of(&document.text, &range),
+                // Lenses only list the dependents, there is nothing to run when they are clicked
+                command: Some(Command { title, command: String::new(), arguments: None }),
+                data: None,
+            })
+            .collect()))
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // Stdout carries the protocol, logs go to stderr
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+
+    let (service, socket) = LspService::new(|client| Backend { client, documents: DashMap::new() });
+    Server::new(tokio::io::stdin(), tokio::io::stdout(), socket).serve(service).await;
+}
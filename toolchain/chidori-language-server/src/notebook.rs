@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+use chidori_core::cells::{CellTypes, CodeCell, SupportedLanguage, TextRange};
+use chidori_core::chidori_static_analysis::language::{javascript, python, ChidoriStaticAnalysisError, Report};
+use chidori_core::execution::execution::validation::ValidationIssue;
+use chidori_core::execution::execution::ExecutionState;
+use chidori_core::uuid::Uuid;
+use chidori_core::sdk::md::{extract_code_blocks, interpret_markdown_code_block, notebook_configuration, InterpretError, MarkdownCodeBlock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a notebook, at a byte range of its markdown file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub range: TextRange,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// A name a cell provides to the rest of the notebook or depends on, at a byte range of the markdown file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub range: TextRange,
+}
+
+#[derive(Debug, Clone)]
+pub struct NotebookCell {
+    pub name: Option<String>,
+    pub tag: String,
+    /// Range of the line opening the fenced block, holding the tag and name of the cell
+    pub header: TextRange,
+    /// Zero based line of the header
+    pub line: usize,
+    pub provides: Vec<Symbol>,
+    pub depends_on: Vec<Symbol>,
+}
+
+impl NotebookCell {
+    /// How diagnostics, hovers and code lenses refer to the cell.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("`{}`", name),
+            None => format!("the {} cell on line {}", self.tag, self.line + 1),
+        }
+    }
+}
+
+/// The cells of a markdown notebook, with what each of them provides to and depends on from the others.
+#[derive(Debug, Clone, Default)]
+pub struct NotebookIndex {
+    pub cells: Vec<NotebookCell>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl NotebookIndex {
+    /// Index the notebook held by the markdown file at `path` with the given contents.
+    pub fn new(path: &str, source: &str) -> Self {
+        let mut index = NotebookIndex::default();
+        if let Err(e) = notebook_configuration(source, None) {
+            let whole_file = TextRange { start: 0, end: source.len() };
+            let range = match &e {
+                InterpretError::YamlDeserializeError(e) => frontmatter_error_range(source, &whole_file, e),
+                _ => None,
+            };
+            index.error(range.unwrap_or_else(|| line_range(source, 0)), format!("Invalid notebook frontmatter: {}", e));
+        }
+        let mut cells = vec![];
+        for block in extract_code_blocks(source) {
+            if let Some(cell) = index.add_block(path, source, &block) {
+                cells.push((index.cells.len() - 1, cell));
+            }
+        }
+        index.check_names(cells);
+        index
+    }
+
+    fn error(&mut self, range: TextRange, message: String) {
+        self.diagnostics.push(Diagnostic { range, severity: Severity::Error, message });
+    }
+
+    /// Index a fenced block, returning the cell it holds when the cell's operation can be built.
+    fn add_block(&mut self, path: &str, source: &str, block: &MarkdownCodeBlock) -> Option<CellTypes> {
+        let header_start = block.range.start + (block.range.end - block.range.start) - source[block.range.start..block.range.end].trim_start().len();
+        let header = line_range(source, header_start);
+        let body = TextRange { start: (header.end + 1).min(block.range.end), end: block.range.end };
+        let mut cell = NotebookCell {
+            name: block.name.clone(),
+            tag: block.tag.clone(),
+            line: header.start_position(source).0,
+            header: header.clone(),
+            provides: vec![],
+            depends_on: vec![],
+        };
+        let mut built = None;
+        match interpret_markdown_code_block(block, Some(path.to_string())) {
+            // Fenced blocks that are not cells are left to the markdown around them
+            Ok(None) => return None,
+            Ok(Some(code @ CellTypes::Code(..))) => {
+                let CellTypes::Code(code_cell, _) = &code else { unreachable!() };
+                if self.add_code_cell(&mut cell, code_cell, source) {
+                    built = Some(code);
+                }
+            }
+            Ok(Some(other)) => {
+                match ExecutionState::new_with_random_id().get_operation_from_cell_type(&other) {
+                    Ok(operation) => {
+                        let signature = operation.signature();
+                        let output_signature = &signature.output_signature;
+                        for name in output_signature.globals.keys().chain(output_signature.functions.keys()) {
+                            let range = name_range(source, &header, name)
+                                .or_else(|| name_range(source, &body, name))
+                                .unwrap_or_else(|| header.clone());
+                            cell.provides.push(Symbol { name: name.clone(), range });
+                        }
+                        for name in signature.input_signature.globals.keys() {
+                            let range = name_range(source, &body, name).unwrap_or_else(|| header.clone());
+                            cell.depends_on.push(Symbol { name: name.clone(), range });
+                        }
+                        built = Some(other);
+                    }
+                    Err(e) => self.error(header.clone(), format!("Invalid {} cell: {}", block.tag, e)),
+                }
+            }
+            Err(InterpretError::YamlDeserializeError(e)) => {
+                let range = frontmatter_error_range(source, &body, &e).unwrap_or_else(|| header.clone());
+                self.error(range, format!("Invalid frontmatter: {}", e));
+            }
+            Err(e) => self.error(header.clone(), e.to_string()),
+        }
+        cell.provides.sort_by(|a, b| a.range.cmp(&b.range).then_with(|| a.name.cmp(&b.name)));
+        cell.depends_on.sort_by(|a, b| a.range.cmp(&b.range).then_with(|| a.name.cmp(&b.name)));
+        self.cells.push(cell);
+        built
+    }
+
+    /// Add the names a code cell provides and depends on, false when its source cannot be analyzed.
+    fn add_code_cell(&mut self, cell: &mut NotebookCell, code_cell: &CodeCell, source: &str) -> bool {
+        let report: Result<Report, ChidoriStaticAnalysisError> = match code_cell.language {
+            SupportedLanguage::PyO3 => python::parse::extract_dependencies_python(&code_cell.source_code)
+                .map(|paths| python::parse::build_report(&paths)),
+            SupportedLanguage::Deno => javascript::parse::extract_dependencies_js(&code_cell.source_code)
+                .map(|paths| javascript::parse::build_report(&paths)),
+        };
+        let header = cell.header.clone();
+        let report = match report {
+            Ok(report) => report,
+            Err(ChidoriStaticAnalysisError::ParseError { msg, offset, .. }) => {
+                let offset = offset as usize;
+                let range = code_cell.range_in_backing_file(source, &TextRange { start: offset, end: offset })
+                    .unwrap_or(header);
+                self.error(range, format!("Syntax error: {}", msg));
+                return false;
+            }
+            Err(e) => {
+                self.error(header, e.to_string());
+                return false;
+            }
+        };
+        let in_file = |range: &chidori_core::chidori_static_analysis::language::TextRange| {
+            code_cell.range_in_backing_file(source, &range.into()).unwrap_or_else(|| header.clone())
+        };
+        for (name, item) in &report.cell_exposed_values {
+            cell.provides.push(Symbol { name: name.clone(), range: in_file(&item.range) });
+        }
+        for (name, function) in &report.triggerable_functions {
+            // Functions are ranged over their whole definition, their names are what other cells refer to
+            let definition = in_file(&function.range);
+            let range = name_range(source, &definition, name).unwrap_or(definition);
+            cell.provides.push(Symbol { name: name.clone(), range });
+        }
+        for (name, item) in &report.cell_depended_values {
+            cell.depends_on.push(Symbol { name: name.clone(), range: in_file(&item.range) });
+        }
+        true
+    }
+
+    /// Report the issues validating the notebook's operations finds: names provided by more than one cell,
+    /// names cells depend on that no cell provides, and cells that depend on each other.
+    fn check_names(&mut self, cells: Vec<(usize, CellTypes)>) {
+        let mut state = ExecutionState::new_with_random_id();
+        let mut cell_of_operation = HashMap::new();
+        for (idx, cell) in cells {
+            let Ok(operation) = state.get_operation_from_cell_type(&cell) else {
+                continue;
+            };
+            let Ok((operation_id, next_state)) = state.upsert_operation(operation, Uuid::now_v7()) else {
+                continue;
+            };
+            cell_of_operation.insert(operation_id, idx);
+            state = next_state;
+        }
+        let cells_of = |operation_ids: &Vec<_>| {
+            let mut cells: Vec<usize> = operation_ids.iter().filter_map(|id| cell_of_operation.get(id).copied()).collect();
+            cells.sort();
+            cells
+        };
+        let mut diagnostics = vec![];
+        for issue in &state.validation.issues {
+            match issue {
+                ValidationIssue::DuplicateExport { name, operation_ids } => {
+                    let cells = cells_of(operation_ids);
+                    let Some((&first, others)) = cells.split_first() else {
+                        continue;
+                    };
+                    for &idx in others {
+                        for symbol in self.cells[idx].provides.iter().filter(|symbol| &symbol.name == name) {
+                            diagnostics.push(Diagnostic {
+                                range: symbol.range.clone(),
+                                severity: Severity::Error,
+                                message: format!("`{}` is also provided by {}, cells must provide distinct names", name, self.cells[first].label()),
+                            });
+                        }
+                    }
+                }
+                ValidationIssue::UnsatisfiableInput { operation_id, name } => {
+                    let Some(&idx) = cell_of_operation.get(operation_id) else {
+                        continue;
+                    };
+                    let cell = &self.cells[idx];
+                    for symbol in cell.depends_on.iter().filter(|symbol| &symbol.name == name) {
+                        diagnostics.push(Diagnostic {
+                            range: symbol.range.clone(),
+                            severity: Severity::Warning,
+                            message: format!("`{}` is not provided by any cell in this notebook, {} will never run", name, cell.label()),
+                        });
+                    }
+                }
+                ValidationIssue::Cycle { operation_ids } => {
+                    let cells = cells_of(operation_ids);
+                    let labels: Vec<String> = cells.iter().map(|idx| self.cells[*idx].label()).collect();
+                    for &idx in &cells {
+                        diagnostics.push(Diagnostic {
+                            range: self.cells[idx].header.clone(),
+                            severity: Severity::Error,
+                            message: format!("{} depend on each other, none of them can run first", labels.join(", ")),
+                        });
+                    }
+                }
+                // The cells a notebook ends with are expected to provide names nothing depends on
+                ValidationIssue::UnusedOutput { .. } => {}
+            }
+        }
+        self.diagnostics.extend(diagnostics);
+    }
+
+    /// The cells providing a name, in the order they appear in the notebook.
+    pub fn providers_of(&self, name: &str) -> Vec<(usize, &Symbol)> {
+        self.cells.iter().enumerate()
+            .flat_map(|(idx, cell)| cell.provides.iter().filter(|symbol| symbol.name == name).map(move |symbol| (idx, symbol)))
+            .collect()
+    }
+
+    /// The cells depending on a name, in the order they appear in the notebook.
+    pub fn dependents_on_name(&self, name: &str) -> Vec<usize> {
+        self.cells.iter().enumerate()
+            .filter(|(_, cell)| cell.depends_on.iter().any(|symbol| symbol.name == name))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// The cells depending on any of the names a cell provides.
+    pub fn dependents_of(&self, cell: usize) -> Vec<usize> {
+        let provides = &self.cells[cell].provides;
+        self.cells.iter().enumerate()
+            .filter(|(idx, other)| *idx != cell && other.depends_on.iter().any(|symbol| provides.iter().any(|provided| provided.name == symbol.name)))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// The symbol at a byte offset of the file, the innermost one where symbols are nested.
+    pub fn symbol_at(&self, offset: usize) -> Option<(usize, &Symbol)> {
+        self.cells.iter().enumerate()
+            .flat_map(|(idx, cell)| cell.provides.iter().chain(cell.depends_on.iter()).map(move |symbol| (idx, symbol)))
+            .filter(|(_, symbol)| symbol.range.start <= offset && offset <= symbol.range.end)
+            .min_by_key(|(_, symbol)| symbol.range.end - symbol.range.start)
+    }
+
+    /// Where the name at a byte offset of the file is provided, in whichever cell provides it.
+    pub fn definition(&self, offset: usize) -> Option<TextRange> {
+        let (_, symbol) = self.symbol_at(offset)?;
+        self.providers_of(&symbol.name).first().map(|(_, provided)| provided.range.clone())
+    }
+
+    /// Markdown describing the cell that provides the name at a byte offset of the file, and the cells using it.
+    pub fn hover(&self, offset: usize) -> Option<String> {
+        let (_, symbol) = self.symbol_at(offset)?;
+        let providers: Vec<String> = self.providers_of(&symbol.name).iter()
+            .map(|(idx, _)| self.cells[*idx].label())
+            .collect();
+        let mut lines = vec![];
+        if providers.is_empty() {
+            lines.push(format!("`{}` is not provided by any cell in this notebook", symbol.name));
+        } else {
+            lines.push(format!("`{}` is provided by {}", symbol.name, providers.join(" and ")));
+        }
+        let dependents: Vec<String> = self.dependents_on_name(&symbol.name).iter()
+            .map(|idx| self.cells[*idx].label())
+            .collect();
+        if !dependents.is_empty() {
+            lines.push(format!("Used by {}", dependents.join(", ")));
+        }
+        Some(lines.join("\n\n"))
+    }
+
+    /// A title for each cell listing the cells that depend on it, shown over the cell's header.
+    pub fn code_lenses(&self) -> Vec<(TextRange, String)> {
+        (0..self.cells.len())
+            .map(|idx| {
+                let dependents: Vec<String> = self.dependents_of(idx).iter()
+                    .map(|dependent| self.cells[*dependent].label())
+                    .collect();
+                let title = match dependents.len() {
+                    0 => "No dependents".to_string(),
+                    1 => format!("1 dependent: {}", dependents[0]),
+                    n => format!("{} dependents: {}", n, dependents.join(", ")),
+                };
+                (self.cells[idx].header.clone(), title)
+            })
+            .collect()
+    }
+}
+
+/// Range of the line starting at `start`, without its line break.
+fn line_range(source: &str, start: usize) -> TextRange {
+    let end = source[start..].find('\n').map_or(source.len(), |idx| start + idx);
+    TextRange { start, end }
+}
+
+/// Range of the first occurrence of `name` as a whole word within `within`.
+fn name_range(source: &str, within: &TextRange, name: &str) -> Option<TextRange> {
+    let within = chidori_core::chidori_static_analysis::language::TextRange { start: within.start, end: within.end };
+    within.find_identifier(source, name).as_ref().map(TextRange::from)
+}
+
+/// Range of the line holding a YAML error in the frontmatter that opens `within`.
+fn frontmatter_error_range(source: &str, within: &TextRange, error: &serde_yaml::Error) -> Option<TextRange> {
+    let text = source.get(within.start..within.end)?;
+    let opening = text.find("---")?;
+    let frontmatter_start = within.start + opening + text[opening..].find('\n')? + 1;
+    let error_start = (frontmatter_start + error.location()?.index()).min(within.end);
+    let line = line_range(source, error_start);
+    Some(TextRange { start: error_start, end: line.end.min(within.end) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    const NOTEBOOK: &str = indoc! { r#"
+        # Greeting
+
+        ```python (names)
+        name = "world"
+        def shout(text):
+            return text.upper()
+        ```
+
+        ```javascript (greeting)
+        const greeting = shout("hello " + name);
+        ```
+
+        ```prompt (poem)
+        Write a poem about {{greeting}} and {{mood}}
+        ```
+        "#
+    };
+
+    fn offset_of(source: &str, needle: &str, occurrence: usize) -> usize {
+        source.match_indices(needle).nth(occurrence).unwrap().0
+    }
+
+    #[test]
+    fn test_cells_provide_and_depend_on_names() {
+        let index = NotebookIndex::new("greeting.md", NOTEBOOK);
+        assert_eq!(index.cells.len(), 3);
+        let names: Vec<&str> = index.cells[0].provides.iter().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(names, vec!["name", "shout"]);
+        let shout = &index.cells[0].provides[1];
+        assert_eq!(&NOTEBOOK[shout.range.start..shout.range.end], "shout");
+        let depends: Vec<&str> = index.cells[1].depends_on.iter().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(depends, vec!["shout", "name"]);
+        assert_eq!(index.dependents_of(0), vec![1]);
+        assert_eq!(index.dependents_of(1), vec![2]);
+        assert!(index.dependents_of(2).is_empty());
+    }
+
+    #[test]
+    fn test_unresolved_names_and_collisions_are_diagnosed() {
+        let index = NotebookIndex::new("greeting.md", NOTEBOOK);
+        assert_eq!(index.diagnostics.len(), 1);
+        let unresolved = &index.diagnostics[0];
+        assert_eq!(unresolved.severity, Severity::Warning);
+        assert_eq!(&NOTEBOOK[unresolved.range.start..unresolved.range.end], "mood");
+
+        let source = indoc! { r#"
+        ```python
+        x = 1
+        ```
+
+        ```python
+        x = 2
+        ```
+        "#
+        };
+        let index = NotebookIndex::new("collision.md", source);
+        assert_eq!(index.diagnostics, vec![Diagnostic {
+            range: TextRange { start: offset_of(source, "x", 1), end: offset_of(source, "x", 1) + 1 },
+            severity: Severity::Error,
+            message: "`x` is also provided by the python cell on line 1, cells must provide distinct names".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_cycles_and_invalid_cells_are_diagnosed() {
+        let source = indoc! { r#"
+        ```python (a)
+        x = y + 1
+        ```
+
+        ```python (b)
+        y = x + 1
+        ```
+
+        ```prompt (broken)
+        {{#if x}} never closed
+        ```
+        "#
+        };
+        let index = NotebookIndex::new("cycle.md", source);
+        let messages: Vec<&str> = index.diagnostics.iter().map(|diagnostic| diagnostic.message.as_str()).collect();
+        assert!(messages[0].starts_with("Invalid prompt cell"));
+        assert_eq!(&messages[1..], &["`a`, `b` depend on each other, none of them can run first"; 2]);
+        assert_eq!(index.diagnostics[1].range, index.cells[0].header);
+    }
+
+    #[test]
+    fn test_frontmatter_errors_point_at_their_line() {
+        let source = indoc! { r#"
+        ```python
+        ---
+        timeout: [1
+        ---
+        x = 1
+        ```
+        "#
+        };
+        let index = NotebookIndex::new("frontmatter.md", source);
+        assert_eq!(index.diagnostics.len(), 1);
+        let diagnostic = &index.diagnostics[0];
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert!(diagnostic.message.starts_with("Invalid frontmatter"));
+        assert_eq!(diagnostic.range.start_position(source).0, 2);
+    }
+
+    #[test]
+    fn test_hover_and_definition_cross_cells() {
+        let index = NotebookIndex::new("greeting.md", NOTEBOOK);
+        let reference = offset_of(NOTEBOOK, "name", 2);
+        let definition = index.definition(reference).unwrap();
+        assert_eq!(definition.start, offset_of(NOTEBOOK, "name =", 0));
+        assert_eq!(index.hover(reference).unwrap(), "`name` is provided by `names`\n\nUsed by `greeting`");
+        assert_eq!(index.hover(offset_of(NOTEBOOK, "mood", 0)).unwrap(), "`mood` is not provided by any cell in this notebook\n\nUsed by `poem`");
+        assert!(index.hover(0).is_none());
+    }
+
+    #[test]
+    fn test_code_lenses_list_dependents() {
+        let index = NotebookIndex::new("greeting.md", NOTEBOOK);
+        let titles: Vec<String> = index.code_lenses().into_iter().map(|(_, title)| title).collect();
+        assert_eq!(titles, vec!["1 dependent: `greeting`", "1 dependent: `poem`", "No dependents"]);
+    }
+}
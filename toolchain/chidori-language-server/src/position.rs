@@ -0,0 +1,56 @@
+use chidori_core::cells::TextRange;
+use tower_lsp::lsp_types::{Position, Range};
+
+/// Position of a byte offset, LSP positions count UTF-16 code units from the start of the line.
+pub fn position_of(text: &str, offset: usize) -> Position {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+    Position::new(
+        before.matches('\n').count() as u32,
+        before[line_start..].encode_utf16().count() as u32,
+    )
+}
+
+/// Byte offset of a position, positions past the end of a line are clamped to it.
+pub fn offset_of(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(idx) => line_start += idx + 1,
+            None => return text.len(),
+        }
+    }
+    let line = &text[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    let mut units = 0;
+    for (idx, c) in line.char_indices() {
+        if units >= position.character {
+            return line_start + idx;
+        }
+        units += c.len_utf16() as u32;
+    }
+    line_start + line.len()
+}
+
+pub fn range_of(text: &str, range: &TextRange) -> Range {
+    Range::new(position_of(text, range.start), position_of(text, range.end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions_count_utf16_code_units() {
+        let text = "# Café ☕\nx = \"🎉\" + y\n";
+        let y = text.find('y').unwrap();
+        assert_eq!(position_of(text, y), Position::new(1, 11));
+        assert_eq!(offset_of(text, Position::new(1, 11)), y);
+        assert_eq!(offset_of(text, Position::new(0, 100)), text.find('\n').unwrap());
+        assert_eq!(offset_of(text, Position::new(5, 0)), text.len());
+    }
+}
@@ -2,7 +2,7 @@ extern crate swc_ecma_parser;
 
 use crate::language::javascript::parse::ContextPath::Constant;
 use crate::language::{InternalCallGraph, python, TextRange};
-use crate::language::{Report, ReportItem, ReportTriggerableFunctions};
+use crate::language::{ArgumentDefault, ArgumentType, FunctionSignature, Report, ReportArgument, ReportItem, ReportTriggerableFunctions};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::env::var;
@@ -11,6 +11,7 @@ use swc_common::{
     errors::{ColorConfig, Handler},
     FileName, FilePathMapping, SourceMap,
 };
+use swc_common::comments::{CommentKind, Comments, SingleThreadedComments};
 use swc_common::source_map::SmallPos;
 use swc_ecma_ast as ast;
 use swc_ecma_ast::{AssignTarget, AssignTargetPat, BlockStmtOrExpr, Callee, Decl, Expr, FnDecl, ForHead, Ident, ImportSpecifier, Lit, MemberProp, ModuleDecl, ModuleItem, ObjectPatProp, Pat, PropName, SimpleAssignTarget, Stmt, SuperProp};
@@ -18,6 +19,15 @@ use swc_ecma_parser::{lexer::Lexer, Parser, StringInput, Syntax};
 use crate::language::ChidoriStaticAnalysisError;
 use crate::language::ContextPath;
 
+/// Range of a span in the source that was parsed. Byte position 0 is reserved for spans that do not point
+/// into a file, so the spans of the one file parsed start at 1.
+fn text_range(span: swc_common::Span) -> TextRange {
+    TextRange {
+        start: span.lo.to_usize().saturating_sub(1),
+        end: span.hi.to_usize().saturating_sub(1),
+    }
+}
+
 fn remove_hash_and_numbers(input: &str) -> String {
     match input.find('#') {
         Some(index) => input[..index].to_string(),
@@ -142,6 +152,7 @@ impl ASTWalkContext {
     }
 
     fn encounter_named_reference(&mut self, name: &ast::Ident) {
+        let range = text_range(name.span);
         let name = remove_hash_and_numbers(&name.to_string());
         let mut var_exists = false;
         if self.globals.contains(&name.to_string()) {
@@ -162,7 +173,8 @@ impl ASTWalkContext {
                 .push(ContextPath::IdentifierReferredTo{
                     name: name.to_string(),
                     in_scope: true,
-                    exposed: self.local_contexts.len() == 0
+                    exposed: self.local_contexts.len() == 0,
+                    range,
                 });
         } else {
             if self.context_stack.contains(&ContextPath::AssignmentToStatement) {
@@ -177,7 +189,8 @@ impl ASTWalkContext {
                     .push(ContextPath::IdentifierReferredTo{
                         name: name.to_string(),
                         in_scope: false,
-                        exposed: self.local_contexts.len() == 0
+                        exposed: self.local_contexts.len() == 0,
+                        range,
                 });
                 return;
             }
@@ -185,7 +198,8 @@ impl ASTWalkContext {
                 .push(ContextPath::IdentifierReferredTo{
                     name: name.to_string(),
                     in_scope: false,
-                    exposed: self.local_contexts.len() == 0
+                    exposed: self.local_contexts.len() == 0,
+                    range,
                 });
         }
     }
@@ -674,10 +688,7 @@ fn traverse_stmt(stmt: &Stmt, machine: &mut ASTWalkContext) {
             }) => {
                 machine.insert_local(ident);
                 let ast::Function { params, body, span, .. } = &**function;
-                let idx = machine.enter_statement_function(ident, TextRange {
-                    start: span.lo.to_usize(),
-                    end: span.hi.to_usize(),
-                });
+                let idx = machine.enter_statement_function(ident, text_range(*span));
                 let params_idx = machine.enter_params();
                 for param in params {
                     traverse_pat(&param.pat, machine);
@@ -756,8 +767,8 @@ fn traverse_stmts(stmts: &[Stmt], machine: &mut ASTWalkContext) {
     }
 }
 
-pub fn extract_dependencies_js(source: &str) -> Result<Vec<Vec<ContextPath>>, ChidoriStaticAnalysisError> {
-    let mut machine = ASTWalkContext::new();
+/// Parse the source as JavaScript, or as TypeScript if it is not valid JavaScript, collecting its comments.
+fn parse_module(source: &str, comments: Option<&dyn Comments>) -> Result<ast::Module, ChidoriStaticAnalysisError> {
     let cm: Lrc<SourceMap> = Default::default();
     let handler = Handler::with_tty_emitter(ColorConfig::Auto, true, false, Some(cm.clone()));
     let fm = cm.new_source_file(Lrc::new(FileName::Custom("test.js".into())), source.to_string());
@@ -767,7 +778,7 @@ pub fn extract_dependencies_js(source: &str) -> Result<Vec<Vec<ContextPath>>, Ch
             syntax,
             Default::default(),
             StringInput::from(&*fm),
-            None,
+            comments,
         );
 
         let mut parser = Parser::new_from(lexer);
@@ -778,33 +789,232 @@ pub fn extract_dependencies_js(source: &str) -> Result<Vec<Vec<ContextPath>>, Ch
         parser.parse_module()
     };
 
-    let module = parse_module(Syntax::Es(Default::default()))
+    parse_module(Syntax::Es(Default::default()))
         .or_else(|_| parse_module(Syntax::Typescript(Default::default())))
-        .map_err(|mut e| {
+        .map_err(|e| {
             // Unrecoverable fatal error occurred
-            e
-            // e.into_diagnostic(&handler).emit()
-        });
-
-    match module {
-        Ok(module) => {
-            for item in module.body {
-                traverse_module(item, &mut machine);
-            }
-            Ok(machine.context_stack_references)
-        },
-        Err(e) => {
-            Err(ChidoriStaticAnalysisError::ParseError {
+            ChidoriStaticAnalysisError::ParseError {
                 msg: format!("{:?}", e),
                 offset: 0,
                 source_path: "".to_string(),
                 source_code: "".to_string(),
-            })
+            }
+        })
+}
+
+pub fn extract_dependencies_js(source: &str) -> Result<Vec<Vec<ContextPath>>, ChidoriStaticAnalysisError> {
+    let mut machine = ASTWalkContext::new();
+    let module = parse_module(source, None)?;
+    for item in module.body {
+        traverse_module(item, &mut machine);
+    }
+    Ok(machine.context_stack_references)
+}
+
+/// Type represented by a TypeScript type annotation, annotations we do not represent are `Any`.
+fn ts_type_to_argument_type(ty: &ast::TsType) -> ArgumentType {
+    match ty {
+        ast::TsType::TsKeywordType(ast::TsKeywordType { kind, .. }) => match kind {
+            ast::TsKeywordTypeKind::TsStringKeyword => ArgumentType::String,
+            ast::TsKeywordTypeKind::TsNumberKeyword => ArgumentType::Float,
+            ast::TsKeywordTypeKind::TsBigIntKeyword => ArgumentType::Int,
+            ast::TsKeywordTypeKind::TsBooleanKeyword => ArgumentType::Bool,
+            ast::TsKeywordTypeKind::TsObjectKeyword => ArgumentType::Object,
+            ast::TsKeywordTypeKind::TsNullKeyword
+            | ast::TsKeywordTypeKind::TsUndefinedKeyword
+            | ast::TsKeywordTypeKind::TsVoidKeyword => ArgumentType::Null,
+            _ => ArgumentType::Any,
+        },
+        ast::TsType::TsArrayType(ast::TsArrayType { elem_type, .. }) => {
+            ArgumentType::List(Box::new(ts_type_to_argument_type(elem_type)))
+        }
+        ast::TsType::TsTupleType(_) => ArgumentType::List(Box::new(ArgumentType::Any)),
+        ast::TsType::TsTypeLit(_) => ArgumentType::Object,
+        ast::TsType::TsFnOrConstructorType(_) => ArgumentType::Function,
+        ast::TsType::TsParenthesizedType(ast::TsParenthesizedType { type_ann, .. }) => ts_type_to_argument_type(type_ann),
+        ast::TsType::TsOptionalType(ast::TsOptionalType { type_ann, .. }) => {
+            ArgumentType::union(vec![ts_type_to_argument_type(type_ann), ArgumentType::Null])
+        }
+        ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsUnionType(ast::TsUnionType { types, .. })) => {
+            ArgumentType::union(types.iter().map(|ty| ts_type_to_argument_type(ty)).collect())
+        }
+        ast::TsType::TsLitType(ast::TsLitType { lit, .. }) => match lit {
+            ast::TsLit::Str(_) | ast::TsLit::Tpl(_) => ArgumentType::String,
+            ast::TsLit::Number(_) => ArgumentType::Float,
+            ast::TsLit::BigInt(_) => ArgumentType::Int,
+            ast::TsLit::Bool(_) => ArgumentType::Bool,
+        },
+        ast::TsType::TsTypeRef(ast::TsTypeRef { type_name: ast::TsEntityName::Ident(name), type_params, .. }) => {
+            let first_param = type_params.as_ref()
+                .and_then(|type_params| type_params.params.first())
+                .map_or(ArgumentType::Any, |ty| ts_type_to_argument_type(ty));
+            match name.sym.to_string().as_str() {
+                "Array" | "ReadonlyArray" | "Set" | "ReadonlySet" | "Iterable" => ArgumentType::List(Box::new(first_param)),
+                "Record" | "Map" | "Object" => ArgumentType::Object,
+                "Function" => ArgumentType::Function,
+                "String" => ArgumentType::String,
+                "Number" => ArgumentType::Float,
+                "Boolean" => ArgumentType::Bool,
+                _ => ArgumentType::Any,
+            }
+        }
+        _ => ArgumentType::Any,
+    }
+}
+
+fn default_to_argument_default(default: &Expr) -> ArgumentDefault {
+    match default {
+        Expr::Lit(Lit::Str(ast::Str { value, .. })) => ArgumentDefault::String(value.to_string()),
+        Expr::Lit(Lit::Num(ast::Number { value, .. })) => {
+            if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+                ArgumentDefault::Int(*value as i64)
+            } else {
+                ArgumentDefault::Float(*value)
+            }
+        }
+        Expr::Lit(Lit::Bool(ast::Bool { value, .. })) => ArgumentDefault::Bool(*value),
+        Expr::Lit(Lit::Null(_)) => ArgumentDefault::Null,
+        Expr::Ident(ident) if ident.sym.to_string() == "undefined" => ArgumentDefault::Null,
+        Expr::Unary(ast::UnaryExpr { op: ast::UnaryOp::Minus, arg, .. }) => match default_to_argument_default(arg) {
+            ArgumentDefault::Int(i) => ArgumentDefault::Int(-i),
+            ArgumentDefault::Float(f) => ArgumentDefault::Float(-f),
+            _ => ArgumentDefault::Expression,
+        },
+        _ => ArgumentDefault::Expression,
+    }
+}
+
+/// Split a JSDoc comment into its summary and the descriptions of its `@param` tags.
+fn parse_jsdoc(comment: &str) -> (Option<String>, HashMap<String, String>) {
+    let mut summary = vec![];
+    let mut arguments: HashMap<String, String> = HashMap::new();
+    let mut current: Option<String> = None;
+    let mut in_summary = true;
+    for line in comment.lines() {
+        let line = line.trim().trim_start_matches('*').trim();
+        if let Some(tag) = line.strip_prefix('@') {
+            in_summary = false;
+            current = None;
+            let Some(param) = tag.strip_prefix("param ").or_else(|| tag.strip_prefix("arg ")) else {
+                continue;
+            };
+            let mut param = param.trim();
+            // `@param {number} count` notes the type before the name
+            if param.starts_with('{') {
+                param = param.split_once('}').map_or("", |(_, rest)| rest.trim());
+            }
+            let (name, description) = param.split_once(char::is_whitespace).unwrap_or((param, ""));
+            // `[count=1]` marks an optional argument and its default
+            let name = name.trim_start_matches('[').trim_end_matches(']');
+            let name = name.split('=').next().unwrap_or_default().to_string();
+            let description = description.trim().trim_start_matches('-').trim().to_string();
+            arguments.insert(name.clone(), description);
+            current = Some(name);
+        } else if in_summary {
+            summary.push(line);
+        } else if let Some(description) = current.as_ref().and_then(|name| arguments.get_mut(name)) {
+            if !line.is_empty() {
+                if !description.is_empty() {
+                    description.push(' ');
+                }
+                description.push_str(line);
+            }
+        }
+    }
+    let summary = summary.join("\n").trim().to_string();
+    (if summary.is_empty() { None } else { Some(summary) }, arguments)
+}
+
+/// The JSDoc comment preceding the given position, if there is one.
+fn jsdoc_at(comments: &SingleThreadedComments, pos: swc_common::BytePos) -> Option<String> {
+    comments.get_leading(pos)?
+        .into_iter()
+        .filter(|comment| matches!(comment.kind, CommentKind::Block) && comment.text.starts_with('*'))
+        .last()
+        .map(|comment| comment.text.to_string())
+}
+
+fn function_signature(function: &ast::Function, jsdoc: Option<String>) -> FunctionSignature {
+    let (description, argument_descriptions) = jsdoc
+        .map(|jsdoc| parse_jsdoc(&jsdoc))
+        .unwrap_or_default();
+    let mut arguments = vec![];
+    for (position, param) in function.params.iter().enumerate() {
+        let (pat, default) = match &param.pat {
+            Pat::Assign(ast::AssignPat { left, right, .. }) => (&**left, Some(default_to_argument_default(right))),
+            pat => (pat, None),
+        };
+        // Destructured parameters bind names that are not arguments of their own
+        let Pat::Ident(ast::BindingIdent { id, type_ann, .. }) = pat else {
+            continue;
+        };
+        let name = id.sym.to_string();
+        let mut ty = type_ann.as_ref().map(|type_ann| ts_type_to_argument_type(&type_ann.type_ann));
+        let mut default = default;
+        // `count?: number` may be left out, in which case it is undefined
+        if id.optional {
+            ty = ty.map(|ty| ArgumentType::union(vec![ty, ArgumentType::Null]));
+            default.get_or_insert(ArgumentDefault::Null);
+        }
+        arguments.push(ReportArgument {
+            description: argument_descriptions.get(&name).cloned(),
+            name,
+            ty,
+            default,
+            position: Some(position),
+        });
+    }
+    FunctionSignature {
+        arguments,
+        description,
+    }
+}
 
+fn collect_function_signatures(
+    stmts: &[Stmt],
+    comments: &SingleThreadedComments,
+    signatures: &mut HashMap<String, FunctionSignature>,
+) {
+    for stmt in stmts {
+        if let Stmt::Decl(Decl::Fn(FnDecl { ident, function, .. })) = stmt {
+            collect_function_signature(ident, function, jsdoc_at(comments, function.span.lo), comments, signatures);
         }
     }
 }
 
+fn collect_function_signature(
+    ident: &Ident,
+    function: &ast::Function,
+    jsdoc: Option<String>,
+    comments: &SingleThreadedComments,
+    signatures: &mut HashMap<String, FunctionSignature>,
+) {
+    signatures.entry(ident.sym.to_string()).or_insert_with(|| function_signature(function, jsdoc));
+    if let Some(body) = &function.body {
+        collect_function_signatures(&body.stmts, comments, signatures);
+    }
+}
+
+/// Signatures of the functions declared in the source, from their TypeScript annotations, defaults and JSDoc comments.
+pub fn extract_function_signatures_js(source: &str) -> Result<HashMap<String, FunctionSignature>, ChidoriStaticAnalysisError> {
+    let comments = SingleThreadedComments::default();
+    let module = parse_module(source, Some(&comments))?;
+    let mut signatures = HashMap::new();
+    for item in &module.body {
+        match item {
+            ModuleItem::Stmt(stmt) => {
+                collect_function_signatures(std::slice::from_ref(stmt), &comments, &mut signatures);
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(ast::ExportDecl { decl: Decl::Fn(FnDecl { ident, function, .. }), span, .. })) => {
+                let jsdoc = jsdoc_at(&comments, span.lo).or_else(|| jsdoc_at(&comments, function.span.lo));
+                collect_function_signature(ident, function, jsdoc, &comments, &mut signatures);
+            }
+            _ => {}
+        }
+    }
+    Ok(signatures)
+}
+
 
 pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
     let mut exposed_values = HashMap::new();
@@ -821,15 +1031,17 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
             encountered.push(context_path_unit);
 
             // If we've declared a top level function, it is exposed
-            if let ContextPath::InFunction(name, _) = context_path_unit {
+            if let ContextPath::InFunction(name, range) = context_path_unit {
                 in_function = Some(name);
                 if !triggerable_functions.contains_key(name) {
                     triggerable_functions
                         .entry(name.clone())
                         .or_insert_with(|| ReportTriggerableFunctions {
                             arguments: vec![],
+                            description: None,
                             emit_event: vec![],
                             trigger_on: vec![],
+                            range: range.clone(),
                         });
                 }
             }
@@ -851,8 +1063,10 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                         .or_insert_with(|| ReportTriggerableFunctions {
 
                             arguments: vec![],
+                            description: None,
                             emit_event: vec![], // Initialize with an empty string or a default value
                             trigger_on: vec![],
+                            range: Default::default(),
                         });
 
                     if attribute_path == vec![&"emitAs".to_string()] {
@@ -870,7 +1084,7 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
             }
 
             // TODO: this needs to be updated to be similar to the python implementation
-            if let ContextPath::IdentifierReferredTo{name: identifier, exposed, in_scope: false} = context_path_unit {
+            if let ContextPath::IdentifierReferredTo{name: identifier, exposed, in_scope: false, range} = context_path_unit {
                 // If this value is not being assigned to, then it is a dependency
 
                 if encountered.iter().any(|x| matches!(x, ContextPath::InFunction(_, _)))
@@ -882,10 +1096,12 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                                 .entry(function_name.clone())
                                 .or_insert_with(|| ReportTriggerableFunctions {
                                     arguments: vec![],
+                                    description: None,
                                     emit_event: vec![], // Initialize with an empty string or a default value
                                     trigger_on: vec![],
+                                    range: Default::default(),
                                 });
-                            x.arguments.push(identifier.clone());
+                            x.arguments.push(ReportArgument::named(identifier.clone()));
                         }
                     }
                     continue;
@@ -905,23 +1121,13 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
 
                 {
                     if encountered.contains(&&ContextPath::AssignmentToStatement) {
-                        exposed_values.insert(
-                            identifier.clone(),
-                            ReportItem {
-                                // context_path: context_path.clone(),
-                            },
-                        );
+                        ReportItem::record(&mut exposed_values, identifier, range);
                         continue;
                     }
                 }
 
                 if !encountered.contains(&&ContextPath::AssignmentToStatement) && !encountered.contains(&&ContextPath::FunctionArguments) {
-                    depended_values.insert(
-                        identifier.clone(),
-                        ReportItem {
-                            // context_path: context_path.clone(),
-                        },
-                    );
+                    ReportItem::record(&mut depended_values, identifier, range);
                     continue;
                 }
             }
@@ -941,6 +1147,16 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
 
     depended_values.retain(|value,_ | !js_built_ins.contains(value.as_str()));
 
+    // Tests are registered by calling `Deno.test` or `Chidori.test`
+    let defines_tests = context_paths.iter().any(|context_path| {
+        context_path.windows(3).any(|window| match window {
+            [ContextPath::InCallExpression, ContextPath::Attribute(attribute), ContextPath::IdentifierReferredTo { name, .. }] => {
+                attribute == "test" && (name == "Deno" || name == "Chidori")
+            }
+            _ => false,
+        })
+    });
+
     Report {
         internal_call_graph: InternalCallGraph {
             graph: Default::default(),
@@ -948,6 +1164,10 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
         cell_exposed_values: exposed_values,
         cell_depended_values: depended_values,
         triggerable_functions: triggerable_functions,
+        imported_values: HashMap::new(),
+        star_imports: vec![],
+        third_party_modules: vec![],
+        defines_tests,
     }
 }
 
@@ -1212,13 +1432,17 @@ mod tests {
             },
             cell_depended_values: {
                 let mut map = std::collections::HashMap::new();
-                map.insert("add_two".to_string(), ReportItem {});
+                map.insert("add_two".to_string(), ReportItem { range: TextRange { start: 143, end: 150 } });
                 map
             },
             triggerable_functions: {
                 let mut map = std::collections::HashMap::new();
                 map
             },
+            imported_values: HashMap::new(),
+            star_imports: vec![],
+            third_party_modules: vec![],
+            defines_tests: true,
         };
         assert_eq!(result, report);
     }
@@ -1252,12 +1476,12 @@ x = random.randint(0, 10)
             },
             cell_exposed_values: {
                 let mut map = std::collections::HashMap::new();
-                map.insert("x".to_string(), ReportItem {});
+                map.insert("x".to_string(), ReportItem { range: TextRange { start: 130, end: 131 } });
                 map
             },
             cell_depended_values: {
                 let mut map = std::collections::HashMap::new();
-                map.insert("function_that_doesnt_exist".to_string(), ReportItem {});
+                map.insert("function_that_doesnt_exist".to_string(), ReportItem { range: TextRange { start: 69, end: 95 } });
                 map
             },
             triggerable_functions: {
@@ -1267,12 +1491,18 @@ x = random.randint(0, 10)
                     ReportTriggerableFunctions {
 
                         arguments: vec![],
+                        description: None,
                         emit_event: vec![],
                         trigger_on: vec![],
+                        range: TextRange { start: 33, end: 128 },
                     },
                 );
                 map
             },
+            imported_values: HashMap::new(),
+            star_imports: vec![],
+            third_party_modules: vec![],
+            defines_tests: false,
         };
         assert_eq!(result, report);
     }
@@ -1306,6 +1536,60 @@ x = random.randint(0, 10)
             insta::assert_yaml_snapshot!(result);
         });
     }
+
+    #[test]
+    fn test_report_generation_with_signatures() {
+        let js_source = indoc! { r#"
+        /**
+         * Search the collection.
+         * @param {string} query - Text to search for
+         * @param [limit=10] Most results to return
+         */
+        export function search(query: string, limit: number = 10, tags?: Array<string>, { exact }: { exact: boolean } = { exact: false }) {
+            return query
+        }
+
+        function untyped(a, b = 2) {
+            return a
+        }
+            "#};
+        let signatures = extract_function_signatures_js(js_source).unwrap();
+        let search = signatures.get("search").unwrap();
+        assert_eq!(search.description, Some("Search the collection.".to_string()));
+        assert_eq!(search.arguments, vec![
+            ReportArgument {
+                name: "query".to_string(),
+                ty: Some(ArgumentType::String),
+                default: None,
+                description: Some("Text to search for".to_string()),
+                position: Some(0),
+            },
+            ReportArgument {
+                name: "limit".to_string(),
+                ty: Some(ArgumentType::Float),
+                default: Some(ArgumentDefault::Int(10)),
+                description: Some("Most results to return".to_string()),
+                position: Some(1),
+            },
+            ReportArgument {
+                name: "tags".to_string(),
+                ty: Some(ArgumentType::Optional(Box::new(ArgumentType::List(Box::new(ArgumentType::String))))),
+                default: Some(ArgumentDefault::Null),
+                description: None,
+                position: Some(2),
+            },
+        ]);
+
+        let untyped = signatures.get("untyped").unwrap();
+        assert_eq!(untyped.arguments[0].ty, None);
+        assert_eq!(untyped.arguments[1].default, Some(ArgumentDefault::Int(2)));
+
+        let context_stack_references = extract_dependencies_js(js_source).unwrap();
+        let result = build_report(&context_stack_references).with_signatures(&signatures);
+        let untyped = result.triggerable_functions.get("untyped").unwrap();
+        assert_eq!(untyped.arguments.iter().map(|argument| argument.name.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(untyped.arguments[1].position, Some(1));
+    }
 }
 
 #[cfg(test)]
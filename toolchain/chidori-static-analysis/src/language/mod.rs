@@ -10,28 +10,177 @@ pub mod typechecker;
 pub mod javascript;
 pub mod python;
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash)]
+/// Byte range in the source of a cell
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, Eq, Hash)]
 pub struct TextRange {
     pub start: usize,
     pub end: usize,
 }
 
+impl TextRange {
+    /// Range of the first occurrence of `name` as a whole identifier within this range of `source`.
+    pub fn find_identifier(&self, source: &str, name: &str) -> Option<TextRange> {
+        let text = source.get(self.start..self.end)?;
+        let is_identifier = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+        text.match_indices(name)
+            .find(|(idx, _)| {
+                !text[..*idx].chars().next_back().map_or(false, is_identifier)
+                    && !text[idx + name.len()..].chars().next().map_or(false, is_identifier)
+            })
+            .map(|(idx, _)| TextRange { start: self.start + idx, end: self.start + idx + name.len() })
+    }
+}
+
 // TODO: implement a function that infers the language from the source code successfully parsing
 
 // TODO: it would be helpful if reports noted if a value is a global, an arg, or a kwarg
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ReportItem {
-    // pub context_path: Vec<ContextPath>,
+    /// Where the value first occurs in the cell, the first assignment to an exposed value and the
+    /// first reference to a depended value
+    pub range: TextRange,
+}
+
+impl ReportItem {
+    /// Note that a value occurs at the given range, keeping the earliest range it occurs at.
+    pub(crate) fn record(items: &mut HashMap<String, ReportItem>, name: &str, range: &TextRange) {
+        match items.get_mut(name) {
+            Some(item) if item.range.start <= range.start => {}
+            Some(item) => item.range = range.clone(),
+            None => {
+                items.insert(name.to_string(), ReportItem { range: range.clone() });
+            }
+        }
+    }
+}
+
+/// Type of a function argument, from its annotation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgumentType {
+    String,
+    Int,
+    Float,
+    Bool,
+    Null,
+    /// Annotated with a type that accepts any value, or with a type we do not represent
+    Any,
+    List(Box<ArgumentType>),
+    Object,
+    Function,
+    Optional(Box<ArgumentType>),
+    Union(Vec<ArgumentType>),
+}
+
+impl ArgumentType {
+    /// The union of the given types, flattening nested unions and representing a union with null as optional.
+    pub fn union(types: Vec<ArgumentType>) -> ArgumentType {
+        fn push(ty: ArgumentType, members: &mut Vec<ArgumentType>) {
+            if !members.contains(&ty) {
+                members.push(ty);
+            }
+        }
+        let mut members: Vec<ArgumentType> = vec![];
+        let mut nullable = false;
+        for ty in types {
+            match ty {
+                ArgumentType::Null => nullable = true,
+                ArgumentType::Optional(ty) => {
+                    nullable = true;
+                    push(*ty, &mut members);
+                }
+                ArgumentType::Union(tys) => {
+                    for ty in tys {
+                        push(ty, &mut members);
+                    }
+                }
+                ty => push(ty, &mut members),
+            }
+        }
+        let ty = if members.iter().any(|ty| *ty == ArgumentType::Any) {
+            ArgumentType::Any
+        } else if members.len() == 1 {
+            members.pop().unwrap()
+        } else if members.is_empty() {
+            return ArgumentType::Null;
+        } else {
+            ArgumentType::Union(members)
+        };
+        if nullable && ty != ArgumentType::Any {
+            ArgumentType::Optional(Box::new(ty))
+        } else {
+            ty
+        }
+    }
+}
+
+/// Default value of a function argument
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgumentDefault {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// A default that is not a literal, it is only known once the function is called
+    Expression,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportArgument {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ty: Option<ArgumentType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<ArgumentDefault>,
+    /// Description of the argument from the function's docstring
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Index of the argument in the function's parameters, None for keyword only arguments and for
+    /// names bound by destructuring a parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+}
+
+impl ReportArgument {
+    pub fn named(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ReportTriggerableFunctions {
-    pub arguments: Vec<String>,
+    pub arguments: Vec<ReportArgument>,
+    /// Summary of the function from its docstring
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
     // pub context_path: Vec<ContextPath>,
     // TODO: these need their own set of depended values
-    // TODO: we need to extract signatures for triggerable functions
     pub emit_event: Vec<String>,
     pub trigger_on: Vec<String>,
+    /// Range of the function's definition in the cell
+    pub range: TextRange,
+}
+
+/// Signature of a function declared in a cell, from its parameters and docstring
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionSignature {
+    pub arguments: Vec<ReportArgument>,
+    pub description: Option<String>,
+}
+
+/// Where a name bound by an import statement comes from
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportImportedValue {
+    /// Module the name is imported from, relative modules keep their leading dots
+    pub module: String,
+    /// Name of the value within the module, None when the module itself is imported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -115,6 +264,39 @@ pub struct Report {
     pub cell_exposed_values: HashMap<String, ReportItem>,
     pub cell_depended_values: HashMap<String, ReportItem>,
     pub triggerable_functions: HashMap<String, ReportTriggerableFunctions>,
+    /// Names bound by import statements, these are provided by libraries rather than by other cells
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub imported_values: HashMap<String, ReportImportedValue>,
+    /// Modules imported with `from module import *`, which may provide any of the values the cell depends on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub star_imports: Vec<String>,
+    /// Top level packages the cell imports that are neither part of the language's standard library
+    /// nor relative to the cell, sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub third_party_modules: Vec<String>,
+    /// Whether the cell defines tests, by importing `unittest` in Python or calling `Deno.test` in JavaScript
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub defines_tests: bool,
+}
+
+impl Report {
+    /// Annotate the arguments of the report's functions with the types, defaults and descriptions
+    /// of their signatures. Arguments are matched by name, those bound by destructuring a parameter
+    /// have no signature of their own.
+    pub fn with_signatures(mut self, signatures: &HashMap<String, FunctionSignature>) -> Self {
+        for (name, function) in self.triggerable_functions.iter_mut() {
+            let Some(signature) = signatures.get(name) else {
+                continue;
+            };
+            function.description = signature.description.clone();
+            for argument in function.arguments.iter_mut() {
+                if let Some(declared) = signature.arguments.iter().find(|declared| declared.name == argument.name) {
+                    *argument = declared.clone();
+                }
+            }
+        }
+        self
+    }
 }
 
 
@@ -135,10 +317,23 @@ pub enum ContextPath {
     IdentifierReferredTo {
         name: String,
         in_scope: bool,
-        exposed: bool
+        exposed: bool,
+        /// Range of the identifier in the cell. Left out when context paths are serialized, which
+        /// describe how a cell refers to names regardless of where in it they are written.
+        #[serde(skip)]
+        range: TextRange,
     },
     Attribute(String),
     Constant(String),
+    /// A name bound by an import statement. `alias` is the name given with `as`, without one
+    /// `import a.b` binds `a` and `from a import b` binds `b`.
+    ImportedName {
+        module: String,
+        name: Option<String>,
+        alias: Option<String>,
+    },
+    /// Every public name of a module, bound by `from module import *`
+    ImportedStar(String),
 }
 
 
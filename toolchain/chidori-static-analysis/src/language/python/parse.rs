@@ -1,4 +1,4 @@
-use crate::language::{ChidoriStaticAnalysisError, InternalCallGraph, Report, ReportItem, ReportTriggerableFunctions, TextRange};
+use crate::language::{ArgumentDefault, ArgumentType, ChidoriStaticAnalysisError, FunctionSignature, InternalCallGraph, Report, ReportArgument, ReportImportedValue, ReportItem, ReportTriggerableFunctions, TextRange};
 use rustpython_parser::ast::{Constant, Expr, Identifier, Stmt};
 use rustpython_parser::{ast, Parse};
 use serde::{Deserialize, Serialize};
@@ -122,7 +122,7 @@ impl ASTWalkContext {
         }
     }
 
-    fn encounter_named_reference(&mut self, name: &Identifier) {
+    fn encounter_named_reference(&mut self, name: &Identifier, range: TextRange) {
         // TODO: we need to check if this is a local variable or not
         if self.var_exists(&name.to_string()) {
             // true, the var exists in the local or global scope
@@ -130,7 +130,8 @@ impl ASTWalkContext {
                 .push(ContextPath::IdentifierReferredTo{
                     name: name.to_string(),
                     in_scope: true,
-                    exposed: false
+                    exposed: false,
+                    range,
                 });
         } else {
             // TODO: this might not be last due to pattern assignments
@@ -144,7 +145,8 @@ impl ASTWalkContext {
                 .push(ContextPath::IdentifierReferredTo{
                     name: name.to_string(),
                     in_scope: false,
-                    exposed: false
+                    exposed: false,
+                    range,
                 });
         }
         self.context_stack_references
@@ -152,6 +154,26 @@ impl ASTWalkContext {
         self.context_stack.pop();
     }
 
+    fn encounter_import(&mut self, module: String, name: Option<String>, alias: Option<String>) {
+        let bound = alias.clone()
+            .or_else(|| name.clone())
+            .unwrap_or_else(|| module.split('.').next().unwrap_or_default().to_string());
+        self.globals.insert(bound);
+        self.context_stack
+            .push(ContextPath::ImportedName { module, name, alias });
+        self.context_stack_references
+            .push(self.context_stack.clone());
+        self.context_stack.pop();
+    }
+
+    fn encounter_star_import(&mut self, module: String) {
+        self.context_stack
+            .push(ContextPath::ImportedStar(module));
+        self.context_stack_references
+            .push(self.context_stack.clone());
+        self.context_stack.pop();
+    }
+
     fn enter_assignment_to_statement(&mut self) -> usize {
         self.context_stack.push(ContextPath::AssignmentToStatement);
         self.context_stack.len()
@@ -320,8 +342,11 @@ fn traverse_expression(expr: &ast::Expr, machine: &mut ASTWalkContext) {
         ast::Expr::Starred(ast::ExprStarred { value, .. }) => {
             traverse_expression(value, machine);
         }
-        ast::Expr::Name(ast::ExprName { id, .. }) => {
-            machine.encounter_named_reference(id);
+        ast::Expr::Name(ast::ExprName { id, range, .. }) => {
+            machine.encounter_named_reference(id, TextRange {
+                start: range.start().to_usize(),
+                end: range.end().to_usize()
+            });
         }
         ast::Expr::List(ast::ExprList { elts, .. }) => {
             for elt in elts {
@@ -455,8 +480,12 @@ pub fn traverse_statements(statements: &[ast::Stmt], machine: &mut ASTWalkContex
                     ..
                 } in &args.args
                 {
-                    if let ast::Arg { arg, .. } = def {
-                        machine.encounter_named_reference(arg);
+                    if let ast::Arg { arg, range, .. } = def {
+                        // The argument's range includes its annotation
+                        machine.encounter_named_reference(arg, TextRange {
+                            start: range.start().to_usize(),
+                            end: range.start().to_usize() + arg.as_str().len()
+                        });
                     }
                 }
                 machine.pop_until(args_idx);
@@ -492,8 +521,12 @@ pub fn traverse_statements(statements: &[ast::Stmt], machine: &mut ASTWalkContex
                     ..
                 } in &args.args
                 {
-                    if let ast::Arg { arg, .. } = def {
-                        machine.encounter_named_reference(arg);
+                    if let ast::Arg { arg, range, .. } = def {
+                        // The argument's range includes its annotation
+                        machine.encounter_named_reference(arg, TextRange {
+                            start: range.start().to_usize(),
+                            end: range.start().to_usize() + arg.as_str().len()
+                        });
                     }
                 }
                 machine.pop_until(args_idx);
@@ -642,16 +675,33 @@ pub fn traverse_statements(statements: &[ast::Stmt], machine: &mut ASTWalkContex
             }
             ast::Stmt::Import(ast::StmtImport { names, .. }) => {
                 for name in names {
-                    if let Some(name) = &name.asname {
-                        machine.globals.insert(name.to_string());
-                    } else {
-                        machine.globals.insert(name.name.to_string());
-                    }
+                    machine.encounter_import(
+                        name.name.to_string(),
+                        None,
+                        name.asname.as_ref().map(|asname| asname.to_string()),
+                    );
                 }
                 // No recursion needed
             }
-            ast::Stmt::ImportFrom(ast::StmtImportFrom { .. }) => {
-                // TODO: Import
+            ast::Stmt::ImportFrom(ast::StmtImportFrom { module, names, level, .. }) => {
+                // Relative imports keep their leading dots, `from . import x` has no module name
+                let level = level.as_ref().map_or(0, |level| level.to_u32() as usize);
+                let module = format!(
+                    "{}{}",
+                    ".".repeat(level),
+                    module.as_ref().map(|module| module.to_string()).unwrap_or_default()
+                );
+                for name in names {
+                    if name.name.as_str() == "*" {
+                        machine.encounter_star_import(module.clone());
+                    } else {
+                        machine.encounter_import(
+                            module.clone(),
+                            Some(name.name.to_string()),
+                            name.asname.as_ref().map(|asname| asname.to_string()),
+                        );
+                    }
+                }
                 // No recursion needed
             }
             ast::Stmt::Global(ast::StmtGlobal { .. }) => {
@@ -676,22 +726,83 @@ pub fn traverse_statements(statements: &[ast::Stmt], machine: &mut ASTWalkContex
     }
 }
 
+/// Modules of the Python standard library, from `sys.stdlib_module_names`. Private modules, those starting
+/// with an underscore, are left out and treated as part of the standard library.
+const PY_STDLIB_MODULES: &[&str] = &[
+    "__future__", "abc", "aifc", "antigravity", "argparse", "array", "ast", "asynchat", "asyncio", "asyncore",
+    "atexit", "audioop", "base64", "bdb", "binascii", "bisect", "builtins", "bz2", "cProfile", "calendar", "cgi",
+    "cgitb", "chunk", "cmath", "cmd", "code", "codecs", "codeop", "collections", "colorsys", "compileall",
+    "concurrent", "configparser", "contextlib", "contextvars", "copy", "copyreg", "crypt", "csv", "ctypes",
+    "curses", "dataclasses", "datetime", "dbm", "decimal", "difflib", "dis", "distutils", "doctest", "email",
+    "encodings", "ensurepip", "enum", "errno", "faulthandler", "fcntl", "filecmp", "fileinput", "fnmatch",
+    "fractions", "ftplib", "functools", "gc", "genericpath", "getopt", "getpass", "gettext", "glob", "graphlib",
+    "grp", "gzip", "hashlib", "heapq", "hmac", "html", "http", "idlelib", "imaplib", "imghdr", "imp",
+    "importlib", "inspect", "io", "ipaddress", "itertools", "json", "keyword", "lib2to3", "linecache", "locale",
+    "logging", "lzma", "mailbox", "mailcap", "marshal", "math", "mimetypes", "mmap", "modulefinder", "msilib",
+    "msvcrt", "multiprocessing", "netrc", "nis", "nntplib", "nt", "ntpath", "nturl2path", "numbers", "opcode",
+    "operator", "optparse", "os", "ossaudiodev", "pathlib", "pdb", "pickle", "pickletools", "pipes", "pkgutil",
+    "platform", "plistlib", "poplib", "posix", "posixpath", "pprint", "profile", "pstats", "pty", "pwd",
+    "py_compile", "pyclbr", "pydoc", "pydoc_data", "pyexpat", "queue", "quopri", "random", "re", "readline",
+    "reprlib", "resource", "rlcompleter", "runpy", "sched", "secrets", "select", "selectors", "shelve", "shlex",
+    "shutil", "signal", "site", "smtpd", "smtplib", "sndhdr", "socket", "socketserver", "spwd", "sqlite3",
+    "sre_compile", "sre_constants", "sre_parse", "ssl", "stat", "statistics", "string", "stringprep", "struct",
+    "subprocess", "sunau", "symtable", "sys", "sysconfig", "syslog", "tabnanny", "tarfile", "telnetlib",
+    "tempfile", "termios", "textwrap", "this", "threading", "time", "timeit", "tkinter", "token", "tokenize",
+    "tomllib", "trace", "traceback", "tracemalloc", "tty", "turtle", "turtledemo", "types", "typing",
+    "unicodedata", "unittest", "urllib", "uu", "uuid", "venv", "warnings", "wave", "weakref", "webbrowser",
+    "winreg", "winsound", "wsgiref", "xdrlib", "xml", "xmlrpc", "zipapp", "zipfile", "zipimport", "zlib",
+    "zoneinfo",
+];
+
+/// Top level package of an imported module that has to be installed for the import to succeed. Relative
+/// modules, the standard library and chidori itself, which the runtime provides, need nothing installed.
+fn third_party_package(module: &str) -> Option<&str> {
+    if module.starts_with('.') {
+        return None;
+    }
+    let package = module.split('.').next().unwrap_or_default();
+    if package.is_empty() || package.starts_with('_') || package == "chidori" || PY_STDLIB_MODULES.contains(&package) {
+        return None;
+    }
+    Some(package)
+}
+
 pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
     // TODO: triggerable functions should note what they are triggered by
     // TODO: for each of these we should store the context path that refers to them
-    // TODO: context paths should include spans
     // TODO: for each of these we should store their type if its available
     let mut exposed_values = HashMap::new();
     let mut depended_values = HashMap::new();
     let mut triggerable_functions = HashMap::new();
+    let mut imported_values = HashMap::new();
+    let mut star_imports = vec![];
     for context_path in context_paths {
         let mut encountered = vec![];
         for (idx, context_path_unit) in context_path.iter().enumerate() {
             // encountered is the reversed order of the context path
             encountered.push(context_path_unit);
 
+            // Imported names are provided by the module they are imported from, not by other cells
+            if let ContextPath::ImportedName { module, name, alias } = context_path_unit {
+                // `import a.b` binds the top level package `a`
+                let (bound, module) = match (alias, name) {
+                    (Some(alias), _) => (alias.clone(), module.clone()),
+                    (None, Some(name)) => (name.clone(), module.clone()),
+                    (None, None) => {
+                        let package = module.split('.').next().unwrap_or_default().to_string();
+                        (package.clone(), package)
+                    }
+                };
+                imported_values.insert(bound, ReportImportedValue { module, name: name.clone() });
+            }
+            if let ContextPath::ImportedStar(module) = context_path_unit {
+                if !star_imports.contains(module) {
+                    star_imports.push(module.clone());
+                }
+            }
+
             // If we've declared a top level function, it is exposed
-            if let ContextPath::InFunction(name, _) = context_path_unit {
+            if let ContextPath::InFunction(name, range) = context_path_unit {
                 if !triggerable_functions.contains_key(name) {
                     triggerable_functions
                         .entry(name.clone())
@@ -699,12 +810,45 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                             
                             // context_path: context_path.clone(),
                             arguments: vec![],
+                            description: None,
                             emit_event: vec![],
                             trigger_on: vec![],
+                            range: range.clone(),
                         });
                 }
             }
 
+            // Decorators of the form @ch.on_event("name") and @ch.emit_as("name") note the events
+            // that a function handles and the event its return value is emitted as
+            if let ContextPath::Attribute(attribute) = context_path_unit {
+                if encountered.iter().any(|x| matches!(x, ContextPath::InFunctionDecorator(_))) {
+                    let function_name = encountered.iter().find_map(|x| match x {
+                        ContextPath::InFunction(name, _) => Some(name.clone()),
+                        _ => None,
+                    });
+                    let event_name = idx.checked_sub(1).and_then(|i| match &context_path[i] {
+                        ContextPath::Constant(event_name) => Some(event_name.clone()),
+                        _ => None,
+                    });
+                    if let (Some(function_name), Some(event_name)) = (function_name, event_name) {
+                        let x = triggerable_functions
+                            .entry(function_name)
+                            .or_insert_with(|| ReportTriggerableFunctions {
+                                arguments: vec![],
+                                description: None,
+                                emit_event: vec![],
+                                trigger_on: vec![],
+                                range: Default::default(),
+                            });
+                        match attribute.as_str() {
+                            "on_event" => x.trigger_on.push(event_name),
+                            "emit_as" => x.emit_event.push(event_name),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+
             // Function arguments get assigned to the triggerable function
             if let ContextPath::FunctionArgument(name) = context_path_unit {
                 // traverse back through path until we hit the InFunction
@@ -715,16 +859,18 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                             .entry(function_name.clone())
                             .or_insert_with(|| ReportTriggerableFunctions {
                                 arguments: vec![],
+                                description: None,
                                 emit_event: vec![], // Initialize with an empty string or a default value
                                 trigger_on: vec![],
+                                range: Default::default(),
                             });
-                        x.arguments.push(name.clone());
+                        x.arguments.push(ReportArgument::named(name.clone()));
                     }
                 }
             }
 
             // If an identifier is referred to, and it has not been assigned to earlier during our interpreting
-            if let ContextPath::IdentifierReferredTo{name: identifier, exposed: false, in_scope: false} = context_path_unit {
+            if let ContextPath::IdentifierReferredTo{name: identifier, exposed: false, in_scope: false, range} = context_path_unit {
                 // If we encounter both FunctionArguments and InFunction, then this is a function argument
                 if encountered.iter().any(|x| matches!(x, ContextPath::InFunction(_, _)))
                     && encountered.iter().any(|x| matches!(x, ContextPath::FunctionArguments))
@@ -735,10 +881,12 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                                 .entry(function_name.clone())
                                 .or_insert_with(|| ReportTriggerableFunctions {
                                     arguments: vec![],
+                                    description: None,
                                     emit_event: vec![], // Initialize with an empty string or a default value
                                     trigger_on: vec![],
+                                    range: Default::default(),
                                 });
-                            x.arguments.push(identifier.clone());
+                            x.arguments.push(ReportArgument::named(identifier.clone()));
                         }
                     }
                     continue;
@@ -751,24 +899,14 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                     .is_none()
                 {
                     if encountered.contains(&&ContextPath::AssignmentToStatement) {
-                        exposed_values.insert(
-                            identifier.clone(),
-                            ReportItem {
-                                // context_path: context_path.clone(),
-                            },
-                        );
+                        ReportItem::record(&mut exposed_values, identifier, range);
                         continue;
                     }
                 }
 
                 // If this value is not being assigned to, then it is a dependency
                 if !encountered.contains(&&ContextPath::AssignmentToStatement) {
-                    depended_values.insert(
-                        identifier.clone(),
-                        ReportItem {
-                            // context_path: context_path.clone(),
-                        },
-                    );
+                    ReportItem::record(&mut depended_values, identifier, range);
                     continue;
                 }
             }
@@ -787,6 +925,22 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
     ].iter().cloned().collect();
 
     depended_values.retain(|value,_ | !py_built_ins.contains(value.as_str()));
+    // Functions may refer to names that are only imported further down the cell
+    depended_values.retain(|value, _| !imported_values.contains_key(value));
+
+    let mut third_party_modules: Vec<String> = imported_values.values()
+        .map(|imported: &ReportImportedValue| imported.module.as_str())
+        .chain(star_imports.iter().map(|module| module.as_str()))
+        .filter_map(third_party_package)
+        .map(|package| package.to_string())
+        .collect();
+    third_party_modules.sort();
+    third_party_modules.dedup();
+
+    let defines_tests = imported_values.values()
+        .map(|imported| imported.module.as_str())
+        .chain(star_imports.iter().map(|module| module.as_str()))
+        .any(|module| module == "unittest" || module.starts_with("unittest."));
 
     Report {
         internal_call_graph: InternalCallGraph {
@@ -795,9 +949,264 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
         cell_exposed_values: exposed_values,
         cell_depended_values: depended_values,
         triggerable_functions: triggerable_functions,
+        imported_values,
+        star_imports,
+        third_party_modules,
+        defines_tests,
     }
 }
 
+/// Type represented by a type annotation, annotations we do not represent are `Any`.
+fn annotation_to_argument_type(annotation: &Expr) -> ArgumentType {
+    match annotation {
+        Expr::Name(ast::ExprName { id, .. }) => named_type(&id.to_string()),
+        Expr::Attribute(ast::ExprAttribute { attr, .. }) => named_type(&attr.to_string()),
+        Expr::Constant(ast::ExprConstant { value: Constant::None, .. }) => ArgumentType::Null,
+        // Forward references are annotations written as strings
+        Expr::Constant(ast::ExprConstant { value: Constant::Str(s), .. }) => {
+            ast::Expr::parse(s, "<annotation>")
+                .map(|annotation| annotation_to_argument_type(&annotation))
+                .unwrap_or(ArgumentType::Any)
+        }
+        Expr::BinOp(ast::ExprBinOp { left, op: ast::Operator::BitOr, right, .. }) => {
+            ArgumentType::union(vec![annotation_to_argument_type(left), annotation_to_argument_type(right)])
+        }
+        Expr::Subscript(ast::ExprSubscript { value, slice, .. }) => {
+            let parameters: Vec<&Expr> = match &**slice {
+                Expr::Tuple(ast::ExprTuple { elts, .. }) => elts.iter().collect(),
+                slice => vec![slice],
+            };
+            let name = match &**value {
+                Expr::Name(ast::ExprName { id, .. }) => id.to_string(),
+                Expr::Attribute(ast::ExprAttribute { attr, .. }) => attr.to_string(),
+                _ => return ArgumentType::Any,
+            };
+            match name.as_str() {
+                "Optional" => ArgumentType::union(vec![
+                    parameters.first().map_or(ArgumentType::Any, |ty| annotation_to_argument_type(ty)),
+                    ArgumentType::Null,
+                ]),
+                "Union" => ArgumentType::union(parameters.into_iter().map(annotation_to_argument_type).collect()),
+                "Annotated" => parameters.first().map_or(ArgumentType::Any, |ty| annotation_to_argument_type(ty)),
+                "Literal" => ArgumentType::union(parameters.into_iter().map(|literal| match literal {
+                    Expr::Constant(ast::ExprConstant { value, .. }) => match value {
+                        Constant::Str(_) => ArgumentType::String,
+                        Constant::Int(_) => ArgumentType::Int,
+                        Constant::Float(_) => ArgumentType::Float,
+                        Constant::Bool(_) => ArgumentType::Bool,
+                        Constant::None => ArgumentType::Null,
+                        _ => ArgumentType::Any,
+                    },
+                    _ => ArgumentType::Any,
+                }).collect()),
+                // tuple[int, ...] and homogeneous tuples hold items of a single type
+                "tuple" | "Tuple" => {
+                    let items: Vec<ArgumentType> = parameters.into_iter()
+                        .filter(|item| !matches!(item, Expr::Constant(ast::ExprConstant { value: Constant::Ellipsis, .. })))
+                        .map(annotation_to_argument_type)
+                        .collect();
+                    ArgumentType::List(Box::new(if items.is_empty() { ArgumentType::Any } else { ArgumentType::union(items) }))
+                }
+                _ => match named_type(&name) {
+                    ArgumentType::List(_) => ArgumentType::List(Box::new(
+                        parameters.first().map_or(ArgumentType::Any, |ty| annotation_to_argument_type(ty))
+                    )),
+                    ty => ty,
+                },
+            }
+        }
+        _ => ArgumentType::Any,
+    }
+}
+
+fn named_type(name: &str) -> ArgumentType {
+    match name {
+        "str" => ArgumentType::String,
+        "int" => ArgumentType::Int,
+        "float" => ArgumentType::Float,
+        "bool" => ArgumentType::Bool,
+        "None" | "NoneType" => ArgumentType::Null,
+        "list" | "List" | "set" | "Set" | "frozenset" | "FrozenSet" | "tuple" | "Tuple" | "Sequence"
+        | "MutableSequence" | "Iterable" | "Iterator" | "Collection" => ArgumentType::List(Box::new(ArgumentType::Any)),
+        "dict" | "Dict" | "Mapping" | "MutableMapping" | "TypedDict" => ArgumentType::Object,
+        "Callable" => ArgumentType::Function,
+        _ => ArgumentType::Any,
+    }
+}
+
+fn default_to_argument_default(default: &Expr) -> ArgumentDefault {
+    match default {
+        Expr::Constant(ast::ExprConstant { value, .. }) => match value {
+            Constant::None => ArgumentDefault::Null,
+            Constant::Bool(b) => ArgumentDefault::Bool(*b),
+            Constant::Str(s) => ArgumentDefault::String(s.clone()),
+            Constant::Int(i) => i.to_string().parse().map_or(ArgumentDefault::Expression, ArgumentDefault::Int),
+            Constant::Float(f) => ArgumentDefault::Float(*f),
+            _ => ArgumentDefault::Expression,
+        },
+        Expr::UnaryOp(ast::ExprUnaryOp { op: ast::UnaryOp::USub, operand, .. }) => match default_to_argument_default(operand) {
+            ArgumentDefault::Int(i) => ArgumentDefault::Int(-i),
+            ArgumentDefault::Float(f) => ArgumentDefault::Float(-f),
+            _ => ArgumentDefault::Expression,
+        },
+        _ => ArgumentDefault::Expression,
+    }
+}
+
+/// Docstring of a function, with its indentation removed as `inspect.cleandoc` does.
+fn function_docstring(body: &[Stmt]) -> Option<String> {
+    let Some(Stmt::Expr(ast::StmtExpr { value, .. })) = body.first() else {
+        return None;
+    };
+    let Expr::Constant(ast::ExprConstant { value: Constant::Str(docstring), .. }) = &**value else {
+        return None;
+    };
+    let mut lines: Vec<&str> = docstring.lines().collect();
+    let indent = lines.iter().skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut cleaned: Vec<String> = lines.drain(..).enumerate()
+        .map(|(i, line)| if i == 0 { line.trim().to_string() } else { line.get(indent..).unwrap_or("").trim_end().to_string() })
+        .collect();
+    while cleaned.first().map_or(false, |line| line.is_empty()) {
+        cleaned.remove(0);
+    }
+    while cleaned.last().map_or(false, |line| line.is_empty()) {
+        cleaned.pop();
+    }
+    if cleaned.is_empty() {
+        None
+    } else {
+        Some(cleaned.join("\n"))
+    }
+}
+
+/// Split a docstring into its summary and the descriptions of its arguments, documented either in a
+/// Google style `Args:` section or with reStructuredText `:param name:` fields.
+fn parse_docstring(docstring: &str) -> (Option<String>, HashMap<String, String>) {
+    const SECTIONS: [&str; 10] = ["Args:", "Arguments:", "Parameters:", "Returns:", "Return:", "Raises:", "Yields:", "Examples:", "Example:", "Note:"];
+    let mut summary = vec![];
+    let mut arguments: HashMap<String, String> = HashMap::new();
+    let mut in_summary = true;
+    let mut in_arguments = false;
+    let mut argument_indent = None;
+    let mut current: Option<String> = None;
+    for line in docstring.lines() {
+        let trimmed = line.trim();
+        let indent = line.len() - line.trim_start().len();
+        if SECTIONS.contains(&trimmed) {
+            in_summary = false;
+            in_arguments = matches!(trimmed, "Args:" | "Arguments:" | "Parameters:");
+            argument_indent = None;
+            current = None;
+            continue;
+        }
+        if let Some(field) = trimmed.strip_prefix(":param ") {
+            in_summary = false;
+            in_arguments = false;
+            if let Some((name, description)) = field.split_once(':') {
+                // `:param int count:` notes the type before the name
+                let name = name.split_whitespace().last().unwrap_or_default().to_string();
+                arguments.insert(name.clone(), description.trim().to_string());
+                current = Some(name);
+            }
+            continue;
+        }
+        if trimmed.starts_with(':') {
+            in_summary = false;
+            current = None;
+            continue;
+        }
+        if in_summary {
+            summary.push(line);
+            continue;
+        }
+        if trimmed.is_empty() {
+            continue;
+        }
+        if in_arguments && argument_indent.map_or(true, |argument_indent| indent <= argument_indent) {
+            if let Some((name, description)) = trimmed.split_once(':') {
+                // `name (type): description`
+                let name = name.split_whitespace().next().unwrap_or_default().trim_start_matches('*').to_string();
+                argument_indent = Some(indent);
+                arguments.insert(name.clone(), description.trim().to_string());
+                current = Some(name);
+                continue;
+            }
+        }
+        // Continuation of the previous argument's description
+        if let Some(name) = &current {
+            if let Some(description) = arguments.get_mut(name) {
+                if !description.is_empty() {
+                    description.push(' ');
+                }
+                description.push_str(trimmed);
+            }
+        }
+    }
+    let summary = summary.join("\n").trim().to_string();
+    (if summary.is_empty() { None } else { Some(summary) }, arguments)
+}
+
+fn function_signature(args: &ast::Arguments, body: &[Stmt]) -> FunctionSignature {
+    let (description, argument_descriptions) = function_docstring(body)
+        .map(|docstring| parse_docstring(&docstring))
+        .unwrap_or_default();
+    let argument = |arg: &ast::ArgWithDefault, position: Option<usize>| {
+        let name = arg.def.arg.to_string();
+        ReportArgument {
+            ty: arg.def.annotation.as_ref().map(|annotation| annotation_to_argument_type(annotation)),
+            default: arg.default.as_ref().map(|default| default_to_argument_default(default)),
+            description: argument_descriptions.get(&name).cloned(),
+            position,
+            name,
+        }
+    };
+    let mut arguments: Vec<ReportArgument> = args.posonlyargs.iter().chain(args.args.iter())
+        .enumerate()
+        .map(|(i, arg)| argument(arg, Some(i)))
+        .collect();
+    arguments.extend(args.kwonlyargs.iter().map(|arg| argument(arg, None)));
+    FunctionSignature {
+        arguments,
+        description,
+    }
+}
+
+fn collect_function_signatures(statements: &[Stmt], signatures: &mut HashMap<String, FunctionSignature>) {
+    for stmt in statements {
+        match stmt {
+            Stmt::FunctionDef(ast::StmtFunctionDef { name, args, body, .. }) |
+            Stmt::AsyncFunctionDef(ast::StmtAsyncFunctionDef { name, args, body, .. }) => {
+                signatures.entry(name.to_string()).or_insert_with(|| function_signature(args, body));
+                collect_function_signatures(body, signatures);
+            }
+            Stmt::ClassDef(ast::StmtClassDef { body, .. }) => {
+                collect_function_signatures(body, signatures);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Signatures of the functions declared in the source, from their annotations, defaults and docstrings.
+pub fn extract_function_signatures_python(source_code: &str) -> Result<HashMap<String, FunctionSignature>, ChidoriStaticAnalysisError> {
+    let ast = ast::Suite::parse(source_code, "<embedded>")
+        .map_err(|e| {
+            ChidoriStaticAnalysisError::ParseError {
+                msg: e.error.to_string(),
+                offset: e.offset.to_u32(),
+                source_path: e.source_path,
+                source_code: source_code.to_string(),
+            }
+        })?;
+    let mut signatures = HashMap::new();
+    collect_function_signatures(&ast, &mut signatures);
+    Ok(signatures)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -988,11 +1397,7 @@ mod tests {
                 map.insert(
                     "y".to_string(),
                     ReportItem {
-                        // context_path: vec![
-                        //     ContextPath::InFunction("testing".to_string()),
-                        //     ContextPath::AssignmentFromStatement,
-                        //     ContextPath::IdentifierReferredTo("y".to_string(), false),
-                        // ],
+                        range: TextRange { start: 27, end: 28 },
                     },
                 );
                 map
@@ -1005,12 +1410,18 @@ mod tests {
                         
                         // context_path: vec![ContextPath::InFunction("testing".to_string())],
                         arguments: vec![],
+                        description: None,
                         emit_event: vec![],
                         trigger_on: vec![],
+                        range: TextRange { start: 0, end: 41 },
                     },
                 );
                 map
             },
+            imported_values: HashMap::new(),
+            star_imports: vec![],
+            third_party_modules: vec![],
+            defines_tests: false,
         };
 
         assert_eq!(result, report);
@@ -1046,6 +1457,7 @@ x = random.randint(0, 10)
                 map.insert(
                     "x".to_string(),
                     ReportItem {
+                        range: TextRange { start: 92, end: 93 },
                     },
                 );
                 map
@@ -1055,6 +1467,7 @@ x = random.randint(0, 10)
                 map.insert(
                     "function_that_doesnt_exist".to_string(),
                     ReportItem {
+                        range: TextRange { start: 39, end: 65 },
                     },
                 );
                 map
@@ -1067,12 +1480,21 @@ x = random.randint(0, 10)
                         
                         // context_path: vec![ContextPath::InFunction("fun_name".to_string())],
                         arguments: vec![],
+                        description: None,
                         emit_event: vec![],
                         trigger_on: vec![],
+                        range: TextRange { start: 15, end: 90 },
                     },
                 );
                 map
             },
+            imported_values: HashMap::from([(
+                "random".to_string(),
+                ReportImportedValue { module: "random".to_string(), name: None },
+            )]),
+            star_imports: vec![],
+            third_party_modules: vec![],
+            defines_tests: false,
         };
         assert_eq!(result, report);
         Ok(())
@@ -1106,19 +1528,50 @@ x = random.randint(0, 10)
                     ReportTriggerableFunctions {
 
                         // context_path: vec![ContextPath::InFunction("testing".to_string())],
-                        arguments: vec!["a", "b", "c", "d"].into_iter().map(|a| a.to_string()).collect(),
+                        arguments: vec!["a", "b", "c", "d"].into_iter().map(ReportArgument::named).collect(),
+                        description: None,
                         emit_event: vec![],
                         trigger_on: vec![],
+                        range: TextRange { start: 0, end: 73 },
                     },
                 );
                 map
             },
+            imported_values: HashMap::new(),
+            star_imports: vec![],
+            third_party_modules: vec![],
+            defines_tests: false,
         };
 
         assert_eq!(result, report);
         Ok(())
     }
 
+    #[test]
+    fn test_report_generation_event_decorators() -> anyhow::Result<()>  {
+        let python_source = indoc! { r#"
+        import chidori as ch
+
+        @ch.on_event("new_file")
+        @ch.emit_as("file_created")
+        def dispatch_agent(ev):
+            return ev
+            "#};
+        let context_stack_references = extract_dependencies_python(python_source).map_err(|e| anyhow::Error::msg(format!("{:?}", e)))?;
+        let result = build_report(&context_stack_references);
+        assert_eq!(
+            result.triggerable_functions.get("dispatch_agent"),
+            Some(&ReportTriggerableFunctions {
+                arguments: vec![ReportArgument::named("ev")],
+                description: None,
+                emit_event: vec!["file_created".to_string()],
+                trigger_on: vec!["new_file".to_string()],
+                range: TextRange { start: 75, end: 112 },
+            })
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_report_generation_for_loop_variable_assignment() -> anyhow::Result<()>  {
         let python_source = indoc! { r#"
@@ -1201,6 +1654,7 @@ x = random.randint(0, 10)
                 map.insert(
                     "addTwo".to_string(),
                     ReportItem {
+                        range: TextRange { start: 116, end: 122 },
                     },
                 );
                 map
@@ -1210,16 +1664,132 @@ x = random.randint(0, 10)
                 map.insert(
                     "test_addTwo".to_string(),
                     ReportTriggerableFunctions {
-                        arguments: vec!["self".to_string()],
+                        arguments: vec![ReportArgument::named("self")],
+                        description: None,
                         emit_event: vec![],
                         trigger_on: vec![],
+                        range: TextRange { start: 68, end: 129 },
                     },
                 );
                 map
             },
+            imported_values: HashMap::from([(
+                "unittest".to_string(),
+                ReportImportedValue { module: "unittest".to_string(), name: None },
+            )]),
+            star_imports: vec![],
+            third_party_modules: vec![],
+            defines_tests: true,
         };
 
         assert_eq!(result, report);
         Ok(())
     }
+
+    #[test]
+    fn test_report_generation_with_signatures() -> anyhow::Result<()> {
+        let python_source = indoc! { r#"
+        from typing import Optional
+
+        def search(query: str, limit: int = 10, tags: list[str] | None = None, *, exact: "bool" = False, mode=compute_mode()):
+            """Search the collection.
+
+            Args:
+                query: Text to search for.
+                limit (int): Most results
+                    to return.
+            """
+            return query
+
+        def untyped(a, b: Optional[dict] = None):
+            return a
+            "#};
+        let context_stack_references = extract_dependencies_python(python_source).map_err(|e| anyhow::Error::msg(format!("{:?}", e)))?;
+        let signatures = extract_function_signatures_python(python_source).map_err(|e| anyhow::Error::msg(format!("{:?}", e)))?;
+        let result = build_report(&context_stack_references).with_signatures(&signatures);
+
+        let search = result.triggerable_functions.get("search").unwrap();
+        assert_eq!(search.description, Some("Search the collection.".to_string()));
+        assert_eq!(search.arguments, vec![
+            ReportArgument {
+                name: "query".to_string(),
+                ty: Some(ArgumentType::String),
+                default: None,
+                description: Some("Text to search for.".to_string()),
+                position: Some(0),
+            },
+            ReportArgument {
+                name: "limit".to_string(),
+                ty: Some(ArgumentType::Int),
+                default: Some(ArgumentDefault::Int(10)),
+                description: Some("Most results to return.".to_string()),
+                position: Some(1),
+            },
+            ReportArgument {
+                name: "tags".to_string(),
+                ty: Some(ArgumentType::Optional(Box::new(ArgumentType::List(Box::new(ArgumentType::String))))),
+                default: Some(ArgumentDefault::Null),
+                description: None,
+                position: Some(2),
+            },
+        ]);
+
+        let signature = signatures.get("search").unwrap();
+        assert_eq!(signature.arguments[3], ReportArgument {
+            name: "exact".to_string(),
+            ty: Some(ArgumentType::Bool),
+            default: Some(ArgumentDefault::Bool(false)),
+            description: None,
+            position: None,
+        });
+        assert_eq!(signature.arguments[4].default, Some(ArgumentDefault::Expression));
+
+        let untyped = result.triggerable_functions.get("untyped").unwrap();
+        assert_eq!(untyped.arguments[0].ty, None);
+        assert_eq!(untyped.arguments[1].ty, Some(ArgumentType::Optional(Box::new(ArgumentType::Object))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_generation_with_import_from() -> anyhow::Result<()> {
+        let python_source = indoc! { r#"
+        import os.path
+        import numpy as np
+        from sklearn.linear_model import LinearRegression as Model
+        from .helpers import clean
+        from . import settings
+        from math import *
+        from chidori import on_event
+
+        def fit(rows):
+            return Model().fit(np.array(list(map(clean, rows))), sqrt(settings.SCALE))
+
+        x = os.path.join(data_dir, "data.csv")
+            "#};
+        let context_stack_references = extract_dependencies_python(python_source).map_err(|e| anyhow::Error::msg(format!("{:?}", e)))?;
+        let result = build_report(&context_stack_references);
+
+        assert_eq!(result.imported_values.get("os"), Some(&ReportImportedValue { module: "os".to_string(), name: None }));
+        assert_eq!(result.imported_values.get("np"), Some(&ReportImportedValue { module: "numpy".to_string(), name: None }));
+        assert_eq!(result.imported_values.get("Model"), Some(&ReportImportedValue {
+            module: "sklearn.linear_model".to_string(),
+            name: Some("LinearRegression".to_string()),
+        }));
+        assert_eq!(result.imported_values.get("clean"), Some(&ReportImportedValue {
+            module: ".helpers".to_string(),
+            name: Some("clean".to_string()),
+        }));
+        assert_eq!(result.imported_values.get("settings"), Some(&ReportImportedValue {
+            module: ".".to_string(),
+            name: Some("settings".to_string()),
+        }));
+        assert_eq!(result.star_imports, vec!["math".to_string()]);
+        assert_eq!(result.third_party_modules, vec!["numpy".to_string(), "sklearn".to_string()]);
+
+        // Imported names are not depended on, names a star import may provide still are
+        let mut depended_values: Vec<&String> = result.cell_depended_values.keys().collect();
+        depended_values.sort();
+        assert_eq!(depended_values, vec!["data_dir", "sqrt"]);
+        Ok(())
+    }
 }